@@ -0,0 +1,121 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use appendfs::block::FsId;
+use appendfs::fs::Filesystem;
+use appendfs::storage::ram::RamStorage;
+use appendfs::storage::Storage;
+
+const FS_ID: FsId = 1813944125;
+
+fn fill<S: Storage, const BS: usize>(fs: &mut Filesystem<S, BS>, blocks: usize) {
+    for i in 0..blocks {
+        fs.append(|blk_data| blk_data.fill(i as u8))
+            .expect("Can't append block");
+    }
+}
+
+fn bench_ram<const SIZE: usize, const BS: usize>(c: &mut Criterion) {
+    let name = format!("ram/{}B-blocks/{}-blocks", BS, SIZE / BS);
+    let mut group = c.benchmark_group(name);
+
+    {
+        let mut storage = Box::new(RamStorage::<SIZE, BS>::new().expect("Can't create storage"));
+        let mut fs = Filesystem::<_, BS>::new(&mut *storage, FS_ID).expect("Can't create fs");
+
+        group.throughput(Throughput::Bytes(fs.data_size() as u64));
+        group.bench_function("append", |b| {
+            b.iter(|| {
+                fs.append(|blk_data| blk_data.fill(42))
+                    .expect("Can't append")
+            })
+        });
+
+        let used = fs.used_blocks();
+        let mut offset = 0;
+        group.bench_function("read", |b| {
+            b.iter(|| {
+                offset = (offset + 1) % used;
+                fs.read(offset, |blk_data| {
+                    criterion::black_box(blk_data);
+                })
+                .expect("Can't read")
+            })
+        });
+    }
+
+    // restore time depends on the position of the last written block, measure half full and full storage
+    for (state, blocks) in [("half", (SIZE / BS) / 2), ("wrapped", SIZE / BS * 3 / 2)] {
+        let mut storage = Box::new(RamStorage::<SIZE, BS>::new().expect("Can't create storage"));
+        fill(
+            &mut Filesystem::<_, BS>::new(&mut *storage, FS_ID).expect("Can't create fs"),
+            blocks,
+        );
+
+        group.throughput(Throughput::Elements(1));
+        group.bench_with_input(BenchmarkId::new("restore", state), &blocks, |b, _| {
+            b.iter(|| {
+                Filesystem::<_, BS>::restore(&mut *storage)
+                    .expect("Can't restore")
+                    .next_blk_id()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(feature = "file_storage")]
+fn bench_file(c: &mut Criterion) {
+    use appendfs::storage::file::FileStorage;
+
+    const BS: usize = 512;
+    const BLOCKS: u32 = 4096;
+
+    let path = std::env::temp_dir().join("appendfs-bench-file-fs");
+    let mut storage = FileStorage::create_image(&path, BLOCKS, BS as u32, FS_ID)
+        .expect("Can't create image for storage");
+
+    let mut group = c.benchmark_group(format!("file/{}B-blocks/{}-blocks", BS, BLOCKS));
+    {
+        let mut fs = Filesystem::<_, BS>::new(&mut storage, FS_ID).expect("Can't create fs");
+        group.throughput(Throughput::Bytes(fs.data_size() as u64));
+        group.bench_function("append", |b| {
+            b.iter(|| {
+                fs.append(|blk_data| blk_data.fill(42))
+                    .expect("Can't append")
+            })
+        });
+        group.bench_function("read", |b| {
+            b.iter(|| {
+                fs.read(0, |blk_data| {
+                    criterion::black_box(blk_data);
+                })
+            })
+        });
+    }
+
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("restore", |b| {
+        b.iter(|| {
+            Filesystem::<_, BS>::restore(&mut storage)
+                .expect("Can't restore")
+                .next_blk_id()
+        })
+    });
+    group.finish();
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(not(feature = "file_storage"))]
+fn bench_file(_c: &mut Criterion) {}
+
+fn bench_all(c: &mut Criterion) {
+    bench_ram::<{ 64 * 1024 }, 512>(c);
+    bench_ram::<{ 1024 * 1024 }, 512>(c);
+    bench_ram::<{ 1024 * 1024 }, 4096>(c);
+    bench_file(c);
+}
+
+criterion_group!(benches, bench_all);
+criterion_main!(benches);
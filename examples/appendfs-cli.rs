@@ -0,0 +1,776 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use appendfs::block::{derive_fs_id, Block, CrcMode, FsId, HeaderVersion};
+use appendfs::error::Error;
+use appendfs::fs::sizing::recommend_block_size;
+use appendfs::fs::{DynFilesystem, FormatPolicy, FsIdCount, RestoreStep, MAX_BLOCK_SIZE};
+use appendfs::io::{FsReader, FsWriter};
+use appendfs::log;
+use appendfs::storage::file::FileStorage;
+use appendfs::storage::Storage;
+
+const DEFAULT_BLOCK_SIZE: u32 = 512;
+const DEFAULT_BEGIN_BLOCK_IDX: u32 = 2048;
+const DEFAULT_END_BLOCK_IDX: u32 = 1024 * 1024 * 1024 * 3 / DEFAULT_BLOCK_SIZE;
+const DEFAULT_RETRIES: u16 = 4;
+
+pub type Fs<'a> = DynFilesystem<'a, FileStorage>;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args, Debug)]
+struct StorageArgs {
+    #[arg(short, long)]
+    device: String,
+
+    #[arg(long, default_value_t = DEFAULT_BEGIN_BLOCK_IDX)]
+    begin_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_END_BLOCK_IDX)]
+    end_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: u32,
+
+    #[arg(long, default_value_t = DEFAULT_RETRIES)]
+    retries: u16,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Format storage, create new filesystem
+    Format {
+        /// Filesystem id, it's derived from the device path and time of format if not set
+        #[arg(long)]
+        fs_id: Option<FsId>,
+        /// Part of data blocks covered by crc
+        #[arg(long, value_enum, default_value_t = CrcModeArg::Full)]
+        crc_mode: CrcModeArg,
+        /// Format storage of another filesystem, its blocks are left in storage till they are overwritten
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print filesystem attributes
+    Info {
+        /// Print blocks read by restore scan and decisions of its binary search, attach it to reports
+        /// of restore which found wrong newest block
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Append all data from stdin to the filesystem, format storage in case it wasn't formatted
+    Write,
+    /// Write all data from the filesystem to stdout, from the oldest block to the newest one
+    Read {
+        /// Offset of the first block to read, 0 is the oldest block
+        #[arg(long, conflicts_with = "from_id")]
+        from_block: Option<usize>,
+
+        /// Id of the first block to read
+        #[arg(long)]
+        from_id: Option<u64>,
+
+        /// Max number of blocks to read
+        #[arg(long)]
+        count: Option<usize>,
+
+        /// Keep waiting for new blocks and write them to stdout as they arrive
+        #[arg(short, long, default_value_t = false)]
+        follow: bool,
+
+        /// How often storage is checked for new blocks in follow mode
+        #[arg(long, default_value_t = 500)]
+        interval_ms: u64,
+    },
+    /// Check all used blocks can be read
+    Fsck {
+        /// Drop blocks starting from the first invalid one (e.g. half written block after power loss)
+        #[arg(long, default_value_t = false)]
+        truncate: bool,
+    },
+    /// Find filesystems in the storage range (use --begin-block=0 to scan the whole device)
+    Scan,
+    /// Count blocks of every fs id in the storage range, check it before formatting storage of another device
+    Ids {
+        /// Check only every N-th block
+        #[arg(long, default_value_t = 1)]
+        step: usize,
+    },
+    /// Write data of blocks with given fs id to stdout, use it to recover data after accidental reformat
+    Recover {
+        /// Id of the previous filesystem, use `ids` subcommand to find it
+        #[arg(long)]
+        fs_id: FsId,
+    },
+    /// Copy filesystem to another device or image file (file is created if it doesn't exist),
+    /// block size of both storages is the same
+    Export {
+        #[arg(long)]
+        to_device: String,
+
+        #[arg(long, default_value_t = 0)]
+        to_begin_block: u32,
+
+        /// Same size as source range if not set
+        #[arg(long)]
+        to_end_block: Option<u32>,
+    },
+    /// Print header and data of storage blocks, blocks are not validated, so it can be used for corrupted storage
+    Dump {
+        /// Index of the first block relative to --begin-block
+        #[arg(long, default_value_t = 0)]
+        from_block: usize,
+
+        /// Max number of blocks to dump
+        #[arg(long)]
+        count: Option<usize>,
+
+        #[arg(long, value_enum, default_value_t = DumpFormat::Hex)]
+        format: DumpFormat,
+
+        /// Print only block headers
+        #[arg(long, default_value_t = false)]
+        header_only: bool,
+    },
+    /// Recommend --block-size for records of given size, storage must be formatted and used with it
+    BlockSize {
+        #[arg(long)]
+        record_size: usize,
+
+        #[arg(long, value_enum, default_value_t = CrcModeArg::Full)]
+        crc_mode: CrcModeArg,
+    },
+    /// Overwrite whole storage range with zeroes
+    Erase {
+        /// Confirm all data will be destroyed
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CrcModeArg {
+    Full,
+    HeaderOnly,
+    Split,
+}
+
+impl From<CrcModeArg> for CrcMode {
+    fn from(arg: CrcModeArg) -> Self {
+        match arg {
+            CrcModeArg::Full => CrcMode::Full,
+            CrcModeArg::HeaderOnly => CrcMode::HeaderOnly,
+            CrcModeArg::Split => CrcMode::Split,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DumpFormat {
+    Hex,
+    Json,
+}
+
+impl Command {
+    /// Command appends to or formats the storage
+    fn writes(&self) -> bool {
+        match self {
+            Command::Format { .. } | Command::Write | Command::Erase { .. } => true,
+            Command::Fsck { truncate } => *truncate,
+            _ => false,
+        }
+    }
+}
+
+impl StorageArgs {
+    fn open(&self) -> Result<FileStorage, String> {
+        if self.block_size as usize > MAX_BLOCK_SIZE {
+            return Err(format!("Max supported block size is {}", MAX_BLOCK_SIZE));
+        }
+
+        FileStorage::new(
+            self.device.clone(),
+            self.begin_block,
+            self.end_block,
+            self.block_size,
+            Some(self.retries),
+        )
+    }
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let cli = Cli::parse();
+    let mut storage = match cli.storage.open() {
+        Ok(s) => s,
+        Err(e) => {
+            log!(error, "Can't create storage: `{:?}`", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // another writer (e.g. logging daemon) would corrupt the head, readers don't take the lock
+    if cli.command.writes() {
+        if let Err(e) = storage.lock() {
+            log!(
+                error,
+                "Can't lock storage, is it used by another writer? `{:?}`",
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let res = match cli.command {
+        Command::Format {
+            fs_id,
+            crc_mode,
+            force,
+        } => format(
+            &mut storage,
+            &cli.storage.device,
+            fs_id,
+            crc_mode.into(),
+            force,
+        ),
+        Command::Info { explain } => info(&mut storage, explain),
+        Command::Write => write(&mut storage, &cli.storage.device),
+        Command::Read {
+            from_block,
+            from_id,
+            count,
+            follow,
+            interval_ms,
+        } => read(
+            &mut storage,
+            ReadRange {
+                from_block,
+                from_id,
+                count,
+            },
+            follow,
+            interval_ms,
+        ),
+        Command::Fsck { truncate } => fsck(&mut storage, truncate),
+        Command::Scan => scan(&mut storage),
+        Command::Ids { step } => ids(&mut storage, step),
+        Command::Recover { fs_id } => recover(&mut storage, fs_id),
+        Command::Export {
+            to_device,
+            to_begin_block,
+            to_end_block,
+        } => export(
+            &mut storage,
+            &cli.storage,
+            to_device,
+            to_begin_block,
+            to_end_block,
+        ),
+        Command::Dump {
+            from_block,
+            count,
+            format,
+            header_only,
+        } => dump(&mut storage, from_block, count, format, header_only),
+        Command::BlockSize {
+            record_size,
+            crc_mode,
+        } => block_size(&storage, record_size, crc_mode.into()),
+        Command::Erase { yes } => erase(&mut storage, yes),
+    };
+
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log!(error, "{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn log_fs(fs: &Fs) {
+    log!(
+        info,
+        "Init filesystem, offset: {}, id: {}, next_blk_id: {}",
+        fs.offset(),
+        fs.id(),
+        fs.next_blk_id()
+    );
+}
+
+/// Id of new filesystem, time of format is the salt, so reformatted device gets new id
+fn new_fs_id(device: &str) -> FsId {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    derive_fs_id(device.as_bytes(), &nanos.to_be_bytes())
+}
+
+fn format(
+    storage: &mut FileStorage,
+    device: &str,
+    fs_id: Option<FsId>,
+    crc_mode: CrcMode,
+    force: bool,
+) -> Result<(), String> {
+    let fs_id = fs_id.unwrap_or_else(|| new_fs_id(device));
+    let fs = Fs::new_with_force(storage, fs_id, crc_mode, force)
+        .map_err(|e| format!("Can't format storage, err: {:?}", e))?;
+    log!(info, "Successfully formatted storage");
+    log_fs(&fs);
+
+    Ok(())
+}
+
+fn info(storage: &mut FileStorage, explain: bool) -> Result<(), String> {
+    // trail is printed as blocks are read, so it's printed even if restore fails
+    let mut trail = |step: RestoreStep| println!("{:?}", step);
+    let mut builder = Fs::builder(storage).read_only(true);
+    if explain {
+        builder = builder.trail(&mut trail);
+    }
+    let fs = builder
+        .build()
+        .map_err(|e| format!("Can't open fs: `{:?}`", e))?;
+    let stats = fs.stats();
+    println!("id: {}", stats.id);
+    println!("offset: {}", stats.offset);
+    println!("next_blk_id: {}", stats.next_blk_id);
+    println!("used_blocks: {}", stats.used_blocks);
+    println!("capacity: {}", stats.capacity);
+    println!(
+        "usage: {:.1}%",
+        stats.used_blocks as f64 * 100.0 / stats.capacity as f64
+    );
+    println!("is_empty: {}", stats.is_empty);
+    println!("is_full: {}", stats.is_full);
+    println!("wrap_count: {}", stats.wrap_count);
+    println!("geometry: {:?}", fs.storage().geometry());
+    println!("crc_mode: {:?}", fs.crc_mode());
+    println!("id_high_water: {}", fs.id_high_water());
+    println!("boot_time: {}", fs.boot_time());
+    println!("boot_count: {}", fs.boot_count());
+    println!("bad_blocks: {:?}", fs.bad_blocks().collect::<Vec<_>>());
+    println!("health: {:?}", fs.health());
+    println!(
+        "blocks_until_id_exhaustion: {}",
+        fs.blocks_until_id_exhaustion()
+    );
+    let wear = fs.wear_stats();
+    println!("total_writes: {}", wear.total_writes);
+    println!("avg_writes_per_block: {}", wear.avg_writes_per_block);
+    println!("ring_cycles: {}", wear.ring_cycles);
+
+    Ok(())
+}
+
+fn write(storage: &mut FileStorage, device: &str) -> Result<(), String> {
+    let mut filesystem =
+        Fs::restore_or_format(storage, || new_fs_id(device), FormatPolicy::Preserve)
+            .map_err(|e| format!("Can't restore fs: `{:?}`", e))?;
+    log_fs(&filesystem);
+
+    let mut writer = FsWriter::new(&mut filesystem);
+    let size = io::copy(&mut io::stdin().lock(), &mut writer)
+        .map_err(|e| format!("Error write block: {:?}", e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("Error write last block: {:?}", e))?;
+    log!(info, "Written {} bytes", size);
+
+    Ok(())
+}
+
+struct ReadRange {
+    from_block: Option<usize>,
+    from_id: Option<u64>,
+    count: Option<usize>,
+}
+
+fn read(
+    storage: &mut FileStorage,
+    range: ReadRange,
+    follow: bool,
+    interval_ms: u64,
+) -> Result<(), String> {
+    // the device can be used by a writer at the same time
+    let mut filesystem =
+        Fs::open_readonly(storage).map_err(|e| format!("Can't open fs: `{:?}`", e))?;
+    log_fs(&filesystem);
+
+    if filesystem.is_empty() && !follow {
+        log!(warn, "Nothing to read, fs is empty!");
+        return Ok(());
+    }
+
+    let from = match range.from_id {
+        Some(id) => filesystem.blk_offset_of(id).ok_or_else(|| {
+            format!(
+                "Block with id {} is not available, available ids: {}..{}",
+                id,
+                filesystem.oldest_blk_id(),
+                filesystem.next_blk_id()
+            )
+        })?,
+        None => range.from_block.unwrap_or(0),
+    };
+    let count = range.count.unwrap_or(usize::MAX);
+
+    log!(
+        info,
+        "Reading from block {}, used blocks: {}",
+        from,
+        filesystem.used_blocks()
+    );
+    let mut reader = FsReader::with_range(&mut filesystem, from, count);
+    let size = io::copy(&mut reader, &mut io::stdout().lock()).map_err(|e| {
+        format!(
+            "Error read block, offset: {}, e: {:?}",
+            reader.blk_offset(),
+            e
+        )
+    })?;
+    log!(info, "Finish reading, read {} bytes", size);
+
+    if follow {
+        return follow_fs(&mut filesystem, Duration::from_millis(interval_ms));
+    }
+
+    Ok(())
+}
+
+fn follow_fs(filesystem: &mut Fs, interval: Duration) -> Result<(), String> {
+    let mut stdout = io::stdout().lock();
+    let mut next_id = filesystem.next_blk_id();
+    log!(info, "Waiting for new blocks, next block id: {}", next_id);
+
+    loop {
+        thread::sleep(interval);
+        filesystem
+            .refresh()
+            .map_err(|e| format!("Can't refresh fs: `{:?}`", e))?;
+
+        let oldest = filesystem.oldest_blk_id();
+        if next_id < oldest {
+            log!(
+                warn,
+                "Missed {} blocks, they were overwritten before read",
+                oldest - next_id
+            );
+            next_id = oldest;
+        }
+
+        let used = filesystem.used_blocks();
+        for offset in (next_id - oldest) as usize..used {
+            let mut res = Ok(());
+            match filesystem.read(offset, |blk_data| res = stdout.write_all(blk_data)) {
+                Ok(_) => res.map_err(|e| format!("Can't write to stdout: {:?}", e))?,
+                Err(Error::NotValidBlockForRead) => {
+                    log!(warn, "Skip invalid block {}", next_id);
+                }
+                // writer overwrote the block after refresh, it's counted as missed after the next one
+                Err(Error::Lapped) => break,
+                Err(e) => return Err(format!("Can't read block at {}: {:?}", offset, e)),
+            }
+            next_id += 1;
+        }
+        stdout
+            .flush()
+            .map_err(|e| format!("Can't write to stdout: {:?}", e))?;
+    }
+}
+
+fn fsck(storage: &mut FileStorage, truncate: bool) -> Result<(), String> {
+    // storage is locked only by fsck which truncates
+    let mut filesystem = if truncate {
+        Fs::restore(storage).map_err(|e| format!("Can't restore fs: `{:?}`", e))?
+    } else {
+        Fs::open_readonly(storage).map_err(|e| format!("Can't open fs: `{:?}`", e))?
+    };
+    log_fs(&filesystem);
+
+    if truncate {
+        let first_invalid = filesystem
+            .find_first_invalid_offset()
+            .map_err(|e| format!("Can't check blocks: {:?}", e))?;
+        if let Some(offset) = first_invalid {
+            let dropped = filesystem
+                .truncate_tail(offset)
+                .map_err(|e| format!("Can't truncate fs: {:?}", e))?;
+            println!("dropped {} blocks starting from offset {}", dropped, offset);
+        }
+    }
+
+    let used = filesystem.used_blocks();
+    let invalid = filesystem
+        .find_gaps(|gap| {
+            println!("missing ids: {}..{}", gap.start, gap.end);
+            true
+        })
+        .map_err(|e| format!("Can't check blocks: {:?}", e))?;
+
+    println!("checked: {}, invalid: {}", used, invalid);
+    if invalid != 0 {
+        return Err(format!("Found {} invalid blocks", invalid));
+    }
+
+    Ok(())
+}
+
+fn scan(storage: &mut FileStorage) -> Result<(), String> {
+    let found = Fs::scan_for_fs(storage, |candidate| {
+        match candidate.end_block {
+            Some(end) => println!(
+                "fs_id: {}, version: {}, --begin-block={} --end-block={}",
+                candidate.fs_id, candidate.version, candidate.begin_block, end
+            ),
+            None => println!(
+                "fs_id: {}, version: {}, --begin-block={} (end block is unknown)",
+                candidate.fs_id, candidate.version, candidate.begin_block
+            ),
+        }
+        true
+    })
+    .map_err(|e| format!("Can't scan storage: {:?}", e))?;
+
+    if found == 0 {
+        return Err("No filesystem was found".to_string());
+    }
+
+    Ok(())
+}
+
+fn ids(storage: &mut FileStorage, step: usize) -> Result<(), String> {
+    let mut ids = [FsIdCount::default(); 64];
+    let report = Fs::count_fs_ids(storage, step, &mut ids)
+        .map_err(|e| format!("Can't count fs ids: {:?}", e))?;
+
+    for count in &ids[..report.ids_found] {
+        println!("fs_id: {}, blocks: {}", count.fs_id, count.blocks);
+    }
+    println!(
+        "checked: {}, valid: {}, blocks of other ids: {}",
+        report.checked_blocks, report.valid_blocks, report.untracked_blocks
+    );
+
+    Ok(())
+}
+
+fn recover(storage: &mut FileStorage, fs_id: FsId) -> Result<(), String> {
+    let mut stdout = io::stdout().lock();
+    let mut write_err = None;
+    let read = Fs::recover_blocks(storage, fs_id, |id, blk_data| {
+        log!(trace, "Recovered block {}", id);
+        match stdout.write_all(blk_data) {
+            Ok(_) => true,
+            Err(e) => {
+                write_err = Some(e);
+                false
+            }
+        }
+    })
+    .map_err(|e| format!("Can't recover blocks: {:?}", e))?;
+
+    if let Some(e) = write_err {
+        return Err(format!("Can't write recovered data: {:?}", e));
+    }
+    log!(info, "Recovered {} blocks of fs {}", read, fs_id);
+
+    Ok(())
+}
+
+fn export(
+    storage: &mut FileStorage,
+    args: &StorageArgs,
+    to_device: String,
+    to_begin_block: u32,
+    to_end_block: Option<u32>,
+) -> Result<(), String> {
+    let mut filesystem =
+        Fs::open_readonly(storage).map_err(|e| format!("Can't open fs: `{:?}`", e))?;
+    log_fs(&filesystem);
+
+    let to_end_block = to_end_block.unwrap_or(to_begin_block + (args.end_block - args.begin_block));
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&to_device)
+        .map_err(|e| format!("Can't open {}: {:?}", to_device, e))?;
+    let len = to_end_block as u64 * args.block_size as u64;
+    let meta = file
+        .metadata()
+        .map_err(|e| format!("Can't get metadata of {}: {:?}", to_device, e))?;
+    if meta.is_file() && meta.len() < len {
+        file.set_len(len)
+            .map_err(|e| format!("Can't resize {}: {:?}", to_device, e))?;
+    }
+
+    let mut dst = FileStorage::new(
+        to_device,
+        to_begin_block,
+        to_end_block,
+        args.block_size,
+        Some(args.retries),
+    )?;
+    let copied = filesystem
+        .export_to(&mut dst)
+        .map_err(|e| format!("Can't export fs: {:?}", e))?;
+    log!(
+        info,
+        "Exported {} of {} blocks",
+        copied,
+        filesystem.used_blocks()
+    );
+
+    Ok(())
+}
+
+fn dump(
+    storage: &mut FileStorage,
+    from_block: usize,
+    count: Option<usize>,
+    format: DumpFormat,
+    header_only: bool,
+) -> Result<(), String> {
+    let begin = storage.min_block_index() + from_block;
+    let end = match count {
+        Some(count) => core::cmp::min(begin + count, storage.max_block_index()),
+        None => storage.max_block_index(),
+    };
+
+    let mut stdout = io::stdout().lock();
+    let mut buf = vec![0_u8; storage.block_size()];
+    for blk_idx in begin..end {
+        storage
+            .read(blk_idx, &mut buf[..])
+            .map_err(|e| format!("Can't read block {}: {:?}", blk_idx, e))?;
+
+        let block = Block::<MAX_BLOCK_SIZE>::from_buffer(&buf[..]);
+        let data = &buf[Block::<MAX_BLOCK_SIZE>::attributes_size()..];
+        let res = match format {
+            DumpFormat::Hex => dump_hex(&mut stdout, blk_idx, &block, data, header_only),
+            DumpFormat::Json => dump_json(&mut stdout, blk_idx, &block, data, header_only),
+        };
+        res.map_err(|e| format!("Can't write to stdout: {:?}", e))?;
+    }
+
+    Ok(())
+}
+
+fn dump_hex(
+    out: &mut impl Write,
+    blk_idx: usize,
+    block: &Block<MAX_BLOCK_SIZE>,
+    data: &[u8],
+    header_only: bool,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "block: {}, valid: {}, id: {}, pending: {}, fs_id: {}, crc: {:#06x}, calculated_crc: {:#06x}",
+        blk_idx,
+        block.is_valid(),
+        block.id(),
+        block.is_pending(),
+        block.fs_id(),
+        block.stored_crc(),
+        block.crc
+    )?;
+    if header_only {
+        return Ok(());
+    }
+
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "  {:08x}: ", i * 16)?;
+        for b in line {
+            write!(out, "{:02x} ", b)?;
+        }
+        for _ in line.len()..16 {
+            write!(out, "   ")?;
+        }
+        let text: String = line
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, " {}", text)?;
+    }
+
+    Ok(())
+}
+
+fn dump_json(
+    out: &mut impl Write,
+    blk_idx: usize,
+    block: &Block<MAX_BLOCK_SIZE>,
+    data: &[u8],
+    header_only: bool,
+) -> io::Result<()> {
+    write!(
+        out,
+        "{{\"block\":{},\"valid\":{},\"id\":{},\"pending\":{},\"fs_id\":{},\"crc\":{},\"calculated_crc\":{}",
+        blk_idx,
+        block.is_valid(),
+        block.id(),
+        block.is_pending(),
+        block.fs_id(),
+        block.stored_crc(),
+        block.crc
+    )?;
+    if !header_only {
+        let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(out, ",\"data\":\"{}\"", hex)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn block_size(storage: &FileStorage, record_size: usize, crc_mode: CrcMode) -> Result<(), String> {
+    let advice = recommend_block_size(
+        &storage.geometry(),
+        record_size,
+        crc_mode,
+        HeaderVersion::V1,
+        MAX_BLOCK_SIZE,
+    )
+    .ok_or_else(|| format!("Record of {} bytes doesn't fit to a block", record_size))?;
+    println!("block_size: {}", advice.block_size);
+    println!("data_size: {}", advice.data_size);
+    println!("records_per_block: {}", advice.records_per_block);
+    println!("write_amplification: {}%", advice.write_amplification);
+
+    Ok(())
+}
+
+fn erase(storage: &mut FileStorage, yes: bool) -> Result<(), String> {
+    if !yes {
+        return Err("Erase destroys all data, confirm it with --yes".to_string());
+    }
+
+    let zeroes = vec![0_u8; storage.block_size()];
+    for blk_idx in storage.min_block_index()..storage.max_block_index() {
+        storage
+            .write(blk_idx, &zeroes[..])
+            .map_err(|e| format!("Can't erase block {}: {:?}", blk_idx, e))?;
+    }
+    log!(info, "Storage was erased");
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::net::TcpListener;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use appendfs::log;
+use appendfs::storage::file::FileStorage;
+use appendfs::storage::net::serve;
+
+const DEFAULT_BLOCK_SIZE: u32 = 512;
+const DEFAULT_BEGIN_BLOCK_IDX: u32 = 2048;
+const DEFAULT_END_BLOCK_IDX: u32 = 1024 * 1024 * 1024 * 3 / DEFAULT_BLOCK_SIZE;
+const DEFAULT_LISTEN: &str = "0.0.0.0:7070";
+
+/// Expose file or block device to a device with `NetStorage`, clients are served one after another,
+/// so only one of them writes the filesystem at a time
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    device: String,
+
+    #[arg(long, default_value_t = DEFAULT_BEGIN_BLOCK_IDX)]
+    begin_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_END_BLOCK_IDX)]
+    end_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: u32,
+
+    /// Address to listen on
+    #[arg(long, default_value = DEFAULT_LISTEN)]
+    listen: String,
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args = Args::parse();
+    let mut storage = match FileStorage::new(
+        args.device,
+        args.begin_block,
+        args.end_block,
+        args.block_size,
+        None,
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            log!(error, "Can't create storage: `{:?}`", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // local writers would corrupt the head of remote client
+    if let Err(e) = storage.lock() {
+        log!(error, "Can't lock storage: `{:?}`", e);
+        return ExitCode::FAILURE;
+    }
+
+    let listener = match TcpListener::bind(&args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log!(error, "Can't listen on {}: `{}`", args.listen, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    log!(info, "Serve storage on {}", args.listen);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log!(warn, "Can't accept connection: `{}`", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        let _ = stream.set_nodelay(true);
+        log!(info, "Client {:?} connected", peer);
+        if let Err(e) = serve(&mut storage, stream) {
+            log!(warn, "Client {:?} failed: `{}`", peer, e);
+        }
+        log!(info, "Client {:?} disconnected", peer);
+    }
+
+    ExitCode::SUCCESS
+}
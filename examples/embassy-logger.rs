@@ -0,0 +1,170 @@
+//! Appendfs in embassy application: acquisition task sends samples over a channel, flush task packs them
+//! to blocks and appends to the filesystem on a partition of async NOR flash.
+//! Runs on host with std executor and flash emulated in RAM, on target use flash driver of your HAL instead of `RamFlash`.
+
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use static_cell::StaticCell;
+
+use appendfs::block::FsId;
+use appendfs::fs::{Filesystem, FormatPolicy};
+use appendfs::storage::nor_flash::BlockingNorFlashStorage;
+
+const ERASE_SIZE: usize = 512;
+const FLASH_SIZE: usize = ERASE_SIZE * 64;
+/// Filesystem takes the second half of the flash, the first one is for firmware
+const FS_OFFSET: usize = FLASH_SIZE / 2;
+const SAMPLES: u32 = 1000;
+const SAMPLE_LEN: usize = 8;
+const FS_ID: FsId = 1097884275;
+
+type Flash = Mutex<NoopRawMutex, RamFlash>;
+type Samples = Channel<NoopRawMutex, Sample, 16>;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    seq: u32,
+    value: u32,
+}
+
+impl Sample {
+    fn to_be_bytes(self) -> [u8; SAMPLE_LEN] {
+        let mut bytes = [0_u8; SAMPLE_LEN];
+        bytes[..4].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.value.to_be_bytes());
+        bytes
+    }
+}
+
+struct RamFlash {
+    data: [u8; FLASH_SIZE],
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let begin = offset as usize;
+        let data = self
+            .data
+            .get(begin..begin + bytes.len())
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let data = self
+            .data
+            .get_mut(from as usize..to as usize)
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        data.fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let begin = offset as usize;
+        let data = self
+            .data
+            .get_mut(begin..begin + bytes.len())
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        // NOR write can only clear bits
+        for (dst, src) in data.iter_mut().zip(bytes) {
+            *dst &= *src;
+        }
+        Ok(())
+    }
+}
+
+#[embassy_executor::task]
+async fn acquisition(samples: &'static Samples) {
+    for seq in 0..SAMPLES {
+        // read sensor here
+        samples
+            .send(Sample {
+                seq,
+                value: seq * 3,
+            })
+            .await;
+    }
+}
+
+/// Storage busy-polls flash futures, so executor is blocked during flash operations and filesystem is used only
+/// by this task, flash isn't shared with other tasks (its mutex is never held by them)
+#[embassy_executor::task]
+async fn flush(flash: &'static Flash, samples: &'static Samples) {
+    let partition = Partition::new(flash, FS_OFFSET as u32, (FLASH_SIZE - FS_OFFSET) as u32);
+    let mut storage = BlockingNorFlashStorage::new(partition).expect("Can't create storage");
+    let mut fs = Filesystem::<_, ERASE_SIZE>::restore_or_format(
+        &mut storage,
+        || FS_ID,
+        FormatPolicy::Preserve,
+    )
+    .expect("Can't restore fs");
+
+    let mut block = [0_u8; ERASE_SIZE];
+    let mut len = 0;
+    loop {
+        let sample = samples.receive().await;
+        block[len..len + SAMPLE_LEN].copy_from_slice(&sample.to_be_bytes());
+        len += SAMPLE_LEN;
+
+        let is_last = sample.seq == SAMPLES - 1;
+        if len + SAMPLE_LEN > fs.data_size() || is_last {
+            fs.append(|data| {
+                data[..len].copy_from_slice(&block[..len]);
+                data[len..].fill(0);
+            })
+            .expect("Can't append");
+            len = 0;
+        }
+
+        if is_last {
+            break;
+        }
+    }
+
+    println!(
+        "Written {} samples, used blocks: {}, next block id: {}",
+        SAMPLES,
+        fs.used_blocks(),
+        fs.next_blk_id()
+    );
+    std::process::exit(0);
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    static FLASH: StaticCell<Flash> = StaticCell::new();
+    static SAMPLES_CHANNEL: StaticCell<Samples> = StaticCell::new();
+
+    let flash = FLASH.init(Mutex::new(RamFlash {
+        data: [0xFF; FLASH_SIZE],
+    }));
+    let samples = SAMPLES_CHANNEL.init(Channel::new());
+
+    spawner
+        .spawn(acquisition(samples))
+        .expect("Can't spawn acquisition");
+    spawner
+        .spawn(flush(flash, samples))
+        .expect("Can't spawn flush");
+}
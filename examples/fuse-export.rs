@@ -0,0 +1,268 @@
+use std::ffi::OsStr;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::Parser;
+use fuser::{
+    Errno, FileAttr, FileHandle, FileType, Filesystem as FuseFilesystem, Generation, INodeNo,
+    LockOwner, MountOption, OpenFlags, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+};
+
+use appendfs::fs::{DynFilesystem, MAX_BLOCK_SIZE};
+use appendfs::log;
+use appendfs::storage::file::FileStorage;
+
+const DEFAULT_BLOCK_SIZE: u32 = 512;
+const DEFAULT_BEGIN_BLOCK_IDX: u32 = 2048;
+const DEFAULT_END_BLOCK_IDX: u32 = 1024 * 1024 * 1024 * 3 / DEFAULT_BLOCK_SIZE;
+const DEFAULT_BLOCKS_PER_FILE: usize = 1024;
+
+const TTL: Duration = Duration::from_secs(1);
+const ALL_BLOCKS_INO: u64 = 2;
+const FIRST_CHUNK_INO: u64 = 3;
+
+pub type Fs = DynFilesystem<'static, FileStorage>;
+
+/// Mount appendfs storage as read only directory, data of every `--blocks-per-file` blocks is exposed as a file,
+/// file `all.bin` contains data of all blocks
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    device: String,
+
+    #[arg(long, default_value_t = DEFAULT_BEGIN_BLOCK_IDX)]
+    begin_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_END_BLOCK_IDX)]
+    end_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: u32,
+
+    #[arg(long, default_value_t = DEFAULT_BLOCKS_PER_FILE)]
+    blocks_per_file: usize,
+
+    /// Directory to mount storage to
+    mount_point: String,
+}
+
+struct RingExport {
+    fs: Mutex<Fs>,
+    used_blocks: usize,
+    blocks_per_file: usize,
+    data_size: usize,
+    oldest_id: u64,
+}
+
+impl RingExport {
+    fn chunks(&self) -> usize {
+        self.used_blocks.div_ceil(self.blocks_per_file)
+    }
+
+    // returns first block offset and number of blocks of the file
+    fn blocks_of(&self, ino: u64) -> Option<(usize, usize)> {
+        if ino == ALL_BLOCKS_INO {
+            return Some((0, self.used_blocks));
+        }
+
+        let chunk = ino.checked_sub(FIRST_CHUNK_INO)? as usize;
+        if chunk >= self.chunks() {
+            return None;
+        }
+
+        let first = chunk * self.blocks_per_file;
+        Some((first, (self.used_blocks - first).min(self.blocks_per_file)))
+    }
+
+    fn file_name(&self, ino: u64) -> String {
+        if ino == ALL_BLOCKS_INO {
+            return "all.bin".to_string();
+        }
+
+        let first = (ino - FIRST_CHUNK_INO) as usize * self.blocks_per_file;
+        format!("blocks-{:020}.bin", self.oldest_id + first as u64)
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let (kind, perm, size) = if ino == INodeNo::ROOT.0 {
+            (FileType::Directory, 0o555, 0)
+        } else {
+            let (_, count) = self.blocks_of(ino)?;
+            (
+                FileType::RegularFile,
+                0o444,
+                (count * self.data_size) as u64,
+            )
+        };
+
+        Some(FileAttr {
+            ino: INodeNo(ino),
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            // files are read only for everyone, so owner doesn't matter
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: self.data_size as u32,
+            flags: 0,
+        })
+    }
+}
+
+impl FuseFilesystem for RingExport {
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        if parent != INodeNo::ROOT {
+            reply.error(Errno::ENOENT);
+            return;
+        }
+
+        let found = (ALL_BLOCKS_INO..FIRST_CHUNK_INO + self.chunks() as u64)
+            .find(|ino| name.to_str() == Some(&self.file_name(*ino)[..]));
+        match found.and_then(|ino| self.attr(ino)) {
+            Some(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.attr(ino.0) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        let Some((first, count)) = self.blocks_of(ino.0) else {
+            reply.error(Errno::ENOENT);
+            return;
+        };
+
+        let file_size = (count * self.data_size) as u64;
+        let end = file_size.min(offset + size as u64);
+        let mut data = Vec::with_capacity(end.saturating_sub(offset) as usize);
+
+        let mut fs = self.fs.lock().expect("Fs lock is poisoned");
+        let mut pos = offset;
+        while pos < end {
+            let blk = (pos / self.data_size as u64) as usize;
+            let blk_pos = (pos % self.data_size as u64) as usize;
+            let to_copy = (self.data_size - blk_pos).min((end - pos) as usize);
+            let res = fs.read(first + blk, |blk_data| {
+                data.extend_from_slice(&blk_data[blk_pos..blk_pos + to_copy]);
+            });
+            if let Err(e) = res {
+                log!(warn, "Can't read block {}: {:?}", first + blk, e);
+                data.resize(data.len() + to_copy, 0);
+            }
+            pos += to_copy as u64;
+        }
+
+        reply.data(&data[..]);
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        if ino != INodeNo::ROOT {
+            reply.error(Errno::ENOENT);
+            return;
+        }
+
+        let entries = [
+            (INodeNo::ROOT.0, FileType::Directory, ".".to_string()),
+            (INodeNo::ROOT.0, FileType::Directory, "..".to_string()),
+        ]
+        .into_iter()
+        .chain(
+            (ALL_BLOCKS_INO..FIRST_CHUNK_INO + self.chunks() as u64)
+                .map(|ino| (ino, FileType::RegularFile, self.file_name(ino))),
+        );
+
+        for (i, (ino, kind, name)) in entries.enumerate().skip(offset as usize) {
+            if reply.add(INodeNo(ino), (i + 1) as u64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Args::parse();
+    if args.block_size as usize > MAX_BLOCK_SIZE || args.blocks_per_file == 0 {
+        log!(error, "Invalid block size or blocks per file");
+        return;
+    }
+
+    let storage = match FileStorage::new(
+        args.device,
+        args.begin_block,
+        args.end_block,
+        args.block_size,
+        None,
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            log!(error, "Can't create storage: `{:?}`", e);
+            return;
+        }
+    };
+
+    // fuse filesystem must be 'static, storage lives until the process exit anyway
+    let storage: &'static mut FileStorage = Box::leak(Box::new(storage));
+    let fs = match Fs::restore(storage) {
+        Ok(fs) => fs,
+        Err(e) => {
+            log!(error, "Can't restore fs: `{:?}`", e);
+            return;
+        }
+    };
+
+    let export = RingExport {
+        used_blocks: fs.used_blocks(),
+        blocks_per_file: args.blocks_per_file,
+        data_size: fs.data_size(),
+        oldest_id: fs.oldest_blk_id(),
+        fs: Mutex::new(fs),
+    };
+    log!(
+        info,
+        "Mounting {} blocks as {} files to {}",
+        export.used_blocks,
+        export.chunks(),
+        &args.mount_point
+    );
+
+    let mut config = fuser::Config::default();
+    config
+        .mount_options
+        .extend([MountOption::RO, MountOption::FSName("appendfs".to_string())]);
+    if let Err(e) = fuser::mount(export, &args.mount_point, &config) {
+        log!(error, "Can't mount fs: {:?}", e);
+    }
+}
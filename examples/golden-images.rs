@@ -0,0 +1,70 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use appendfs::format::golden::{Corruption, GOLDEN_FS_ID, GOLDEN_IMAGES};
+
+/// Write reference images of the on-disk format (see `appendfs::format::golden`) and `manifest.txt` which describes
+/// how every image was made, conformance tests check images of `fixtures/format`
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory for images, existing images are replaced
+    #[arg(long, default_value = "fixtures/format")]
+    out: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if let Err(e) = fs::create_dir_all(&args.out) {
+        eprintln!("Can't create {}: {}", args.out.display(), e);
+        return ExitCode::FAILURE;
+    }
+
+    let mut manifest = format!(
+        "# fs id {:#x}, data bytes of block are `(id * 17) as u8`\n\
+         # name block_size blocks crc_mode header appends corruption\n",
+        GOLDEN_FS_ID
+    );
+    for golden in GOLDEN_IMAGES {
+        let mut image = vec![0_u8; golden.len()];
+        if let Err(e) = golden.generate(&mut image) {
+            eprintln!("Can't generate {}: {:?}", golden.name, e);
+            return ExitCode::FAILURE;
+        }
+        let path = args.out.join(format!("{}.img", golden.name));
+        if let Err(e) = fs::write(&path, &image) {
+            eprintln!("Can't write {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+
+        let corruption = match golden.corruption {
+            Corruption::None => String::from("none"),
+            Corruption::FlipBit(blk_offset) => format!("flip-bit:{}", blk_offset),
+            Corruption::TornNewest => String::from("torn-newest"),
+            Corruption::BrokenConfig => String::from("broken-config"),
+        };
+        let _ = writeln!(
+            manifest,
+            "{} {} {} {:?} {:?} {} {}",
+            golden.name,
+            golden.block_size,
+            golden.blocks,
+            golden.crc_mode,
+            golden.header,
+            golden.appends,
+            corruption
+        );
+    }
+
+    let path = args.out.join("manifest.txt");
+    if let Err(e) = fs::write(&path, manifest) {
+        eprintln!("Can't write {}: {}", path.display(), e);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
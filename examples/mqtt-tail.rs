@@ -0,0 +1,272 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use appendfs::block::BlockId;
+use appendfs::fs::cursor::Cursor;
+use appendfs::fs::DynFilesystem;
+use appendfs::log;
+use appendfs::storage::file::FileStorage;
+
+const DEFAULT_BLOCK_SIZE: u32 = 512;
+const DEFAULT_BEGIN_BLOCK_IDX: u32 = 2048;
+const DEFAULT_END_BLOCK_IDX: u32 = 1024 * 1024 * 1024 * 3 / DEFAULT_BLOCK_SIZE;
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+pub type Fs<'a> = DynFilesystem<'a, FileStorage>;
+
+/// Tail the filesystem and publish data of every new block to MQTT topic (payload is block data, user property
+/// isn't available in MQTT 3.1.1, so id of the block is appended to the topic: `<topic>/<block id>`).
+/// Cursor (id of the next block to publish) is saved to `--cursor` file: with QoS 0 after the block is sent,
+/// with QoS 1 after broker acknowledged it, so after restart publishing continues without losing blocks.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    device: String,
+
+    #[arg(long, default_value_t = DEFAULT_BEGIN_BLOCK_IDX)]
+    begin_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_END_BLOCK_IDX)]
+    end_block: u32,
+
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: u32,
+
+    /// Broker address, e.g. `localhost:1883`
+    #[arg(long)]
+    broker: String,
+
+    #[arg(long, default_value = "appendfs")]
+    topic: String,
+
+    #[arg(long, default_value = "appendfs-tail")]
+    client_id: String,
+
+    /// 0 - at most once, 1 - at least once
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=1))]
+    qos: u8,
+
+    /// File with id of the next block to publish, publishing starts from the oldest block without it
+    #[arg(long)]
+    cursor: String,
+
+    /// How often storage is checked for new blocks
+    #[arg(long, default_value_t = 1000)]
+    interval_ms: u64,
+}
+
+/// Minimal MQTT 3.1.1 client: clean session, publish with QoS 0 or 1 and keep alive
+struct Mqtt {
+    stream: TcpStream,
+    next_packet_id: u16,
+    last_sent: Instant,
+}
+
+impl Mqtt {
+    fn connect(addr: &str, client_id: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut mqtt = Self {
+            stream,
+            next_packet_id: 1,
+            last_sent: Instant::now(),
+        };
+
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        // protocol level 4, clean session
+        body.extend_from_slice(&[4, 0x02]);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        put_str(&mut body, client_id);
+        mqtt.send(0x10, &body)?;
+
+        let (packet_type, body) = mqtt.receive()?;
+        if packet_type != 0x20 || body.get(1) != Some(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("broker refused connection: {:?}", body),
+            ));
+        }
+
+        Ok(mqtt)
+    }
+
+    /// Returns when the message is sent (QoS 0) or acknowledged (QoS 1)
+    fn publish(&mut self, topic: &str, payload: &[u8], qos: u8) -> io::Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+        put_str(&mut body, topic);
+        let packet_id = self.next_packet_id;
+        if qos > 0 {
+            self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+            body.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        body.extend_from_slice(payload);
+        self.send(0x30 | (qos << 1), &body)?;
+
+        if qos == 0 {
+            return Ok(());
+        }
+        loop {
+            let (packet_type, body) = self.receive()?;
+            if packet_type == 0x40 && body[..] == packet_id.to_be_bytes() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn ping_if_idle(&mut self) -> io::Result<()> {
+        if self.last_sent.elapsed() < KEEP_ALIVE / 2 {
+            return Ok(());
+        }
+
+        self.send(0xC0, &[])?;
+        while self.receive()?.0 != 0xD0 {}
+        Ok(())
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        packet.push(header);
+        // remaining length, 7 bits per byte
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            packet.push(if len > 0 { byte | 0x80 } else { byte });
+            if len == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Type (high 4 bits of the first byte) and body of the next packet
+    fn receive(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut byte = [0_u8; 1];
+        self.stream.read_exact(&mut byte)?;
+        let packet_type = byte[0] & 0xF0;
+
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            self.stream.read_exact(&mut byte)?;
+            len |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0_u8; len];
+        self.stream.read_exact(&mut body)?;
+
+        Ok((packet_type, body))
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn load_cursor(path: &str) -> Cursor {
+    let next_id = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<BlockId>().ok())
+        .unwrap_or(0);
+    Cursor::new(next_id)
+}
+
+/// Cursor is written to temporary file and renamed, so it's never half written
+fn save_cursor(path: &str, cursor: &Cursor) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, cursor.next_blk_id().to_string())?;
+    fs::rename(&tmp, path)
+}
+
+fn tail(filesystem: &mut Fs, mqtt: &mut Mqtt, args: &Args) -> Result<(), String> {
+    let mut cursor = load_cursor(&args.cursor);
+    log!(info, "Publish from block {}", cursor.next_blk_id());
+    let mut data = vec![0_u8; filesystem.data_size()];
+    loop {
+        let mut len = 0;
+        let peeked = cursor
+            .peek(filesystem, |_, blk_data| {
+                len = blk_data.len();
+                data[..len].copy_from_slice(blk_data);
+            })
+            .map_err(|e| format!("Can't read block: {:?}", e))?;
+        if cursor.missed() > 0 {
+            log!(
+                warn,
+                "{} blocks were overwritten before publish",
+                cursor.missed()
+            );
+        }
+
+        let Some(id) = peeked else {
+            mqtt.ping_if_idle()
+                .map_err(|e| format!("Can't ping broker: {}", e))?;
+            thread::sleep(Duration::from_millis(args.interval_ms));
+            filesystem
+                .refresh()
+                .map_err(|e| format!("Can't refresh fs: `{:?}`", e))?;
+            continue;
+        };
+
+        mqtt.publish(&format!("{}/{}", args.topic, id), &data[..len], args.qos)
+            .map_err(|e| format!("Can't publish block {}: {}", id, e))?;
+        cursor.commit(id);
+        save_cursor(&args.cursor, &cursor).map_err(|e| format!("Can't save cursor: {}", e))?;
+        log!(debug, "Published block {}", id);
+    }
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args = Args::parse();
+    let mut storage = match FileStorage::new(
+        args.device.clone(),
+        args.begin_block,
+        args.end_block,
+        args.block_size,
+        None,
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            log!(error, "Can't create storage: `{:?}`", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // the device is written by a logger at the same time
+    let mut filesystem = match Fs::open_readonly(&mut storage) {
+        Ok(fs) => fs,
+        Err(e) => {
+            log!(error, "Can't open fs: `{:?}`", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut mqtt = match Mqtt::connect(&args.broker, &args.client_id) {
+        Ok(mqtt) => mqtt,
+        Err(e) => {
+            log!(error, "Can't connect to {}: {}", args.broker, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match tail(&mut filesystem, &mut mqtt, &args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log!(error, "{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
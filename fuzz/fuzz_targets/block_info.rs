@@ -0,0 +1,21 @@
+//! Parse header of arbitrary block in every crc mode
+#![no_main]
+
+use appendfs::block::{Block, BlockInfo, CrcMode};
+use appendfs::fs::MAX_BLOCK_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // storage never returns blocks shorter than header with crc trailer
+    let min_len = Block::<MAX_BLOCK_SIZE>::attributes_size() + CrcMode::Split.trailer_len();
+    if data.len() <= min_len || data.len() > MAX_BLOCK_SIZE {
+        return;
+    }
+
+    for mode in [CrcMode::Full, CrcMode::HeaderOnly, CrcMode::Split] {
+        let info = BlockInfo::<MAX_BLOCK_SIZE>::from_buffer_with_mode(data, mode);
+        if !info.is_valid {
+            assert_eq!(info.id, 0);
+        }
+    }
+});
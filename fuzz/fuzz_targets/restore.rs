@@ -0,0 +1,18 @@
+//! Restore filesystem from corrupted image, first byte selects block size
+#![no_main]
+
+use appendfs::fs::DynFilesystem;
+use appendfs::storage::slice::SliceStorage;
+use libfuzzer_sys::fuzz_target;
+
+const BLOCK_SIZES: [usize; 4] = [64, 128, 256, 512];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, image)) = data.split_first() else {
+        return;
+    };
+    let block_size = BLOCK_SIZES[*selector as usize % BLOCK_SIZES.len()];
+    let len = image.len() - image.len() % block_size;
+    let mut image = image[..len].to_vec();
+    let _ = DynFilesystem::<SliceStorage>::probe_image(&mut image, block_size);
+});
@@ -0,0 +1,271 @@
+//! Tier old blocks of the ring to object storage (e.g. gateway uploads to S3), see [`Archiver`].
+//!
+//! Object is a group of consecutive sealed blocks, it's stored as raw blocks (with headers and crc) under key
+//! `<prefix><fs_id hex>/<first block id>-<last block id>.blk`, ids are zero padded, so keys are sorted by id.
+
+use std::format;
+use std::string::String;
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::block::{BlockId, BlockInfo, BlockLayout};
+use crate::error::Error;
+use crate::fs::Filesystem;
+use crate::log;
+use crate::storage::Storage;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+/// Destination of archived objects
+pub trait ObjectSink {
+    /// Store `body` as object `key`, object with the same key is replaced. Failed put is retried by [`Archiver`].
+    fn put(&mut self, key: &str, body: &[u8]) -> Result<(), Error>;
+}
+
+/// Failed put is retried `attempts` times in total, delay doubles after every attempt up to `max_delay`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Uploads blocks of the filesystem to [`ObjectSink`] by groups of `blocks_per_object`. Group is sealed when
+/// all its blocks are appended, so call `archive` periodically and `archive_all` before shutdown.
+/// Id of the first not archived block is the only state, persist it (see `next_blk_id`) and pass it to `new`
+/// after restart. Blocks overwritten by the ring before upload are counted in `lost_blocks`.
+pub struct Archiver<O: ObjectSink> {
+    sink: O,
+    retry: RetryPolicy,
+    prefix: String,
+    blocks_per_object: usize,
+    next_id: BlockId,
+    lost: BlockId,
+}
+
+impl<O: ObjectSink> Archiver<O> {
+    /// Blocks with ids from `next_id` are archived, `prefix` is prepended to keys (e.g. `device-7/`)
+    pub fn new(sink: O, prefix: &str, blocks_per_object: usize, next_id: BlockId) -> Self {
+        Self {
+            sink,
+            retry: RetryPolicy::default(),
+            prefix: String::from(prefix),
+            blocks_per_object: blocks_per_object.max(1),
+            next_id,
+            lost: 0,
+        }
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Id of the first block which isn't archived yet
+    pub fn next_blk_id(&self) -> BlockId {
+        self.next_id
+    }
+
+    /// Blocks which were overwritten or invalid before upload
+    pub fn lost_blocks(&self) -> BlockId {
+        self.lost
+    }
+
+    pub fn sink(&mut self) -> &mut O {
+        &mut self.sink
+    }
+
+    /// Upload sealed groups of blocks, returns number of uploaded objects
+    pub fn archive<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+        &mut self,
+        fs: &mut Filesystem<'_, S, BS, L, B>,
+    ) -> Result<usize, Error> {
+        self.upload(fs, false)
+    }
+
+    /// Same as `archive`, the newest blocks are uploaded even if their group isn't full
+    pub fn archive_all<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+        &mut self,
+        fs: &mut Filesystem<'_, S, BS, L, B>,
+    ) -> Result<usize, Error> {
+        self.upload(fs, true)
+    }
+
+    fn upload<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+        &mut self,
+        fs: &mut Filesystem<'_, S, BS, L, B>,
+        partial: bool,
+    ) -> Result<usize, Error> {
+        let mut objects = 0;
+        let mut body = Vec::with_capacity(self.blocks_per_object * fs.block_size());
+        loop {
+            let oldest = fs.oldest_blk_id();
+            if self.next_id < oldest {
+                log!(
+                    warn,
+                    "Blocks {}..{} were not archived",
+                    self.next_id,
+                    oldest
+                );
+                self.lost += oldest - self.next_id;
+                self.next_id = oldest;
+            }
+            let available = fs.next_blk_id().saturating_sub(self.next_id) as usize;
+            let count = available.min(self.blocks_per_object);
+            if count == 0 || (count < self.blocks_per_object && !partial) {
+                return Ok(objects);
+            }
+
+            body.clear();
+            let first = self.next_id;
+            for id in first..first + count as BlockId {
+                let blk_offset = fs.blk_offset_of(id).ok_or(Error::BlockOutOfRange)?;
+                let blk_idx = fs.blk_idx_of(blk_offset)?;
+                let (crc_mode, header, fs_id) = (fs.crc_mode(), fs.header_version(), fs.id());
+                fs.read_raw(blk_idx, |blk| {
+                    let info = BlockInfo::<BS>::from_buffer_with_header::<L>(blk, crc_mode, header);
+                    if info.is_valid && info.fs_id == fs_id && info.id == id {
+                        body.extend_from_slice(blk);
+                    } else {
+                        log!(warn, "Block {} is invalid, it's not archived", id);
+                        self.lost += 1;
+                    }
+                })?;
+            }
+
+            let last = first + count as BlockId - 1;
+            let key = format!(
+                "{}{:x}/{:020}-{:020}.blk",
+                self.prefix,
+                fs.id(),
+                first,
+                last
+            );
+            self.put_with_retry(&key, &body)?;
+            log!(info, "Archived blocks {}..={} to {}", first, last, key);
+            self.next_id = last + 1;
+            objects += 1;
+        }
+    }
+
+    fn put_with_retry(&mut self, key: &str, body: &[u8]) -> Result<(), Error> {
+        let mut delay = self.retry.initial_delay;
+        let mut attempt = 1;
+        loop {
+            match self.sink.put(key, body) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.retry.attempts => return Err(e),
+                Err(e) => {
+                    log!(
+                        warn,
+                        "Put of {} failed ({:?}), retry in {:?}",
+                        key,
+                        e,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(self.retry.max_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::{Archiver, ObjectSink, RetryPolicy};
+    use crate::block::{BlockInfo, CrcMode, FsId};
+    use crate::error::Error;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 0x5A3;
+
+    /// Keeps objects in memory, the first `failures` puts fail
+    #[derive(Default)]
+    struct MemorySink {
+        objects: Vec<(String, Vec<u8>)>,
+        failures: usize,
+    }
+
+    impl ObjectSink for MemorySink {
+        fn put(&mut self, key: &str, body: &[u8]) -> Result<(), Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::CanNotPerformWrite);
+            }
+            self.objects.push((String::from(key), body.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_archiver() {
+        const BLOCK_SIZE: usize = 128;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * 9 }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..5u8 {
+            fs.append(|data| data.fill(i)).expect("Can't append");
+        }
+
+        let sink = MemorySink {
+            failures: 2,
+            ..MemorySink::default()
+        };
+        let mut archiver = Archiver::new(sink, "dev/", 2, 0);
+        archiver.set_retry_policy(RetryPolicy {
+            attempts: 3,
+            initial_delay: std::time::Duration::ZERO,
+            max_delay: std::time::Duration::ZERO,
+        });
+        // the 5th block isn't sealed
+        assert_eq!(archiver.archive(&mut fs).expect("Can't archive"), 2);
+        assert_eq!(archiver.next_blk_id(), 4);
+        let objects = &archiver.sink().objects;
+        assert_eq!(objects.len(), 2);
+        assert_eq!(
+            objects[0].0,
+            "dev/5a3/00000000000000000000-00000000000000000001.blk"
+        );
+        assert_eq!(objects[1].1.len(), 2 * BLOCK_SIZE);
+        let info = BlockInfo::<BLOCK_SIZE>::from_buffer_with_mode(
+            &objects[1].1[BLOCK_SIZE..],
+            CrcMode::Full,
+        );
+        assert!(info.is_valid);
+        assert_eq!(info.id, 3);
+
+        assert_eq!(archiver.archive_all(&mut fs).expect("Can't archive"), 1);
+        assert_eq!(archiver.next_blk_id(), 5);
+
+        // blocks overwritten before upload are lost
+        for i in 0..10u8 {
+            fs.append(|data| data.fill(i)).expect("Can't append");
+        }
+        assert_eq!(archiver.archive_all(&mut fs).expect("Can't archive"), 4);
+        assert_eq!(archiver.lost_blocks(), 3);
+        assert_eq!(archiver.next_blk_id(), fs.next_blk_id());
+
+        // put fails after all attempts
+        archiver.sink().failures = 3;
+        fs.append(|data| data.fill(0)).expect("Can't append");
+        assert!(archiver.archive_all(&mut fs).is_err());
+        assert_eq!(archiver.archive_all(&mut fs).expect("Can't archive"), 1);
+    }
+}
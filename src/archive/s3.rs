@@ -0,0 +1,177 @@
+//! [`ObjectSink`] of S3 compatible object storage (AWS S3, MinIO, ..), requests are signed with AWS Signature V4.
+
+use std::format;
+use std::string::String;
+use std::time::SystemTime;
+use std::vec::Vec;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::ObjectSink;
+use crate::error::Error;
+use crate::log;
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// Bucket of S3 compatible storage, objects are put with path-style urls `<endpoint>/<bucket>/<key>`
+pub struct S3Sink {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://minio.local:9000`
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    agent: ureq::Agent,
+}
+
+impl S3Sink {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Self {
+        Self {
+            endpoint: String::from(endpoint.trim_end_matches('/')),
+            bucket: String::from(bucket),
+            region: String::from(region),
+            access_key: String::from(access_key),
+            secret_key: String::from(secret_key),
+            agent: ureq::Agent::new(),
+        }
+    }
+
+    /// Value of `Authorization` header of PUT request
+    fn authorization(&self, host: &str, path: &str, payload_hash: &str, amz_date: &str) -> String {
+        let date = &amz_date[..8];
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, host, payload_hash, amz_date, SIGNED_HEADERS, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, date, &self.region, "s3");
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, SIGNED_HEADERS, signature
+        )
+    }
+}
+
+impl ObjectSink for S3Sink {
+    fn put(&mut self, key: &str, body: &[u8]) -> Result<(), Error> {
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(&self.endpoint[..], |(_, host)| host);
+        let path = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key));
+        let payload_hash = hex(&Sha256::digest(body));
+        let amz_date = amz_date(SystemTime::now());
+        let authorization = self.authorization(host, &path, &payload_hash, &amz_date);
+
+        self.agent
+            .put(&format!("{}{}", self.endpoint, path))
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &amz_date)
+            .set("Authorization", &authorization)
+            .send_bytes(body)
+            .map_err(|e| {
+                log!(error, "Put of {} failed: {}", key, e);
+                Error::CanNotPerformWrite
+            })?;
+
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent encoding of path, `/` and unreserved characters are kept
+fn uri_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                String::from(b as char)
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `YYYYMMDD'T'HHMMSS'Z'` in UTC
+fn amz_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // civil date from days since epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{amz_date, hex, signing_key, uri_encode};
+
+    #[test]
+    fn test_s3_signing() {
+        // example of AWS Signature V4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(amz_date(time), "20240229T123456Z");
+        assert_eq!(uri_encode("dev 1/a+b.blk"), "dev%201/a%2Bb.blk");
+    }
+}
@@ -1,50 +1,255 @@
 use crc;
 
+use crate::format::header_v2;
+
 pub type CRC = u16;
-pub type FsId = u32;
+/// Id of filesystem, ids up to [`NARROW_FS_ID_MAX`] take 4 bytes of block header. Wide id makes collisions
+/// of random ids practically impossible, its high bits take 12 more bytes after v2 header fields, width is
+/// stored in config block.
+pub type FsId = u128;
 pub type BlockId = u64;
+/// Caller supplied sequence number of [`crate::fs::Filesystem::append_seq`]
+pub type SeqNum = u32;
+/// Number of repeats of block data, see [`crate::fs::Filesystem::append_dedup`]
+pub type RepeatCount = u32;
 
 pub const CRC_ALGORITHM: crc::Crc<CRC> = crc::Crc::<CRC>::new(&crc::CRC_16_CDMA2000);
 
-pub(crate) mod fields {
-    use core::mem::size_of;
+/// Part of data block covered by crc, it is chosen on format and stored in config block
+/// (config block itself always uses `Full` mode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrcMode {
+    /// Crc covers header and data
+    #[default]
+    Full,
+    /// Crc covers only header (v2 header fields and custom metadata included), appends are faster, integrity
+    /// of data must be checked by application
+    HeaderOnly,
+    /// Header crc covers header (v2 header fields and custom metadata included), second crc at the end of
+    /// the block covers data, damaged data is detected even if header is valid
+    Split,
+}
+
+impl CrcMode {
+    /// Bytes at the end of the block taken by data crc, they are not available for data
+    pub const fn trailer_len(self) -> usize {
+        match self {
+            CrcMode::Split => fields::CRC_LEN,
+            CrcMode::Full | CrcMode::HeaderOnly => 0,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(CrcMode::Full),
+            1 => Some(CrcMode::HeaderOnly),
+            2 => Some(CrcMode::Split),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            CrcMode::Full => 0,
+            CrcMode::HeaderOnly => 1,
+            CrcMode::Split => 2,
+        }
+    }
+}
+
+/// Highest bit of stored block id marks block of not committed transaction, it is not a part of the id
+const PENDING_FLAG: BlockId = 1 << (BlockId::BITS - 1);
+/// Max id which can be stored in block header
+pub const MAX_BLOCK_ID: BlockId = !PENDING_FLAG;
 
-    pub(crate) const CRC_BEGIN: usize = 0;
-    pub(crate) const CRC_LEN: usize = size_of::<super::CRC>();
-    pub(crate) const CRC_END: usize = CRC_BEGIN + CRC_LEN;
+pub(crate) use crate::format::data_block as fields;
 
-    pub(crate) const FS_ID_BEGIN: usize = CRC_END;
-    pub(crate) const FS_ID_LEN: usize = size_of::<super::FsId>();
-    pub(crate) const FS_ID_END: usize = FS_ID_BEGIN + FS_ID_LEN;
+/// Max fs id which fits to fs id field of block header, see [`FsId`]
+pub const NARROW_FS_ID_MAX: FsId = u32::MAX as FsId;
 
-    pub(crate) const BLOCK_ID_BEGIN: usize = FS_ID_END;
-    pub(crate) const BLOCK_ID_LEN: usize = size_of::<super::BlockId>();
-    pub(crate) const BLOCK_ID_END: usize = BLOCK_ID_BEGIN + BLOCK_ID_LEN;
+/// Fs id needs high bits after v2 header fields, see [`FsId`]
+pub const fn is_wide_fs_id(fs_id: FsId) -> bool {
+    fs_id > NARROW_FS_ID_MAX
+}
 
-    pub(crate) const DATA_BEGIN: usize = BLOCK_ID_END;
+/// Bytes of header after v1 fields, `version` fields are followed by high bits of wide fs id
+pub const fn header_extension_len(version: HeaderVersion, wide_fs_id: bool) -> usize {
+    let fs_id_len = if wide_fs_id {
+        fields::FS_ID_HIGH_LEN
+    } else {
+        0
+    };
+    version.extension_len() + fs_id_len
+}
+
+/// Version of data block header, it is chosen on format and stored in config block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderVersion {
+    /// Crc, fs id and block id only, see [`crate::format::data_block`]
+    #[default]
+    V1,
+    /// V1 fields followed by [`HeaderV2`] fields, see [`crate::fs::Filesystem::new_with_header`]
+    V2,
+}
+
+impl HeaderVersion {
+    /// Bytes of header after v1 fields, they are not available for data
+    pub const fn extension_len(self) -> usize {
+        match self {
+            HeaderVersion::V1 => 0,
+            HeaderVersion::V2 => HeaderV2::LEN,
+        }
+    }
+}
+
+/// Marks v2 header, block with another value isn't valid
+pub const HEADER_V2_MAGIC: u16 = 0xA2F5;
+
+/// Fields of v2 header, they are written after v1 fields and before custom metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderV2 {
+    /// Application defined flags
+    pub flags: u8,
+    /// Number of meaningful data bytes, `append` sets it to data size
+    pub length: u16,
+    /// `append` sets it from time source (see [`crate::fs::Filesystem::set_time_source`]), otherwise it's 0
+    pub timestamp: crate::time::Timestamp,
+    /// Application defined tag (e.g. record type)
+    pub tag: u32,
+}
+
+impl HeaderV2 {
+    /// Block must be kept (e.g. crash dump), flags with common meaning use the lowest bits,
+    /// the highest bits are left to the application
+    pub const FLAG_CRITICAL: u8 = 1 << 0;
+    /// Data is compressed
+    pub const FLAG_COMPRESSED: u8 = 1 << 1;
+    /// Data is encrypted
+    pub const FLAG_ENCRYPTED: u8 = 1 << 2;
+    /// Marker block of `append_dedup`, it repeats data of the block `tag` ids before it, the flag is set only by
+    /// appendfs
+    pub const FLAG_REPEAT: u8 = 1 << 3;
+    /// Block of `append_seq`, `tag` is its sequence number, the flag is set only by appendfs
+    pub const FLAG_SEQ: u8 = 1 << 4;
+    /// Block of `append_with_priority` with `Priority::High`, write head skips it till it's unpinned, the flag
+    /// is set only by appendfs
+    pub const FLAG_PRIORITY: u8 = 1 << 5;
+    /// Flags which can't be passed to `append_with_header`
+    pub(crate) const RESERVED_FLAGS: u8 = Self::FLAG_REPEAT | Self::FLAG_SEQ | Self::FLAG_PRIORITY;
+
+    const MAGIC_END: usize = header_v2::MAGIC_END;
+    const FLAGS_END: usize = header_v2::FLAGS_END;
+    const LENGTH_END: usize = header_v2::LENGTH_END;
+    const TIMESTAMP_END: usize = header_v2::TIMESTAMP_END;
+    const TAG_END: usize = header_v2::TAG_END;
+    pub(crate) const LEN: usize = header_v2::LEN;
+
+    /// Parse fields at the beginning of `buf`, `None` in case magic doesn't match
+    pub(crate) fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..Self::LEN)?;
+        if buf[..Self::MAGIC_END] != HEADER_V2_MAGIC.to_be_bytes() {
+            return None;
+        }
+
+        let mut timestamp = [0_u8; 8];
+        timestamp.copy_from_slice(&buf[Self::LENGTH_END..Self::TIMESTAMP_END]);
+        let mut tag = [0_u8; 4];
+        tag.copy_from_slice(&buf[Self::TIMESTAMP_END..Self::TAG_END]);
+        Some(Self {
+            flags: buf[Self::MAGIC_END],
+            length: u16::from_be_bytes([buf[Self::FLAGS_END], buf[Self::FLAGS_END + 1]]),
+            timestamp: crate::time::Timestamp::from_be_bytes(timestamp),
+            tag: u32::from_be_bytes(tag),
+        })
+    }
+
+    /// Write fields to the beginning of `buf`
+    pub(crate) fn write(&self, buf: &mut [u8]) {
+        buf[..Self::MAGIC_END].copy_from_slice(&HEADER_V2_MAGIC.to_be_bytes());
+        buf[Self::MAGIC_END] = self.flags;
+        buf[Self::FLAGS_END..Self::LENGTH_END].copy_from_slice(&self.length.to_be_bytes());
+        buf[Self::LENGTH_END..Self::TIMESTAMP_END].copy_from_slice(&self.timestamp.to_be_bytes());
+        buf[Self::TIMESTAMP_END..Self::TAG_END].copy_from_slice(&self.tag.to_be_bytes());
+    }
+}
+
+/// Layout of custom metadata (e.g. device serial or channel id) stored in every data block after header fields
+/// of appendfs, it is passed to `append_with_metadata` and `read_with_metadata` callbacks apart from data.
+/// Metadata is covered by block crc, storage written with one layout can't be read with another.
+pub trait BlockLayout {
+    /// Size of custom metadata in bytes, it can't exceed [`MAX_METADATA_LEN`]
+    const METADATA_LEN: usize;
+}
+
+/// Max size of custom metadata, it is kept in [`BlockInfo`]
+pub const MAX_METADATA_LEN: usize = 32;
+
+/// Block header without custom metadata
+#[derive(Debug)]
+pub struct DefaultLayout;
+
+impl BlockLayout for DefaultLayout {
+    const METADATA_LEN: usize = 0;
 }
 
 #[derive(Debug)]
 pub struct Block<'a, const S: usize> {
     pub data: &'a [u8],
     pub crc: CRC,
+    mode: CrcMode,
+    /// End of header covered by header crc of `HeaderOnly` and `Split` modes
+    header_end: usize,
 }
 
 impl<'a, const S: usize> Block<'a, S> {
     pub fn from_buffer(buf: &'a [u8]) -> Self {
-        let crc = Self::calculated_crc(buf);
-        Self { data: buf, crc }
+        Self::from_buffer_with_mode(buf, CrcMode::Full)
+    }
+
+    /// Block of filesystem formatted with `mode`, `crc` is calculated according to it
+    pub fn from_buffer_with_mode(buf: &'a [u8], mode: CrcMode) -> Self {
+        Self::from_buffer_with_header_end(buf, mode, fields::DATA_BEGIN)
+    }
+
+    /// Same as `from_buffer_with_mode`, header of the block ends at `header_end` (offset of data,
+    /// v2 header fields and custom metadata are before it)
+    pub fn from_buffer_with_header_end(buf: &'a [u8], mode: CrcMode, header_end: usize) -> Self {
+        let crc = Self::calculated_crc_with_header_end(buf, mode, header_end);
+        Self {
+            data: buf,
+            crc,
+            mode,
+            header_end,
+        }
     }
 
     pub fn from_other(other: Block<'a, S>) -> Self {
         Self {
             data: other.data,
             crc: other.crc,
+            mode: other.mode,
+            header_end: other.header_end,
         }
     }
 
     pub fn is_valid(&self) -> bool {
-        self.stored_crc() == self.crc
+        if self.stored_crc() != self.crc {
+            return false;
+        }
+
+        match self.mode {
+            CrcMode::Split => self.stored_data_crc() == Some(Self::calculated_data_crc(self.data)),
+            CrcMode::Full | CrcMode::HeaderOnly => true,
+        }
+    }
+
+    /// Crc of data stored at the end of the block in `Split` mode, `None` for too short block
+    pub fn stored_data_crc(&self) -> Option<CRC> {
+        let begin = self.data.len().checked_sub(fields::CRC_LEN)?;
+        let data: [u8; fields::CRC_LEN] = self.data[begin..].try_into().ok()?;
+
+        Some(CRC::from_be_bytes(data))
     }
 
     pub fn stored_crc(&self) -> CRC {
@@ -54,12 +259,32 @@ impl<'a, const S: usize> Block<'a, S> {
         CRC::from_be_bytes(data)
     }
 
+    #[cfg(test)]
     pub(crate) fn set_crc(buf: &mut [u8]) {
-        let crc = CRC::to_be_bytes(Self::calculated_crc(buf));
+        Self::set_crc_with_mode(buf, CrcMode::Full, fields::DATA_BEGIN);
+    }
+
+    pub(crate) fn set_crc_with_mode(buf: &mut [u8], mode: CrcMode, header_end: usize) {
+        if mode == CrcMode::Split {
+            let data_crc = CRC::to_be_bytes(Self::calculated_data_crc(buf));
+            let begin = buf.len() - fields::CRC_LEN;
+            buf[begin..].copy_from_slice(&data_crc[..]);
+        }
+
+        let crc = CRC::to_be_bytes(Self::calculated_crc_with_header_end(buf, mode, header_end));
         buf[fields::CRC_BEGIN..fields::CRC_END].copy_from_slice(&crc[..]);
     }
 
     pub fn id(&self) -> BlockId {
+        self.stored_id() & !PENDING_FLAG
+    }
+
+    /// Block is appended inside of transaction, which wasn't committed yet
+    pub fn is_pending(&self) -> bool {
+        self.stored_id() & PENDING_FLAG != 0
+    }
+
+    fn stored_id(&self) -> BlockId {
         let mut data = [0_u8; fields::BLOCK_ID_LEN];
         data[..].copy_from_slice(&self.data[fields::BLOCK_ID_BEGIN..fields::BLOCK_ID_END]);
 
@@ -71,41 +296,133 @@ impl<'a, const S: usize> Block<'a, S> {
         buf[fields::BLOCK_ID_BEGIN..fields::BLOCK_ID_END].copy_from_slice(&id[..]);
     }
 
-    pub(crate) fn fs_id(&self) -> FsId {
+    /// Low bits of fs id, high bits of wide id are after v2 header fields, see [`BlockInfo::from_buffer_with_format`]
+    pub fn fs_id(&self) -> FsId {
         let mut data = [0_u8; fields::FS_ID_LEN];
         data[..].copy_from_slice(&self.data[fields::FS_ID_BEGIN..fields::FS_ID_END]);
 
-        FsId::from_be_bytes(data)
+        FsId::from(u32::from_be_bytes(data))
     }
 
     pub(crate) fn set_fs_id(buf: &mut [u8], id: FsId) {
-        let id: [u8; 4] = FsId::to_be_bytes(id);
-        buf[fields::FS_ID_BEGIN..fields::FS_ID_END].copy_from_slice(&id[..]);
+        let id = FsId::to_be_bytes(id);
+        buf[fields::FS_ID_BEGIN..fields::FS_ID_END].copy_from_slice(&id[fields::FS_ID_HIGH_LEN..]);
+    }
+
+    /// Write high bits of wide fs id to the beginning of `buf`
+    pub(crate) fn set_fs_id_high(buf: &mut [u8], id: FsId) {
+        let id = FsId::to_be_bytes(id);
+        buf[..fields::FS_ID_HIGH_LEN].copy_from_slice(&id[..fields::FS_ID_HIGH_LEN]);
     }
 
     pub fn calculated_crc(data: &[u8]) -> CRC {
         CRC_ALGORITHM.checksum(&data[fields::CRC_END..])
     }
 
+    /// Crc stored in header of the block with v1 header without custom metadata
+    pub fn calculated_crc_with_mode(data: &[u8], mode: CrcMode) -> CRC {
+        Self::calculated_crc_with_header_end(data, mode, fields::DATA_BEGIN)
+    }
+
+    /// Crc stored in header of the block, header crc of `HeaderOnly` and `Split` modes covers bytes
+    /// till `header_end` (v2 header fields and custom metadata are before it)
+    pub fn calculated_crc_with_header_end(data: &[u8], mode: CrcMode, header_end: usize) -> CRC {
+        match mode {
+            CrcMode::Full => Self::calculated_crc(data),
+            CrcMode::HeaderOnly | CrcMode::Split => {
+                let end = header_end.clamp(fields::DATA_BEGIN, data.len());
+                CRC_ALGORITHM.checksum(&data[fields::CRC_END..end])
+            }
+        }
+    }
+
+    /// Crc of data (including custom metadata) without data crc at the end of the block
+    pub fn calculated_data_crc(data: &[u8]) -> CRC {
+        let end = data
+            .len()
+            .saturating_sub(fields::CRC_LEN)
+            .max(fields::DATA_BEGIN);
+        CRC_ALGORITHM.checksum(&data[fields::DATA_BEGIN..end])
+    }
+
     pub const fn attributes_size() -> usize {
         fields::DATA_BEGIN
     }
 }
 
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// Generate fs id from hardware unique id (e.g. MCU serial number) and a random value,
+/// so ids of different devices don't collide even if random source is poor (e.g. the same seed after reset).
+/// Id is FNV-1a 128 bit hash of `hw_unique_id` bytes followed by big endian bytes of `random`,
+/// truncated to [`NARROW_FS_ID_MAX`], see `generate_wide_fs_id` for id of full width.
+pub fn generate_fs_id(hw_unique_id: &[u8], random: u64) -> FsId {
+    generate_wide_fs_id(hw_unique_id, random) & NARROW_FS_ID_MAX
+}
+
+/// Same as `generate_fs_id`, but the hash isn't truncated
+pub fn generate_wide_fs_id(hw_unique_id: &[u8], random: u64) -> FsId {
+    fnv1a(hw_unique_id.iter().chain(random.to_be_bytes().iter()))
+}
+
+/// Derive stable fs id from device unique id (e.g. MCU serial number or flash unique id) and `salt` (e.g.
+/// name of the partition, or number of formats of the device to get new id after reformat), so firmware
+/// doesn't need random source. Id is FNV-1a 128 bit hash of big endian `u32` length of `device_unique_id`,
+/// its bytes and bytes of `salt`, truncated to [`NARROW_FS_ID_MAX`] (low bits). Length prefix keeps pairs
+/// which concatenate to the same bytes apart, e.g. `(b"ab", b"c")` and `(b"a", b"bc")`.
+pub fn derive_fs_id(device_unique_id: &[u8], salt: &[u8]) -> FsId {
+    derive_wide_fs_id(device_unique_id, salt) & NARROW_FS_ID_MAX
+}
+
+/// Same as `derive_fs_id`, but the hash isn't truncated
+pub fn derive_wide_fs_id(device_unique_id: &[u8], salt: &[u8]) -> FsId {
+    let len = (device_unique_id.len() as u32).to_be_bytes();
+    fnv1a(len.iter().chain(device_unique_id).chain(salt))
+}
+
+fn fnv1a<'a>(bytes: impl Iterator<Item = &'a u8>) -> u128 {
+    bytes.fold(FNV_OFFSET_BASIS, |hash, b| {
+        (hash ^ *b as u128).wrapping_mul(FNV_PRIME)
+    })
+}
+
 #[derive(Debug)]
 pub struct BlockFactory {
     pub id: BlockId,
+    pending: bool,
+    crc_mode: CrcMode,
+    header_end: usize,
 }
 
 impl BlockFactory {
     pub fn new() -> BlockFactory {
-        BlockFactory { id: 0 }
+        BlockFactory {
+            id: 0,
+            pending: false,
+            crc_mode: CrcMode::Full,
+            header_end: fields::DATA_BEGIN,
+        }
     }
 
     pub(crate) fn set_id(&mut self, id: BlockId) {
         self.id = id;
     }
 
+    pub(crate) fn set_crc_mode(&mut self, crc_mode: CrcMode) {
+        self.crc_mode = crc_mode;
+    }
+
+    /// End of header of created blocks, see [`Block::from_buffer_with_header_end`]
+    pub(crate) fn set_header_end(&mut self, header_end: usize) {
+        self.header_end = header_end;
+    }
+
+    /// Mark created blocks as pending, see [`Block::is_pending`]
+    pub(crate) fn set_pending(&mut self, pending: bool) {
+        self.pending = pending;
+    }
+
     pub fn create_with_writer<'a, F, const S: usize>(
         &mut self,
         buf: &'a mut [u8],
@@ -115,12 +432,14 @@ impl BlockFactory {
     where
         F: FnOnce(&mut [u8]),
     {
-        writer(&mut buf[fields::DATA_BEGIN..]);
-        Block::<'a, S>::set_id(buf, self.get_next_id());
+        let data_end = buf.len() - self.crc_mode.trailer_len();
+        writer(&mut buf[fields::DATA_BEGIN..data_end]);
+        let flags = if self.pending { PENDING_FLAG } else { 0 };
+        Block::<'a, S>::set_id(buf, self.get_next_id() | flags);
         Block::<'a, S>::set_fs_id(buf, fs_id);
-        Block::<'a, S>::set_crc(buf);
+        Block::<'a, S>::set_crc_with_mode(buf, self.crc_mode, self.header_end);
 
-        Block::<'a, S>::from_buffer(buf)
+        Block::<'a, S>::from_buffer_with_header_end(buf, self.crc_mode, self.header_end)
     }
 
     pub fn get_next_id(&mut self) -> BlockId {
@@ -138,10 +457,22 @@ impl Default for BlockFactory {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlockInfo<const S: usize> {
-    pub id: u64,
-    pub fs_id: u32,
+    pub id: BlockId,
+    pub fs_id: FsId,
     pub is_valid: bool,
+    pub is_pending: bool,
+    /// Sequence number of `append_seq`, 0 for blocks written by `append` and for v1 blocks, see
+    /// [`HeaderV2::FLAG_SEQ`]
+    pub seq: SeqNum,
+    /// Marker block written by `append_dedup` repeats data of the block with id `id - repeat`, 0 for blocks
+    /// with own data and for v1 blocks, see [`HeaderV2::FLAG_REPEAT`]
+    pub repeat: RepeatCount,
+    /// Fields of v2 header, `None` for v1 blocks, see [`HeaderVersion`]
+    pub header: Option<HeaderV2>,
+    metadata: [u8; MAX_METADATA_LEN],
+    metadata_len: usize,
 }
 
 impl<const BS: usize> BlockInfo<BS> {
@@ -149,15 +480,116 @@ impl<const BS: usize> BlockInfo<BS> {
         let is_valid = block.is_valid();
         let fs_id = block.fs_id();
         let id = if is_valid { block.id() } else { 0 };
+        let is_pending = is_valid && block.is_pending();
 
         Self {
             id,
             fs_id,
             is_valid,
+            is_pending,
+            seq: 0,
+            repeat: 0,
+            header: None,
+            metadata: [0_u8; MAX_METADATA_LEN],
+            metadata_len: 0,
         }
     }
 
+    /// Metadata is empty, use `from_buffer_with_layout` to get it
     pub fn from_buffer(data: &[u8]) -> Self {
         Self::from_block(&Block::<BS>::from_buffer(data))
     }
+
+    /// Parse header of block of filesystem formatted with `crc_mode`
+    pub fn from_buffer_with_mode(data: &[u8], crc_mode: CrcMode) -> Self {
+        Self::from_block(&Block::<BS>::from_buffer_with_mode(data, crc_mode))
+    }
+
+    /// Parse header which ends at `header_end`, see [`Block::from_buffer_with_header_end`]
+    pub fn from_buffer_with_header_end(data: &[u8], crc_mode: CrcMode, header_end: usize) -> Self {
+        Self::from_block(&Block::<BS>::from_buffer_with_header_end(
+            data, crc_mode, header_end,
+        ))
+    }
+
+    /// Parse header with custom metadata of layout `L`
+    pub fn from_buffer_with_layout<L: BlockLayout>(data: &[u8], crc_mode: CrcMode) -> Self {
+        Self::from_buffer_with_header::<L>(data, crc_mode, HeaderVersion::V1)
+    }
+
+    /// Parse header of `version` with custom metadata of layout `L`, v2 block without magic isn't valid
+    pub fn from_buffer_with_header<L: BlockLayout>(
+        data: &[u8],
+        crc_mode: CrcMode,
+        version: HeaderVersion,
+    ) -> Self {
+        Self::from_buffer_with_format::<L>(data, crc_mode, version, false)
+    }
+
+    /// Same as `from_buffer_with_header`, `wide_fs_id` header keeps high bits of fs id after v2 header fields
+    pub fn from_buffer_with_format<L: BlockLayout>(
+        data: &[u8],
+        crc_mode: CrcMode,
+        version: HeaderVersion,
+        wide_fs_id: bool,
+    ) -> Self {
+        let begin = fields::DATA_BEGIN + header_extension_len(version, wide_fs_id);
+        let mut info = Self::from_buffer_with_header_end(data, crc_mode, begin + L::METADATA_LEN);
+        if wide_fs_id {
+            info.read_fs_id_high(data, fields::DATA_BEGIN + version.extension_len());
+        }
+        if version == HeaderVersion::V2 {
+            info.header = data
+                .get(fields::DATA_BEGIN..)
+                .and_then(HeaderV2::from_bytes);
+            match info.header {
+                Some(header) if info.is_valid => {
+                    if header.flags & HeaderV2::FLAG_REPEAT != 0 {
+                        info.repeat = header.tag;
+                    }
+                    if header.flags & HeaderV2::FLAG_SEQ != 0 {
+                        info.seq = header.tag;
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    info.is_valid = false;
+                    info.is_pending = false;
+                    info.id = 0;
+                }
+            }
+        }
+        // buffer may be shorter than the header, e.g. only part of the block is read
+        let metadata = data.get(begin..).unwrap_or_default();
+        let len = L::METADATA_LEN.min(MAX_METADATA_LEN).min(metadata.len());
+        info.metadata[..len].copy_from_slice(&metadata[..len]);
+        info.metadata_len = len;
+
+        info
+    }
+
+    /// Add high bits of wide fs id which begin at `begin` of `data` to `fs_id`
+    pub(crate) fn read_fs_id_high(&mut self, data: &[u8], begin: usize) {
+        let Some(high) = data.get(begin..begin + fields::FS_ID_HIGH_LEN) else {
+            return;
+        };
+        let mut id = self.fs_id.to_be_bytes();
+        id[..fields::FS_ID_HIGH_LEN].copy_from_slice(high);
+        self.fs_id = FsId::from_be_bytes(id);
+    }
+
+    /// Flags of v2 header set by `append_with_flags`, 0 for v1 blocks
+    pub fn flags(&self) -> u8 {
+        self.header.map_or(0, |h| h.flags)
+    }
+
+    /// All bits of `mask` are set in flags of the block, see `HeaderV2::FLAG_CRITICAL`
+    pub fn has_flags(&self, mask: u8) -> bool {
+        self.flags() & mask == mask
+    }
+
+    /// Custom metadata of the block, see [`BlockLayout`]
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata[..self.metadata_len]
+    }
 }
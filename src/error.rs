@@ -1,3 +1,5 @@
+use crate::block::FsId;
+
 #[derive(Clone, Debug)]
 pub enum Error {
     TooSmallFilesystem,
@@ -15,4 +17,45 @@ pub enum Error {
     CanNotWriteConfig,
     NotValidBlockForRead,
     InvalidHeaderBlock,
+    ArithmeticOverflow,
+    PinTableIsFull,
+    BlockIsNotPinned,
+    TransactionIsOpen,
+    TransactionIsNotOpen,
+    UnsupportedCrcMode,
+    BlockIdsExhausted,
+    Throttled,
+    MountValidationFailed,
+    BadBlockTableIsFull,
+    /// Storage is formatted with another fs id, `new` doesn't format it unless it's forced
+    ForeignFilesystem {
+        found_fs_id: FsId,
+    },
+    /// Operation needs another header version of data blocks, see [`crate::block::HeaderVersion`]
+    UnsupportedHeaderVersion,
+    /// The newest block isn't valid after sync, see [`crate::fs::Filesystem::barrier`]
+    BarrierFailed,
+    /// Another process holds lock of the storage, see [`crate::storage::file::FileStorage::lock`]
+    StorageIsLocked,
+    /// Filesystem is mounted with `open_readonly`
+    ReadOnly,
+    /// Block was overwritten by the writer after read-only filesystem was mounted or refreshed
+    Lapped,
+    /// Key is empty or longer than [`crate::fs::kv::MAX_KEY_LEN`]
+    InvalidKey,
+    KeyTableIsFull,
+    /// Record begins with [`crate::fs::kv::KV_RECORD_TAG`], it's reserved for key-value records
+    ReservedRecordTag,
+    /// Flag of v2 header is set only by appendfs, see [`crate::block::HeaderV2::FLAG_REPEAT`]
+    ReservedFlag,
+    /// Block size of the storage exceeds `BS` of the filesystem, use bigger `BS` or split blocks of the filesystem
+    /// with [`crate::storage::chunked::ChunkedStorage`]
+    BlockSizeMismatch {
+        block_size: usize,
+        max_block_size: usize,
+    },
+    /// Config blocks have magic of appendfs, but none of them is valid, storage isn't formatted unless it's forced
+    DamagedConfig,
+    /// Storage can't perform optional operation, e.g. [`crate::storage::Storage::discard`] of file storage
+    UnsupportedOperation,
 }
@@ -0,0 +1,151 @@
+//! Reference images of the on-disk format (geometries, wrap states and corruption patterns), they are checked in
+//! to `fixtures/format` and verified by conformance tests, implementations in other languages can use them too.
+//! `golden-images` example writes [`GOLDEN_IMAGES`] with a manifest. Images are generated with default features,
+//! blocks are filled with [`pattern`] of their id.
+
+use crate::block::{BlockId, CrcMode, FsId, HeaderVersion};
+use crate::error::Error;
+use crate::fs::config_block::CONFIG_COPIES;
+use crate::fs::DynFilesystem;
+use crate::storage::slice::SliceStorage;
+
+/// Fs id of all golden images
+pub const GOLDEN_FS_ID: FsId = 0x0A5F5A11;
+
+/// Damage applied to the image after appends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    None,
+    /// Flip the lowest bit of the last byte of the block at offset from the oldest one
+    FlipBit(usize),
+    /// Zero the second half of the newest block (write interrupted by power loss)
+    TornNewest,
+    /// Flip the lowest bit of crc of all config blocks
+    BrokenConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GoldenImage {
+    /// File name without extension
+    pub name: &'static str,
+    pub block_size: usize,
+    /// Number of blocks including config blocks
+    pub blocks: usize,
+    pub crc_mode: CrcMode,
+    pub header: HeaderVersion,
+    /// Blocks appended after format, ids start from 0
+    pub appends: usize,
+    pub corruption: Corruption,
+}
+
+const fn image(
+    name: &'static str,
+    block_size: usize,
+    blocks: usize,
+    appends: usize,
+) -> GoldenImage {
+    GoldenImage {
+        name,
+        block_size,
+        blocks,
+        crc_mode: CrcMode::Full,
+        header: HeaderVersion::V1,
+        appends,
+        corruption: Corruption::None,
+    }
+}
+
+pub const GOLDEN_IMAGES: [GoldenImage; 10] = [
+    image("v1-full-128x8", 128, 8, 10),
+    GoldenImage {
+        crc_mode: CrcMode::Split,
+        ..image("v1-split-128x8", 128, 8, 5)
+    },
+    GoldenImage {
+        header: HeaderVersion::V2,
+        ..image("v2-256x4", 256, 4, 3)
+    },
+    GoldenImage {
+        crc_mode: CrcMode::HeaderOnly,
+        ..image("v1-header-only-128x8", 128, 8, 4)
+    },
+    image("empty-64x8", 64, 8, 0),
+    // ring is full, the next append overwrites the oldest block
+    image("full-64x8", 64, 8, 6),
+    image("wrapped-512x16", 512, 16, 100),
+    GoldenImage {
+        corruption: Corruption::FlipBit(3),
+        ..image("bit-flip-128x8", 128, 8, 10)
+    },
+    GoldenImage {
+        corruption: Corruption::TornNewest,
+        ..image("torn-newest-128x8", 128, 8, 10)
+    },
+    GoldenImage {
+        corruption: Corruption::BrokenConfig,
+        ..image("broken-config-128x8", 128, 8, 3)
+    },
+];
+
+/// Data byte of block `id`
+pub fn pattern(id: BlockId) -> u8 {
+    (id as u8).wrapping_mul(17)
+}
+
+impl GoldenImage {
+    /// Size of the image in bytes
+    pub const fn len(&self) -> usize {
+        self.block_size * self.blocks
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the image to `image` of `len` bytes, it must be zeroed
+    pub fn generate(&self, image: &mut [u8]) -> Result<(), Error> {
+        let image = image.get_mut(..self.len()).ok_or(Error::TooSmallBuffer)?;
+        let damaged = {
+            let mut storage = SliceStorage::new(&mut image[..], self.block_size)?;
+            // images are written by the first boot of a writer
+            let mut fs = DynFilesystem::builder(&mut storage)
+                .fs_id(GOLDEN_FS_ID)
+                .crc_mode(self.crc_mode)
+                .header(self.header)
+                .count_boot(true)
+                .build()?;
+            for id in 0..self.appends as BlockId {
+                fs.append(|blk_data| blk_data.fill(pattern(id)))?;
+            }
+
+            match self.corruption {
+                Corruption::None => return Ok(()),
+                Corruption::FlipBit(blk_offset) => {
+                    (fs.blk_idx_of(blk_offset)?, self.block_size - 1)
+                }
+                Corruption::TornNewest => {
+                    let newest = fs
+                        .used_blocks()
+                        .checked_sub(1)
+                        .ok_or(Error::BlockOutOfRange)?;
+                    (fs.blk_idx_of(newest)?, self.block_size / 2)
+                }
+                Corruption::BrokenConfig => (0, 0),
+            }
+        };
+
+        let (blk_idx, offset) = damaged;
+        let begin = blk_idx * self.block_size;
+        match self.corruption {
+            Corruption::TornNewest => image[begin + offset..begin + self.block_size].fill(0),
+            Corruption::BrokenConfig => {
+                for copy in 0..CONFIG_COPIES {
+                    image[copy * self.block_size] ^= 1;
+                }
+            }
+            _ => image[begin + offset] ^= 1,
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! On-disk format of appendfs, the layout is defined here and used by [`crate::block`], tables of fields
+//! let external tools parse images without the library. Changes of the layout must be deliberate:
+//! golden images in `fixtures/format` are checked by conformance tests, they must stay readable. Images written
+//! by config version 0x1 are kept in `fixtures/format/compat-v1` and checked for read compatibility.
+//!
+//! - Storage range `min_block_index..max_block_index` is a ring, the first [`config::CONFIG_COPIES`] blocks are
+//!   config blocks, then `reserved_blocks` of [`CONFIG_BLOCK_FIELDS`] blocks which the filesystem never touches,
+//!   other blocks are data blocks, see [`crate::utils::ring_blk_idx`]. Images of version 1 have one config block
+//!   and no reserved blocks.
+//! - All integers are big endian, crc is CRC-16/CDMA2000 ([`crate::block::CRC_ALGORITHM`]).
+//! - Every block begins with [`data_block::FIELDS`]. Crc of `Full` mode covers the whole block after the crc
+//!   field, crc of `HeaderOnly` and `Split` modes covers header fields including v2 header fields and custom
+//!   metadata (the bytes before data), `Split` mode stores crc of the rest of the block in the last
+//!   [`data_block::CRC_LEN`] bytes. Highest bit of block id marks not committed block of transaction.
+//! - Data blocks of v2 header filesystem continue with [`header_v2::FIELDS`], then custom metadata
+//!   ([`crate::block::BlockLayout`]) and data.
+//! - Config block (always `Full` crc mode) has header fields and [`CONFIG_BLOCK_FIELDS`] at
+//!   [`data_block::DATA_BEGIN`], fields which don't fit the block are read as 0. Block id of config is its
+//!   generation: config is written to the block `generation % CONFIG_COPIES` with generation + 1, the valid copy
+//!   with the highest generation is the config of the filesystem.
+
+use crate::fs::config_block as config;
+
+pub mod golden;
+
+/// Field of a block: `len` bytes from `begin`, `len` is 0 for fields of disabled features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub begin: usize,
+    pub len: usize,
+}
+
+impl Field {
+    pub const fn end(&self) -> usize {
+        self.begin + self.len
+    }
+}
+
+/// Header fields at the beginning of every block, fields after them are chosen on format and stored in config
+/// block, see [`crate::block::header_extension_len`]
+pub mod data_block {
+    use core::mem::size_of;
+
+    use super::Field;
+
+    pub const CRC_BEGIN: usize = 0;
+    pub const CRC_LEN: usize = size_of::<crate::block::CRC>();
+    pub const CRC_END: usize = CRC_BEGIN + CRC_LEN;
+
+    pub const FS_ID_BEGIN: usize = CRC_END;
+    /// Low bits of [`crate::block::FsId`]
+    pub const FS_ID_LEN: usize = size_of::<u32>();
+    pub const FS_ID_END: usize = FS_ID_BEGIN + FS_ID_LEN;
+    /// High bits of wide fs id, they follow v2 header fields, see [`crate::block::header_extension_len`]
+    pub const FS_ID_HIGH_LEN: usize = size_of::<crate::block::FsId>() - FS_ID_LEN;
+
+    pub const BLOCK_ID_BEGIN: usize = FS_ID_END;
+    pub const BLOCK_ID_LEN: usize = size_of::<crate::block::BlockId>();
+    pub const BLOCK_ID_END: usize = BLOCK_ID_BEGIN + BLOCK_ID_LEN;
+
+    /// End of header, v2 header fields or data follow it
+    pub const DATA_BEGIN: usize = BLOCK_ID_END;
+
+    pub const FIELDS: [Field; 3] = [
+        Field {
+            name: "crc",
+            begin: CRC_BEGIN,
+            len: CRC_LEN,
+        },
+        Field {
+            name: "fs_id",
+            begin: FS_ID_BEGIN,
+            len: FS_ID_LEN,
+        },
+        Field {
+            name: "block_id",
+            begin: BLOCK_ID_BEGIN,
+            len: BLOCK_ID_LEN,
+        },
+    ];
+}
+
+/// Fields of v2 header (see [`crate::block::HeaderV2`]), offsets are relative to [`data_block::DATA_BEGIN`]
+pub mod header_v2 {
+    use super::Field;
+
+    /// [`crate::block::HEADER_V2_MAGIC`]
+    pub const MAGIC_END: usize = 2;
+    pub const FLAGS_END: usize = MAGIC_END + 1;
+    pub const LENGTH_END: usize = FLAGS_END + 2;
+    pub const TIMESTAMP_END: usize = LENGTH_END + 8;
+    pub const TAG_END: usize = TIMESTAMP_END + 4;
+    pub const LEN: usize = TAG_END;
+
+    pub const FIELDS: [Field; 5] = [
+        Field {
+            name: "magic",
+            begin: 0,
+            len: MAGIC_END,
+        },
+        Field {
+            name: "flags",
+            begin: MAGIC_END,
+            len: FLAGS_END - MAGIC_END,
+        },
+        Field {
+            name: "length",
+            begin: FLAGS_END,
+            len: LENGTH_END - FLAGS_END,
+        },
+        Field {
+            name: "timestamp",
+            begin: LENGTH_END,
+            len: TIMESTAMP_END - LENGTH_END,
+        },
+        Field {
+            name: "tag",
+            begin: TIMESTAMP_END,
+            len: TAG_END - TIMESTAMP_END,
+        },
+    ];
+}
+
+/// Fields of config block (see [`crate::fs::config_block::FsConfigBlock`]), offsets are relative to
+/// [`data_block::DATA_BEGIN`]
+pub const CONFIG_BLOCK_FIELDS: [Field; 17] = [
+    Field {
+        name: "version",
+        begin: config::VERSION_BEGIN,
+        len: config::VERSION_LEN,
+    },
+    Field {
+        name: "magic",
+        begin: config::MAGIC_BEGIN,
+        len: config::MAGIC_LEN,
+    },
+    Field {
+        name: "block_count",
+        begin: config::BLOCK_COUNT_BEGIN,
+        len: config::BLOCK_COUNT_LEN,
+    },
+    Field {
+        name: "writes_before_format",
+        begin: config::WRITES_BEGIN,
+        len: config::WRITES_LEN,
+    },
+    Field {
+        name: "crc_mode",
+        begin: config::CRC_MODE_BEGIN,
+        len: config::CRC_MODE_LEN,
+    },
+    Field {
+        name: "id_high_water",
+        begin: config::ID_HIGH_WATER_BEGIN,
+        len: config::ID_HIGH_WATER_LEN,
+    },
+    Field {
+        name: "boot_time",
+        begin: config::BOOT_TIME_BEGIN,
+        len: config::BOOT_TIME_LEN,
+    },
+    Field {
+        name: "boot_count",
+        begin: config::BOOT_COUNT_BEGIN,
+        len: config::BOOT_COUNT_LEN,
+    },
+    Field {
+        name: "bad_blocks",
+        begin: config::BAD_BLOCKS_BEGIN,
+        len: config::BAD_BLOCKS_LEN,
+    },
+    Field {
+        name: "crc_failures",
+        begin: config::CRC_FAILURES_BEGIN,
+        len: config::CRC_FAILURES_LEN,
+    },
+    Field {
+        name: "write_retries",
+        begin: config::WRITE_RETRIES_BEGIN,
+        len: config::WRITE_RETRIES_LEN,
+    },
+    Field {
+        name: "remapped_blocks",
+        begin: config::REMAPPED_BLOCKS_BEGIN,
+        len: config::REMAPPED_BLOCKS_LEN,
+    },
+    Field {
+        name: "reserved_blocks",
+        begin: config::RESERVED_BLOCKS_BEGIN,
+        len: config::RESERVED_BLOCKS_LEN,
+    },
+    Field {
+        name: "quarantine",
+        begin: config::QUARANTINE_BEGIN,
+        len: config::QUARANTINE_LEN,
+    },
+    Field {
+        name: "fs_id",
+        begin: config::FS_ID_BEGIN,
+        len: config::FS_ID_LEN,
+    },
+    Field {
+        name: "front_id",
+        begin: config::FRONT_ID_BEGIN,
+        len: config::FRONT_ID_LEN,
+    },
+    Field {
+        name: "priority",
+        begin: config::PRIORITY_BEGIN,
+        len: config::PRIORITY_LEN,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{data_block, header_v2, Field, CONFIG_BLOCK_FIELDS};
+    use crate::fs::config_block;
+
+    /// Fields follow each other without gaps and end at `end`
+    fn check_table(fields: &[Field], end: usize) {
+        let mut begin = 0;
+        for field in fields {
+            assert_eq!(field.begin, begin, "Gap before field {}", field.name);
+            begin = field.end();
+        }
+        assert_eq!(begin, end);
+    }
+
+    #[test]
+    fn test_format_tables() {
+        check_table(&data_block::FIELDS, data_block::DATA_BEGIN);
+        check_table(&header_v2::FIELDS, header_v2::LEN);
+        check_table(&CONFIG_BLOCK_FIELDS, config_block::BLOCK_LEN);
+    }
+
+    /// Layout doesn't depend on features, golden images are checked with every feature set
+    mod golden {
+        use core::mem::discriminant;
+
+        use super::super::golden::{pattern, GOLDEN_FS_ID, GOLDEN_IMAGES};
+        use crate::block::BlockId;
+        use crate::error::Error;
+        use crate::fs::DynFilesystem;
+        use crate::storage::slice::SliceStorage;
+
+        macro_rules! fixtures {
+            ($dir:literal: $($name:literal),*) => {
+                [$(($name, include_bytes!(concat!("../../fixtures/format/", $dir, $name, ".img")) as &[u8])),*]
+            };
+        }
+
+        const FIXTURES: [(&str, &[u8]); 10] = fixtures!(
+            "":
+            "v1-full-128x8",
+            "v1-split-128x8",
+            "v2-256x4",
+            "v1-header-only-128x8",
+            "empty-64x8",
+            "full-64x8",
+            "wrapped-512x16",
+            "bit-flip-128x8",
+            "torn-newest-128x8",
+            "broken-config-128x8"
+        );
+
+        /// Images written by appendfs 0.1.0 (config version 0x1 in one block), they must stay readable
+        const COMPAT_V1_FIXTURES: [(&str, &[u8]); 7] = fixtures!(
+            "compat-v1/":
+            "v1-full-128x8",
+            "empty-64x8",
+            "full-64x8",
+            "wrapped-512x16",
+            "bit-flip-128x8",
+            "torn-newest-128x8",
+            "broken-config-128x8"
+        );
+
+        /// Oldest and next block ids, used and not valid blocks of restored image
+        type Summary = (BlockId, BlockId, usize, usize);
+
+        fn restore(image: &mut [u8], block_size: usize) -> Result<Summary, Error> {
+            let mut storage = SliceStorage::new(image, block_size)?;
+            let mut fs = DynFilesystem::restore(&mut storage)?;
+            assert_eq!(fs.id(), GOLDEN_FS_ID);
+
+            let mut invalid = 0;
+            for blk_offset in 0..fs.used_blocks() {
+                let id = fs.oldest_blk_id() + blk_offset as BlockId;
+                match fs.read_with_info(blk_offset, |info, blk_data| {
+                    assert_eq!(info.id, id);
+                    assert!(blk_data.iter().all(|b| *b == pattern(id)));
+                }) {
+                    Ok(_) => {}
+                    Err(Error::NotValidBlockForRead) => invalid += 1,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok((
+                fs.oldest_blk_id(),
+                fs.next_blk_id(),
+                fs.used_blocks(),
+                invalid,
+            ))
+        }
+
+        fn check_summary(
+            name: &str,
+            summary: Result<Summary, Error>,
+            expected: Result<Summary, Error>,
+        ) {
+            let matches = match (&summary, &expected) {
+                (Ok(summary), Ok(expected)) => summary == expected,
+                (Err(e), Err(expected)) => discriminant(e) == discriminant(expected),
+                _ => false,
+            };
+            assert!(matches, "Content of {} is {:?}", name, summary);
+        }
+
+        #[test]
+        fn test_format_golden_images() {
+            let expected: [Result<Summary, Error>; 10] = [
+                Ok((4, 10, 6, 0)),
+                Ok((0, 5, 5, 0)),
+                Ok((1, 3, 2, 0)),
+                Ok((0, 4, 4, 0)),
+                Ok((0, 0, 0, 0)),
+                Ok((0, 6, 6, 0)),
+                Ok((86, 100, 14, 0)),
+                Ok((4, 10, 6, 1)),
+                Ok((3, 9, 6, 1)),
+                Err(Error::DamagedConfig),
+            ];
+
+            for ((golden, (name, fixture)), expected) in
+                GOLDEN_IMAGES.iter().zip(FIXTURES).zip(expected)
+            {
+                assert_eq!(golden.name, name);
+                let mut image = [0_u8; 8192];
+                let image = &mut image[..golden.len()];
+                // layout written by current version is the same
+                golden.generate(image).expect("Can't generate image");
+                assert!(
+                    image == fixture,
+                    "Layout differs from golden image {}",
+                    name
+                );
+
+                // golden image is read by current version
+                image.copy_from_slice(fixture);
+                check_summary(name, restore(image, golden.block_size), expected);
+            }
+        }
+
+        #[test]
+        fn test_format_compat_v1_images() {
+            let expected: [Result<Summary, Error>; 7] = [
+                Ok((3, 10, 7, 0)),
+                Ok((0, 0, 0, 0)),
+                Ok((0, 7, 7, 0)),
+                Ok((85, 100, 15, 0)),
+                Ok((3, 10, 7, 1)),
+                Ok((2, 9, 7, 1)),
+                // config of version 0x1 has no magic, damaged one isn't distinguished from empty storage
+                Err(Error::InvalidHeaderBlock),
+            ];
+
+            for ((name, fixture), expected) in COMPAT_V1_FIXTURES.into_iter().zip(expected) {
+                // geometry is the same as of current golden image with the name
+                let golden = GOLDEN_IMAGES
+                    .iter()
+                    .find(|golden| golden.name == name)
+                    .expect("Can't find golden image");
+                let mut image = [0_u8; 8192];
+                let image = &mut image[..golden.len()];
+                image.copy_from_slice(fixture);
+                // only undamaged images are appended to
+                let next_id = match expected {
+                    Ok((_, next_id, _, 0)) => Some(next_id),
+                    _ => None,
+                };
+                check_summary(name, restore(image, golden.block_size), expected);
+                let Some(next_id) = next_id else {
+                    continue;
+                };
+
+                // current version appends to the image, config of version 0x1 isn't rewritten
+                {
+                    let mut storage =
+                        SliceStorage::new(image, golden.block_size).expect("Can't create storage");
+                    let mut fs = DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+                    fs.append(|blk_data| blk_data.fill(pattern(next_id)))
+                        .expect("Can't append");
+                }
+                assert_eq!(image[..golden.block_size], fixture[..golden.block_size]);
+                let summary = restore(image, golden.block_size).expect("Can't restore fs");
+                assert_eq!(summary.1, next_id + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Construction of [`Filesystem`] with many options, see [`FilesystemBuilder`].
+
+use super::metrics::{Metrics, MetricsSink};
+use super::observer::{FsObserver, Observer};
+use super::{
+    Filesystem, FormatOptions, FormatPolicy, MountCallback, MountHooks, MountMode, MountProgress,
+    MountValidation, Pin, ReadPolicy, RestoreStep, RestoreTrail,
+};
+use crate::block::{BlockId, BlockLayout, CrcMode, DefaultLayout, FsId, HeaderVersion};
+use crate::error::Error;
+use crate::storage::Storage;
+use crate::time::TimeSource;
+
+/// Where id of the filesystem comes from
+enum IdSource<'a> {
+    /// Id of formatted storage, not formatted storage fails with `InvalidHeaderBlock`
+    Restored,
+    /// Storage is formatted with the id unless it's already formatted with it
+    Fixed(FsId),
+    /// Id of formatted storage, not formatted storage is formatted with id returned by the callback
+    Generated(&'a mut dyn FnMut() -> FsId),
+}
+
+/// Options of [`Filesystem`] collected before mount, created by [`Filesystem::builder`]. Options which are set
+/// with setters of the filesystem are applied after mount, so `build` is the same as constructor followed by them.
+/// Options of format (crc mode, header, reserved blocks) are used only in case storage is formatted by `build`,
+/// restored filesystem keeps options stored in its config block. Constructors of the filesystem are shortcuts
+/// of the builder.
+pub struct FilesystemBuilder<
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout = DefaultLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    storage: &'a mut S,
+    id: IdSource<'a>,
+    format: FormatOptions,
+    policy: FormatPolicy,
+    force: bool,
+    read_only: bool,
+    count_boot: bool,
+    validation: MountValidation,
+    read_policy: ReadPolicy,
+    quarantine: bool,
+    id_reserve: BlockId,
+    hooks: MountHooks<'a>,
+    observer: Option<Observer<'a>>,
+    metrics: Option<Metrics<'a>>,
+    pins: Option<&'a mut [Option<Pin>]>,
+    time_source: Option<&'a mut (dyn TimeSource + Send)>,
+    buffer: B,
+    layout: core::marker::PhantomData<fn() -> L>,
+}
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout> FilesystemBuilder<'a, S, BS, L> {
+    /// Builder which restores filesystem from `storage`, the same as `Filesystem::restore`
+    pub fn new(storage: &'a mut S) -> Self {
+        Self {
+            storage,
+            id: IdSource::Restored,
+            format: FormatOptions::new(CrcMode::Full),
+            policy: FormatPolicy::Preserve,
+            force: false,
+            read_only: false,
+            count_boot: false,
+            validation: MountValidation::Fast,
+            read_policy: ReadPolicy::Verify,
+            quarantine: false,
+            id_reserve: 0,
+            hooks: MountHooks::default(),
+            observer: None,
+            metrics: None,
+            pins: None,
+            time_source: None,
+            buffer: [0_u8; BS],
+            layout: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>
+    FilesystemBuilder<'a, S, BS, L, B>
+{
+    /// Format storage with `fs_id` or restore filesystem with it, see `Filesystem::new`
+    pub fn fs_id(mut self, fs_id: FsId) -> Self {
+        self.id = IdSource::Fixed(fs_id);
+        self
+    }
+
+    /// Restore filesystem, not formatted storage is formatted with id returned by `source` (e.g. `derive_fs_id`),
+    /// see `Filesystem::restore_or_format`
+    pub fn id_source(mut self, source: &'a mut dyn FnMut() -> FsId) -> Self {
+        self.id = IdSource::Generated(source);
+        self
+    }
+
+    /// What happens with blocks left in storage formatted by `build`, `Preserve` by default
+    pub fn format_policy(mut self, policy: FormatPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Storage of another filesystem is formatted instead of failing with `ForeignFilesystem`,
+    /// see `Filesystem::new_with_force`
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Mount without writes to the storage, see `Filesystem::open_readonly`
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Increment number of boots stored in config block, see `Filesystem::mount_for_write`,
+    /// it's ignored by read only mount
+    pub fn count_boot(mut self, count_boot: bool) -> Self {
+        self.count_boot = count_boot;
+        self
+    }
+
+    /// Check used blocks before anything is written to the storage, see `Filesystem::restore_with_validation`,
+    /// it's ignored in case storage is formatted by `build`
+    pub fn validation(mut self, validation: MountValidation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// See `Filesystem::new_with_buffer`
+    pub fn buffer<T: AsMut<[u8]>>(self, buffer: T) -> FilesystemBuilder<'a, S, BS, L, T> {
+        FilesystemBuilder {
+            storage: self.storage,
+            id: self.id,
+            format: self.format,
+            policy: self.policy,
+            force: self.force,
+            read_only: self.read_only,
+            count_boot: self.count_boot,
+            validation: self.validation,
+            read_policy: self.read_policy,
+            quarantine: self.quarantine,
+            id_reserve: self.id_reserve,
+            hooks: self.hooks,
+            observer: self.observer,
+            metrics: self.metrics,
+            pins: self.pins,
+            time_source: self.time_source,
+            buffer,
+            layout: self.layout,
+        }
+    }
+
+    /// See `Filesystem::new_with_crc_mode`
+    pub fn crc_mode(mut self, crc_mode: CrcMode) -> Self {
+        self.format.crc_mode = crc_mode;
+        self
+    }
+
+    /// See `Filesystem::new_with_header`
+    pub fn header(mut self, header: HeaderVersion) -> Self {
+        self.format.header = header;
+        self
+    }
+
+    /// See `Filesystem::new_with_reserved`
+    pub fn reserved(mut self, reserved: usize) -> Self {
+        self.format.reserved = reserved;
+        self
+    }
+
+    /// See `Filesystem::set_read_policy`
+    pub fn read_policy(mut self, policy: ReadPolicy) -> Self {
+        self.read_policy = policy;
+        self
+    }
+
+    /// See `Filesystem::set_quarantine`
+    pub fn quarantine(mut self, enabled: bool) -> Self {
+        self.quarantine = enabled;
+        self
+    }
+
+    /// See `Filesystem::set_id_reserve`
+    pub fn id_reserve(mut self, chunk: BlockId) -> Self {
+        self.id_reserve = chunk;
+        self
+    }
+
+    /// See `Filesystem::restore_with_progress`
+    pub fn progress(mut self, progress: &'a mut (dyn FnMut(MountProgress) + Send)) -> Self {
+        self.hooks.progress = Some(MountCallback {
+            callback: progress,
+            probed: 0,
+        });
+        self
+    }
+
+    /// See `Filesystem::restore_with_trail`
+    pub fn trail(mut self, trail: &'a mut (dyn FnMut(RestoreStep) + Send)) -> Self {
+        self.hooks.trail = Some(RestoreTrail { callback: trail });
+        self
+    }
+
+    /// See `Filesystem::set_observer`
+    pub fn observer(
+        mut self,
+        observer: &'a mut (dyn FsObserver + Send),
+        watermarks: &'a [u8],
+    ) -> Self {
+        self.observer = Some(Observer {
+            observer,
+            watermarks,
+        });
+        self
+    }
+
+    /// See `Filesystem::set_metrics`
+    pub fn metrics(mut self, sink: &'a mut (dyn MetricsSink + Send)) -> Self {
+        self.metrics = Some(Metrics { sink });
+        self
+    }
+
+    /// See `Filesystem::set_pins`
+    pub fn pins(mut self, pins: &'a mut [Option<Pin>]) -> Self {
+        self.pins = Some(pins);
+        self
+    }
+
+    /// See `Filesystem::set_time_source`
+    pub fn time_source(mut self, source: &'a mut (dyn TimeSource + Send)) -> Self {
+        self.time_source = Some(source);
+        self
+    }
+
+    /// Mount the filesystem and apply options
+    pub fn build(self) -> Result<Filesystem<'a, S, BS, L, B>, Error> {
+        let restored = Filesystem::<S, BS, L, B>::restored_fs_id(self.storage);
+        let fs_id = match (self.id, &restored) {
+            (IdSource::Fixed(fs_id), _) => fs_id,
+            (_, Ok(fs_id)) => *fs_id,
+            (IdSource::Generated(source), Err(Error::InvalidHeaderBlock)) => source(),
+            (_, Err(e)) => return Err(e.clone()),
+        };
+        // format happens only in case storage doesn't have the filesystem, other errors fail mount below
+        let formats = !self.read_only && !matches!(restored, Ok(found) if found == fs_id);
+        let wipes = formats && self.policy == FormatPolicy::Wipe;
+        // nothing is written until blocks are validated
+        let validates = !self.read_only && !formats && self.validation != MountValidation::Fast;
+        let mode = if self.read_only || validates {
+            MountMode::ReadOnly
+        } else if self.force {
+            MountMode::Force
+        } else {
+            MountMode::ReadWrite
+        };
+
+        let mut fs = Filesystem::with_buffer(
+            self.storage,
+            fs_id,
+            self.format,
+            self.buffer,
+            self.hooks,
+            mode,
+        )?;
+        if validates {
+            fs.validate_used(self.validation)?;
+            fs.read_only = false;
+            fs.drop_uncommitted()?;
+        }
+        if wipes {
+            fs.wipe_foreign_blocks()?;
+        }
+        if self.count_boot && !self.read_only {
+            fs.count_boot()?;
+        }
+        fs.set_read_policy(self.read_policy);
+        fs.set_quarantine(self.quarantine);
+        fs.set_id_reserve(self.id_reserve);
+        fs.observer = self.observer;
+        fs.metrics = self.metrics;
+        if let Some(pins) = self.pins {
+            fs.set_pins(pins)?;
+        }
+        if let Some(source) = self.time_source {
+            fs.set_time_source(source)?;
+        }
+
+        Ok(fs)
+    }
+}
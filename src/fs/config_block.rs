@@ -0,0 +1,482 @@
+//! To add new field:
+//! - add ${FIELD}_BEGIN, ${FIELD}_LEN, ${FIELD}_END, constants
+//! - possible change BLOCK_END constant in case this field will be last one
+//! - implement method write_${field} for FsConfigBlock, see `write_version` as an example
+//! - call `write_${field}` method in `to_be_bytes`
+//! - implement method read_${field} for FsConfigBlock, see `read_version` as an example
+//! - call `read_${field}` method in `from_be_bytes`
+//!
+//! Trailing zero bytes of config are not written in case they don't fit to the block (e.g. 64 byte blocks
+//! with wide header), missing bytes are read as 0.
+
+use crate::block::{BlockId, FsId};
+use crate::time::Timestamp;
+
+pub type Version = u32;
+pub type Magic = u32;
+pub type BlockCount = u64;
+pub type WriteCount = u64;
+pub type CrcModeId = u8;
+pub type BootCount = u32;
+pub type HealthCount = u32;
+
+// add mapping to map FS_VERSION to package version (detect braking changes)
+pub const FS_VERSION: Version = 0x2;
+
+/// Marks config block of appendfs, lets to distinguish it from random data with valid crc
+pub const FS_MAGIC: Magic = Magic::from_be_bytes(*b"ApFs");
+/// Config blocks of released version 0x1 contain only version field, other fields are read as 0
+/// (tables are read as empty, crc mode is `Full`, header is v1)
+pub const FIELDS_SINCE_VERSION: Version = 0x2;
+/// Config of this version is stored in a pair of blocks at the beginning of storage range, a write goes to
+/// the copy which isn't the newest one, so torn write of config leaves the previous copy readable. Generation
+/// of the copy is stored in block id field of its header, copy of generation `g` is at `begin + g % CONFIG_COPIES`.
+/// Config of version 0x1 is the only block before data blocks, it's never rewritten.
+pub const CONFIG_COPIES: usize = 2;
+/// Highest bit of `crc_mode` marks filesystem with v2 header of data blocks, see [`crate::block::HeaderVersion`]
+pub const HEADER_V2_FLAG: CrcModeId = 0x80;
+/// Bit of `crc_mode` which marks fs id wider than `u32`, the whole id is in `fs_id` field, see [`FsId`]
+pub const WIDE_FS_ID_FLAG: CrcModeId = 0x40;
+
+/// Size of the table of bad blocks
+pub const MAX_BAD_BLOCKS: usize = 4;
+/// Size of the table of quarantined blocks
+pub const MAX_QUARANTINED: usize = 4;
+/// Size of the table of high priority blocks
+pub const MAX_PRIORITY_BLOCKS: usize = 4;
+/// Bit of entry of the table of high priority blocks which marks unpinned block
+pub const PRIORITY_RELEASED: BlockCount = 1 << 63;
+
+pub(crate) const BLOCK_BEGIN: usize = 0;
+
+pub(crate) const VERSION_BEGIN: usize = BLOCK_BEGIN;
+pub(crate) const VERSION_LEN: usize = core::mem::size_of::<Version>();
+pub(crate) const VERSION_END: usize = VERSION_BEGIN + VERSION_LEN;
+
+pub(crate) const MAGIC_BEGIN: usize = VERSION_END;
+pub(crate) const MAGIC_LEN: usize = core::mem::size_of::<Magic>();
+pub(crate) const MAGIC_END: usize = MAGIC_BEGIN + MAGIC_LEN;
+
+pub(crate) const BLOCK_COUNT_BEGIN: usize = MAGIC_END;
+pub(crate) const BLOCK_COUNT_LEN: usize = core::mem::size_of::<BlockCount>();
+pub(crate) const BLOCK_COUNT_END: usize = BLOCK_COUNT_BEGIN + BLOCK_COUNT_LEN;
+
+pub(crate) const WRITES_BEGIN: usize = BLOCK_COUNT_END;
+pub(crate) const WRITES_LEN: usize = core::mem::size_of::<WriteCount>();
+pub(crate) const WRITES_END: usize = WRITES_BEGIN + WRITES_LEN;
+
+pub(crate) const CRC_MODE_BEGIN: usize = WRITES_END;
+pub(crate) const CRC_MODE_LEN: usize = core::mem::size_of::<CrcModeId>();
+pub(crate) const CRC_MODE_END: usize = CRC_MODE_BEGIN + CRC_MODE_LEN;
+
+pub(crate) const ID_HIGH_WATER_BEGIN: usize = CRC_MODE_END;
+pub(crate) const ID_HIGH_WATER_LEN: usize = core::mem::size_of::<BlockId>();
+pub(crate) const ID_HIGH_WATER_END: usize = ID_HIGH_WATER_BEGIN + ID_HIGH_WATER_LEN;
+
+pub(crate) const BOOT_TIME_BEGIN: usize = ID_HIGH_WATER_END;
+pub(crate) const BOOT_TIME_LEN: usize = core::mem::size_of::<Timestamp>();
+pub(crate) const BOOT_TIME_END: usize = BOOT_TIME_BEGIN + BOOT_TIME_LEN;
+
+pub(crate) const BOOT_COUNT_BEGIN: usize = BOOT_TIME_END;
+pub(crate) const BOOT_COUNT_LEN: usize = core::mem::size_of::<BootCount>();
+pub(crate) const BOOT_COUNT_END: usize = BOOT_COUNT_BEGIN + BOOT_COUNT_LEN;
+
+pub(crate) const BAD_BLOCKS_BEGIN: usize = BOOT_COUNT_END;
+pub(crate) const BAD_BLOCK_LEN: usize = core::mem::size_of::<BlockCount>();
+pub(crate) const BAD_BLOCKS_LEN: usize = BAD_BLOCK_LEN * MAX_BAD_BLOCKS;
+pub(crate) const BAD_BLOCKS_END: usize = BAD_BLOCKS_BEGIN + BAD_BLOCKS_LEN;
+
+pub(crate) const CRC_FAILURES_BEGIN: usize = BAD_BLOCKS_END;
+pub(crate) const CRC_FAILURES_LEN: usize = core::mem::size_of::<HealthCount>();
+pub(crate) const CRC_FAILURES_END: usize = CRC_FAILURES_BEGIN + CRC_FAILURES_LEN;
+
+pub(crate) const WRITE_RETRIES_BEGIN: usize = CRC_FAILURES_END;
+pub(crate) const WRITE_RETRIES_LEN: usize = core::mem::size_of::<HealthCount>();
+pub(crate) const WRITE_RETRIES_END: usize = WRITE_RETRIES_BEGIN + WRITE_RETRIES_LEN;
+
+pub(crate) const REMAPPED_BLOCKS_BEGIN: usize = WRITE_RETRIES_END;
+pub(crate) const REMAPPED_BLOCKS_LEN: usize = core::mem::size_of::<HealthCount>();
+pub(crate) const REMAPPED_BLOCKS_END: usize = REMAPPED_BLOCKS_BEGIN + REMAPPED_BLOCKS_LEN;
+
+pub(crate) const RESERVED_BLOCKS_BEGIN: usize = REMAPPED_BLOCKS_END;
+pub(crate) const RESERVED_BLOCKS_LEN: usize = core::mem::size_of::<BlockCount>();
+pub(crate) const RESERVED_BLOCKS_END: usize = RESERVED_BLOCKS_BEGIN + RESERVED_BLOCKS_LEN;
+
+pub(crate) const QUARANTINE_BEGIN: usize = RESERVED_BLOCKS_END;
+pub(crate) const QUARANTINE_ENTRY_LEN: usize = core::mem::size_of::<BlockCount>();
+pub(crate) const QUARANTINE_LEN: usize = QUARANTINE_ENTRY_LEN * MAX_QUARANTINED;
+pub(crate) const QUARANTINE_END: usize = QUARANTINE_BEGIN + QUARANTINE_LEN;
+
+pub(crate) const FS_ID_BEGIN: usize = QUARANTINE_END;
+pub(crate) const FS_ID_LEN: usize = core::mem::size_of::<FsId>();
+pub(crate) const FS_ID_END: usize = FS_ID_BEGIN + FS_ID_LEN;
+
+pub(crate) const FRONT_ID_BEGIN: usize = FS_ID_END;
+pub(crate) const FRONT_ID_LEN: usize = core::mem::size_of::<BlockId>();
+pub(crate) const FRONT_ID_END: usize = FRONT_ID_BEGIN + FRONT_ID_LEN;
+
+pub(crate) const PRIORITY_BEGIN: usize = FRONT_ID_END;
+pub(crate) const PRIORITY_ENTRY_LEN: usize = core::mem::size_of::<BlockCount>();
+pub(crate) const PRIORITY_LEN: usize = PRIORITY_ENTRY_LEN * MAX_PRIORITY_BLOCKS;
+pub(crate) const PRIORITY_END: usize = PRIORITY_BEGIN + PRIORITY_LEN;
+
+pub(crate) const BLOCK_END: usize = PRIORITY_END;
+pub(crate) const BLOCK_LEN: usize = BLOCK_END - BLOCK_BEGIN;
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsConfigBlock {
+    pub version: Version,
+    pub magic: Magic,
+    /// Number of blocks used by filesystem including config blocks, 0 if unknown
+    pub block_count: BlockCount,
+    /// Number of block writes to the storage range by previous filesystems (before the last format)
+    pub writes_before_format: WriteCount,
+    /// Id of [`crate::block::CrcMode`] of data blocks, `HEADER_V2_FLAG` marks v2 header, `WIDE_FS_ID_FLAG`
+    /// marks wide fs id
+    pub crc_mode: CrcModeId,
+    /// Block ids below it could be used by data blocks, 0 if ids are not reserved
+    pub id_high_water: BlockId,
+    /// Timestamp of the latest `set_time_source`, see [`crate::time`]
+    pub boot_time: Timestamp,
+    /// Number of mounts, see [`crate::fs::Filesystem::boot_count`]
+    pub boot_count: BootCount,
+    /// Storage indexes of bad blocks plus 1, 0 is empty entry, see [`crate::fs::Filesystem::mark_bad`]
+    pub bad_blocks: [BlockCount; MAX_BAD_BLOCKS],
+    /// Blocks with wrong crc seen by reads and scrub, see [`crate::fs::Filesystem::health`]
+    pub crc_failures: HealthCount,
+    /// Appends written again to the next block after write failure, see [`crate::fs::Filesystem::health`]
+    pub write_retries: HealthCount,
+    /// Blocks marked as bad, see [`crate::fs::Filesystem::health`]
+    pub remapped_blocks: HealthCount,
+    /// Blocks after config blocks which aren't used by filesystem, see [`crate::fs::Filesystem::reserved_blocks`]
+    pub reserved_blocks: BlockCount,
+    /// Storage indexes of damaged blocks plus 1, 0 is empty entry, see [`crate::fs::Filesystem::set_quarantine`]
+    pub quarantine: [BlockCount; MAX_QUARANTINED],
+    /// Id of filesystem with `WIDE_FS_ID_FLAG`, 0 for narrow id which is stored in block header only
+    pub fs_id: FsId,
+    /// Id of the first block available for read, see [`crate::fs::Filesystem::truncate_front`]
+    pub front_id: BlockId,
+    /// Storage indexes of high priority blocks plus 1, 0 is empty entry, `PRIORITY_RELEASED` marks unpinned
+    /// block, see [`crate::fs::Filesystem::append_with_priority`]
+    pub priority: [BlockCount; MAX_PRIORITY_BLOCKS],
+}
+
+impl FsConfigBlock {
+    pub fn new() -> FsConfigBlock {
+        FsConfigBlock {
+            version: FS_VERSION,
+            magic: FS_MAGIC,
+            block_count: 0,
+            writes_before_format: 0,
+            crc_mode: 0,
+            id_high_water: 0,
+            boot_time: 0,
+            boot_count: 0,
+            bad_blocks: [0; MAX_BAD_BLOCKS],
+            crc_failures: 0,
+            write_retries: 0,
+            remapped_blocks: 0,
+            reserved_blocks: 0,
+            quarantine: [0; MAX_QUARANTINED],
+            fs_id: 0,
+            front_id: 0,
+            priority: [0; MAX_PRIORITY_BLOCKS],
+        }
+    }
+
+    /// Config contains fields after version, see `FIELDS_SINCE_VERSION`
+    pub fn has_fields(&self) -> bool {
+        self.version >= FIELDS_SINCE_VERSION
+    }
+
+    /// Check config was written by appendfs, config blocks without magic are valid only for version 0x1
+    pub fn is_valid(&self) -> bool {
+        if self.has_fields() {
+            self.magic == FS_MAGIC
+        } else {
+            self.version != 0
+        }
+    }
+
+    /// Can be as member method
+    /// implemented it as non member method to be aligned with to_be_bytes method in other types
+    pub fn to_be_bytes(config: &FsConfigBlock) -> [u8; BLOCK_LEN] {
+        let mut buf = [0_u8; BLOCK_LEN];
+
+        config.write_version(&mut buf);
+        config.write_magic(&mut buf);
+        config.write_block_count(&mut buf);
+        config.write_writes_before_format(&mut buf);
+        config.write_crc_mode(&mut buf);
+        config.write_id_high_water(&mut buf);
+        config.write_boot_time(&mut buf);
+        config.write_boot_count(&mut buf);
+        config.write_bad_blocks(&mut buf);
+        config.write_crc_failures(&mut buf);
+        config.write_write_retries(&mut buf);
+        config.write_remapped_blocks(&mut buf);
+        config.write_reserved_blocks(&mut buf);
+        config.write_quarantine(&mut buf);
+        config.write_fs_id(&mut buf);
+        config.write_front_id(&mut buf);
+        config.write_priority(&mut buf);
+
+        buf
+    }
+
+    fn write_version(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let version = self.version.to_be_bytes();
+        buf[VERSION_BEGIN..VERSION_END].copy_from_slice(&version[..]);
+    }
+
+    fn write_magic(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let magic = self.magic.to_be_bytes();
+        buf[MAGIC_BEGIN..MAGIC_END].copy_from_slice(&magic[..]);
+    }
+
+    fn write_block_count(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let block_count = self.block_count.to_be_bytes();
+        buf[BLOCK_COUNT_BEGIN..BLOCK_COUNT_END].copy_from_slice(&block_count[..]);
+    }
+
+    fn write_writes_before_format(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let writes = self.writes_before_format.to_be_bytes();
+        buf[WRITES_BEGIN..WRITES_END].copy_from_slice(&writes[..]);
+    }
+
+    fn write_crc_mode(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let crc_mode = self.crc_mode.to_be_bytes();
+        buf[CRC_MODE_BEGIN..CRC_MODE_END].copy_from_slice(&crc_mode[..]);
+    }
+
+    fn write_id_high_water(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let id_high_water = self.id_high_water.to_be_bytes();
+        buf[ID_HIGH_WATER_BEGIN..ID_HIGH_WATER_END].copy_from_slice(&id_high_water[..]);
+    }
+
+    fn write_boot_time(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let boot_time = self.boot_time.to_be_bytes();
+        buf[BOOT_TIME_BEGIN..BOOT_TIME_END].copy_from_slice(&boot_time[..]);
+    }
+
+    fn write_boot_count(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let boot_count = self.boot_count.to_be_bytes();
+        buf[BOOT_COUNT_BEGIN..BOOT_COUNT_END].copy_from_slice(&boot_count[..]);
+    }
+
+    fn write_bad_blocks(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let table = &mut buf[BAD_BLOCKS_BEGIN..BAD_BLOCKS_END];
+        for (dst, bad_block) in table.chunks_exact_mut(BAD_BLOCK_LEN).zip(self.bad_blocks) {
+            dst.copy_from_slice(&bad_block.to_be_bytes());
+        }
+    }
+
+    fn write_crc_failures(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let crc_failures = self.crc_failures.to_be_bytes();
+        buf[CRC_FAILURES_BEGIN..CRC_FAILURES_END].copy_from_slice(&crc_failures[..]);
+    }
+
+    fn write_write_retries(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let write_retries = self.write_retries.to_be_bytes();
+        buf[WRITE_RETRIES_BEGIN..WRITE_RETRIES_END].copy_from_slice(&write_retries[..]);
+    }
+
+    fn write_remapped_blocks(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let remapped_blocks = self.remapped_blocks.to_be_bytes();
+        buf[REMAPPED_BLOCKS_BEGIN..REMAPPED_BLOCKS_END].copy_from_slice(&remapped_blocks[..]);
+    }
+
+    fn write_reserved_blocks(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let reserved_blocks = self.reserved_blocks.to_be_bytes();
+        buf[RESERVED_BLOCKS_BEGIN..RESERVED_BLOCKS_END].copy_from_slice(&reserved_blocks[..]);
+    }
+
+    fn write_quarantine(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let table = &mut buf[QUARANTINE_BEGIN..QUARANTINE_END];
+        for (dst, entry) in table
+            .chunks_exact_mut(QUARANTINE_ENTRY_LEN)
+            .zip(self.quarantine)
+        {
+            dst.copy_from_slice(&entry.to_be_bytes());
+        }
+    }
+
+    fn write_fs_id(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let fs_id = self.fs_id.to_be_bytes();
+        buf[FS_ID_BEGIN..FS_ID_END].copy_from_slice(&fs_id[..]);
+    }
+
+    fn write_front_id(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let front_id = self.front_id.to_be_bytes();
+        buf[FRONT_ID_BEGIN..FRONT_ID_END].copy_from_slice(&front_id[..]);
+    }
+
+    fn write_priority(&self, buf: &mut [u8; BLOCK_LEN]) {
+        let table = &mut buf[PRIORITY_BEGIN..PRIORITY_END];
+        for (dst, entry) in table
+            .chunks_exact_mut(PRIORITY_ENTRY_LEN)
+            .zip(self.priority)
+        {
+            dst.copy_from_slice(&entry.to_be_bytes());
+        }
+    }
+
+    /// Length of `bytes` without trailing zeros, only this part has to be written
+    pub fn used_len(bytes: &[u8; BLOCK_LEN]) -> usize {
+        bytes.iter().rposition(|b| *b != 0).map_or(0, |pos| pos + 1)
+    }
+
+    pub fn from_be_bytes(block: [u8; BLOCK_LEN]) -> FsConfigBlock {
+        let mut config: FsConfigBlock = FsConfigBlock::default();
+        config.read_version(&block);
+        config.read_magic(&block);
+        config.read_block_count(&block);
+        config.read_writes_before_format(&block);
+        config.read_crc_mode(&block);
+        config.read_id_high_water(&block);
+        config.read_boot_time(&block);
+        config.read_boot_count(&block);
+        config.read_bad_blocks(&block);
+        config.read_crc_failures(&block);
+        config.read_write_retries(&block);
+        config.read_remapped_blocks(&block);
+        config.read_reserved_blocks(&block);
+        config.read_quarantine(&block);
+        config.read_fs_id(&block);
+        config.read_front_id(&block);
+        config.read_priority(&block);
+
+        config
+    }
+
+    /// Parse config from data part of the block, `None` if data is too short to contain magic,
+    /// fields which don't fit to data are read as 0
+    pub fn from_block_data(data: &[u8]) -> Option<FsConfigBlock> {
+        let data = data.get(BLOCK_BEGIN..)?;
+        if data.len() < MAGIC_END {
+            return None;
+        }
+        let mut block = [0_u8; BLOCK_LEN];
+        let len = data.len().min(BLOCK_LEN);
+        block[..len].copy_from_slice(&data[..len]);
+        Some(Self::from_be_bytes(block))
+    }
+
+    fn read_version(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; VERSION_LEN];
+        buf[..].copy_from_slice(&block[VERSION_BEGIN..VERSION_END]);
+        self.version = Version::from_be_bytes(buf);
+    }
+
+    fn read_magic(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; MAGIC_LEN];
+        buf[..].copy_from_slice(&block[MAGIC_BEGIN..MAGIC_END]);
+        self.magic = Magic::from_be_bytes(buf);
+    }
+
+    fn read_block_count(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; BLOCK_COUNT_LEN];
+        buf[..].copy_from_slice(&block[BLOCK_COUNT_BEGIN..BLOCK_COUNT_END]);
+        self.block_count = BlockCount::from_be_bytes(buf);
+    }
+
+    fn read_writes_before_format(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; WRITES_LEN];
+        buf[..].copy_from_slice(&block[WRITES_BEGIN..WRITES_END]);
+        self.writes_before_format = WriteCount::from_be_bytes(buf);
+    }
+
+    fn read_crc_mode(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; CRC_MODE_LEN];
+        buf[..].copy_from_slice(&block[CRC_MODE_BEGIN..CRC_MODE_END]);
+        self.crc_mode = CrcModeId::from_be_bytes(buf);
+    }
+
+    fn read_id_high_water(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; ID_HIGH_WATER_LEN];
+        buf[..].copy_from_slice(&block[ID_HIGH_WATER_BEGIN..ID_HIGH_WATER_END]);
+        self.id_high_water = BlockId::from_be_bytes(buf);
+    }
+
+    fn read_boot_time(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; BOOT_TIME_LEN];
+        buf[..].copy_from_slice(&block[BOOT_TIME_BEGIN..BOOT_TIME_END]);
+        self.boot_time = Timestamp::from_be_bytes(buf);
+    }
+
+    fn read_boot_count(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; BOOT_COUNT_LEN];
+        buf[..].copy_from_slice(&block[BOOT_COUNT_BEGIN..BOOT_COUNT_END]);
+        self.boot_count = BootCount::from_be_bytes(buf);
+    }
+
+    fn read_bad_blocks(&mut self, block: &[u8; BLOCK_LEN]) {
+        let table = &block[BAD_BLOCKS_BEGIN..BAD_BLOCKS_END];
+        for (src, bad_block) in table.chunks_exact(BAD_BLOCK_LEN).zip(&mut self.bad_blocks) {
+            let mut buf = [0_u8; BAD_BLOCK_LEN];
+            buf[..].copy_from_slice(src);
+            *bad_block = BlockCount::from_be_bytes(buf);
+        }
+    }
+
+    fn read_crc_failures(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; CRC_FAILURES_LEN];
+        buf[..].copy_from_slice(&block[CRC_FAILURES_BEGIN..CRC_FAILURES_END]);
+        self.crc_failures = HealthCount::from_be_bytes(buf);
+    }
+
+    fn read_write_retries(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; WRITE_RETRIES_LEN];
+        buf[..].copy_from_slice(&block[WRITE_RETRIES_BEGIN..WRITE_RETRIES_END]);
+        self.write_retries = HealthCount::from_be_bytes(buf);
+    }
+
+    fn read_remapped_blocks(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; REMAPPED_BLOCKS_LEN];
+        buf[..].copy_from_slice(&block[REMAPPED_BLOCKS_BEGIN..REMAPPED_BLOCKS_END]);
+        self.remapped_blocks = HealthCount::from_be_bytes(buf);
+    }
+
+    fn read_reserved_blocks(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; RESERVED_BLOCKS_LEN];
+        buf[..].copy_from_slice(&block[RESERVED_BLOCKS_BEGIN..RESERVED_BLOCKS_END]);
+        self.reserved_blocks = BlockCount::from_be_bytes(buf);
+    }
+
+    fn read_quarantine(&mut self, block: &[u8; BLOCK_LEN]) {
+        let table = &block[QUARANTINE_BEGIN..QUARANTINE_END];
+        for (src, entry) in table
+            .chunks_exact(QUARANTINE_ENTRY_LEN)
+            .zip(&mut self.quarantine)
+        {
+            let mut buf = [0_u8; QUARANTINE_ENTRY_LEN];
+            buf[..].copy_from_slice(src);
+            *entry = BlockCount::from_be_bytes(buf);
+        }
+    }
+
+    fn read_fs_id(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; FS_ID_LEN];
+        buf[..].copy_from_slice(&block[FS_ID_BEGIN..FS_ID_END]);
+        self.fs_id = FsId::from_be_bytes(buf);
+    }
+
+    fn read_front_id(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut buf = [0_u8; FRONT_ID_LEN];
+        buf[..].copy_from_slice(&block[FRONT_ID_BEGIN..FRONT_ID_END]);
+        self.front_id = BlockId::from_be_bytes(buf);
+    }
+
+    fn read_priority(&mut self, block: &[u8; BLOCK_LEN]) {
+        let table = &block[PRIORITY_BEGIN..PRIORITY_END];
+        for (src, entry) in table
+            .chunks_exact(PRIORITY_ENTRY_LEN)
+            .zip(&mut self.priority)
+        {
+            let mut buf = [0_u8; PRIORITY_ENTRY_LEN];
+            buf[..].copy_from_slice(src);
+            *entry = BlockCount::from_be_bytes(buf);
+        }
+    }
+}
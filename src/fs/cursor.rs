@@ -0,0 +1,86 @@
+//! Position of a consumer of blocks (e.g. uploader or publisher), see [`Cursor`].
+
+use super::Filesystem;
+use crate::block::{BlockId, BlockLayout};
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+
+/// Id of the next block to consume. Consumer reads the block with `peek`, processes it (e.g. publishes it) and
+/// `commit`s it when processing is confirmed (e.g. acknowledged by broker). Persist `next_blk_id` after commit
+/// (e.g. to a file or [`super::kv::KvOverlay`]) and create cursor with it after restart, so blocks are not lost
+/// and only not committed ones are processed again. Blocks overwritten or damaged before they were consumed
+/// are counted in `missed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    next_id: BlockId,
+    missed: BlockId,
+}
+
+impl Cursor {
+    pub const fn new(next_id: BlockId) -> Self {
+        Self { next_id, missed: 0 }
+    }
+
+    /// Id of the next block to consume, persist it after `commit`
+    pub fn next_blk_id(&self) -> BlockId {
+        self.next_id
+    }
+
+    /// Blocks which were overwritten or invalid before they were consumed
+    pub fn missed(&self) -> BlockId {
+        self.missed
+    }
+
+    /// `reader` gets id and data of the next block, returns its id or `None` in case there is no new block
+    /// (call `refresh` of read-only filesystem to find blocks of another writer). Cursor doesn't move till `commit`.
+    pub fn peek<S, const BS: usize, L, B, F>(
+        &mut self,
+        fs: &mut Filesystem<'_, S, BS, L, B>,
+        reader: F,
+    ) -> Result<Option<BlockId>, Error>
+    where
+        S: Storage,
+        L: BlockLayout,
+        B: AsMut<[u8]>,
+        F: FnOnce(BlockId, &[u8]),
+    {
+        let mut reader = Some(reader);
+        loop {
+            let oldest = fs.oldest_blk_id();
+            if self.next_id < oldest {
+                log!(
+                    warn,
+                    "Blocks {}..{} were not consumed",
+                    self.next_id,
+                    oldest
+                );
+                self.missed += oldest - self.next_id;
+                self.next_id = oldest;
+            }
+            let Some(blk_offset) = fs.blk_offset_of(self.next_id) else {
+                return Ok(None);
+            };
+
+            let id = self.next_id;
+            match fs.read(blk_offset, |data| {
+                if let Some(reader) = reader.take() {
+                    reader(id, data);
+                }
+            }) {
+                Ok(_) => return Ok(Some(id)),
+                Err(Error::NotValidBlockForRead | Error::Lapped) => {
+                    log!(warn, "Block {} is invalid, it's skipped", id);
+                    self.missed += 1;
+                    self.next_id += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Block `id` (and all blocks before it) is consumed
+    pub fn commit(&mut self, id: BlockId) {
+        self.next_id = self.next_id.max(id + 1);
+    }
+}
@@ -0,0 +1,140 @@
+//! Stage appended blocks in RAM and write them as one burst, see [`GroupCommitFs`].
+
+use super::{AppendedBlock, Filesystem};
+use crate::block::BlockLayout;
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+use crate::time::{TimeSource, Timestamp};
+
+/// Staged blocks are written when `blocks` are staged or the oldest staged block waits `max_delay` milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitThresholds {
+    pub blocks: usize,
+    pub max_delay: Timestamp,
+}
+
+/// Wrapper of filesystem which keeps up to `N` appended blocks in RAM and writes them back to back followed by
+/// one storage sync (e.g. fsync of SD card), so throughput is much higher than with sync of every block.
+/// Staged blocks are lost on power cut, loss window is bounded by [`CommitThresholds`].
+pub struct GroupCommitFs<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    const N: usize,
+    L: BlockLayout,
+    T: TimeSource,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    time: T,
+    thresholds: CommitThresholds,
+    staged: [[u8; BS]; N],
+    staged_count: usize,
+    /// Ticks of the oldest staged block
+    staged_at: Timestamp,
+}
+
+impl<
+        'f,
+        'a,
+        S: Storage,
+        const BS: usize,
+        const N: usize,
+        L: BlockLayout,
+        T: TimeSource,
+        B: AsMut<[u8]>,
+    > GroupCommitFs<'f, 'a, S, BS, N, L, T, B>
+{
+    /// `time` ticks are used to measure `max_delay` of `thresholds`, `blocks` threshold is limited by `N`
+    pub fn new(
+        fs: &'f mut Filesystem<'a, S, BS, L, B>,
+        time: T,
+        thresholds: CommitThresholds,
+    ) -> Self {
+        Self {
+            fs,
+            time,
+            thresholds,
+            staged: [[0_u8; BS]; N],
+            staged_count: 0,
+            staged_at: 0,
+        }
+    }
+
+    /// Stage block filled by `writer` (see [`Filesystem::append`]), returns the last written block in case
+    /// threshold is passed and staged blocks are written, `None` in case block is only staged
+    pub fn append<F>(&mut self, writer: F) -> Result<Option<AppendedBlock>, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if N == 0 {
+            return self.fs.append(writer).map(Some);
+        }
+        if self.staged_count == N {
+            self.flush()?;
+        }
+
+        let data_size = self.fs.data_size();
+        writer(&mut self.staged[self.staged_count][..data_size]);
+        if self.staged_count == 0 {
+            self.staged_at = self.time.ticks();
+        }
+        self.staged_count += 1;
+
+        self.poll()
+    }
+
+    /// Write staged blocks in case threshold is passed, call it periodically so staged blocks don't wait
+    /// for the next append longer than `max_delay`
+    pub fn poll(&mut self) -> Result<Option<AppendedBlock>, Error> {
+        if self.staged_count == 0 {
+            return Ok(None);
+        }
+
+        let waited = self.time.ticks().saturating_sub(self.staged_at);
+        if self.staged_count >= self.thresholds.blocks.min(N) || waited >= self.thresholds.max_delay
+        {
+            return self.flush();
+        }
+
+        Ok(None)
+    }
+
+    /// Write all staged blocks and sync the storage (see [`Filesystem::barrier`]), returns the last written
+    /// block, `None` if nothing was staged. Blocks which were not written because of error stay staged.
+    pub fn flush(&mut self) -> Result<Option<AppendedBlock>, Error> {
+        let data_size = self.fs.data_size();
+        let mut last = None;
+        for i in 0..self.staged_count {
+            let data = &self.staged[i][..data_size];
+            match self.fs.append(|blk_data| blk_data.copy_from_slice(data)) {
+                Ok(blk) => last = Some(blk),
+                Err(e) => {
+                    self.staged.copy_within(i..self.staged_count, 0);
+                    self.staged_count -= i;
+                    return Err(e);
+                }
+            }
+        }
+
+        if self.staged_count > 0 {
+            log!(trace, "Group commit of {} blocks", self.staged_count);
+            self.staged_count = 0;
+            self.fs.barrier(false)?;
+        }
+
+        Ok(last)
+    }
+
+    /// Number of blocks kept in RAM, they are not written yet
+    pub fn staged(&self) -> usize {
+        self.staged_count
+    }
+
+    /// Staged blocks are dropped, `flush` them before
+    pub fn into_inner(self) -> &'f mut Filesystem<'a, S, BS, L, B> {
+        self.fs
+    }
+}
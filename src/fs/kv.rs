@@ -0,0 +1,261 @@
+//! Key-value overlay on records of a filesystem (e.g. device settings and event log on one flash), see [`KvOverlay`].
+//!
+//! Key-value record is `[KV_RECORD_TAG, key_len, key.., value..]`, the latest record of a key wins.
+
+use super::record::{RecordStream, RecordWriter, CHUNK_HEADER_LEN};
+use super::Filesystem;
+use crate::block::{BlockId, BlockLayout};
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+
+/// The first byte of key-value records, other records must begin with another byte
+pub const KV_RECORD_TAG: u8 = 0xFF;
+/// The longest key
+pub const MAX_KEY_LEN: usize = 16;
+
+const KV_HEADER_LEN: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct KeyEntry {
+    key: [u8; MAX_KEY_LEN],
+    key_len: usize,
+    /// Block where the latest record of the key begins
+    blk_id: BlockId,
+}
+
+impl KeyEntry {
+    const EMPTY: Self = Self {
+        key: [0_u8; MAX_KEY_LEN],
+        key_len: 0,
+        blk_id: 0,
+    };
+
+    fn key(&self) -> &[u8] {
+        &self.key[..self.key_len]
+    }
+}
+
+/// Key and value of key-value record
+fn parse(record: &[u8]) -> Option<(&[u8], &[u8])> {
+    match record {
+        [KV_RECORD_TAG, key_len, rest @ ..] if (*key_len as usize) <= rest.len() => {
+            Some(rest.split_at(*key_len as usize))
+        }
+        _ => None,
+    }
+}
+
+/// Write key-value record to the beginning of `record`, returns its length
+fn encode(record: &mut [u8], key: &[u8], value: &[u8]) -> usize {
+    let value_begin = KV_HEADER_LEN + key.len();
+    record[0] = KV_RECORD_TAG;
+    record[1] = key.len() as u8;
+    record[KV_HEADER_LEN..value_begin].copy_from_slice(key);
+    record[value_begin..value_begin + value.len()].copy_from_slice(value);
+    value_begin + value.len()
+}
+
+/// Key-value records and other records packed to blocks by [`RecordWriter`]. Location of the latest record of
+/// up to `N` keys is kept in RAM (it's found by scan of all records on `new`), records are up to `R` bytes.
+/// When the ring wraps, the latest records of keys which are in blocks about to be overwritten are written again,
+/// so keys are never lost while event log rotates.
+pub struct KvOverlay<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    const R: usize,
+    const N: usize,
+    L: BlockLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    writer: RecordWriter<'f, 'a, S, BS, L, B>,
+    keys: [KeyEntry; N],
+    len: usize,
+}
+
+impl<
+        'f,
+        'a,
+        S: Storage,
+        const BS: usize,
+        const R: usize,
+        const N: usize,
+        L: BlockLayout,
+        B: AsMut<[u8]>,
+    > KvOverlay<'f, 'a, S, BS, R, N, L, B>
+{
+    /// Scan records of `fs` to find the latest record of every key
+    pub fn new(fs: &'f mut Filesystem<'a, S, BS, L, B>) -> Result<Self, Error> {
+        let mut keys = [KeyEntry::EMPTY; N];
+        let mut len = 0;
+        let mut stream = RecordStream::<_, BS, R, L, B>::new(&mut *fs);
+        while let Some(record) = stream.next_record()? {
+            let Some((key, _)) = parse(record) else {
+                continue;
+            };
+            if key.is_empty() || key.len() > MAX_KEY_LEN {
+                continue;
+            }
+            let mut entry = KeyEntry::EMPTY;
+            entry.key[..key.len()].copy_from_slice(key);
+            entry.key_len = key.len();
+            entry.blk_id = stream.record_blk_id();
+
+            match keys[..len].iter().position(|e| e.key() == entry.key()) {
+                Some(i) => keys[i] = entry,
+                None if len < N => {
+                    keys[len] = entry;
+                    len += 1;
+                }
+                None => return Err(Error::KeyTableIsFull),
+            }
+        }
+        log!(debug, "Found {} keys", len);
+
+        Ok(Self {
+            writer: RecordWriter::new(fs),
+            keys,
+            len,
+        })
+    }
+
+    /// `reader` gets the latest value of `key`, returns `false` in case key is not set
+    pub fn get<F>(&mut self, key: &[u8], reader: F) -> Result<bool, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        match self.position(key) {
+            Some(i) => self.read_entry(i, |_, value| reader(value)),
+            None => Ok(false),
+        }
+    }
+
+    /// Write record with the new `value` of `key`, the block is appended (with other pending records) right away
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(Error::InvalidKey);
+        }
+        let record_len = KV_HEADER_LEN + key.len() + value.len();
+        if record_len > R {
+            return Err(Error::TooSmallBuffer);
+        }
+        if self.position(key).is_none() && self.len == N {
+            return Err(Error::KeyTableIsFull);
+        }
+
+        self.compact(record_len)?;
+        let mut record = [0_u8; R];
+        encode(&mut record, key, value);
+        let blk_id = self.write_kv(&record[..record_len])?;
+
+        let i = self.position(key).unwrap_or_else(|| {
+            self.keys[self.len] = KeyEntry::EMPTY;
+            self.keys[self.len].key[..key.len()].copy_from_slice(key);
+            self.keys[self.len].key_len = key.len();
+            self.len += 1;
+            self.len - 1
+        });
+        self.keys[i].blk_id = blk_id;
+
+        Ok(())
+    }
+
+    /// Call `f` with every key which is set
+    pub fn iter_keys<F>(&self, mut f: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        self.keys[..self.len].iter().for_each(|e| f(e.key()));
+    }
+
+    /// Write record which isn't key-value record (e.g. event), it's appended with the next full block or `flush`
+    pub fn append_record(&mut self, record: &[u8]) -> Result<(), Error> {
+        if record.first() == Some(&KV_RECORD_TAG) {
+            return Err(Error::ReservedRecordTag);
+        }
+
+        self.compact(record.len())?;
+        self.writer.write(record)
+    }
+
+    /// Append the block with pending records
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+
+    /// Write again the latest records of keys which would be overwritten by writing `len` bytes
+    fn compact(&mut self, len: usize) -> Result<(), Error> {
+        // every rewrite moves one key to the newest block, ring which can't keep all keys never settles
+        for _ in 0..=2 * N {
+            let pending = self.writer.pending();
+            let fs = self.writer.fs();
+            let chunk_size = fs.data_size().saturating_sub(CHUNK_HEADER_LEN).max(1);
+            let appended = (pending + len) / chunk_size + 2;
+            let free = fs.capacity().saturating_sub(fs.used_blocks());
+            let horizon = fs.oldest_blk_id() + appended.saturating_sub(free) as BlockId;
+            let Some(i) = self.keys[..self.len]
+                .iter()
+                .position(|e| e.blk_id < horizon)
+            else {
+                return Ok(());
+            };
+
+            let mut record = [0_u8; R];
+            let mut record_len = 0;
+            let found = self.read_entry(i, |key, value| {
+                record_len = encode(&mut record, key, value);
+            })?;
+            if found {
+                log!(debug, "Rewrite key at {}", self.keys[i].blk_id);
+                self.keys[i].blk_id = self.write_kv(&record[..record_len])?;
+            } else {
+                log!(error, "Record of key at {} is lost", self.keys[i].blk_id);
+                self.keys.copy_within(i + 1..self.len, i);
+                self.len -= 1;
+            }
+        }
+
+        Err(Error::TooSmallFilesystem)
+    }
+
+    /// Write and flush key-value record, returns id of the block where it begins
+    fn write_kv(&mut self, record: &[u8]) -> Result<BlockId, Error> {
+        // record begins in the pending block, it's appended with the next id
+        let blk_id = self.writer.fs().next_blk_id();
+        self.writer.write(record)?;
+        self.writer.flush()?;
+
+        Ok(blk_id)
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        self.keys[..self.len].iter().position(|e| e.key() == key)
+    }
+
+    /// `reader` gets key and value of the latest record of key `i`, `false` in case it isn't valid anymore
+    fn read_entry<F>(&mut self, i: usize, reader: F) -> Result<bool, Error>
+    where
+        F: FnOnce(&[u8], &[u8]),
+    {
+        let entry = self.keys[i];
+        let mut stream =
+            RecordStream::<_, BS, R, L, B>::from_blk_id(self.writer.fs(), entry.blk_id);
+        while let Some(record) = stream.next_record()? {
+            // records of other keys can be in the same block
+            match parse(record) {
+                Some((key, value)) if key == entry.key() => {
+                    reader(key, value);
+                    return Ok(true);
+                }
+                _ => {}
+            }
+            if stream.record_blk_id() > entry.blk_id {
+                break;
+            }
+        }
+
+        Ok(false)
+    }
+}
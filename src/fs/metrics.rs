@@ -0,0 +1,58 @@
+//! Counters of filesystem internals for telemetry of the application, set sink with
+//! [`super::Filesystem::set_metrics`]. Counters are reported as increments without formatting, so they are
+//! cheap compared to `logging` feature.
+
+use core::fmt;
+
+/// Counter incremented by the filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// Blocks written by `append` and its variants
+    Appends,
+    /// Reads which failed with `NotValidBlockForRead` or `Lapped`
+    ReadErrors,
+    /// Blocks with wrong crc seen by reads and scrub, see [`super::Health`]
+    CrcFailures,
+    /// Appends written again to the next block after write failure
+    WriteRetries,
+    /// Blocks marked as bad
+    RemappedBlocks,
+    /// Filesystem attributes loaded from the storage by `refresh` and `set_pins` (mount happens before
+    /// the sink is set)
+    Restores,
+}
+
+impl Counter {
+    /// Name of the counter for telemetry, e.g. `appends`
+    pub const fn name(self) -> &'static str {
+        match self {
+            Counter::Appends => "appends",
+            Counter::ReadErrors => "read_errors",
+            Counter::CrcFailures => "crc_failures",
+            Counter::WriteRetries => "write_retries",
+            Counter::RemappedBlocks => "remapped_blocks",
+            Counter::Restores => "restores",
+        }
+    }
+}
+
+/// Receiver of counter increments, the default implementation ignores them
+pub trait MetricsSink {
+    fn add(&mut self, _counter: Counter, _value: u64) {}
+}
+
+/// Sink which ignores all counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {}
+
+pub(crate) struct Metrics<'a> {
+    pub(crate) sink: &'a mut (dyn MetricsSink + Send),
+}
+
+impl fmt::Debug for Metrics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
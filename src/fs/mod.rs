@@ -0,0 +1,6662 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::block::{
+    fields, Block, BlockFactory, BlockId, BlockInfo, BlockLayout, CrcMode, DefaultLayout, FsId,
+    HeaderV2, HeaderVersion, RepeatCount, SeqNum, MAX_BLOCK_ID, MAX_METADATA_LEN, NARROW_FS_ID_MAX,
+};
+use crate::block::{header_extension_len, is_wide_fs_id};
+use crate::error::Error;
+use crate::logging::{log, target};
+use crate::storage::ram::RamStorage;
+use crate::storage::slice::SliceStorage;
+use crate::storage::{IoCounters, SharedRead, StaticGeometry, Storage};
+use crate::time::{Clock, TimeSource, Timestamp};
+use crate::utils::trim_block_idx_with_wraparound;
+
+pub mod builder;
+pub mod config_block;
+pub mod cursor;
+pub mod group_commit;
+pub mod kv;
+pub mod metrics;
+pub mod observer;
+pub mod record;
+pub mod sizing;
+pub mod throttle;
+
+#[cfg(all(test, feature = "std"))]
+mod model_tests;
+mod pins;
+mod quarantine;
+mod scrub;
+mod transaction;
+mod truncate;
+
+pub use pins::{Pin, Priority};
+pub use scrub::ScrubReport;
+
+use builder::FilesystemBuilder;
+use metrics::{Counter, Metrics, MetricsSink};
+use observer::{FsObserver, Observer};
+
+/// How storage is mounted by `with_buffer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MountMode {
+    /// Storage of another filesystem isn't formatted (`ForeignFilesystem`)
+    ReadWrite,
+    /// Storage of another filesystem is formatted
+    Force,
+    /// Nothing is written, see `open_readonly`
+    ReadOnly,
+}
+
+/// Parameters of new filesystem used by `with_buffer` in case storage is formatted,
+/// restored filesystem keeps parameters stored in its config block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FormatOptions {
+    crc_mode: CrcMode,
+    header: HeaderVersion,
+    /// Blocks after config block which aren't used by filesystem
+    reserved: usize,
+}
+
+impl FormatOptions {
+    const fn new(crc_mode: CrcMode) -> Self {
+        Self {
+            crc_mode,
+            header: HeaderVersion::V1,
+            reserved: 0,
+        }
+    }
+}
+
+/// Valid copy of config block found by restore
+#[derive(Debug)]
+struct ConfigCopy {
+    fs_id: FsId,
+    /// It's stored in block id field of the header, the newest copy has the biggest one
+    generation: BlockId,
+    /// Number of config blocks of the filesystem, 1 for version 0x1
+    copies: usize,
+    config: config_block::FsConfigBlock,
+}
+
+/// Callbacks of restore scan given to constructor, they are kept for `refresh`
+#[derive(Debug, Default)]
+struct MountHooks<'a> {
+    progress: Option<MountCallback<'a>>,
+    trail: Option<RestoreTrail<'a>>,
+}
+
+/// Maximum block size supported by [`DynFilesystem`]
+pub const MAX_BLOCK_SIZE: usize = 4096;
+
+/// Filesystem with block size chosen at runtime by the storage, it can be any size up to [`MAX_BLOCK_SIZE`].
+pub type DynFilesystem<'a, S> = Filesystem<'a, S, MAX_BLOCK_SIZE>;
+
+/// [`DynFilesystem`] over storage chosen at runtime (e.g. file or network), see `impl Storage for &mut dyn Storage`.
+pub type DynStorageFilesystem<'a, 's> = DynFilesystem<'a, &'s mut dyn Storage>;
+
+/// `BS` is the size of internal buffer, block size of the storage must not exceed it.
+/// `L` describes custom metadata stored in every block, see [`BlockLayout`].
+/// `B` is internal buffer, it's embedded array by default, see [`Filesystem::new_with_buffer`].
+#[derive(Debug)]
+pub struct Filesystem<
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout = DefaultLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    storage: &'a mut S,
+    id: FsId,
+    offset: usize,
+    blk_factory: BlockFactory,
+    is_empty: bool,
+    is_full: bool,
+    observer: Option<Observer<'a>>,
+    metrics: Option<Metrics<'a>>,
+    pins: &'a mut [Option<Pin>],
+    /// Blocks of `append_with_priority` excluded from the ring, it's stored in config block
+    priority: [Option<Pin>; config_block::MAX_PRIORITY_BLOCKS],
+    /// Storage indexes of blocks excluded from the ring, it's stored in config block
+    bad_blocks: [Option<usize>; config_block::MAX_BAD_BLOCKS],
+    /// Storage indexes of damaged blocks, they become bad when write head reaches them, it's stored in config block
+    quarantine: [Option<usize>; config_block::MAX_QUARANTINED],
+    /// Damaged blocks found by reads and scrub are quarantined
+    quarantine_enabled: bool,
+    /// Error counters, they are stored in config block
+    health: Health,
+    writes_before_format: config_block::WriteCount,
+    run_writes: config_block::WriteCount,
+    /// Logical appends since mount, see `write_amplification`
+    run_appends: config_block::WriteCount,
+    /// Counters of erase-aware storage after mount
+    io_at_mount: Option<IoCounters>,
+    last_append_io: IoCounters,
+    front_id: BlockId,
+    in_tx: bool,
+    /// Newest blocks of not committed transaction, they are not available for read
+    uncommitted: usize,
+    crc_mode: CrcMode,
+    header: HeaderVersion,
+    read_policy: ReadPolicy,
+    /// Number of blocks after config blocks which aren't used by filesystem, it's stored in config block
+    reserved: usize,
+    /// Number of config blocks before reserved blocks, 1 for filesystem of version 0x1
+    config_copies: usize,
+    /// Generation of the newest copy of config block
+    config_generation: BlockId,
+    /// Mounted with `open_readonly`, nothing is written to the storage
+    read_only: bool,
+    /// Number of ids reserved by config block at once, 0 if reservation is disabled
+    id_reserve: BlockId,
+    id_high_water: BlockId,
+    /// Id of the next block checked by `scrub`
+    scrub_id: BlockId,
+    /// Timestamp at ticks 0 of time source, it's stored in config block
+    boot_time: Timestamp,
+    boot_count: config_block::BootCount,
+    clock: Option<Clock<'a>>,
+    progress: Option<MountCallback<'a>>,
+    trail: Option<RestoreTrail<'a>>,
+    buffer: B,
+    layout: PhantomData<fn() -> L>,
+}
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout> Filesystem<'a, S, BS, L> {
+    // will create new filesystem or restore previous in case previous one has the same fs_id,
+    // storage of another filesystem isn't formatted (`ForeignFilesystem`), see `new_with_force`
+    pub fn new(storage: &'a mut S, fs_id: FsId) -> Result<Self, Error> {
+        Self::builder(storage).fs_id(fs_id).build()
+    }
+
+    /// Builder of filesystem with options of constructors and setters, see [`FilesystemBuilder`]
+    pub fn builder(storage: &'a mut S) -> FilesystemBuilder<'a, S, BS, L> {
+        FilesystemBuilder::new(storage)
+    }
+
+    /// Same as `new`, storage is formatted with `crc_mode` (see [`CrcMode`]),
+    /// restored filesystem keeps crc mode it was formatted with
+    pub fn new_with_crc_mode(
+        storage: &'a mut S,
+        fs_id: FsId,
+        crc_mode: CrcMode,
+    ) -> Result<Self, Error> {
+        Self::builder(storage)
+            .fs_id(fs_id)
+            .crc_mode(crc_mode)
+            .build()
+    }
+
+    /// Same as `new_with_crc_mode`, with `force` storage formatted with another fs id is formatted
+    /// instead of returning `ForeignFilesystem`, its blocks are left in storage (see `recover_blocks`)
+    pub fn new_with_force(
+        storage: &'a mut S,
+        fs_id: FsId,
+        crc_mode: CrcMode,
+        force: bool,
+    ) -> Result<Self, Error> {
+        Self::builder(storage)
+            .fs_id(fs_id)
+            .crc_mode(crc_mode)
+            .force(force)
+            .build()
+    }
+
+    /// Same as `new_with_crc_mode`, data blocks of new filesystem get `header` (see [`HeaderVersion`]),
+    /// restored filesystem keeps header version it was formatted with, so v1 rings stay readable
+    pub fn new_with_header(
+        storage: &'a mut S,
+        fs_id: FsId,
+        crc_mode: CrcMode,
+        header: HeaderVersion,
+    ) -> Result<Self, Error> {
+        Self::builder(storage)
+            .fs_id(fs_id)
+            .crc_mode(crc_mode)
+            .header(header)
+            .build()
+    }
+
+    /// Same as `new_with_crc_mode`, `reserved` blocks after config block are left to the user (e.g. bootloader
+    /// scratch or device identity page), filesystem never touches them, see `read_reserved` and `write_reserved`.
+    /// Number of reserved blocks is stored in config block, restored filesystem keeps number it was formatted with.
+    pub fn new_with_reserved(
+        storage: &'a mut S,
+        fs_id: FsId,
+        crc_mode: CrcMode,
+        reserved: usize,
+    ) -> Result<Self, Error> {
+        Self::builder(storage)
+            .fs_id(fs_id)
+            .crc_mode(crc_mode)
+            .reserved(reserved)
+            .build()
+    }
+
+    /// Restore filesystem from storage, use fs_id from config block as id for the filesystem
+    pub fn restore(storage: &'a mut S) -> Result<Self, Error> {
+        Self::builder(storage).build()
+    }
+
+    /// Same as `restore`, number of boots stored in config block is incremented (see `boot_count`), mount the
+    /// filesystem with it once per power cycle by the application which owns the storage
+    pub fn mount_for_write(storage: &'a mut S) -> Result<Self, Error> {
+        Self::builder(storage).count_boot(true).build()
+    }
+
+    /// Mount filesystem for reading while another process (or instance) appends to it: nothing is written
+    /// to the storage (writes fail with `ReadOnly`), `refresh` finds blocks appended by the writer. Block
+    /// overwritten by the writer after mount or `refresh` fails read with `Lapped` instead of returning data
+    /// of newer generation, `refresh` and continue from `oldest_blk_id` in this case.
+    pub fn open_readonly(storage: &'a mut S) -> Result<Self, Error> {
+        Self::builder(storage).read_only(true).build()
+    }
+
+    /// Same as `restore`, `progress` is called after every block read by restore scan (e.g. to feed watchdog
+    /// or show progress bar during mount of big storage) and by later `refresh`
+    pub fn restore_with_progress(
+        storage: &'a mut S,
+        progress: &'a mut (dyn FnMut(MountProgress) + Send),
+    ) -> Result<Self, Error> {
+        Self::builder(storage).progress(progress).build()
+    }
+
+    /// Same as `restore`, `trail` is called with every block read by restore scan and every decision of
+    /// the search (see [`RestoreStep`]), also by later `refresh`. Attach the trail to reports of restore
+    /// which found wrong newest block.
+    pub fn restore_with_trail(
+        storage: &'a mut S,
+        trail: &'a mut (dyn FnMut(RestoreStep) + Send),
+    ) -> Result<Self, Error> {
+        Self::builder(storage).trail(trail).build()
+    }
+
+    /// Same as `restore`, used blocks are checked according to `validation`, in case some of them are
+    /// not valid `MountValidationFailed` is returned (restore it and use `find_first_invalid_offset`
+    /// with `truncate_tail` to drop damaged blocks)
+    pub fn restore_with_validation(
+        storage: &'a mut S,
+        validation: MountValidation,
+    ) -> Result<Self, Error> {
+        Self::builder(storage).validation(validation).build()
+    }
+
+    /// Restore filesystem, in case storage isn't formatted (`InvalidHeaderBlock`), create new one with id
+    /// returned by `id_source` (e.g. `generate_fs_id`), `policy` defines what happens to blocks of other
+    /// filesystems left in storage. Storage with damaged config blocks (`DamagedConfig`) isn't formatted,
+    /// blocks with the new id (e.g. id derived from hardware) aren't wiped.
+    pub fn restore_or_format<F>(
+        storage: &'a mut S,
+        id_source: F,
+        policy: FormatPolicy,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce() -> FsId,
+    {
+        let fs_id = match Self::restored_fs_id(storage) {
+            Ok(fs_id) => fs_id,
+            Err(Error::InvalidHeaderBlock) => {
+                let fs_id = id_source();
+                log!(info, "Fs can't be restored, format with id {}", fs_id);
+                fs_id
+            }
+            Err(e) => return Err(e),
+        };
+
+        Self::builder(storage)
+            .fs_id(fs_id)
+            .format_policy(policy)
+            .build()
+    }
+}
+
+impl<'a, S: StaticGeometry, const BS: usize, L: BlockLayout> Filesystem<'a, S, BS, L> {
+    /// Geometry of the storage is valid for the filesystem, it's evaluated by compiler
+    const GEOMETRY_IS_VALID: () = {
+        assert!(S::BLOCK_SIZE <= BS, "Block size of the storage exceeds BS");
+        assert!(
+            S::BLOCK_SIZE > Self::DATA_BEGIN + CrcMode::Split.trailer_len(),
+            "Block size of the storage doesn't fit block header"
+        );
+        assert!(
+            S::BLOCK_COUNT >= 2 + config_block::CONFIG_COPIES,
+            "Ring must have at least 2 data blocks after config blocks"
+        );
+    };
+
+    /// Same as `new`, invalid combination of `BS` and geometry of the storage fails compilation
+    /// instead of returning error
+    pub fn new_checked(storage: &'a mut S, fs_id: FsId) -> Result<Self, Error> {
+        let () = Self::GEOMETRY_IS_VALID;
+        Self::new(storage, fs_id)
+    }
+
+    /// Same as `restore`, see `new_checked`
+    pub fn restore_checked(storage: &'a mut S) -> Result<Self, Error> {
+        let () = Self::GEOMETRY_IS_VALID;
+        Self::restore(storage)
+    }
+}
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    pub const BLOCK_SIZE: usize = BS;
+    /// Offset of data in a block, custom metadata is before it
+    const DATA_BEGIN: usize = {
+        assert!(
+            L::METADATA_LEN <= MAX_METADATA_LEN,
+            "Too big block metadata"
+        );
+        fields::DATA_BEGIN + L::METADATA_LEN
+    };
+
+    /// Same as `new`, `buffer` (at least block size) is used instead of embedded array, so size of the filesystem
+    /// doesn't depend on block size (e.g. one static scratch buffer on MCU). `BS` still limits block size and size
+    /// of temporary buffers on stack.
+    pub fn new_with_buffer(storage: &'a mut S, fs_id: FsId, buffer: B) -> Result<Self, Error> {
+        FilesystemBuilder::<S, BS, L>::new(storage)
+            .buffer(buffer)
+            .fs_id(fs_id)
+            .build()
+    }
+
+    /// Same as `restore`, see `new_with_buffer`
+    pub fn restore_with_buffer(storage: &'a mut S, buffer: B) -> Result<Self, Error> {
+        FilesystemBuilder::<S, BS, L>::new(storage)
+            .buffer(buffer)
+            .build()
+    }
+
+    /// Check used blocks according to `validation`, see `restore_with_validation`
+    fn validate_used(&mut self, validation: MountValidation) -> Result<(), Error> {
+        let checked = match validation {
+            MountValidation::Fast => 0,
+            MountValidation::Newest(count) => count.min(self.used_blocks()),
+            MountValidation::Full => self.used_blocks(),
+        };
+
+        let begin = self.used_blocks() - checked;
+        if let Some(blk_offset) = self.first_invalid_offset_from(begin)? {
+            log!(error, "Block at offset {} is not valid", blk_offset);
+            return Err(Error::MountValidationFailed);
+        }
+
+        Ok(())
+    }
+
+    fn with_buffer(
+        storage: &'a mut S,
+        fs_id: FsId,
+        format: FormatOptions,
+        mut buffer: B,
+        hooks: MountHooks<'a>,
+        mode: MountMode,
+    ) -> Result<Self, Error> {
+        Self::validate_block_size(storage)?;
+        if buffer.as_mut().len() < storage.block_size() {
+            return Err(Error::TooSmallBuffer);
+        }
+        if mode == MountMode::ReadWrite {
+            match Self::restored_fs_id(storage) {
+                Ok(found_fs_id) if found_fs_id != fs_id => {
+                    log!(error, "Storage is formatted with fs id {}", found_fs_id);
+                    return Err(Error::ForeignFilesystem { found_fs_id });
+                }
+                Ok(_) | Err(Error::InvalidHeaderBlock) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut fs = Filesystem {
+            storage,
+            id: fs_id,
+            offset: 0,
+            blk_factory: BlockFactory::new(),
+            is_empty: true,
+            is_full: false,
+            observer: None,
+            metrics: None,
+            pins: &mut [],
+            priority: [None; config_block::MAX_PRIORITY_BLOCKS],
+            bad_blocks: [None; config_block::MAX_BAD_BLOCKS],
+            quarantine: [None; config_block::MAX_QUARANTINED],
+            quarantine_enabled: false,
+            health: Health::default(),
+            writes_before_format: 0,
+            run_writes: 0,
+            run_appends: 0,
+            io_at_mount: None,
+            last_append_io: IoCounters::default(),
+            front_id: 0,
+            in_tx: false,
+            uncommitted: 0,
+            crc_mode: format.crc_mode,
+            header: format.header,
+            read_policy: ReadPolicy::Verify,
+            reserved: format.reserved,
+            config_copies: config_block::CONFIG_COPIES,
+            config_generation: 0,
+            read_only: mode == MountMode::ReadOnly,
+            id_reserve: 0,
+            id_high_water: 0,
+            scrub_id: 0,
+            boot_time: 0,
+            boot_count: 0,
+            clock: None,
+            progress: hooks.progress,
+            trail: hooks.trail,
+            buffer,
+            layout: PhantomData,
+        };
+        fs.init()?;
+        // blocks of transaction which is still open by the writer are not available for reader
+        if !fs.read_only {
+            fs.drop_uncommitted()?;
+        }
+        fs.io_at_mount = fs.storage.io_counters();
+        #[cfg(feature = "strict_invariants")]
+        fs.check_invariants();
+
+        Ok(fs)
+    }
+
+    /// Transaction interrupted by restart will never be committed, its blocks are dropped by the writer
+    fn drop_uncommitted(&mut self) -> Result<(), Error> {
+        if self.uncommitted > 0 {
+            log!(
+                info,
+                "Drop {} blocks of not committed transaction",
+                self.uncommitted
+            );
+            self.truncate_tail(self.used_blocks())?;
+        }
+        Ok(())
+    }
+
+    /// Increment persisted number of mounts, config block of small blocks has no space for it
+    fn count_boot(&mut self) -> Result<(), Error> {
+        let config_len = self.storage.block_size() - fields::DATA_BEGIN;
+        if config_len < config_block::BOOT_COUNT_END {
+            log!(warn, "Block is too small to count boots");
+            return Ok(());
+        }
+
+        self.boot_count = self.boot_count.wrapping_add(1);
+        log!(debug, "Boot {}", self.boot_count);
+        self.write_config()
+    }
+
+    /// Number of writer mounts (`mount_for_write` or builder with `count_boot`) of the filesystem including
+    /// the current one, it's stored in config block (rewritten by every such mount), write it to application
+    /// records to distinguish power cycles. Other constructors don't change it. It's 0 in case block is too small
+    /// for config with the counter. Config of filesystem of version 0x1 isn't rewritten, its counter is kept
+    /// only in RAM.
+    pub fn boot_count(&self) -> config_block::BootCount {
+        self.boot_count
+    }
+
+    /// Id of the filesystem from the newest copy of config block
+    fn restored_fs_id(storage: &mut S) -> Result<FsId, Error> {
+        Self::validate_block_size(storage)?;
+        let buf = &mut [0_u8; BS];
+        let buf = &mut buf[..storage.block_size()];
+        let begin = storage.min_block_index();
+        let end = storage.max_block_index();
+        let newest = Self::newest_config(begin, end, buf, |blk_idx, buf| {
+            storage.read(blk_idx, buf)?;
+            Ok(BlockInfo::<BS>::from_buffer(buf))
+        })?;
+        let Some(newest) = newest else {
+            // blocks of the filesystem are still in storage, format would lose them
+            if Self::has_damaged_config(storage, buf)? {
+                log!(target: target::INIT, error, "Config blocks are damaged");
+                return Err(Error::DamagedConfig);
+            }
+            return Err(Error::InvalidHeaderBlock);
+        };
+
+        log!(target: target::INIT, debug, "Restore config: {:?}", newest);
+        log!(target: target::INIT, info, "Restore storage with fs id: {}", newest.fs_id);
+        Ok(newest.fs_id)
+    }
+
+    /// Find the newest valid copy of config block, `read` reads block at storage index to the buffer
+    /// and parses its header. Copy which isn't valid (e.g. torn write) is skipped.
+    fn newest_config<R>(
+        begin: usize,
+        end: usize,
+        buf: &mut [u8],
+        mut read: R,
+    ) -> Result<Option<ConfigCopy>, Error>
+    where
+        R: FnMut(usize, &mut [u8]) -> Result<BlockInfo<BS>, Error>,
+    {
+        let copies = config_block::CONFIG_COPIES;
+        let mut newest: Option<ConfigCopy> = None;
+        for blk_idx in begin..end.min(begin.saturating_add(copies)) {
+            let info = read(blk_idx, buf)?;
+            let config =
+                match config_block::FsConfigBlock::from_block_data(&buf[fields::DATA_BEGIN..]) {
+                    Some(config) if info.is_valid && config.is_valid() => config,
+                    config => {
+                        log!(
+                            target: target::INIT,
+                            debug,
+                            "Block at {} is not appendfs config: {:?}",
+                            blk_idx,
+                            config
+                        );
+                        continue;
+                    }
+                };
+
+            if !config.has_fields() {
+                // config of version 0x1 is the only one, data blocks are after it
+                if blk_idx == begin {
+                    return Ok(Some(ConfigCopy {
+                        fs_id: Self::fs_id_of(&config, info.fs_id),
+                        generation: 0,
+                        copies: 1,
+                        config,
+                    }));
+                }
+                continue;
+            }
+            // copy of generation `g` is at `begin + g % CONFIG_COPIES`
+            if info.id % copies as BlockId != (blk_idx - begin) as BlockId {
+                continue;
+            }
+            if newest.as_ref().is_none_or(|n| info.id > n.generation) {
+                newest = Some(ConfigCopy {
+                    fs_id: Self::fs_id_of(&config, info.fs_id),
+                    generation: info.id,
+                    copies,
+                    config,
+                });
+            }
+        }
+
+        Ok(newest)
+    }
+
+    /// Set observer to get notified about filesystem events, `watermarks` are percents of capacity
+    /// to call [`FsObserver::on_watermark`] for (e.g. `&[80, 100]`).
+    /// Observer must be `Send` to keep filesystem `Send`.
+    pub fn set_observer(
+        &mut self,
+        observer: &'a mut (dyn FsObserver + Send),
+        watermarks: &'a [u8],
+    ) {
+        self.observer = Some(Observer {
+            observer,
+            watermarks,
+        });
+    }
+
+    /// Remove observer set with `set_observer`
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Set sink of counters of filesystem internals (appends, read errors, retries, restores), see
+    /// [`metrics::Counter`]. Sink must be `Send` to keep filesystem `Send`.
+    pub fn set_metrics(&mut self, sink: &'a mut (dyn MetricsSink + Send)) {
+        self.metrics = Some(Metrics { sink });
+    }
+
+    /// Remove sink set with `set_metrics`
+    pub fn clear_metrics(&mut self) {
+        self.metrics = None;
+    }
+
+    fn count(&mut self, counter: Counter) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.sink.add(counter, 1);
+        }
+    }
+
+    /// Reload filesystem attributes from the storage, use it to find blocks appended by another writer.
+    /// Blocks of transaction which is not committed by another writer yet are not available for read.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        self.init()?;
+        self.count(Counter::Restores);
+        #[cfg(feature = "strict_invariants")]
+        self.check_invariants();
+        Ok(())
+    }
+
+    /// Scan all storage blocks for appendfs config blocks, `on_found` is called for every found filesystem,
+    /// scan stops when `on_found` returns false. Returns number of found filesystems.
+    /// Only config blocks with magic (version 2 and later) can be found, both copies of config are reported
+    /// as one filesystem.
+    pub fn scan_for_fs<F>(storage: &mut S, mut on_found: F) -> Result<usize, Error>
+    where
+        F: FnMut(FsCandidate) -> bool,
+    {
+        Self::validate_block_size(storage)?;
+        let buf = &mut [0_u8; BS];
+        let buf = &mut buf[..storage.block_size()];
+
+        let mut found = 0;
+        let mut prev_begin = None;
+        for blk_idx in storage.min_block_index()..storage.max_block_index() {
+            storage.read(blk_idx, buf)?;
+            let block = Block::<BS>::from_buffer(buf);
+            if !block.is_valid() {
+                continue;
+            }
+
+            let config =
+                match config_block::FsConfigBlock::from_block_data(&buf[fields::DATA_BEGIN..]) {
+                    Some(c) if c.has_fields() && c.is_valid() => c,
+                    _ => continue,
+                };
+
+            // copy of generation `g` is `g % CONFIG_COPIES` blocks after the beginning of the filesystem
+            let copy = (block.id() % config_block::CONFIG_COPIES as BlockId) as usize;
+            let Some(begin_block) = blk_idx.checked_sub(copy) else {
+                continue;
+            };
+            if prev_begin == Some(begin_block) {
+                continue;
+            }
+            prev_begin = Some(begin_block);
+
+            log!(debug, "Found fs config at {}: {:?}", blk_idx, config);
+            found += 1;
+            let end_block = match config.block_count {
+                0 => None,
+                count => Some(begin_block + count as usize),
+            };
+            let candidate = FsCandidate {
+                begin_block,
+                end_block,
+                fs_id: Self::fs_id_of(&config, block.fs_id()),
+                version: config.version,
+            };
+            if !on_found(candidate) {
+                break;
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Count valid blocks of every fs id found in the storage, use it to detect that storage still holds
+    /// data of another filesystem (e.g. of previous device) before formatting it.
+    /// Every `step`-th block is checked, so big storage can be sampled (step 1 checks all blocks).
+    /// Counts are collected to `ids`, blocks of ids which don't fit to `ids` are counted as `untracked_blocks`.
+    /// Width of foreign fs id is unknown, only low bits of wide ids are counted (see [`NARROW_FS_ID_MAX`]).
+    pub fn count_fs_ids(
+        storage: &mut S,
+        step: usize,
+        ids: &mut [FsIdCount],
+    ) -> Result<FsIdsReport, Error> {
+        Self::validate_block_size(storage)?;
+        let buf = &mut [0_u8; BS];
+        let buf = &mut buf[..storage.block_size()];
+
+        let mut report = FsIdsReport::default();
+        for blk_idx in (storage.min_block_index()..storage.max_block_index()).step_by(step.max(1)) {
+            storage.read(blk_idx, buf)?;
+            report.checked_blocks += 1;
+            let info = Self::foreign_block_info(buf);
+            if !info.is_valid {
+                continue;
+            }
+            report.valid_blocks += 1;
+
+            let capacity = ids.len();
+            match ids[..report.ids_found]
+                .iter_mut()
+                .find(|c| c.fs_id == info.fs_id)
+            {
+                Some(count) => count.blocks += 1,
+                None if report.ids_found < capacity => {
+                    log!(debug, "Found fs id {} at {}", info.fs_id, blk_idx);
+                    ids[report.ids_found] = FsIdCount {
+                        fs_id: info.fs_id,
+                        blocks: 1,
+                    };
+                    report.ids_found += 1;
+                }
+                None => report.untracked_blocks += 1,
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read data blocks of filesystem with `fs_id` (e.g. of the filesystem which was accidentally reformatted
+    /// with another id) from the oldest block to the newest one, blocks overwritten by other filesystem are skipped.
+    /// `reader` gets block id and block data (v2 header fields are the beginning of it, header version of lost
+    /// filesystem is unknown), reading stops when it returns false. Returns number of read blocks.
+    /// Blocks are matched by low bits of wide `fs_id`.
+    pub fn recover_blocks<F>(storage: &mut S, fs_id: FsId, mut reader: F) -> Result<usize, Error>
+    where
+        F: FnMut(BlockId, &[u8]) -> bool,
+    {
+        Self::validate_block_size(storage)?;
+        let buf = &mut [0_u8; BS];
+        let buf = &mut buf[..storage.block_size()];
+
+        // config block is always first, data blocks (or the second copy of config) are after it
+        let begin = storage
+            .min_block_index()
+            .checked_add(1)
+            .ok_or(Error::ArithmeticOverflow)?;
+        let end = storage.max_block_index();
+        if end <= begin {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        // ids are increasing along the ring, so the oldest block is the one with min id
+        let mut oldest = None;
+        for blk_idx in begin..end {
+            storage.read(blk_idx, buf)?;
+            let info = Self::foreign_block_info(buf);
+            if !info.is_valid || info.fs_id != fs_id & NARROW_FS_ID_MAX || Self::is_config_copy(buf)
+            {
+                continue;
+            }
+            match oldest {
+                Some((_, id)) if id <= info.id => {}
+                _ => oldest = Some((blk_idx, info.id)),
+            }
+        }
+
+        let Some((oldest_idx, _)) = oldest else {
+            log!(debug, "No blocks with fs id {}", fs_id);
+            return Ok(0);
+        };
+
+        let mut read = 0;
+        for blk_idx in (oldest_idx..end).chain(begin..oldest_idx) {
+            storage.read(blk_idx, buf)?;
+            let info = Self::foreign_block_info(buf);
+            if !info.is_valid || info.fs_id != fs_id & NARROW_FS_ID_MAX || Self::is_config_copy(buf)
+            {
+                continue;
+            }
+
+            read += 1;
+            if !reader(info.id, &buf[Self::DATA_BEGIN..]) {
+                break;
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// Config block with magic fails crc check (e.g. bit rot), no copy of config is valid
+    fn has_damaged_config(storage: &mut S, buf: &mut [u8]) -> Result<bool, Error> {
+        let begin = storage.min_block_index();
+        let end = storage.max_block_index();
+        for blk_idx in begin..end.min(begin.saturating_add(config_block::CONFIG_COPIES)) {
+            storage.read(blk_idx, buf)?;
+            let info = BlockInfo::<BS>::from_buffer(buf);
+            if !info.is_valid && Self::is_config_copy(buf) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Block is copy of config with magic, e.g. the second copy after the first config block
+    fn is_config_copy(buf: &[u8]) -> bool {
+        config_block::FsConfigBlock::from_block_data(&buf[fields::DATA_BEGIN..])
+            .is_some_and(|config| config.has_fields() && config.is_valid())
+    }
+
+    /// Crc mode and header of filesystem with another id are unknown, block with valid header crc of v1 or v2
+    /// header (custom metadata of `L` or without it) is treated as valid
+    fn foreign_block_info(buf: &[u8]) -> BlockInfo<BS> {
+        let info = BlockInfo::<BS>::from_buffer(buf);
+        if info.is_valid {
+            return info;
+        }
+
+        let metadata_lens = [0, L::METADATA_LEN];
+        let header_ends = [
+            (HeaderVersion::V1, false),
+            (HeaderVersion::V2, false),
+            (HeaderVersion::V1, true),
+            (HeaderVersion::V2, true),
+        ]
+        .into_iter()
+        .flat_map(|(header, wide_fs_id)| {
+            metadata_lens
+                .into_iter()
+                .map(move |len| fields::DATA_BEGIN + header_extension_len(header, wide_fs_id) + len)
+        });
+        for header_end in header_ends {
+            let info =
+                BlockInfo::<BS>::from_buffer_with_header_end(buf, CrcMode::HeaderOnly, header_end);
+            if info.is_valid {
+                return info;
+            }
+        }
+
+        info
+    }
+
+    fn validate_block_size(storage: &S) -> Result<(), Error> {
+        let blk_len = storage.block_size();
+        if blk_len > BS {
+            log!(
+                error,
+                "Storage block size {} exceeds max block size {}",
+                blk_len,
+                BS
+            );
+            return Err(Error::BlockSizeMismatch {
+                block_size: blk_len,
+                max_block_size: BS,
+            });
+        }
+        // data crc of split crc mode takes the end of the block
+        if blk_len <= Self::DATA_BEGIN + CrcMode::Split.trailer_len() {
+            log!(error, "Storage block size {} is too small", blk_len);
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        Ok(())
+    }
+
+    fn setup_attributes(
+        &mut self,
+        next_offset: usize,
+        next_id: BlockId,
+        is_empty: bool,
+        is_full: bool,
+    ) {
+        log!(
+            target: target::INIT,
+            debug,
+            "Setup fs attributes, offset: {:?}, block_id: {:?}, is_empty: {:?}, is_full: {:?}",
+            next_offset,
+            next_id,
+            is_empty,
+            is_full
+        );
+        self.offset = next_offset;
+        self.blk_factory.set_id(next_id);
+        self.is_empty = is_empty;
+        self.is_full = is_full;
+    }
+
+    /// Write new block, `writer` fills its data. Returns id and storage index of written block.
+    pub fn append<F>(&mut self, writer: F) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        self.append_with_metadata(|metadata, data| {
+            metadata.fill(0);
+            writer(data);
+        })
+    }
+
+    /// Return only after all appended blocks are durable (e.g. ack record to the radio only when it's on flash):
+    /// storage is synced with [`Storage::sync`], with `verify` the newest block is read back and checked,
+    /// damaged block fails the barrier with `BarrierFailed`. Blocks of open transaction are synced too.
+    pub fn barrier(&mut self, verify: bool) -> Result<(), Error> {
+        self.storage.sync()?;
+
+        let written = self.used_blocks() + self.uncommitted;
+        if !verify || written == 0 {
+            return Ok(());
+        }
+
+        let blk_idx = self.blk_idx_of(written - 1)?;
+        let info = self.read_info(blk_idx)?;
+        if !info.is_valid || info.fs_id != self.id {
+            log!(
+                error,
+                "The newest block at {} is not valid after sync",
+                blk_idx
+            );
+            return Err(Error::BarrierFailed);
+        }
+
+        Ok(())
+    }
+
+    /// Set source of block timestamps, see [`crate::time`]. Timestamps are stored in v2 header (see
+    /// [`HeaderV2::timestamp`]), filesystem with v1 header returns `UnsupportedHeaderVersion`. In case wall clock
+    /// is known, timestamps are milliseconds since unix epoch, otherwise they continue from timestamp of the newest
+    /// block (or previous boot time), so they never go back after restart. Boot time (timestamp at ticks 0)
+    /// is stored in config block.
+    pub fn set_time_source(
+        &mut self,
+        source: &'a mut (dyn TimeSource + Send),
+    ) -> Result<(), Error> {
+        if self.header != HeaderVersion::V2 {
+            return Err(Error::UnsupportedHeaderVersion);
+        }
+
+        let ticks = source.ticks();
+        let boot_time = match source.wall_clock() {
+            Some(now) => now.saturating_sub(ticks),
+            None => {
+                let newest = match self.used_blocks() {
+                    0 => 0,
+                    used => self.blk_timestamp(used - 1)?,
+                };
+                self.boot_time.max(newest)
+            }
+        };
+        self.clock = Some(Clock { source });
+
+        if boot_time != self.boot_time {
+            log!(debug, "Boot time {}", boot_time);
+            self.boot_time = boot_time;
+            self.write_config()?;
+        }
+
+        Ok(())
+    }
+
+    /// Timestamp of the next append, `None` without time source
+    pub fn now(&mut self) -> Option<Timestamp> {
+        let boot_time = self.boot_time;
+        let clock = self.clock.as_mut()?;
+        Some(boot_time.saturating_add(clock.source.ticks()))
+    }
+
+    /// Timestamp at ticks 0 of the latest time source, 0 if time source was never set
+    pub fn boot_time(&self) -> Timestamp {
+        self.boot_time
+    }
+
+    /// Offsets of blocks with timestamps in `from..to`
+    fn time_window(&mut self, from: Timestamp, to: Timestamp) -> Result<Range<usize>, Error> {
+        let begin = self.first_blk_at(from)?;
+        let end = self.first_blk_at(to)?.max(begin);
+        Ok(begin..end)
+    }
+
+    /// Offset of the first block appended at `timestamp` or later, timestamps of blocks are not decreasing
+    fn first_blk_at(&mut self, timestamp: Timestamp) -> Result<usize, Error> {
+        let mut begin = 0;
+        let mut end = self.used_blocks();
+        while begin < end {
+            let mid = begin + (end - begin) / 2;
+            if self.blk_timestamp(mid)? < timestamp {
+                begin = mid + 1;
+            } else {
+                end = mid;
+            }
+        }
+
+        Ok(begin)
+    }
+
+    fn blk_timestamp(&mut self, blk_offset: usize) -> Result<Timestamp, Error> {
+        let blk_idx = self.blk_idx_of(blk_offset)?;
+        let info = self.read_info(blk_idx)?;
+        if !info.is_valid || info.fs_id != self.id {
+            return Err(Error::NotValidBlockForRead);
+        }
+
+        Ok(info.header.map_or(0, |header| header.timestamp))
+    }
+
+    /// Persist block ids high-water mark in config block: ids are reserved by `chunk` (e.g. 1024), config block
+    /// is rewritten when all reserved ids are used. In case the newest blocks are damaged (e.g. torn write),
+    /// restore continues ids from the high-water mark, so ids are never reused even if damaged blocks were
+    /// already seen by a reader. Skipped ids are reported by `find_gaps` and `oldest_blk_id` is approximate till
+    /// blocks before the gap are overwritten. 0 disables reservation, high-water mark stays in config block.
+    pub fn set_id_reserve(&mut self, chunk: BlockId) {
+        self.id_reserve = chunk;
+    }
+
+    /// Block ids below it could be used, it's stored in config block, see `set_id_reserve`
+    pub fn id_high_water(&self) -> BlockId {
+        self.id_high_water
+    }
+
+    fn reserve_ids(&mut self) -> Result<(), Error> {
+        // ids above MAX_BLOCK_ID can't be stored, no need to reserve them
+        self.id_high_water = self
+            .next_blk_id()
+            .saturating_add(self.id_reserve)
+            .min(MAX_BLOCK_ID + 1);
+        log!(debug, "Reserve block ids till {}", self.id_high_water);
+        self.write_config()
+    }
+
+    /// Same as `append`, `writer` also fills custom metadata of the block, see [`BlockLayout`]
+    pub fn append_with_metadata<F>(&mut self, writer: F) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8], &mut [u8]),
+    {
+        let header = match self.header {
+            HeaderVersion::V1 => None,
+            HeaderVersion::V2 => Some(HeaderV2 {
+                length: u16::try_from(self.data_size()).unwrap_or(u16::MAX),
+                ..HeaderV2::default()
+            }),
+        };
+        self.append_block(header, writer)
+    }
+
+    /// Same as `append`, `header` is written to v2 header of the block (`timestamp` 0 is replaced by
+    /// time of append in case filesystem has time source). Filesystem with v1 header returns
+    /// `UnsupportedHeaderVersion`, `HeaderV2::FLAG_REPEAT` and `FLAG_SEQ` are rejected with `ReservedFlag`.
+    pub fn append_with_header<F>(
+        &mut self,
+        header: HeaderV2,
+        writer: F,
+    ) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if self.header != HeaderVersion::V2 {
+            return Err(Error::UnsupportedHeaderVersion);
+        }
+        if header.flags & HeaderV2::RESERVED_FLAGS != 0 {
+            return Err(Error::ReservedFlag);
+        }
+
+        self.append_block(Some(header), |metadata, data| {
+            metadata.fill(0);
+            writer(data);
+        })
+    }
+
+    /// Same as `append`, `flags` are written to v2 header of the block (see `HeaderV2::FLAG_CRITICAL`), readers
+    /// get them from [`BlockInfo::flags`] without spending data bytes. Filesystem with v1 header returns
+    /// `UnsupportedHeaderVersion`.
+    pub fn append_with_flags<F>(&mut self, flags: u8, writer: F) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let header = HeaderV2 {
+            flags,
+            length: u16::try_from(self.data_size()).unwrap_or(u16::MAX),
+            ..HeaderV2::default()
+        };
+        self.append_with_header(header, writer)
+    }
+
+    fn append_block<F>(
+        &mut self,
+        header: Option<HeaderV2>,
+        writer: F,
+    ) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8], &mut [u8]),
+    {
+        if self.next_blk_id() > MAX_BLOCK_ID {
+            return Err(Error::BlockIdsExhausted);
+        }
+
+        self.check_writable()?;
+        self.retire_quarantined()?;
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "append",
+            blk_idx = tracing::field::Empty,
+            id = tracing::field::Empty
+        )
+        .entered();
+        let io_before = self.storage.io_counters();
+        if self.id_reserve > 0 && self.next_blk_id() >= self.id_high_water {
+            self.reserve_ids()?;
+        }
+
+        let timestamp = self.now().unwrap_or(0);
+        let header = header.map(|h| match h.timestamp {
+            0 => HeaderV2 { timestamp, ..h },
+            _ => h,
+        });
+
+        let used_before = self.used_blocks();
+        #[cfg(feature = "strict_invariants")]
+        let shadow = Shadow::of(self);
+        let blk_len = self.storage.block_size();
+        let data_begin = self.data_begin();
+        let v2_len = self.header.extension_len();
+        let header_len = header_extension_len(self.header, is_wide_fs_id(self.id));
+
+        let wants_evicted = self
+            .observer
+            .as_ref()
+            .is_some_and(|observer| observer.observer.wants_evicted_blocks());
+        if self.is_full && wants_evicted {
+            // offset of the next write is the oldest block in full storage
+            let info = self.read_info(self.offset)?;
+            if info.is_valid && info.fs_id == self.id {
+                log!(target: target::IO, trace, "Evict block {} at {}", info.id, self.offset);
+                let data_end = blk_len - self.crc_mode.trailer_len();
+                let data_buf = &self.buffer.as_mut()[..blk_len];
+                if let Some(observer) = self.observer.as_mut() {
+                    observer
+                        .observer
+                        .on_evict(info.id, &data_buf[data_begin..data_end]);
+                }
+            }
+        }
+
+        let fs_id = self.id;
+        let fill = |blk_factory: &mut BlockFactory, buf: &mut [u8]| {
+            blk_factory
+                .create_with_writer::<_, BS>(buf, fs_id, |blk_data| {
+                    let (ext, blk_data) = blk_data.split_at_mut(header_len);
+                    let (ext, fs_id_high) = ext.split_at_mut(v2_len);
+                    if let Some(header) = header {
+                        header.write(ext);
+                    }
+                    if is_wide_fs_id(fs_id) {
+                        Block::<BS>::set_fs_id_high(fs_id_high, fs_id);
+                    }
+                    let (metadata, data) = blk_data.split_at_mut(L::METADATA_LEN);
+                    writer(metadata, data);
+                })
+                .id()
+        };
+        let id = self.write_block(blk_len, fill)?;
+        log!(target: target::IO, trace, "Appended block {} to offset: {}", id, self.offset);
+        self.run_writes += 1;
+        self.count(Counter::Appends);
+        self.account_append(io_before);
+        self.is_empty = false;
+        if self.in_tx {
+            self.uncommitted += 1;
+        } else {
+            // block without pending flag commits all pending blocks before it
+            self.uncommitted = 0;
+        }
+
+        let appended = AppendedBlock {
+            id,
+            offset: self.offset,
+            data_size: self.data_size(),
+        };
+        #[cfg(feature = "tracing")]
+        span.record("blk_idx", appended.offset).record("id", id);
+        let prev_offset = self.offset;
+        self.incr_offset();
+        log!(target: target::IO, trace, "Offset changed to {}", self.offset);
+        if self.offset <= prev_offset {
+            log!(trace, "Fs is full, next write will overwrite old data");
+            self.is_full = true;
+        }
+        #[cfg(feature = "strict_invariants")]
+        shadow.check_append(self, id);
+
+        if self.observer.is_some() {
+            let stats = self.stats();
+            if let Some(observer) = self.observer.as_mut() {
+                observer.notify_usage(used_before, &stats);
+            }
+        }
+
+        Ok(appended)
+    }
+
+    /// Append block with caller supplied sequence number `seq` stored in v2 header (see `HeaderV2::FLAG_SEQ`),
+    /// use it to retry failed appends: in case the newest block already has `seq`, nothing is written and append
+    /// is considered successful. Blocks written by `append` have sequence 0, so sequences should start from 1.
+    /// Filesystem with v1 header returns `UnsupportedHeaderVersion`.
+    pub fn append_seq<F>(&mut self, seq: SeqNum, writer: F) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if self.header != HeaderVersion::V2 {
+            return Err(Error::UnsupportedHeaderVersion);
+        }
+        // blocks of open transaction are checked too
+        let written = self.used_blocks() + self.uncommitted;
+        if written > 0 {
+            let blk_idx = self.blk_idx_of(written - 1)?;
+            let info = self.read_info(blk_idx)?;
+            if info.is_valid && info.fs_id == self.id && info.seq == seq {
+                log!(debug, "Block with seq {} is already appended", seq);
+                return Ok(AppendedBlock {
+                    id: info.id,
+                    offset: blk_idx,
+                    data_size: self.data_size(),
+                });
+            }
+        }
+
+        let header = HeaderV2 {
+            flags: HeaderV2::FLAG_SEQ,
+            length: u16::try_from(self.data_size()).unwrap_or(u16::MAX),
+            tag: seq,
+            ..HeaderV2::default()
+        };
+        self.append_block(Some(header), |metadata, data| {
+            metadata.fill(0);
+            writer(data);
+        })
+    }
+
+    /// Same as `append`, but in case data is equal to data of the newest block (e.g. idle sensor readings), marker
+    /// block which refers to the block with the data is appended instead, see [`BlockInfo::repeat`]. `read` and
+    /// `read_with_info` of the marker return data of the referred block, so it can't be read after the referred
+    /// block is overwritten. Blocks of open transaction and blocks with flags or tag are never referred.
+    /// Marker is flagged in v2 header, filesystem with v1 header returns `UnsupportedHeaderVersion`.
+    pub fn append_dedup<F>(&mut self, writer: F) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        self.check_writable()?;
+        if self.header != HeaderVersion::V2 {
+            return Err(Error::UnsupportedHeaderVersion);
+        }
+        let data_size = self.data_size();
+        let mut new_data = [0_u8; BS];
+        let new_data = &mut new_data[..data_size];
+        writer(new_data);
+
+        let used = self.used_blocks();
+        let mut repeat = None;
+        if !self.in_tx && self.uncommitted == 0 && used > 0 {
+            let blk_idx = self.blk_idx_of(used - 1)?;
+            let next_id = self.next_blk_id();
+            let res = self.read_blk_idx(blk_idx, |info, metadata, data| {
+                let plain =
+                    info.repeat > 0 || info.header.is_some_and(|h| h.flags == 0 && h.tag == 0);
+                if info.is_valid && plain && metadata.iter().all(|b| *b == 0) && data == new_data {
+                    let referred = info.id - BlockId::from(info.repeat);
+                    repeat = RepeatCount::try_from(next_id - referred).ok();
+                }
+            });
+            // newest block which can't be read isn't referred
+            match res {
+                Ok(_) | Err(Error::NotValidBlockForRead) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        match repeat {
+            Some(repeat) => {
+                log!(target: target::IO, trace, "Repeat block {}", self.next_blk_id() - BlockId::from(repeat));
+                let header = HeaderV2 {
+                    flags: HeaderV2::FLAG_REPEAT,
+                    length: u16::try_from(data_size).unwrap_or(u16::MAX),
+                    tag: repeat,
+                    ..HeaderV2::default()
+                };
+                self.append_block(Some(header), |metadata, data| {
+                    metadata.fill(0);
+                    data.fill(0);
+                })
+            }
+            None => self.append(|blk_data| blk_data.copy_from_slice(new_data)),
+        }
+    }
+
+    /// Read data from the beginning of the stream (the oldest write).
+    pub fn read<F>(&mut self, blk_offset: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        // self.offset is next position for write, so it is the oldest position for read
+        // in case storage is full, next offset will be position of oldest write
+        // in case storage is NOT full, first block will be position of oldest write
+        let offset = self.blk_idx_of(blk_offset)?;
+        log!(target: target::IO, trace, "Read (trimmed) offset {}", offset);
+        self.read_blk_idx(offset, |_, _, data| reader(data))
+    }
+
+    /// Same as `read`, `reader` also gets parsed block header (e.g. to log block id)
+    pub fn read_with_info<F>(&mut self, blk_offset: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&BlockInfo<BS>, &[u8]),
+    {
+        let offset = self.blk_idx_of(blk_offset)?;
+        self.read_blk_idx(offset, |info, _, data| reader(info, data))
+    }
+
+    /// Same as `read`, `reader` gets custom metadata of the block and its data, see [`BlockLayout`]
+    pub fn read_with_metadata<F>(&mut self, blk_offset: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8], &[u8]),
+    {
+        let offset = self.blk_idx_of(blk_offset)?;
+        self.read_blk_idx(offset, |_, metadata, data| reader(metadata, data))
+    }
+
+    /// Copy all valid blocks from the oldest to the newest one to a new filesystem on `dst` storage
+    /// (e.g. to backup ring from RAM to SD card). Blocks are copied as is, so fs id and block ids are preserved
+    /// and the copy can be opened with `restore`. Block size of `dst` must be the same, in case `dst` is smaller,
+    /// the oldest blocks are not copied. Pinned blocks are not copied. Returns number of copied blocks.
+    pub fn export_to<S2: Storage>(&mut self, dst: &mut S2) -> Result<usize, Error> {
+        self.export_offsets(dst, 0..self.used_blocks())
+    }
+
+    /// Same as `export_to`, only blocks appended in time window `from..to` (milliseconds, see [`HeaderV2::timestamp`])
+    /// are copied (e.g. yesterday's data for support). Blocks are found by binary search of timestamps.
+    pub fn export_range<S2: Storage>(
+        &mut self,
+        from: Timestamp,
+        to: Timestamp,
+        dst: &mut S2,
+    ) -> Result<usize, Error> {
+        let offsets = self.time_window(from, to)?;
+        self.export_offsets(dst, offsets)
+    }
+
+    /// Write data of blocks appended in time window `from..to` to `writer` (e.g. file or socket), blocks are
+    /// written from the oldest to the newest one, invalid blocks are skipped. Returns number of written blocks.
+    #[cfg(feature = "std")]
+    pub fn export_range_to_writer<W: std::io::Write>(
+        &mut self,
+        from: Timestamp,
+        to: Timestamp,
+        mut writer: W,
+    ) -> Result<usize, Error> {
+        let mut written = 0;
+        for blk_offset in self.time_window(from, to)? {
+            let mut res = Ok(());
+            match self.read(blk_offset, |data| res = writer.write_all(data)) {
+                Ok(_) => {}
+                Err(Error::NotValidBlockForRead | Error::Lapped) => continue,
+                Err(e) => return Err(e),
+            }
+            res.map_err(|e| {
+                log!(error, "Can't write block {}: {}", blk_offset, e);
+                Error::CanNotPerformWrite
+            })?;
+            written += 1;
+        }
+        writer.flush().map_err(|_| Error::CanNotPerformWrite)?;
+
+        Ok(written)
+    }
+
+    /// Copy valid blocks at `offsets` to a new filesystem on `dst`, the oldest ones are dropped in case `dst`
+    /// is smaller, see `export_to`
+    fn export_offsets<S2: Storage>(
+        &mut self,
+        dst: &mut S2,
+        offsets: Range<usize>,
+    ) -> Result<usize, Error> {
+        if dst.block_size() != self.block_size() {
+            log!(
+                error,
+                "Export storage block size {} is not equal to {}",
+                dst.block_size(),
+                self.block_size()
+            );
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        let dst_config = dst.min_block_index();
+        let dst_begin = dst_config
+            .checked_add(config_block::CONFIG_COPIES)
+            .ok_or(Error::ArithmeticOverflow)?;
+        let dst_end = dst.max_block_index();
+        if dst_end <= dst_begin {
+            return Err(Error::TooSmallFilesystem);
+        }
+        let mut config = config_block::FsConfigBlock::new();
+        config.crc_mode = self.config_crc_mode();
+        config.id_high_water = self.id_high_water;
+        config.boot_time = self.boot_time;
+        config.boot_count = self.boot_count;
+        for generation in 1..=config_block::CONFIG_COPIES as BlockId {
+            Self::write_config_to(dst, self.id, generation, config.clone())?;
+        }
+
+        let skip = offsets.len().saturating_sub(dst_end - dst_begin);
+        let mut copied = 0;
+        for blk_offset in offsets.start + skip..offsets.end {
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let info = self.read_info(blk_idx)?;
+            if !info.is_valid || info.fs_id != self.id {
+                log!(warn, "Skip invalid block at {} on export", blk_idx);
+                continue;
+            }
+
+            dst.write(
+                dst_begin + copied,
+                &self.buffer.as_mut()[..self.storage.block_size()],
+            )?;
+            copied += 1;
+        }
+
+        // old blocks of the same filesystem after the copied ones would be treated as its part on restore,
+        // zeroed block has invalid crc
+        let data_buf = &mut self.buffer.as_mut()[..self.storage.block_size()];
+        for blk_idx in dst_begin + copied..dst_end {
+            dst.read(blk_idx, data_buf)?;
+            let info = Self::info_of(data_buf, self.crc_mode, self.header, self.id);
+            if info.is_valid && info.fs_id == self.id {
+                data_buf.fill(0);
+                dst.write(blk_idx, data_buf)?;
+            }
+        }
+        log!(info, "Exported {} of {} blocks", copied, offsets.len());
+
+        Ok(copied)
+    }
+
+    /// Copy blocks for which `keep` returns true (e.g. drop debug-tagged blocks, keep errors) from the oldest to
+    /// the newest one to `dst` filesystem (e.g. long-term archive). Unlike `export_to`, blocks are appended,
+    /// so they get ids (and block timestamps) of `dst`. Data, custom metadata and v2 header fields are copied,
+    /// data size of `dst` must not be smaller. Invalid blocks are skipped. Returns number of copied blocks.
+    pub fn compact_into<S2, const BS2: usize, L2, B2, F>(
+        &mut self,
+        dst: &mut Filesystem<'_, S2, BS2, L2, B2>,
+        mut keep: F,
+    ) -> Result<usize, Error>
+    where
+        S2: Storage,
+        L2: BlockLayout,
+        B2: AsMut<[u8]>,
+        F: FnMut(&BlockInfo<BS>, &[u8]) -> bool,
+    {
+        if dst.data_size() < self.data_size() {
+            log!(
+                error,
+                "Data size {} of compaction target is less than {}",
+                dst.data_size(),
+                self.data_size()
+            );
+            return Err(Error::InvalidBlockSizeForWrite);
+        }
+
+        let used = self.used_blocks();
+        let mut copied = 0;
+        for blk_offset in 0..used {
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let mut appended = Ok(false);
+            let res = self.read_blk_idx(blk_idx, |info, metadata, data| {
+                if !keep(info, data) {
+                    return;
+                }
+                let header = match dst.header {
+                    HeaderVersion::V1 => None,
+                    HeaderVersion::V2 => Some(info.header.unwrap_or(HeaderV2 {
+                        length: u16::try_from(data.len()).unwrap_or(u16::MAX),
+                        ..HeaderV2::default()
+                    })),
+                };
+                appended = dst
+                    .append_block(header, |dst_metadata, dst_data| {
+                        let len = metadata.len().min(dst_metadata.len());
+                        dst_metadata[..len].copy_from_slice(&metadata[..len]);
+                        dst_metadata[len..].fill(0);
+                        dst_data[..data.len()].copy_from_slice(data);
+                        dst_data[data.len()..].fill(0);
+                    })
+                    .map(|_| true);
+            });
+            match res {
+                Ok(_) => {}
+                Err(Error::NotValidBlockForRead | Error::Lapped) => {
+                    log!(warn, "Skip invalid block at {} on compaction", blk_idx);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            if appended? {
+                copied += 1;
+            }
+        }
+        log!(info, "Compacted {} of {} blocks", copied, used);
+
+        Ok(copied)
+    }
+
+    /// Copy the filesystem to RAM preserving its layout (e.g. to run search or stats at RAM speed instead of
+    /// reading SD card): config blocks and all valid blocks of the filesystem are copied to the same positions
+    /// relative to `min_block_index`, open the copy with `restore`. Block size must be `RB` and the storage range
+    /// must fit `RS` bytes, otherwise `InvalidBlockSizeForStorage` or `TooSmallFilesystem` is returned.
+    pub fn load_into<const RS: usize, const RB: usize>(
+        &mut self,
+    ) -> Result<RamStorage<RS, RB>, Error> {
+        if RB != self.block_size() {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        let mut ram = RamStorage::new()?;
+        self.copy_layout_to(&mut ram)?;
+        Ok(ram)
+    }
+
+    /// Same as `load_into`, image of the storage range is allocated on heap, open it with [`SliceStorage`]
+    #[cfg(feature = "std")]
+    pub fn load_to_vec(&mut self) -> Result<std::vec::Vec<u8>, Error> {
+        let blocks = self.storage.max_block_index() - self.storage.min_block_index();
+        let mut image = std::vec![0_u8; blocks * self.block_size()];
+        let mut slice = SliceStorage::new(&mut image, self.block_size())?;
+        self.copy_layout_to(&mut slice)?;
+        Ok(image)
+    }
+
+    /// Copy config and valid blocks of the filesystem to `dst` which begins at index 0, returns number of copied blocks
+    fn copy_layout_to<S2: Storage>(&mut self, dst: &mut S2) -> Result<usize, Error> {
+        let begin = self.storage.min_block_index();
+        let end = self.storage.max_block_index();
+        if dst.min_block_index() != 0 || dst.max_block_index() < end - begin {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        let config_blocks = self.config_blocks();
+        let mut copied = 0;
+        for blk_idx in begin..end {
+            let info = self.read_info(blk_idx)?;
+            let is_config = config_blocks.contains(&blk_idx);
+            if is_config || (info.is_valid && info.fs_id == self.id) {
+                dst.write(
+                    blk_idx - begin,
+                    &self.buffer.as_mut()[..self.storage.block_size()],
+                )?;
+                copied += 1;
+            }
+        }
+        log!(debug, "Loaded {} blocks", copied);
+
+        Ok(copied)
+    }
+
+    /// Destroy all data: overwrite every block of the storage range with `pattern` and erase it
+    /// (see [`Storage::erase`]), then write new config blocks. Filesystem keeps its id and becomes empty,
+    /// pinned blocks are erased too and the table of pins is cleared. Reserved blocks are kept, config block
+    /// of filesystem of version 0x1 is kept too (it has no data and isn't rewritten).
+    pub fn secure_erase(&mut self, pattern: u8) -> Result<(), Error> {
+        self.check_writable()?;
+        let begin = self.storage.min_block_index();
+        let end = self.storage.max_block_index();
+        log!(info, "Secure erase of blocks {}..{}", begin, end);
+
+        let blk_len = self.storage.block_size();
+        self.buffer.as_mut()[..blk_len].fill(pattern);
+        let data_begin = self.data_blk_offset();
+        let config_blocks = if self.rewrites_config() {
+            self.config_blocks()
+        } else {
+            begin..begin
+        };
+        for blk_idx in config_blocks.clone().chain(data_begin..end) {
+            let data_buf = &self.buffer.as_mut()[..blk_len];
+            let res = self
+                .storage
+                .write(blk_idx, data_buf)
+                .and_then(|_| self.storage.erase(blk_idx));
+            self.run_writes += 1;
+            match res {
+                Ok(()) => {}
+                // bad block stays in the table, config blocks can't be skipped
+                Err(Error::CanNotPerformWrite) if !config_blocks.contains(&blk_idx) => {
+                    self.mark_bad(blk_idx)?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.writes_before_format = self
+            .total_writes()
+            .saturating_add((end - data_begin + config_blocks.len()) as config_block::WriteCount);
+        self.pins.fill(None);
+        self.priority = [None; config_block::MAX_PRIORITY_BLOCKS];
+        self.id_high_water = 0;
+        self.front_id = 0;
+        self.format_config()?;
+        let is_empty = true;
+        let is_full = false;
+        self.setup_attributes(self.data_idx(0)?, 0, is_empty, is_full);
+
+        Ok(())
+    }
+
+    /// Overwrite valid data blocks of other filesystems with zeroes and erase them, blocks with id of the
+    /// filesystem are kept. Returns number of wiped blocks.
+    fn wipe_foreign_blocks(&mut self) -> Result<usize, Error> {
+        self.check_writable()?;
+        let blk_len = self.storage.block_size();
+        let fs_id = self.id & NARROW_FS_ID_MAX;
+        let mut wiped = 0;
+        for blk_idx in self.data_blk_offset()..self.storage.max_block_index() {
+            if self.is_bad(blk_idx) {
+                continue;
+            }
+            let buf = &mut self.buffer.as_mut()[..blk_len];
+            self.storage.read(blk_idx, buf)?;
+            let info = Self::foreign_block_info(buf);
+            if !info.is_valid || info.fs_id == fs_id {
+                continue;
+            }
+
+            buf.fill(0);
+            let res = self
+                .storage
+                .write(blk_idx, buf)
+                .and_then(|_| self.storage.erase(blk_idx));
+            self.run_writes += 1;
+            match res {
+                Ok(()) => wiped += 1,
+                Err(Error::CanNotPerformWrite) => self.mark_bad(blk_idx)?,
+                Err(e) => return Err(e),
+            }
+        }
+        log!(info, "Wiped {} blocks of other filesystems", wiped);
+
+        Ok(wiped)
+    }
+
+    /// Discard (see [`Storage::discard`]) data blocks which are not used by the filesystem, it's the part
+    /// of the ring after write head until the ring wraps around for the first time (it can contain data
+    /// of previous filesystem). Full filesystem has no unused blocks. Returns number of discarded blocks.
+    pub fn trim(&mut self) -> Result<usize, Error> {
+        self.check_writable()?;
+        if self.is_full {
+            return Ok(0);
+        }
+
+        // pinned blocks are always before write head of not full filesystem
+        let blocks = self.offset..self.storage.max_block_index();
+        log!(debug, "Discard blocks {:?}", blocks);
+        let count = blocks.len();
+        self.storage.discard(blocks)?;
+
+        Ok(count)
+    }
+
+    /// Advanced: read the whole block at storage index `blk_idx` including header, block is not validated
+    /// (it can be config block, corrupted block or block of another filesystem). Intended for diagnostic tools,
+    /// use `blk_idx_of` to find storage index of block at read offset. Returns block size.
+    pub fn read_raw<F>(&mut self, blk_idx: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        if blk_idx < self.storage.min_block_index() || blk_idx >= self.storage.max_block_index() {
+            return Err(Error::BlockOutOfRange);
+        }
+
+        let data_buf = &mut self.buffer.as_mut()[..self.storage.block_size()];
+        self.storage.read(blk_idx, data_buf)?;
+        reader(data_buf);
+        Ok(data_buf.len())
+    }
+
+    /// Advanced: underlying storage, e.g. to get its geometry. Reads don't change filesystem state,
+    /// but filesystem doesn't know about blocks changed behind it, call `refresh` after that.
+    pub fn storage(&self) -> &S {
+        self.storage
+    }
+
+    /// Write block filled by `fill` at write head, in memory of the storage in case it can write in place or from
+    /// the buffer. Blocks which can't be written are marked as bad and block is written to the next one.
+    fn write_block<F>(&mut self, blk_len: usize, fill: F) -> Result<BlockId, Error>
+    where
+        F: FnOnce(&mut BlockFactory, &mut [u8]) -> BlockId,
+    {
+        self.check_writable()?;
+        let in_place = self.storage.can_write_in_place();
+        let mut fill = Some(fill);
+        let mut id = None;
+        loop {
+            let result = if in_place {
+                let blk_factory = &mut self.blk_factory;
+                self.storage.write_in_place(self.offset, |buf| {
+                    if let Some(fill) = fill.take() {
+                        id = Some(fill(blk_factory, buf));
+                    }
+                })
+            } else {
+                let data_buf = &mut self.buffer.as_mut()[..blk_len];
+                if let Some(fill) = fill.take() {
+                    id = Some(fill(&mut self.blk_factory, data_buf));
+                }
+                self.storage.write(self.offset, data_buf)
+            };
+            match (result, id) {
+                (Ok(_), Some(id)) => return Ok(id),
+                (Ok(_), None) => return Err(Error::CanNotPerformWrite),
+                // data filled in memory of the storage is lost, it can't be written to the next block
+                (Err(Error::CanNotPerformWrite), _) if in_place && fill.is_none() => {
+                    return Err(Error::CanNotPerformWrite);
+                }
+                (Err(Error::CanNotPerformWrite), _) => {
+                    let blk_idx = self.offset;
+                    self.mark_bad(blk_idx).map_err(|e| {
+                        log!(error, "Can't mark block at {} as bad: {:?}", blk_idx, e);
+                        Error::CanNotPerformWrite
+                    })?;
+                    self.health.write_retries = self.health.write_retries.saturating_add(1);
+                    self.count(Counter::WriteRetries);
+                }
+                (Err(e), _) => return Err(e),
+            }
+        }
+    }
+
+    /// Exclude block at storage index `blk_idx` from the ring (e.g. worn sector of SD card), write head and
+    /// offsets skip it like pinned block, its pin is removed. The table of bad blocks is stored in config block,
+    /// `append` and `secure_erase` mark blocks which can't be written (`CanNotPerformWrite`) and continue
+    /// with the next block. Returns `BadBlockTableIsFull` in case table has no free entry.
+    pub fn mark_bad(&mut self, blk_idx: usize) -> Result<(), Error> {
+        if blk_idx < self.data_blk_offset() || blk_idx >= self.storage.max_block_index() {
+            return Err(Error::BlockOutOfRange);
+        }
+        if self.is_bad(blk_idx) {
+            return Ok(());
+        }
+        // at least one block must be left for writes
+        if self.capacity() < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        let slot = self
+            .bad_blocks
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::BadBlockTableIsFull)?;
+        log!(warn, "Mark block at {} as bad", blk_idx);
+        self.bad_blocks[slot] = Some(blk_idx);
+        self.health.remapped_blocks = self.health.remapped_blocks.saturating_add(1);
+        self.count(Counter::RemappedBlocks);
+        for pin in self.pins.iter_mut().chain(self.priority.iter_mut()) {
+            if pin.is_some_and(|p| p.blk_idx == blk_idx) {
+                *pin = None;
+            }
+        }
+        for quarantined in self.quarantine.iter_mut() {
+            if *quarantined == Some(blk_idx) {
+                *quarantined = None;
+            }
+        }
+
+        if self.offset == blk_idx {
+            let next = self.skip_pinned(self.offset, 0)?;
+            if next < self.offset {
+                log!(trace, "Fs is full, next write will overwrite old data");
+                self.is_full = true;
+            }
+            self.offset = next;
+        }
+        #[cfg(feature = "strict_invariants")]
+        self.check_invariants();
+
+        self.write_config()
+    }
+
+    /// Error counters since format (or since the storage was first formatted, they are kept by format like
+    /// bad blocks). Counters are stored in config block by `persist_health` and every config write
+    /// (e.g. `mark_bad`), they aren't stored in case config block is too small (blocks smaller than 128 bytes).
+    pub fn health(&self) -> Health {
+        self.health
+    }
+
+    /// Write error counters to config block, so they survive reboot
+    pub fn persist_health(&mut self) -> Result<(), Error> {
+        self.write_config()
+    }
+
+    /// Storage indexes of bad blocks, see `mark_bad`
+    pub fn bad_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bad_blocks.iter().flatten().copied()
+    }
+
+    /// Storage indexes of reserved blocks, see `new_with_reserved`
+    pub fn reserved_blocks(&self) -> Range<usize> {
+        let begin = self.config_blocks().end;
+        begin..begin + self.reserved
+    }
+
+    /// Read the whole reserved block at `index` in the reserved area, `reader` gets raw content of the block
+    /// (it has no header). Returns block size.
+    pub fn read_reserved<F>(&mut self, index: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        let blk_idx = self.reserved_blk_idx(index)?;
+        let data_buf = &mut self.buffer.as_mut()[..self.storage.block_size()];
+        self.storage.read(blk_idx, data_buf)?;
+        reader(data_buf);
+        Ok(data_buf.len())
+    }
+
+    /// Write `data` (exactly block size) to reserved block at `index` in the reserved area as is
+    pub fn write_reserved(&mut self, index: usize, data: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+        let blk_idx = self.reserved_blk_idx(index)?;
+        self.storage.write(blk_idx, data)?;
+        self.run_writes += 1;
+        Ok(())
+    }
+
+    fn reserved_blk_idx(&self, index: usize) -> Result<usize, Error> {
+        if index >= self.reserved {
+            return Err(Error::BlockOutOfRange);
+        }
+
+        Ok(self.reserved_blocks().start + index)
+    }
+
+    fn is_bad(&self, blk_idx: usize) -> bool {
+        self.bad_blocks.contains(&Some(blk_idx))
+    }
+
+    /// Storage indexes of data blocks excluded from the ring: pinned, high priority and bad blocks
+    fn skipped_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pins
+            .iter()
+            .chain(&self.priority)
+            .flatten()
+            .map(|p| p.blk_idx)
+            .chain(self.bad_blocks())
+    }
+
+    /// Storage index of the block at `blk_offset` from the oldest block, use it with `read_raw`
+    pub fn blk_idx_of(&self, blk_offset: usize) -> Result<usize, Error> {
+        // self.offset is next position for write, so it is the oldest position for read
+        // in case storage is full, next offset will be position of oldest write
+        // in case storage is NOT full, first block will be position of oldest write
+        let base = if self.is_full() {
+            self.offset
+        } else {
+            self.data_blk_offset()
+        };
+        // blocks dropped by truncate_front are skipped
+        let ring_offset = blk_offset
+            .checked_add(self.dropped_blocks())
+            .ok_or(Error::ArithmeticOverflow)?;
+        if base.checked_add(ring_offset).is_none() {
+            return Err(Error::ArithmeticOverflow);
+        }
+
+        self.skip_pinned(base, ring_offset % self.capacity().max(1))
+    }
+
+    /// Storage index of the data block `idx`, pinned blocks are not counted
+    fn data_idx(&self, idx: usize) -> Result<usize, Error> {
+        self.skip_pinned(self.data_blk_offset(), idx)
+    }
+
+    /// Read block at storage index `blk_idx` to the internal buffer and parse its header
+    fn read_info(&mut self, blk_idx: usize) -> Result<BlockInfo<BS>, Error> {
+        self.read_info_with_mode(blk_idx, self.crc_mode)
+    }
+
+    fn read_info_with_mode(
+        &mut self,
+        blk_idx: usize,
+        crc_mode: CrcMode,
+    ) -> Result<BlockInfo<BS>, Error> {
+        let data_buf = &mut self.buffer.as_mut()[..self.storage.block_size()];
+        self.storage.read(blk_idx, data_buf)?;
+        Ok(Self::info_of(data_buf, crc_mode, self.header, self.id))
+    }
+
+    /// Parse header of a block read to `buf`
+    fn info_of(buf: &[u8], crc_mode: CrcMode, header: HeaderVersion, fs_id: FsId) -> BlockInfo<BS> {
+        BlockInfo::<BS>::from_buffer_with_format::<L>(buf, crc_mode, header, is_wide_fs_id(fs_id))
+    }
+
+    fn read_blk_idx<F>(&mut self, blk_idx: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&BlockInfo<BS>, &[u8], &[u8]),
+    {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("read", blk_idx).entered();
+        if self.is_quarantined(blk_idx) && self.read_policy != ReadPolicy::Report {
+            log!(debug, "Block at {} is quarantined", blk_idx);
+            self.count(Counter::ReadErrors);
+            return Err(Error::NotValidBlockForRead);
+        }
+        let blk_len = self.storage.block_size();
+        let crc_mode = self.read_crc_mode();
+        let info = self.read_info_with_mode(blk_idx, crc_mode)?;
+        // header of another filesystem isn't damaged data of this one (e.g. block of previous format)
+        let damaged = !info.is_valid && info.fs_id == self.id;
+        if damaged {
+            self.health.crc_failures = self.health.crc_failures.saturating_add(1);
+        }
+        if let Err(e) = self.check_read(blk_idx, &info) {
+            if damaged {
+                self.count(Counter::CrcFailures);
+            }
+            self.count(Counter::ReadErrors);
+            if damaged {
+                self.quarantine_blk(blk_idx)?;
+            }
+            return Err(e);
+        }
+        if info.repeat > 0 {
+            self.read_referred(&info, crc_mode)?;
+        }
+        let data_end = blk_len - self.crc_mode.trailer_len();
+        let metadata_begin = self.data_begin() - L::METADATA_LEN;
+        let data_buf = &self.buffer.as_mut()[..blk_len];
+        let (metadata, data) = data_buf[metadata_begin..data_end].split_at(L::METADATA_LEN);
+        reader(&info, metadata, data);
+        if damaged {
+            self.count(Counter::CrcFailures);
+            self.quarantine_blk(blk_idx)?;
+        }
+        Ok(self.data_size())
+    }
+
+    /// Crc mode of block reads according to read policy
+    fn read_crc_mode(&self) -> CrcMode {
+        match (self.read_policy, self.crc_mode) {
+            (ReadPolicy::HeaderOnly, CrcMode::Split) => CrcMode::HeaderOnly,
+            (_, crc_mode) => crc_mode,
+        }
+    }
+
+    /// Check block read from `blk_idx` according to read policy, it's the same for `read` and `read_with_buf`
+    fn check_read(&self, blk_idx: usize, info: &BlockInfo<BS>) -> Result<(), Error> {
+        if info.fs_id != self.id || (!info.is_valid && self.read_policy != ReadPolicy::Report) {
+            log!(debug, "Block at {} is invalid", blk_idx);
+            return Err(Error::NotValidBlockForRead);
+        }
+        if !info.is_valid {
+            log!(
+                warn,
+                "Block at {} is damaged, it's returned by read policy",
+                blk_idx
+            );
+        }
+        // ids are increasing, block with id after the head was written after mount or refresh
+        if self.read_only && info.id >= self.blk_factory.id {
+            log!(debug, "Block at {} was overwritten by writer", blk_idx);
+            return Err(Error::Lapped);
+        }
+        Ok(())
+    }
+
+    /// Read block referred by `marker` block of `append_dedup` to the buffer
+    fn read_referred(&mut self, marker: &BlockInfo<BS>, crc_mode: CrcMode) -> Result<(), Error> {
+        let blk_idx = match self.referred_blk_idx(marker) {
+            Ok(blk_idx) => blk_idx,
+            Err(e) => {
+                self.count(Counter::ReadErrors);
+                return Err(e);
+            }
+        };
+        let info = self.read_info_with_mode(blk_idx, crc_mode)?;
+        if let Err(e) = self.check_referred(marker, &info) {
+            self.count(Counter::ReadErrors);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Storage index of the block referred by `marker` block of `append_dedup`
+    fn referred_blk_idx(&self, marker: &BlockInfo<BS>) -> Result<usize, Error> {
+        let referred = marker.id - BlockId::from(marker.repeat);
+        let Some(blk_offset) = self.blk_offset_of(referred) else {
+            log!(
+                debug,
+                "Block {} referred by block {} is overwritten",
+                referred,
+                marker.id
+            );
+            return Err(Error::NotValidBlockForRead);
+        };
+        self.blk_idx_of(blk_offset)
+    }
+
+    /// Block read from index returned by `referred_blk_idx` has data of `marker`
+    fn check_referred(&self, marker: &BlockInfo<BS>, info: &BlockInfo<BS>) -> Result<(), Error> {
+        let referred = marker.id - BlockId::from(marker.repeat);
+        if !info.is_valid || info.fs_id != self.id || info.id != referred || info.repeat != 0 {
+            log!(
+                debug,
+                "Block {} referred by block {} is invalid",
+                referred,
+                marker.id
+            );
+            return Err(Error::NotValidBlockForRead);
+        }
+        Ok(())
+    }
+
+    /// Size of data in a block with v1 header, in case storage block size is equal to `BS`
+    pub const fn data_block_size() -> usize {
+        BS - Self::DATA_BEGIN
+    }
+
+    /// Size of the block, defined by the storage
+    pub fn block_size(&self) -> usize {
+        self.storage.block_size()
+    }
+
+    /// Size of data passed to `append` and `read` callbacks
+    pub fn data_size(&self) -> usize {
+        self.block_size() - self.data_begin() - self.crc_mode.trailer_len()
+    }
+
+    /// Offset of data in a block, v2 header fields and custom metadata are before it
+    fn data_begin(&self) -> usize {
+        Self::data_begin_of(self.header, is_wide_fs_id(self.id))
+    }
+
+    /// Offset of data in a block with `header`, header crc of `HeaderOnly` and `Split` modes covers bytes before it
+    fn data_begin_of(header: HeaderVersion, wide_fs_id: bool) -> usize {
+        Self::DATA_BEGIN + header_extension_len(header, wide_fs_id)
+    }
+
+    /// Crc mode the filesystem was formatted with
+    pub fn crc_mode(&self) -> CrcMode {
+        self.crc_mode
+    }
+
+    /// Filesystem is mounted with `open_readonly`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// Header version of data blocks the filesystem was formatted with
+    pub fn header_version(&self) -> HeaderVersion {
+        self.header
+    }
+
+    /// Set validation of blocks by `read`, `read_with_info` and other reads (e.g. pipeline which prefers
+    /// damaged payload with a warning to an error uses `ReadPolicy::Report` and checks `BlockInfo::is_valid`).
+    /// Policy isn't stored, it's `Verify` after mount. Restore and `scrub` always verify blocks.
+    pub fn set_read_policy(&mut self, policy: ReadPolicy) {
+        self.read_policy = policy;
+    }
+
+    pub fn read_policy(&self) -> ReadPolicy {
+        self.read_policy
+    }
+
+    pub fn incr_offset(&mut self) {
+        let mut next = self.trim_offset(self.offset + 1);
+        // pinned and bad blocks are skipped, released pin is removed when write head reaches it
+        loop {
+            if self.is_bad(next) {
+                next = self.trim_offset(next + 1);
+                continue;
+            }
+            let Some(pin) = self
+                .pins
+                .iter_mut()
+                .chain(self.priority.iter_mut())
+                .find(|p| matches!(p, Some(p) if p.blk_idx == next))
+            else {
+                break;
+            };
+            if pin.is_some_and(|p| p.released) {
+                log!(debug, "Unpin block at {}", next);
+                *pin = None;
+                break;
+            }
+            next = self.trim_offset(next + 1);
+        }
+        self.offset = next;
+    }
+
+    /// Consistency of write head, ids and usage counters, panics at the operation which broke them
+    #[cfg(feature = "strict_invariants")]
+    fn check_invariants(&mut self) {
+        assert!(
+            self.buffer.as_mut().len() >= self.storage.block_size(),
+            "Buffer is smaller than block"
+        );
+        let data_blocks = self.data_blk_offset()..self.storage.max_block_index();
+        assert!(
+            data_blocks.contains(&self.offset),
+            "Offset {} is out of data blocks {:?}",
+            self.offset,
+            data_blocks
+        );
+        assert!(
+            !self.skipped_blocks().any(|blk_idx| blk_idx == self.offset),
+            "Offset {} is pinned or bad block",
+            self.offset
+        );
+        assert!(!(self.is_empty && self.is_full), "Fs is empty and full");
+        let ring_used = self.ring_used_blocks();
+        assert!(
+            ring_used <= self.capacity(),
+            "Used blocks {} exceed capacity {}",
+            ring_used,
+            self.capacity()
+        );
+        assert!(
+            self.uncommitted <= ring_used,
+            "Uncommitted blocks {} exceed used blocks {}",
+            self.uncommitted,
+            ring_used
+        );
+        assert!(
+            self.oldest_blk_id() <= self.next_blk_id(),
+            "Oldest id {} is above next id {}",
+            self.oldest_blk_id(),
+            self.next_blk_id()
+        );
+    }
+
+    fn data_blk_offset(&self) -> usize {
+        // the first blocks are FS config, reserved blocks are after them
+        self.config_blocks().end + self.reserved
+    }
+
+    /// Storage indexes of copies of config block
+    fn config_blocks(&self) -> Range<usize> {
+        let begin = self.storage.min_block_index();
+        begin..begin + self.config_copies
+    }
+
+    /// Config of filesystem of version 0x1 isn't rewritten, the only copy would be lost by torn write
+    fn rewrites_config(&self) -> bool {
+        self.config_copies == config_block::CONFIG_COPIES
+    }
+
+    /// At least one data block is left after config and reserved blocks
+    fn validate_reserved(&self) -> Result<(), Error> {
+        let blocks = self.storage.max_block_index() - self.storage.min_block_index();
+        if self.reserved >= blocks.saturating_sub(self.config_copies) {
+            log!(
+                error,
+                "{} reserved blocks don't leave data blocks in {} blocks",
+                self.reserved,
+                blocks
+            );
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        Ok(())
+    }
+
+    fn trim_offset(&self, offset: usize) -> usize {
+        trim_block_idx_with_wraparound(
+            offset,
+            self.data_blk_offset(),
+            self.storage.max_block_index(),
+        )
+    }
+
+    fn init(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "init",
+            begin = self.storage.min_block_index(),
+            end = self.storage.max_block_index()
+        )
+        .entered();
+        if let Some(progress) = self.progress.as_mut() {
+            progress.probed = 0;
+        }
+        let mut buf = [0_u8; BS];
+        let buf = &mut buf[..];
+        let (read_buf, _) = buf.split_at_mut(self.storage.block_size());
+
+        let begin = self.storage.min_block_index();
+        let end = self.storage.max_block_index();
+
+        log!(target: target::INIT, debug, "Init storage with begin: {}, end: {}", begin, end);
+        if begin > usize::MAX - 2 || end < begin + 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+        self.validate_reserved()?;
+
+        // both copies of config, ends of the ring and binary search
+        let remaining = Self::search_reads(self.capacity()) + 4;
+        let newest = Self::newest_config(begin, end, read_buf, |blk_idx, buf| {
+            self.probe(
+                blk_idx,
+                buf,
+                CrcMode::Full,
+                fields::DATA_BEGIN,
+                false,
+                remaining,
+            )
+        })?;
+        let copy = match newest {
+            Some(copy) if copy.fs_id == self.id => copy,
+            previous => {
+                // storage wasn't formatted, it is empty, offset is begin
+                if self.read_only {
+                    return Err(Error::InvalidHeaderBlock);
+                }
+                self.explain(RestoreStep::Decision(RestoreDecision::Format));
+                log!(target: target::INIT, debug, "Storage was not formatted. Making empty one");
+                let is_empty = true;
+                let is_full = false;
+                // ring of previous filesystem is counted with its layout
+                self.config_copies = previous
+                    .as_ref()
+                    .map_or(config_block::CONFIG_COPIES, |p| p.copies);
+                self.writes_before_format =
+                    self.writes_of_previous_fs(previous.as_ref(), read_buf)?;
+                self.config_copies = config_block::CONFIG_COPIES;
+                self.validate_reserved()?;
+                // bad blocks are property of the storage, they are kept by format
+                self.bad_blocks = match &previous {
+                    Some(previous) => {
+                        self.health = Self::health_of(&previous.config);
+                        self.quarantine = self.quarantine_of(&previous.config);
+                        self.bad_blocks_of(&previous.config)
+                    }
+                    None => {
+                        self.health = Health::default();
+                        self.quarantine = [None; config_block::MAX_QUARANTINED];
+                        [None; config_block::MAX_BAD_BLOCKS]
+                    }
+                };
+                self.priority = [None; config_block::MAX_PRIORITY_BLOCKS];
+                self.id_high_water = 0;
+                self.boot_count = 0;
+                self.validate_header()?;
+                self.blk_factory.set_crc_mode(self.crc_mode);
+                self.blk_factory.set_header_end(self.data_begin());
+                self.format_config()?;
+                let blk_idx = self.data_idx(0)?;
+                self.explain(RestoreStep::Decision(RestoreDecision::Head {
+                    blk_idx,
+                    next_id: 0,
+                }));
+                self.setup_attributes(blk_idx, 0, is_empty, is_full);
+                return Ok(());
+            }
+        };
+
+        let config = copy.config;
+        self.config_copies = copy.copies;
+        self.config_generation = copy.generation;
+        self.writes_before_format = if config.has_fields() {
+            config.writes_before_format
+        } else {
+            0
+        };
+        self.crc_mode = Self::crc_mode_of(&config)?;
+        self.header = Self::header_of(&config);
+        self.validate_header()?;
+        self.blk_factory.set_crc_mode(self.crc_mode);
+        self.blk_factory.set_header_end(self.data_begin());
+        self.id_high_water = if config.has_fields() {
+            config.id_high_water
+        } else {
+            0
+        };
+        self.boot_time = if config.has_fields() {
+            config.boot_time
+        } else {
+            0
+        };
+        self.boot_count = if config.has_fields() {
+            config.boot_count
+        } else {
+            0
+        };
+        self.front_id = if config.has_fields() {
+            config.front_id
+        } else {
+            0
+        };
+        self.reserved = if config.has_fields() {
+            usize::try_from(config.reserved_blocks).map_err(|_| Error::TooSmallFilesystem)?
+        } else {
+            0
+        };
+        self.validate_reserved()?;
+        self.bad_blocks = self.bad_blocks_of(&config);
+        self.quarantine = self.quarantine_of(&config);
+        self.health = Self::health_of(&config);
+        // high priority blocks are skipped by the search of the head
+        self.priority = self.priority_of(&config, read_buf)?;
+
+        let attrs = self.find_head(self.id, self.crc_mode, self.data_begin(), read_buf)?;
+        let mut next_id = attrs.next_id;
+        if next_id < self.id_high_water && self.is_damaged_block(attrs.next_offset)? {
+            log!(
+                target: target::INIT,
+                warn,
+                "The newest block at {} is damaged, continue ids from {} instead of {}",
+                attrs.next_offset,
+                self.id_high_water,
+                next_id
+            );
+            next_id = self.id_high_water;
+        }
+        self.explain(RestoreStep::Decision(RestoreDecision::Head {
+            blk_idx: attrs.next_offset,
+            next_id,
+        }));
+        self.setup_attributes(attrs.next_offset, next_id, attrs.is_empty, attrs.is_full);
+        self.uncommitted = 0;
+        self.uncommitted = self.count_uncommitted()?;
+        Ok(())
+    }
+
+    /// Block has id of the filesystem, but it isn't valid (e.g. write was interrupted)
+    fn is_damaged_block(&mut self, blk_idx: usize) -> Result<bool, Error> {
+        if blk_idx >= self.storage.max_block_index() {
+            return Ok(false);
+        }
+
+        let info = self.read_info(blk_idx)?;
+        Ok(!info.is_valid && info.fs_id == self.id)
+    }
+
+    /// Table of bad blocks from `config`, entries out of data range are ignored
+    fn bad_blocks_of(
+        &self,
+        config: &config_block::FsConfigBlock,
+    ) -> [Option<usize>; config_block::MAX_BAD_BLOCKS] {
+        let mut bad_blocks = [None; config_block::MAX_BAD_BLOCKS];
+        if !config.has_fields() {
+            return bad_blocks;
+        }
+
+        let data_blocks = self.data_blk_offset()..self.storage.max_block_index();
+        for (bad_block, entry) in bad_blocks.iter_mut().zip(config.bad_blocks) {
+            *bad_block = entry
+                .checked_sub(1)
+                .and_then(|blk_idx| usize::try_from(blk_idx).ok())
+                .filter(|blk_idx| data_blocks.contains(blk_idx));
+        }
+
+        bad_blocks
+    }
+
+    fn health_of(config: &config_block::FsConfigBlock) -> Health {
+        if !config.has_fields() {
+            return Health::default();
+        }
+
+        Health {
+            crc_failures: config.crc_failures,
+            write_retries: config.write_retries,
+            remapped_blocks: config.remapped_blocks,
+        }
+    }
+
+    /// Number of block writes of filesystem which is going to be overwritten by format,
+    /// `previous` is the newest copy of its config
+    fn writes_of_previous_fs(
+        &mut self,
+        previous: Option<&ConfigCopy>,
+        read_buf: &mut [u8],
+    ) -> Result<config_block::WriteCount, Error> {
+        let Some(previous) = previous else {
+            return Ok(0);
+        };
+        let config = &previous.config;
+        let writes = if config.has_fields() {
+            config.writes_before_format
+        } else {
+            0
+        };
+
+        // unknown crc mode of newer version, header crc is checked in all modes
+        let crc_mode = Self::crc_mode_of(config).unwrap_or(CrcMode::HeaderOnly);
+        // ids are sequential, so next id is number of appends of previous filesystem
+        // custom metadata of previous filesystem is unknown, it's assumed to be the same
+        let header_end =
+            Self::data_begin_of(Self::header_of(config), is_wide_fs_id(previous.fs_id));
+        let appends = match self.find_head(previous.fs_id, crc_mode, header_end, read_buf) {
+            Ok(head) => head.next_id,
+            // corrupted ids of previous filesystem must not prevent format
+            Err(Error::ArithmeticOverflow) => 0,
+            Err(e) => return Err(e),
+        };
+        log!(
+            debug,
+            "Previous fs {} has {} appends",
+            previous.fs_id,
+            appends
+        );
+        // one write per config block
+        Ok(writes
+            .saturating_add(appends)
+            .saturating_add(previous.copies as config_block::WriteCount))
+    }
+
+    fn crc_mode_of(config: &config_block::FsConfigBlock) -> Result<CrcMode, Error> {
+        if !config.has_fields() {
+            return Ok(CrcMode::Full);
+        }
+
+        let flags = config_block::HEADER_V2_FLAG | config_block::WIDE_FS_ID_FLAG;
+        CrcMode::from_id(config.crc_mode & !flags).ok_or(Error::UnsupportedCrcMode)
+    }
+
+    fn header_of(config: &config_block::FsConfigBlock) -> HeaderVersion {
+        if config.has_fields() && config.crc_mode & config_block::HEADER_V2_FLAG != 0 {
+            HeaderVersion::V2
+        } else {
+            HeaderVersion::V1
+        }
+    }
+
+    /// Id of filesystem of `config`, `header_fs_id` of config block header keeps only low bits of wide id
+    fn fs_id_of(config: &config_block::FsConfigBlock, header_fs_id: FsId) -> FsId {
+        if config.has_fields() && config.crc_mode & config_block::WIDE_FS_ID_FLAG != 0 {
+            config.fs_id
+        } else {
+            header_fs_id
+        }
+    }
+
+    /// Crc mode id with header version flag, it's stored in config block
+    fn config_crc_mode(&self) -> config_block::CrcModeId {
+        match self.header {
+            HeaderVersion::V1 => self.crc_mode.id(),
+            HeaderVersion::V2 => self.crc_mode.id() | config_block::HEADER_V2_FLAG,
+        }
+    }
+
+    /// Block must have space for data after v2 header, config block must have space for wide fs id
+    fn validate_header(&self) -> Result<(), Error> {
+        if self.block_size() <= self.data_begin() + self.crc_mode.trailer_len() {
+            log!(error, "Block is too small for header {:?}", self.header);
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+        if is_wide_fs_id(self.id)
+            && self.block_size() < fields::DATA_BEGIN + config_block::FS_ID_END
+        {
+            log!(error, "Block is too small for config of wide fs id");
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        Ok(())
+    }
+
+    /// Find position of the next write for filesystem with `fs_id`, header of its blocks ends at `header_end`
+    fn find_head(
+        &mut self,
+        fs_id: FsId,
+        crc_mode: CrcMode,
+        header_end: usize,
+        read_buf: &mut [u8],
+    ) -> Result<FsInitAttrs, Error> {
+        // search works with indexes of not pinned data blocks, `data_idx` maps them to storage indexes,
+        // so ids are increasing along the ring even if some blocks are skipped by write head
+        let mut begin = 0;
+        let mut end = self.capacity();
+        if end == 0 {
+            return Err(Error::TooSmallFilesystem);
+        }
+        let wide_fs_id = is_wide_fs_id(fs_id);
+
+        let remaining = Self::search_reads(end) + 2;
+        let left_block = self.probe(
+            self.data_idx(begin)?,
+            &mut read_buf[..],
+            crc_mode,
+            header_end,
+            wide_fs_id,
+            remaining,
+        )?;
+        if !left_block.is_valid && left_block.fs_id == fs_id && end > 1 {
+            // write of the first block was interrupted after wraparound, the last block is the newest one
+            let right_block = self.probe(
+                self.data_idx(end - 1)?,
+                &mut read_buf[..],
+                crc_mode,
+                header_end,
+                wide_fs_id,
+                remaining - 1,
+            )?;
+            if right_block.is_valid && right_block.fs_id == fs_id {
+                self.explain(RestoreStep::Decision(RestoreDecision::DamagedFirst));
+                log!(
+                    target: target::INIT,
+                    debug,
+                    "First block is damaged, wraparound is after last block"
+                );
+                let next_id = right_block
+                    .id
+                    .checked_add(1)
+                    .ok_or(Error::ArithmeticOverflow)?;
+                return Ok(FsInitAttrs {
+                    next_offset: self.data_idx(begin)?,
+                    next_id,
+                    is_empty: false,
+                    is_full: true,
+                });
+            }
+        }
+        if !left_block.is_valid || left_block.fs_id != fs_id {
+            // storage was formatted, but first block was not written, it is empty, offset is begin
+            log!(
+                target: target::INIT,
+                debug,
+                "Storage was formatted, but first block is not valid. Treat it as empty storage"
+            );
+            self.explain(RestoreStep::Decision(RestoreDecision::Empty));
+            let is_empty = true;
+            let is_full = false;
+            return Ok(FsInitAttrs {
+                next_offset: self.data_idx(begin)?,
+                next_id: 0,
+                is_empty,
+                is_full,
+            });
+        }
+        // as first block is valid is can't be empty
+        let is_empty = false;
+
+        let remaining = Self::search_reads(end) + 1;
+        let mut right_block = self.probe(
+            self.data_idx(end - 1)?,
+            &mut read_buf[..],
+            crc_mode,
+            header_end,
+            wide_fs_id,
+            remaining,
+        )?;
+        if right_block.is_valid && right_block.fs_id == fs_id && right_block.id > left_block.id {
+            // wraparound is after end, next block to write is begin
+            log!(
+                target: target::INIT,
+                debug,
+                "Storage is full, wraparound is after last block, next block is first storage block"
+            );
+            self.explain(RestoreStep::Decision(RestoreDecision::WrapAtEnd));
+            let is_empty = false;
+            let is_full = true;
+            let next_id = right_block
+                .id
+                .checked_add(1)
+                .ok_or(Error::ArithmeticOverflow)?;
+            return Ok(FsInitAttrs {
+                next_offset: self.data_idx(begin)?,
+                next_id,
+                is_empty,
+                is_full,
+            });
+        }
+
+        let is_full = right_block.is_valid;
+
+        // must be always the same as begin.id
+        let mut last_id = left_block.id;
+
+        // at least 2 elements must be present
+        // will found only wraparound, last block must be checked to have wraparound
+        // begin of the range will always point to last written element
+        while end - begin > 2 {
+            // doesn't overflow for indexes close to usize::MAX on 32 bit targets
+            let mid = begin + (end - begin) / 2;
+
+            let remaining = Self::search_reads(end - begin) - 1;
+            let mid_idx = self.data_idx(mid)?;
+            let mid_block = self.probe(
+                mid_idx,
+                &mut read_buf[..],
+                crc_mode,
+                header_end,
+                wide_fs_id,
+                remaining,
+            )?;
+            log!(target: target::INIT, trace, "Mid: {:?}, right: {:?}", &mid_block, right_block);
+
+            if Self::can_have_tail(fs_id, &mid_block, &right_block) {
+                self.explain(RestoreStep::Decision(RestoreDecision::SearchRight {
+                    blk_idx: mid_idx,
+                }));
+                begin = mid;
+                last_id = mid_block.id;
+            } else {
+                self.explain(RestoreStep::Decision(RestoreDecision::SearchLeft {
+                    blk_idx: mid_idx,
+                }));
+                end = mid + 1;
+                right_block = mid_block;
+            };
+        }
+
+        // in case not all memory was used wraparound will not exists,
+        // place for new block will be after last block
+        if end - begin == 2 {
+            let blk_idx = self.data_idx(begin + 1)?;
+            let block_inf = self.probe(
+                blk_idx,
+                &mut read_buf[..],
+                crc_mode,
+                header_end,
+                wide_fs_id,
+                0,
+            )?;
+            log!(target: target::INIT, trace, "Possible right block: {:?}", &block_inf);
+            if block_inf.is_valid && block_inf.fs_id == fs_id && block_inf.id > last_id {
+                self.explain(RestoreStep::Decision(RestoreDecision::StepRight {
+                    blk_idx,
+                }));
+                begin += 1;
+                last_id = block_inf.id;
+            }
+        }
+
+        // begin will be last value before wraparound,
+        // ids are never wrapped, next id above MAX_BLOCK_ID makes fs read only
+        let next_id = last_id.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+        // ring of one block is full after the first append, next write is at the same block
+        Ok(FsInitAttrs {
+            next_offset: self.data_idx((begin + 1) % self.capacity())?,
+            next_id,
+            is_empty,
+            is_full,
+        })
+    }
+
+    /// Estimated number of reads of binary search over `len` blocks
+    fn search_reads(len: usize) -> usize {
+        (usize::BITS - len.leading_zeros()) as usize
+    }
+
+    /// Read block of restore scan and report progress, `remaining` is estimated number of reads left
+    fn probe(
+        &mut self,
+        blk_idx: usize,
+        read_buf: &mut [u8],
+        crc_mode: CrcMode,
+        header_end: usize,
+        wide_fs_id: bool,
+        remaining: usize,
+    ) -> Result<BlockInfo<BS>, Error> {
+        match crc_mode {
+            // data isn't checked, only header is read
+            CrcMode::HeaderOnly => self.storage.read_prefix(blk_idx, header_end, read_buf)?,
+            CrcMode::Full | CrcMode::Split => self.storage.read(blk_idx, read_buf)?,
+        };
+        if let Some(progress) = self.progress.as_mut() {
+            progress.probed += 1;
+            (progress.callback)(MountProgress {
+                probed: progress.probed,
+                remaining,
+            });
+        }
+
+        let mut info = BlockInfo::<BS>::from_buffer_with_header_end(read_buf, crc_mode, header_end);
+        if wide_fs_id {
+            // high bits of fs id are before custom metadata
+            let begin = header_end - L::METADATA_LEN - fields::FS_ID_HIGH_LEN;
+            info.read_fs_id_high(read_buf, begin);
+        }
+        self.explain(RestoreStep::Probed {
+            blk_idx,
+            fs_id: info.fs_id,
+            id: info.is_valid.then_some(info.id),
+        });
+        Ok(info)
+    }
+
+    /// Report step of restore scan to the trail
+    fn explain(&mut self, step: RestoreStep) {
+        if let Some(trail) = self.trail.as_mut() {
+            (trail.callback)(step);
+        }
+    }
+
+    fn can_have_tail(fs_id: FsId, left: &BlockInfo<BS>, right: &BlockInfo<BS>) -> bool {
+        if !left.is_valid || left.fs_id != fs_id {
+            return false;
+        }
+
+        if !right.is_valid || right.fs_id != fs_id {
+            return true;
+        }
+
+        left.id > right.id
+    }
+
+    /// Write both copies of config block of new (or erased) filesystem
+    fn format_config(&mut self) -> Result<(), Error> {
+        self.config_generation = 0;
+        for _ in 0..config_block::CONFIG_COPIES {
+            self.write_config()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write config to the copy which isn't the newest one, see [`config_block::CONFIG_COPIES`]
+    fn write_config(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        if !self.rewrites_config() {
+            log!(debug, "Config of version 0x1 isn't rewritten");
+            return Ok(());
+        }
+        self.run_writes += 1;
+        let mut config = config_block::FsConfigBlock::new();
+        config.writes_before_format = self.writes_before_format;
+        config.crc_mode = self.config_crc_mode();
+        config.id_high_water = self.id_high_water;
+        config.boot_time = self.boot_time;
+        config.boot_count = self.boot_count;
+        for (entry, bad_block) in config.bad_blocks.iter_mut().zip(self.bad_blocks) {
+            *entry = bad_block.map_or(0, |blk_idx| blk_idx as config_block::BlockCount + 1);
+        }
+        // counters are optional, config of small blocks is written without them
+        let config_size = self.config_size();
+        if config_size >= config_block::REMAPPED_BLOCKS_END {
+            config.crc_failures = self.health.crc_failures;
+            config.write_retries = self.health.write_retries;
+            config.remapped_blocks = self.health.remapped_blocks;
+        }
+        config.reserved_blocks = self.reserved as config_block::BlockCount;
+        // quarantine is kept only in RAM in case it doesn't fit
+        if config_size >= config_block::QUARANTINE_END {
+            for (entry, quarantined) in config.quarantine.iter_mut().zip(self.quarantine) {
+                *entry = quarantined.map_or(0, |blk_idx| blk_idx as config_block::BlockCount + 1);
+            }
+        }
+        // front is kept only in RAM in case it doesn't fit, see `truncate_front`
+        if config_size >= config_block::FRONT_ID_END {
+            config.front_id = self.front_id;
+        }
+        // high priority blocks are appended only in case table fits, see `append_with_priority`
+        if config_size >= config_block::PRIORITY_END {
+            for (entry, pin) in config.priority.iter_mut().zip(self.priority) {
+                *entry = pin.map_or(0, |pin| {
+                    let released = if pin.released {
+                        config_block::PRIORITY_RELEASED
+                    } else {
+                        0
+                    };
+                    (pin.blk_idx as config_block::BlockCount + 1) | released
+                });
+            }
+        }
+        let generation = self.config_generation + 1;
+        Self::write_config_to(self.storage, self.id, generation, config)?;
+        self.config_generation = generation;
+        Ok(())
+    }
+
+    /// Space for config fields in config block
+    fn config_size(&self) -> usize {
+        self.storage.block_size() - fields::DATA_BEGIN - CrcMode::Full.trailer_len()
+    }
+
+    /// Write copy of config of `generation`, `block_count` of `config` is set from the storage
+    fn write_config_to<S2: Storage>(
+        storage: &mut S2,
+        fs_id: FsId,
+        generation: BlockId,
+        mut config: config_block::FsConfigBlock,
+    ) -> Result<(), Error> {
+        let mut config_was_not_written = false;
+        let begin = storage.min_block_index();
+        let blk_idx = begin + (generation % config_block::CONFIG_COPIES as BlockId) as usize;
+        config.block_count = (storage.max_block_index() - begin) as config_block::BlockCount;
+        // header of config block keeps only low bits of wide fs id
+        if is_wide_fs_id(fs_id) {
+            config.crc_mode |= config_block::WIDE_FS_ID_FLAG;
+            config.fs_id = fs_id;
+        }
+        let data_buf = &mut [0_u8; BS];
+        let data_buf = &mut data_buf[..storage.block_size()];
+        // config block is always protected by full crc, its id is generation of the copy
+        let mut factory = BlockFactory::new();
+        factory.set_id(generation);
+        let _ = factory.create_with_writer::<_, BS>(data_buf, fs_id, |block_data| {
+            let config_data = config_block::FsConfigBlock::to_be_bytes(&config);
+            // trailing zeros are read as 0 in case they don't fit
+            let used_len = config_block::FsConfigBlock::used_len(&config_data);
+            let to_copy = core::cmp::min(config_data.len(), block_data.len());
+            if to_copy < used_len {
+                config_was_not_written = true;
+            }
+            block_data[..to_copy].copy_from_slice(&config_data[..to_copy]);
+        });
+        storage.write(blk_idx, data_buf)?;
+
+        if config_was_not_written {
+            return Err(Error::CanNotWriteConfig);
+        }
+
+        Ok(())
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn next_blk_id(&self) -> BlockId {
+        self.blk_factory.id
+    }
+
+    /// Number of blocks which can be appended before ids are exhausted, after that `append` fails with
+    /// `BlockIdsExhausted` (existing blocks are still readable) and storage has to be formatted.
+    /// Ids are not wrapped, so order of blocks is always defined by ids,
+    /// `MAX_BLOCK_ID` is enough for ~292k years of 1M appends per second.
+    pub fn blocks_until_id_exhaustion(&self) -> BlockId {
+        (MAX_BLOCK_ID + 1).saturating_sub(self.next_blk_id())
+    }
+
+    pub fn id(&self) -> FsId {
+        self.id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.is_empty
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.is_full
+    }
+
+    /// Number of data blocks the filesystem can hold (config block and pinned blocks are excluded)
+    pub fn capacity(&self) -> usize {
+        let skipped = self.skipped_blocks().count();
+        (self.storage.max_block_index() - self.data_blk_offset()).saturating_sub(skipped)
+    }
+
+    /// Number of data blocks available for read, `read` accepts offsets in range `0..used_blocks()`
+    pub fn used_blocks(&self) -> usize {
+        self.ring_used_blocks() - self.dropped_blocks() - self.uncommitted
+    }
+
+    /// Number of valid blocks in the ring, including blocks dropped by `truncate_front`
+    fn ring_used_blocks(&self) -> usize {
+        if self.is_full {
+            self.capacity()
+        } else {
+            let skipped = self
+                .skipped_blocks()
+                .filter(|blk_idx| *blk_idx < self.offset)
+                .count();
+            self.offset - self.data_blk_offset() - skipped
+        }
+    }
+
+    /// Snapshot of filesystem attributes and usage
+    pub fn stats(&self) -> FsStats {
+        let capacity = self.capacity();
+        FsStats {
+            id: self.id,
+            offset: self.offset,
+            next_blk_id: self.next_blk_id(),
+            used_blocks: self.used_blocks(),
+            capacity,
+            is_empty: self.is_empty,
+            is_full: self.is_full,
+            wrap_count: self.next_blk_id() / capacity as BlockId,
+        }
+    }
+
+    /// Estimation of storage wear, based on number of block writes
+    pub fn wear_stats(&self) -> WearStats {
+        let total_writes = self.total_writes();
+        let blocks = self.storage.max_block_index() - self.storage.min_block_index();
+        WearStats {
+            total_writes,
+            run_writes: self.run_writes,
+            avg_writes_per_block: total_writes / blocks.max(1) as config_block::WriteCount,
+            ring_cycles: total_writes / self.capacity().max(1) as config_block::WriteCount,
+        }
+    }
+
+    /// Physical operations of erase-aware storage (see [`Storage::io_counters`]) per logical append since
+    /// the filesystem was created or restored, config block rewrites are included. `None` in case storage
+    /// doesn't count operations.
+    pub fn write_amplification(&self) -> Option<WriteAmplification> {
+        let io = self.storage.io_counters()?.since(&self.io_at_mount?);
+        let appends = self.run_appends.max(1);
+        let appended_bytes = appends.saturating_mul(self.data_size() as u64);
+        Some(WriteAmplification {
+            appends: self.run_appends,
+            io,
+            last_append: self.last_append_io,
+            programs_per_100_appends: io.programs.saturating_mul(100) / appends,
+            erases_per_100_appends: io.erases.saturating_mul(100) / appends,
+            bytes_amplification: io.programmed_bytes.saturating_mul(100) / appended_bytes.max(1),
+        })
+    }
+
+    fn account_append(&mut self, io_before: Option<IoCounters>) {
+        self.run_appends += 1;
+        if let (Some(before), Some(after)) = (io_before, self.storage.io_counters()) {
+            self.last_append_io = after.since(&before);
+        }
+    }
+
+    /// Writes of previous filesystems (saved in config on format), config blocks and every append
+    fn total_writes(&self) -> config_block::WriteCount {
+        // one write per config block
+        self.writes_before_format
+            .saturating_add(self.next_blk_id())
+            .saturating_add(self.config_copies as config_block::WriteCount)
+    }
+
+    /// Id of the oldest block available for read (block at offset 0)
+    pub fn oldest_blk_id(&self) -> BlockId {
+        self.ring_oldest_blk_id() + self.dropped_blocks() as BlockId
+    }
+
+    fn ring_oldest_blk_id(&self) -> BlockId {
+        // ids of corrupted storage can be smaller than number of blocks
+        self.next_blk_id()
+            .saturating_sub(self.ring_used_blocks() as BlockId)
+    }
+
+    /// Number of the oldest blocks dropped by `truncate_front`, which are still in the ring
+    fn dropped_blocks(&self) -> usize {
+        let oldest = self.ring_oldest_blk_id();
+        let committed = self.next_blk_id() - self.uncommitted as BlockId;
+        (self.front_id.clamp(oldest, committed) - oldest) as usize
+    }
+
+    /// Offset (for `read`) of the block with given id, `None` if block was overwritten or not written yet.
+    /// Ids of the blocks are sequential, so offset is calculated without any io.
+    pub fn blk_offset_of(&self, id: BlockId) -> Option<usize> {
+        if id < self.oldest_blk_id() || id >= self.next_blk_id() {
+            return None;
+        }
+
+        Some((id - self.oldest_blk_id()) as usize)
+    }
+
+    /// Offset (for `read`) of the first block for which `is_before` returns false, `used_blocks` if there is no
+    /// such block. Data of blocks must be sorted by the key checked in `is_before` (e.g. timestamp written
+    /// by application), then binary search performs `log_2(used_blocks) + 1` reads without any index.
+    /// Use `blk_offset_of` to find block by id, it doesn't need io at all.
+    pub fn find_block<F>(&mut self, mut is_before: F) -> Result<usize, Error>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut begin = 0;
+        let mut end = self.used_blocks();
+        while begin < end {
+            let mid = begin + (end - begin) / 2;
+            let mut before = false;
+            self.read(mid, |data| before = is_before(data))?;
+            if before {
+                begin = mid + 1;
+            } else {
+                end = mid;
+            }
+        }
+
+        Ok(begin)
+    }
+
+    /// First block from `from` offset to the newest one which data matches `pred` (e.g. block with marker),
+    /// see [`Self::find_map`]
+    pub fn find<F>(&mut self, from: usize, mut pred: F) -> Result<Option<FoundBlock>, Error>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let found = self.find_map(from, |data| pred(data).then_some(()))?;
+        Ok(found.map(|(blk, _)| blk))
+    }
+
+    /// Scan blocks from `from` offset to the newest one, returns the first block for which `f` returns `Some`
+    /// with its value. Every block is read once to the internal buffer, invalid (and lapped) blocks are skipped.
+    /// Pass `offset + 1` of the found block to continue search.
+    pub fn find_map<T, F>(&mut self, from: usize, f: F) -> Result<Option<(FoundBlock, T)>, Error>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        self.scan(from..self.used_blocks(), f)
+    }
+
+    /// Same as `find`, but blocks are scanned from the newest one to the oldest (e.g. the last marker)
+    pub fn rfind<F>(&mut self, mut pred: F) -> Result<Option<FoundBlock>, Error>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let found = self.scan((0..self.used_blocks()).rev(), |data| {
+            pred(data).then_some(())
+        })?;
+        Ok(found.map(|(blk, _)| blk))
+    }
+
+    fn scan<T, F>(
+        &mut self,
+        offsets: impl Iterator<Item = usize>,
+        mut f: F,
+    ) -> Result<Option<(FoundBlock, T)>, Error>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        for blk_offset in offsets {
+            let mut found = None;
+            match self.read_with_info(blk_offset, |info, data| {
+                found = f(data).map(|value| (info.id, value));
+            }) {
+                Ok(_) => {}
+                Err(Error::NotValidBlockForRead | Error::Lapped) => continue,
+                Err(e) => return Err(e),
+            }
+            if let Some((id, value)) = found {
+                return Ok(Some((FoundBlock { id, blk_offset }, value)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<'a, S: SharedRead, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>
+    Filesystem<'a, S, BS, L, B>
+{
+    /// Same as `read`, block is read to `buf` (at least block size) instead of internal buffer, so it works
+    /// through shared reference (e.g. concurrent readers behind `RwLock`). Block is checked the same way
+    /// (read policy, quarantine, `Lapped` of read only filesystem, markers of `append_dedup`), though
+    /// read errors aren't counted and damaged block isn't quarantined, `read` does it.
+    pub fn read_with_buf<F>(
+        &self,
+        buf: &mut [u8],
+        blk_offset: usize,
+        reader: F,
+    ) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        let blk_len = self.storage.block_size();
+        if buf.len() < blk_len {
+            return Err(Error::TooSmallBuffer);
+        }
+
+        let blk_idx = self.blk_idx_of(blk_offset)?;
+        if self.is_quarantined(blk_idx) && self.read_policy != ReadPolicy::Report {
+            log!(debug, "Block at {} is quarantined", blk_idx);
+            return Err(Error::NotValidBlockForRead);
+        }
+        let data_buf = &mut buf[..blk_len];
+        self.storage.read_shared(blk_idx, data_buf)?;
+
+        let crc_mode = self.read_crc_mode();
+        let info = Self::info_of(data_buf, crc_mode, self.header, self.id);
+        self.check_read(blk_idx, &info)?;
+        if info.repeat > 0 {
+            let referred_idx = self.referred_blk_idx(&info)?;
+            self.storage.read_shared(referred_idx, data_buf)?;
+            let referred = Self::info_of(data_buf, crc_mode, self.header, self.id);
+            self.check_referred(&info, &referred)?;
+        }
+        let data_end = blk_len - self.crc_mode.trailer_len();
+        reader(&data_buf[self.data_begin()..data_end]);
+        Ok(self.data_size())
+    }
+}
+
+impl<const BS: usize> Filesystem<'_, SliceStorage<'_>, BS> {
+    /// Restore filesystem from untrusted image (e.g. uploaded file or fuzzer input) and read all its used blocks,
+    /// corrupted image must lead to error, never to panic
+    pub fn probe_image(image: &mut [u8], block_size: usize) -> Result<FsStats, Error> {
+        let mut storage = SliceStorage::new(image, block_size)?;
+        let mut fs = Filesystem::<_, BS>::restore(&mut storage)?;
+        for blk_offset in 0..fs.used_blocks() {
+            match fs.read(blk_offset, |_| {}) {
+                Ok(_) | Err(Error::NotValidBlockForRead) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(fs.stats())
+    }
+}
+
+/// How many blocks [`Filesystem::restore_with_validation`] checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MountValidation {
+    /// Only blocks read by binary search of the newest block, reads `log_2(capacity)` blocks
+    #[default]
+    Fast,
+    /// The newest blocks (e.g. written since the last clean shutdown), in addition to `Fast`
+    Newest(usize),
+    /// All used blocks (fsck), reads the whole ring
+    Full,
+}
+
+/// How blocks are validated by reads, see [`Filesystem::set_read_policy`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// Block with wrong crc of its crc mode fails read with `NotValidBlockForRead`
+    #[default]
+    Verify,
+    /// Only crc of header fields is checked, data crc of `Split` mode isn't. Crc of `Full` mode covers
+    /// header and data together, so it's checked as with `Verify`
+    HeaderOnly,
+    /// Block with wrong crc is returned with `BlockInfo::is_valid` false (its id is 0) instead of error,
+    /// block of another filesystem still fails read
+    Report,
+}
+
+/// What [`Filesystem::restore_or_format`] does with blocks of not formatted storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FormatPolicy {
+    /// Blocks of other filesystems stay till they are overwritten, they can be read with `recover_blocks`
+    #[default]
+    Preserve,
+    /// Valid blocks of other fs ids are overwritten and erased after format, blocks with the new id
+    /// (e.g. id derived from hardware) are kept
+    Wipe,
+}
+
+/// Block written by [`Filesystem::append`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppendedBlock {
+    pub id: BlockId,
+    /// Storage index of the block
+    pub offset: usize,
+    pub data_size: usize,
+}
+
+/// Block matched by [`Filesystem::find`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FoundBlock {
+    pub id: BlockId,
+    /// Offset for `read`, from the oldest block
+    pub blk_offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsStats {
+    pub id: FsId,
+    /// Storage index of the block for the next write
+    pub offset: usize,
+    pub next_blk_id: BlockId,
+    pub used_blocks: usize,
+    pub capacity: usize,
+    pub is_empty: bool,
+    pub is_full: bool,
+    /// How many times write position went through the whole storage
+    pub wrap_count: BlockId,
+}
+
+/// Result of [`Filesystem::wear_stats`]. Writes of previous filesystems are counted on format,
+/// so storage must be formatted by appendfs to keep the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WearStats {
+    /// Block writes to the storage range since it was formatted for the first time
+    pub total_writes: config_block::WriteCount,
+    /// Block writes since the filesystem was created or restored
+    pub run_writes: config_block::WriteCount,
+    pub avg_writes_per_block: config_block::WriteCount,
+    /// How many times data blocks were rewritten
+    pub ring_cycles: config_block::WriteCount,
+}
+
+/// Cost of appends in physical operations, see [`Filesystem::write_amplification`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WriteAmplification {
+    /// Appends since the filesystem was created or restored
+    pub appends: config_block::WriteCount,
+    /// Operations since the filesystem was created or restored
+    pub io: IoCounters,
+    /// Operations of the latest append (including config block rewrite caused by it)
+    pub last_append: IoCounters,
+    pub programs_per_100_appends: u64,
+    pub erases_per_100_appends: u64,
+    /// Programmed bytes per 100 bytes of appended data, 100 means no amplification
+    pub bytes_amplification: u64,
+}
+
+/// Filesystem found by [`Filesystem::scan_for_fs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsCandidate {
+    /// Storage index of the first config block
+    pub begin_block: usize,
+    /// Storage index after the last block of filesystem, `None` if config doesn't contain it
+    pub end_block: Option<usize>,
+    pub fs_id: FsId,
+    pub version: config_block::Version,
+}
+
+/// Number of valid blocks with `fs_id`, filled by [`Filesystem::count_fs_ids`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsIdCount {
+    pub fs_id: FsId,
+    pub blocks: usize,
+}
+
+/// Result of [`Filesystem::count_fs_ids`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsIdsReport {
+    pub checked_blocks: usize,
+    pub valid_blocks: usize,
+    /// Number of filled entries of `ids`
+    pub ids_found: usize,
+    /// Valid blocks with ids which didn't fit to `ids`
+    pub untracked_blocks: usize,
+}
+
+/// Error counters of the storage, see [`Filesystem::health`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Health {
+    /// Blocks with wrong crc found by reads and `scrub`
+    pub crc_failures: config_block::HealthCount,
+    /// Appends which were written to the next block because write failed
+    pub write_retries: config_block::HealthCount,
+    /// Blocks marked as bad
+    pub remapped_blocks: config_block::HealthCount,
+}
+
+/// State before append, it's compared with state after append in `strict_invariants` mode
+#[cfg(feature = "strict_invariants")]
+struct Shadow {
+    next_id: BlockId,
+    ring_used: usize,
+    is_full: bool,
+}
+
+#[cfg(feature = "strict_invariants")]
+impl Shadow {
+    fn of<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+        fs: &Filesystem<S, BS, L, B>,
+    ) -> Self {
+        Self {
+            next_id: fs.next_blk_id(),
+            ring_used: fs.ring_used_blocks(),
+            is_full: fs.is_full,
+        }
+    }
+
+    fn check_append<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+        &self,
+        fs: &mut Filesystem<S, BS, L, B>,
+        id: BlockId,
+    ) {
+        fs.check_invariants();
+        assert_eq!(id, self.next_id, "Appended block has unexpected id");
+        assert!(
+            fs.next_blk_id() > id,
+            "Next id {} isn't above appended {}",
+            fs.next_blk_id(),
+            id
+        );
+        assert!(!fs.is_empty, "Fs is empty after append");
+        assert!(
+            fs.is_full || !self.is_full,
+            "Full fs isn't full after append"
+        );
+        // released pin reached by write head returns its block to the ring, so capacity can grow
+        let expected = if self.is_full {
+            fs.capacity()
+        } else {
+            (self.ring_used + 1).min(fs.capacity())
+        };
+        assert_eq!(
+            fs.ring_used_blocks(),
+            expected,
+            "Unexpected used blocks after append, full: {}",
+            fs.is_full
+        );
+        assert_eq!(
+            fs.is_full,
+            self.is_full || self.ring_used + 1 >= fs.capacity(),
+            "Unexpected full flag"
+        );
+    }
+}
+
+/// Progress of restore scan, see [`Filesystem::restore_with_progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MountProgress {
+    /// Blocks read since restore (or `refresh`) started
+    pub probed: usize,
+    /// Estimated number of blocks left to read
+    pub remaining: usize,
+}
+
+/// Step of restore scan, see [`Filesystem::restore_with_trail`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RestoreStep {
+    /// Block read by the scan, `fs_id` is from its header even if it isn't valid, `id` is `None` for not
+    /// valid block
+    Probed {
+        blk_idx: usize,
+        fs_id: FsId,
+        id: Option<BlockId>,
+    },
+    /// Decision made after the last probed block
+    Decision(RestoreDecision),
+}
+
+/// Branch taken by restore scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RestoreDecision {
+    /// Config block doesn't belong to the filesystem, storage is formatted. Steps of the scan of
+    /// the previous filesystem (to count its writes) follow it
+    Format,
+    /// The first data block isn't written, ring is empty
+    Empty,
+    /// The first data block is damaged and the last one is valid, write of the first block after wraparound
+    /// was interrupted
+    DamagedFirst,
+    /// The last data block is newer than the first one, ring is full and wraparound is after the last block
+    WrapAtEnd,
+    /// Block at `blk_idx` is newer than the right end of the search, the newest block is at or after it
+    SearchRight { blk_idx: usize },
+    /// Block at `blk_idx` is older than the right end of the search or isn't valid, the newest block is before it
+    SearchLeft { blk_idx: usize },
+    /// Block at `blk_idx` after the left end of the search is newer, it is the newest block
+    StepRight { blk_idx: usize },
+    /// Scan is finished, the next block is written to `blk_idx` with `next_id`
+    Head { blk_idx: usize, next_id: BlockId },
+}
+
+struct RestoreTrail<'a> {
+    callback: &'a mut (dyn FnMut(RestoreStep) + Send),
+}
+
+impl<'a> core::fmt::Debug for RestoreTrail<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RestoreTrail").finish_non_exhaustive()
+    }
+}
+
+struct MountCallback<'a> {
+    callback: &'a mut (dyn FnMut(MountProgress) + Send),
+    probed: usize,
+}
+
+impl<'a> core::fmt::Debug for MountCallback<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MountCallback")
+            .field("probed", &self.probed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct FsInitAttrs {
+    pub next_offset: usize,
+    pub next_id: BlockId,
+    pub is_empty: bool,
+    pub is_full: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::config_block::FS_VERSION;
+    use core::ops::Range;
+
+    use super::metrics::{Counter, MetricsSink};
+    use super::observer::FsObserver;
+    use super::{
+        fields, Block, BlockInfo, Filesystem, FormatPolicy, FsCandidate, FsIdCount, FsIdsReport,
+        FsStats, Health, MountProgress, MountValidation, ReadPolicy, RestoreDecision, RestoreStep,
+        WearStats,
+    };
+    use crate::block::{
+        derive_fs_id, derive_wide_fs_id, generate_fs_id, BlockFactory, BlockId, BlockLayout,
+        CrcMode, DefaultLayout, FsId, HeaderV2, HeaderVersion, MAX_BLOCK_ID, NARROW_FS_ID_MAX,
+    };
+    use crate::error::Error;
+    use crate::storage::ram::RamStorage;
+    use crate::storage::slice::SliceStorage;
+    use crate::storage::Storage;
+    use crate::utils::slices_are_equal;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_init() {
+        crate::logging::init();
+
+        const BLOCK_SIZE: usize = 128;
+        const BLOCK_COUNT: usize = 512;
+        const SIZE: usize = BLOCK_SIZE * BLOCK_COUNT;
+        // first two blocks are copies of fs config block
+        const AVAILABLE_BLOCK_COUNT: usize = BLOCK_COUNT - 2;
+        const AVAILABLE_SIZE: usize = BLOCK_SIZE * AVAILABLE_BLOCK_COUNT;
+
+        type DefaultStorage = RamStorage<SIZE, BLOCK_SIZE>;
+        type Fs<'a> = Filesystem<'a, DefaultStorage, BLOCK_SIZE>;
+
+        let mut storage = DefaultStorage::new().expect("Can't create storage for test_fs_full");
+
+        {
+            let first_block = BlockInfo::<BLOCK_SIZE>::from_buffer(&storage.data[..BLOCK_SIZE]);
+            assert!(
+                !first_block.is_valid,
+                "First block must not be valid, it contains invalid crc!"
+            );
+        }
+
+        {
+            let fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs for test_fs_empty");
+            assert_eq!(
+                fs.offset, 2,
+                "Storage has no writes, offset must be eq to 2 (0 and 1 are config blocks, next is 2)"
+            );
+        }
+
+        let begin_id = 42;
+        let mut factory = BlockFactory::new();
+        factory.set_id(begin_id);
+
+        let mut i = 0_u8;
+        let mut fill_block = |blk_data: &mut [u8]| {
+            blk_data.fill(i);
+            i = if i == u8::MAX { 0 } else { i + 1 };
+        };
+
+        // fill n-th block,
+        // first AVAILABLE_BLOCK_COUNT iterations test offset initialization for not full storage.
+        // next 2 * AVAILABLE_BLOCK_COUNT iterations test offset initialization for full storage after wraparound
+        for i in 0..AVAILABLE_BLOCK_COUNT * 3 {
+            // first blocks are fs config blocks, so add 2 blocks offset
+            let begin = (i * BLOCK_SIZE) % AVAILABLE_SIZE + 2 * BLOCK_SIZE;
+            let end = begin + BLOCK_SIZE;
+
+            let blk = factory.create_with_writer::<_, BLOCK_SIZE>(
+                &mut storage.data[begin..end],
+                FS_ID,
+                &mut fill_block,
+            );
+
+            let cur_id = begin_id + i as u64;
+            assert_eq!(blk.id(), cur_id);
+
+            {
+                let fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs for test_fs_full");
+                // config blocks are skipped so always add 2 to expected offset
+                let expected_offset = 2 + (i + 1) % AVAILABLE_BLOCK_COUNT;
+                assert_eq!(fs.offset, expected_offset);
+
+                assert_eq!(fs.blk_factory.id, cur_id + 1);
+            }
+        }
+
+        const NEW_BLOCKS: usize = 35;
+        const NEW_FS_ID: FsId = 1585159336;
+
+        // init new blocks with new fs id
+        for b in 0..NEW_BLOCKS {
+            let begin = b * BLOCK_SIZE;
+            let end = begin + BLOCK_SIZE;
+            let block_data = &mut storage.data[begin..end];
+            // write different fs id to first blocks
+            Block::<'_, 256>::set_fs_id(block_data, NEW_FS_ID);
+            Block::<'_, 256>::set_crc(block_data);
+        }
+
+        // validate storage blockes were actually initialized and they are valid
+        for b in 0..AVAILABLE_BLOCK_COUNT {
+            let begin = b * BLOCK_SIZE;
+            let end = begin + BLOCK_SIZE;
+            let block = BlockInfo::<BLOCK_SIZE>::from_buffer(&storage.data[begin..end]);
+            // let first_block = BlockInfo::<BLOCK_SIZE>::from_buffer();
+            assert!(block.is_valid, "Block {} must be valid after write!", b);
+
+            if b < NEW_BLOCKS {
+                assert_eq!(
+                    block.fs_id, NEW_FS_ID,
+                    "First blocks must be init with new fs id"
+                );
+            } else {
+                assert_eq!(
+                    block.fs_id, FS_ID,
+                    "Last blocks must be init with old fs id"
+                );
+            }
+        }
+
+        {
+            let fs = Fs::new(&mut storage, NEW_FS_ID).expect("Can't create fs for new blocks");
+            assert_eq!(
+                fs.offset, NEW_BLOCKS,
+                "Storage was initialized, offset must be after last new block, old blocks must be skipped during fs init"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fs_io() {
+        crate::logging::init();
+
+        const BLOCK_SIZE: usize = 128;
+        const BLOCK_COUNT: usize = 80;
+        const SIZE: usize = BLOCK_SIZE * BLOCK_COUNT;
+        // first two blocks are copies of fs config block
+        const AVAILABLE_BLOCK_COUNT: usize = BLOCK_COUNT - 2;
+        const AVAILABLE_SIZE: usize = BLOCK_SIZE * AVAILABLE_BLOCK_COUNT;
+
+        type DefaultStorage = RamStorage<SIZE, BLOCK_SIZE>;
+        type Fs<'a> = Filesystem<'a, DefaultStorage, BLOCK_SIZE>;
+
+        const DATA_SIZE: usize = Fs::data_block_size();
+
+        let mut storage = DefaultStorage::new().expect("Can't create storage for test_fs_full");
+
+        // write n-th block,
+        // first BLOCK_COUNT iterations test IO for not full storage.
+        // next 2 * BLOCK_COUNT iterations test IO for full storage after wraparound
+        for i in 0..AVAILABLE_BLOCK_COUNT * 3 {
+            // first blocks are fs config blocks, so add 2 blocks offset, to get block end add additional 1 block offset
+            let end = (i * BLOCK_SIZE) % AVAILABLE_SIZE + 3 * BLOCK_SIZE;
+            let begin = end - DATA_SIZE;
+            let mut expected_data = [0_u8; DATA_SIZE];
+            expected_data.copy_from_slice(&storage.data[begin..end]);
+
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs for test_fs_full");
+
+            if i == 0 {
+                assert!(fs.is_empty(), "Before first write fs must be empty!");
+            } else {
+                assert!(
+                    !fs.is_empty(),
+                    "After first write fs must be non empty! i: {}",
+                    i
+                );
+            }
+
+            if i < AVAILABLE_BLOCK_COUNT {
+                assert!(
+                    !fs.is_full(),
+                    "Fs can't be full before AVAILABLE_BLOCK_COUNT writes, i: {}",
+                    i
+                );
+            } else {
+                assert!(
+                    fs.is_full(),
+                    "Fs must be full after AVAILABLE_BLOCK_COUNT writes, i: {}",
+                    i
+                );
+            }
+
+            // read the oldest block, that will be overwritten
+            let blk_offset = if i >= AVAILABLE_BLOCK_COUNT { 0 } else { i };
+            let read_before = fs.read(blk_offset, |blk_data| {
+                assert!(
+                    slices_are_equal(&expected_data[..], blk_data),
+                    "Wrong data was read at i: {}, {:?} vs {:?}",
+                    i,
+                    &expected_data[..],
+                    blk_data
+                );
+            });
+
+            match read_before {
+                Ok(_) => {
+                    assert!(
+                        i >= AVAILABLE_BLOCK_COUNT,
+                        "Data must be read only after wraparound, i: {}",
+                        i
+                    );
+                }
+                Err(Error::NotValidBlockForRead) => {
+                    assert!(
+                        i < AVAILABLE_BLOCK_COUNT,
+                        "Data must not be read before wraparound, i: {}",
+                        i
+                    );
+                }
+                Err(e) => {
+                    panic!("Err read data before write at i: {}, err: {:?}", i, e);
+                }
+            }
+
+            assert!(
+                i < u8::MAX as usize,
+                "I will be wrapped around, can't continue test."
+            );
+
+            let fill_value = (i + 1) as u8;
+            let write = fs.append(|blk_data| {
+                blk_data.fill(fill_value);
+            });
+            assert!(write.is_ok(), "Err write data i: {}, err: {:?}", i, write);
+
+            expected_data.fill(fill_value);
+
+            const LAST_WRITE_BEFORE_FS_BECOME_FULL: usize = AVAILABLE_BLOCK_COUNT - 1;
+            let blk_offset = if i < LAST_WRITE_BEFORE_FS_BECOME_FULL {
+                assert!(!fs.is_full(), "Fs must not be full after write {}", i);
+                i
+            } else {
+                assert!(fs.is_full(), "Fs must be full after write {}", i);
+                AVAILABLE_BLOCK_COUNT - 1
+            };
+            let read_after = fs.read(blk_offset, |blk_data| {
+                assert!(
+                    slices_are_equal(&expected_data[..], blk_data),
+                    "Wrong data was read after write at i: {}, {:?} vs {:?}",
+                    i,
+                    &expected_data[..],
+                    blk_data
+                );
+            });
+            assert!(
+                read_after.is_ok(),
+                "Err read data after write at i: {}, err: {:?}",
+                i,
+                read_after
+            );
+        }
+    }
+
+    #[test]
+    fn test_fs_runtime_block_size() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+        const MAX_BLOCK_SIZE: usize = 128;
+        const DATA_SIZE: usize = BLOCK_SIZE - Block::<BLOCK_SIZE>::attributes_size();
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, MAX_BLOCK_SIZE>;
+
+        let mut storage = RamStorage::<SIZE, BLOCK_SIZE>::new()
+            .expect("Can't create storage for test_fs_runtime_block_size");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs with smaller blocks");
+        assert_eq!(fs.block_size(), BLOCK_SIZE);
+        assert_eq!(
+            fs.data_size(),
+            BLOCK_SIZE - Block::<BLOCK_SIZE>::attributes_size()
+        );
+
+        let written = fs.append(|blk_data| blk_data.fill(42));
+        assert_eq!(written.ok().map(|b| b.data_size), Some(fs.data_size()));
+        let read = fs.read(0, |blk_data| {
+            assert_eq!(blk_data.len(), DATA_SIZE);
+            assert!(blk_data.iter().all(|b| *b == 42));
+        });
+        assert!(read.is_ok(), "Can't read block: {:?}", read);
+
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs with smaller blocks");
+        assert_eq!(fs.used_blocks(), 1);
+
+        type TooSmallFs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, 32>;
+        assert!(matches!(
+            TooSmallFs::new(&mut storage, FS_ID),
+            Err(Error::BlockSizeMismatch {
+                block_size: BLOCK_SIZE,
+                max_block_size: 32
+            })
+        ));
+    }
+
+    #[test]
+    fn test_fs_stats() {
+        const BLOCK_SIZE: usize = 64;
+        const BLOCK_COUNT: usize = 8;
+        const SIZE: usize = BLOCK_SIZE * BLOCK_COUNT;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage for test_fs_stats");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs for test_fs_stats");
+
+        let stats = fs.stats();
+        assert!(stats.is_empty && !stats.is_full);
+        assert_eq!(stats.capacity, BLOCK_COUNT - 2);
+        assert_eq!((stats.used_blocks, stats.wrap_count), (0, 0));
+
+        for _ in 0..BLOCK_COUNT + 2 {
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append block");
+        }
+
+        let stats = fs.stats();
+        assert!(!stats.is_empty && stats.is_full);
+        assert_eq!(stats.used_blocks, BLOCK_COUNT - 2);
+        assert_eq!(stats.next_blk_id, BLOCK_COUNT as u64 + 2);
+        assert_eq!(stats.wrap_count, 1);
+        assert_eq!(stats.offset, 6);
+    }
+
+    #[test]
+    fn test_fs_restore_checks_config() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert!(Fs::restore(&mut storage).is_ok());
+
+        let config = &mut storage.data[fields::DATA_BEGIN..BLOCK_SIZE];
+        // config of version 1 has no magic
+        config.fill(0);
+        config[..4].copy_from_slice(&1_u32.to_be_bytes());
+        Block::<BLOCK_SIZE>::set_crc(&mut storage.data[..BLOCK_SIZE]);
+        assert!(
+            Fs::restore(&mut storage).is_ok(),
+            "Old config must be valid"
+        );
+
+        // block with valid crc, but random data, the other copy of config is used
+        storage.data[fields::DATA_BEGIN..BLOCK_SIZE].fill(0x5a);
+        Block::<BLOCK_SIZE>::set_crc(&mut storage.data[..BLOCK_SIZE]);
+        assert!(Fs::restore(&mut storage).is_ok());
+
+        for copy in storage.data[..BLOCK_SIZE * 2].chunks_exact_mut(BLOCK_SIZE) {
+            copy[fields::DATA_BEGIN..].fill(0x5a);
+            Block::<BLOCK_SIZE>::set_crc(copy);
+        }
+        assert!(matches!(
+            Fs::restore(&mut storage),
+            Err(Error::InvalidHeaderBlock)
+        ));
+    }
+
+    #[test]
+    fn test_fs_scan() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 32;
+
+        type Fs<'a> = Filesystem<'a, SliceStorage<'a>, BLOCK_SIZE>;
+
+        let mut image = [0_u8; SIZE];
+        {
+            let mut storage =
+                SliceStorage::new(&mut image[BLOCK_SIZE * 5..BLOCK_SIZE * 20], BLOCK_SIZE)
+                    .expect("Can't create storage");
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for _ in 0..20 {
+                fs.append(|blk_data| blk_data.fill(1))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut storage =
+            SliceStorage::new(&mut image[..], BLOCK_SIZE).expect("Can't create storage");
+        let mut candidate = None;
+        let found = Fs::scan_for_fs(&mut storage, |c| {
+            candidate = Some(c);
+            true
+        })
+        .expect("Can't scan storage");
+        assert_eq!(found, 1);
+        assert_eq!(
+            candidate,
+            Some(FsCandidate {
+                begin_block: 5,
+                end_block: Some(20),
+                fs_id: FS_ID,
+                version: FS_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn test_fs_watermarks() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        #[derive(Default)]
+        struct Watermarks {
+            // percent and used blocks for every notification
+            hits: [(u8, usize); 4],
+            len: usize,
+        }
+
+        impl FsObserver for Watermarks {
+            fn on_watermark(&mut self, percent: u8, stats: &FsStats) {
+                self.hits[self.len] = (percent, stats.used_blocks);
+                self.len += 1;
+            }
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut observer = Watermarks::default();
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.set_observer(&mut observer, &[50, 100]);
+            for _ in 0..fs.capacity() * 2 {
+                fs.append(|blk_data| blk_data.fill(1))
+                    .expect("Can't append");
+            }
+        }
+
+        // capacity is 7 blocks
+        assert_eq!(&observer.hits[..observer.len], &[(50, 4), (100, 7)]);
+    }
+
+    #[test]
+    fn test_fs_evict() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        #[derive(Default)]
+        struct Archive {
+            evicted: [u64; 8],
+            len: usize,
+        }
+
+        impl FsObserver for Archive {
+            fn wants_evicted_blocks(&self) -> bool {
+                true
+            }
+
+            fn on_evict(&mut self, id: u64, data: &[u8]) {
+                assert!(data.iter().all(|b| *b as u64 == id));
+                self.evicted[self.len] = id;
+                self.len += 1;
+            }
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut observer = Archive::default();
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.set_observer(&mut observer, &[]);
+            // capacity is 7 blocks, last 3 appends overwrite blocks 0..3
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+
+            let mut oldest = 0;
+            fs.read(0, |blk_data| oldest = blk_data[0])
+                .expect("Can't read oldest block");
+            assert_eq!(oldest, 3);
+        }
+
+        assert_eq!(&observer.evicted[..observer.len], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_fs_export() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 17;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..20 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+
+        // stale blocks of the same fs after the copied ones must be ignored
+        let mut image = [0_u8; BLOCK_SIZE * 32];
+        for blk in image.chunks_mut(BLOCK_SIZE) {
+            BlockFactory::new().create_with_writer::<_, BLOCK_SIZE>(blk, FS_ID, |d| d.fill(0));
+        }
+        {
+            let mut dst = SliceStorage::new(&mut image[..], BLOCK_SIZE).expect("Can't create dst");
+            assert_eq!(fs.export_to(&mut dst).expect("Can't export"), 15);
+
+            let mut copy = Filesystem::<_, BLOCK_SIZE>::restore(&mut dst).expect("Can't restore");
+            assert_eq!(copy.id(), FS_ID);
+            assert_eq!((copy.oldest_blk_id(), copy.next_blk_id()), (5, 20));
+            for i in 0..copy.used_blocks() {
+                copy.read(i, |blk_data| assert_eq!(blk_data[0] as usize, i + 5))
+                    .expect("Can't read copy");
+            }
+        }
+
+        // only the newest blocks fit to smaller storage
+        let mut small = [0_u8; BLOCK_SIZE * 7];
+        {
+            let mut dst = SliceStorage::new(&mut small[..], BLOCK_SIZE).expect("Can't create dst");
+            assert_eq!(fs.export_to(&mut dst).expect("Can't export"), 5);
+            let copy = Filesystem::<_, BLOCK_SIZE>::restore(&mut dst).expect("Can't restore");
+            assert!(copy.is_full());
+            assert_eq!((copy.oldest_blk_id(), copy.next_blk_id()), (15, 20));
+        }
+
+        let mut other = [0_u8; 128 * 4];
+        let mut dst = SliceStorage::new(&mut other[..], 128).expect("Can't create dst");
+        assert!(matches!(
+            fs.export_to(&mut dst),
+            Err(Error::InvalidBlockSizeForStorage)
+        ));
+    }
+
+    #[test]
+    fn test_fs_secure_erase() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        const PATTERN: u8 = 0xa5;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            fs.secure_erase(PATTERN).expect("Can't erase fs");
+            assert!(fs.is_empty());
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (0, 0));
+        }
+
+        assert!(storage.data[BLOCK_SIZE * 2..].iter().all(|b| *b == PATTERN));
+        let fs = Fs::restore(&mut storage).expect("Can't restore erased fs");
+        assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 0));
+    }
+
+    #[test]
+    fn test_fs_bad_blocks() {
+        // table of bad blocks doesn't fit to config of 64 byte block
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+        const BAD: usize = 3;
+        const NEW_FS_ID: FsId = 1585159336;
+
+        struct Worn {
+            inner: RamStorage<SIZE, BLOCK_SIZE>,
+            in_place: bool,
+        }
+
+        impl Storage for Worn {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.inner.read(blk_idx, data)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                if blk_idx == BAD {
+                    return Err(Error::CanNotPerformWrite);
+                }
+                self.inner.write(blk_idx, data)
+            }
+            fn can_write_in_place(&self) -> bool {
+                self.in_place
+            }
+            fn write_in_place<F>(&mut self, blk_idx: usize, writer: F) -> Result<usize, Error>
+            where
+                F: FnOnce(&mut [u8]),
+            {
+                if blk_idx == BAD {
+                    return Err(Error::CanNotPerformWrite);
+                }
+                self.inner.write_in_place(blk_idx, writer)
+            }
+            fn block_size(&self) -> usize {
+                self.inner.block_size()
+            }
+            fn min_block_index(&self) -> usize {
+                self.inner.min_block_index()
+            }
+            fn max_block_index(&self) -> usize {
+                self.inner.max_block_index()
+            }
+        }
+
+        type Fs<'a> = Filesystem<'a, Worn, BLOCK_SIZE>;
+
+        let check = |fs: &mut Fs| {
+            assert!(fs.bad_blocks().eq([BAD]));
+            assert_eq!((fs.capacity(), fs.used_blocks()), (6, 6));
+            for offset in 0..6 {
+                fs.read(offset, |blk_data| assert_eq!(blk_data[0], offset as u8 + 4))
+                    .expect("Can't read");
+            }
+        };
+        // block which can't be written is skipped by both write paths
+        for in_place in [true, false] {
+            let mut storage = Worn {
+                inner: RamStorage::new().expect("Can't create storage"),
+                in_place,
+            };
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                let appended = fs
+                    .append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+                assert_ne!(appended.offset, BAD);
+            }
+            assert_eq!(fs.next_blk_id(), 10);
+            assert_eq!(fs.health().write_retries, 1);
+            check(&mut fs);
+        }
+
+        let mut storage = Worn {
+            inner: RamStorage::new().expect("Can't create storage"),
+            in_place: false,
+        };
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            check(&mut fs);
+            assert!(matches!(fs.mark_bad(0), Err(Error::BlockOutOfRange)));
+            for blk_idx in 4..7 {
+                fs.mark_bad(blk_idx).expect("Can't mark bad block");
+            }
+            assert!(matches!(fs.mark_bad(7), Err(Error::BadBlockTableIsFull)));
+            assert_eq!(fs.capacity(), 3);
+        }
+
+        // table is kept by format with another id
+        let fs = Fs::new_with_force(&mut storage, NEW_FS_ID, CrcMode::Full, true)
+            .expect("Can't create fs");
+        assert_eq!(fs.bad_blocks().count(), 4);
+        assert_eq!((fs.capacity(), fs.used_blocks()), (3, 0));
+    }
+
+    #[test]
+    fn test_fs_health() {
+        // counters don't fit to config of 128 byte block with all header features
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert_eq!(fs.health(), Health::default());
+            for i in 0..4 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        storage.data[BLOCK_SIZE * 3 + 30] ^= 0xFF;
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert!(matches!(
+                fs.read(1, |_| {}),
+                Err(Error::NotValidBlockForRead)
+            ));
+            fs.mark_bad(6).expect("Can't mark bad block");
+            let health = fs.health();
+            assert_eq!((health.crc_failures, health.remapped_blocks), (1, 1));
+            assert!(fs.read(1, |_| {}).is_err());
+            fs.persist_health().expect("Can't persist health");
+        }
+        // invalid block of another filesystem isn't a crc failure of this one
+        storage.data[BLOCK_SIZE * 4 + fields::FS_ID_BEGIN] ^= 0xFF;
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert!(fs.read(2, |_| {}).is_err());
+            assert_eq!(fs.health().crc_failures, 2);
+        }
+
+        let expected = Health {
+            crc_failures: 2,
+            write_retries: 0,
+            remapped_blocks: 1,
+        };
+        assert_eq!(
+            Fs::restore(&mut storage)
+                .expect("Can't restore fs")
+                .health(),
+            expected
+        );
+        // counters are kept by format like bad blocks
+        let fs = Fs::new_with_force(&mut storage, FS_ID + 1, CrcMode::Full, true)
+            .expect("Can't create fs");
+        assert_eq!(fs.health(), expected);
+    }
+
+    #[test]
+    fn test_fs_foreign_filesystem() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        const NEW_FS_ID: FsId = 1300452877;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append");
+        }
+        assert!(matches!(
+            Fs::new(&mut storage, NEW_FS_ID),
+            Err(Error::ForeignFilesystem { found_fs_id: FS_ID })
+        ));
+        let fs = Fs::new(&mut storage, FS_ID).expect("Can't restore fs");
+        assert_eq!(fs.used_blocks(), 1);
+
+        let fs = Fs::new_with_force(&mut storage, NEW_FS_ID, CrcMode::Full, true)
+            .expect("Can't format fs");
+        assert_eq!((fs.id(), fs.used_blocks()), (NEW_FS_ID, 0));
+    }
+
+    #[test]
+    fn test_fs_probe_image() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, SliceStorage<'a>, BLOCK_SIZE>;
+
+        let mut image = [0_u8; SIZE];
+        {
+            let mut storage =
+                SliceStorage::new(&mut image, BLOCK_SIZE).expect("Can't create storage");
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        let stats = Fs::probe_image(&mut image, BLOCK_SIZE).expect("Can't probe image");
+        assert_eq!((stats.id, stats.used_blocks), (FS_ID, 6));
+
+        // every byte of header and config is flipped, image is either restored or rejected
+        for i in 0..BLOCK_SIZE * 3 {
+            let mut damaged = image;
+            damaged[i] ^= 0xA5;
+            let _ = Fs::probe_image(&mut damaged, BLOCK_SIZE);
+        }
+        assert!(Fs::probe_image(&mut [0xFF; SIZE], BLOCK_SIZE).is_err());
+        assert!(Fs::probe_image(&mut image, 100).is_err());
+    }
+
+    #[cfg(feature = "strict_invariants")]
+    #[test]
+    #[should_panic(expected = "is out of data blocks")]
+    fn test_fs_strict_invariants() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        let mut storage = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        let mut fs =
+            Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+        fs.append(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+        // write head moved to config block by a bug
+        fs.offset = 0;
+        fs.check_invariants();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fs_serde() {
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        let mut storage = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+        let mut fs =
+            Filesystem::<_, BLOCK_SIZE>::mount_for_write(&mut storage).expect("Can't mount fs");
+        fs.append(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+
+        let stats = fs.stats();
+        let json = serde_json::to_string(&stats).expect("Can't serialize stats");
+        assert!(json.contains("\"used_blocks\":1"));
+        let parsed: FsStats = serde_json::from_str(&json).expect("Can't parse stats");
+        assert_eq!(parsed, stats);
+        let json = serde_json::to_string(&fs.health()).expect("Can't serialize health");
+        let parsed: Health = serde_json::from_str(&json).expect("Can't parse health");
+        assert_eq!(parsed, fs.health());
+
+        let blk_idx = fs.blk_idx_of(0).expect("Can't get block index");
+        let mut json = None;
+        fs.read_raw(blk_idx, |block| {
+            json = Some(serde_json::to_string(
+                &BlockInfo::<BLOCK_SIZE>::from_buffer(block),
+            ))
+        })
+        .expect("Can't read block");
+        let json = json
+            .expect("Block wasn't read")
+            .expect("Can't serialize block info");
+        let info: BlockInfo<BLOCK_SIZE> =
+            serde_json::from_str(&json).expect("Can't parse block info");
+        assert!(info.is_valid);
+        assert_eq!((info.id, info.fs_id), (0, FS_ID));
+
+        // boot counter is written to the second config block
+        let mut config = None;
+        fs.read_raw(1, |block| {
+            config =
+                super::config_block::FsConfigBlock::from_block_data(&block[fields::DATA_BEGIN..])
+        })
+        .expect("Can't read config");
+        let json = serde_json::to_string(&config.expect("Config isn't valid"))
+            .expect("Can't serialize config");
+        assert!(json.contains("\"boot_count\":1"));
+    }
+
+    #[test]
+    fn test_fs_header_v2() {
+        const BLOCK_SIZE: usize = 256;
+        const BLOCKS: usize = 8;
+        const FS_ID: FsId = 2017384957;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * BLOCKS }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        // ring formatted with v1 header stays v1
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        fs.append(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+        let res = fs.append_with_header(HeaderV2::default(), |blk_data| blk_data.fill(2));
+        assert!(matches!(res, Err(Error::UnsupportedHeaderVersion)));
+
+        let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+            .expect("Can't restore fs");
+        assert_eq!(fs.header_version(), HeaderVersion::V1);
+        assert_eq!(fs.used_blocks(), 1);
+        fs.read_with_info(0, |info, data| {
+            assert!(info.header.is_none());
+            assert!(data.iter().all(|b| *b == 1));
+        })
+        .expect("Can't read");
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs =
+            Fs::new_with_header(&mut storage, FS_ID, CrcMode::HeaderOnly, HeaderVersion::V2)
+                .expect("Can't create fs");
+        assert_eq!(
+            fs.data_size(),
+            Fs::data_block_size() - HeaderVersion::V2.extension_len()
+        );
+        fs.append(|blk_data| blk_data.fill(3))
+            .expect("Can't append");
+        let header = HeaderV2 {
+            flags: 1,
+            length: 4,
+            timestamp: 5,
+            tag: 6,
+        };
+        fs.append_with_header(header, |blk_data| blk_data.fill(4))
+            .expect("Can't append");
+        fs.append(|blk_data| blk_data.fill(5))
+            .expect("Can't append");
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.header_version(), HeaderVersion::V2);
+        let data_size = fs.data_size();
+        fs.read_with_info(0, |info, data| {
+            let expected = HeaderV2 {
+                length: data_size as u16,
+                ..HeaderV2::default()
+            };
+            assert_eq!(info.header, Some(expected));
+            assert_eq!(data.len(), data_size);
+            assert!(data.iter().all(|b| *b == 3));
+        })
+        .expect("Can't read");
+        fs.read_with_info(1, |info, data| {
+            assert_eq!(info.header, Some(header));
+            assert!(data.iter().all(|b| *b == 4));
+        })
+        .expect("Can't read");
+
+        // data isn't covered by crc in this mode, v2 header fields are
+        let data_begin = fields::DATA_BEGIN + HeaderVersion::V2.extension_len();
+        storage.data[2 * BLOCK_SIZE + data_begin] ^= 0xff;
+        storage.data[3 * BLOCK_SIZE + data_begin - 1] ^= 0xff;
+        storage.data[4 * BLOCK_SIZE + fields::DATA_BEGIN] ^= 0xff;
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert!(fs.read(0, |_| {}).is_ok());
+        for blk_offset in 1..3 {
+            assert!(matches!(
+                fs.read(blk_offset, |_| {}),
+                Err(Error::NotValidBlockForRead)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_fs_barrier() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 5;
+        const LOST: usize = 3;
+        const FS_ID: FsId = 1650555506;
+
+        /// Write of `LOST` block is acknowledged, but never reaches the medium
+        struct Lossy {
+            inner: RamStorage<SIZE, BLOCK_SIZE>,
+            syncs: usize,
+        }
+
+        impl Storage for Lossy {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.inner.read(blk_idx, data)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                if blk_idx == LOST {
+                    return Ok(data.len());
+                }
+                self.inner.write(blk_idx, data)
+            }
+            fn sync(&mut self) -> Result<(), Error> {
+                self.syncs += 1;
+                Ok(())
+            }
+            fn block_size(&self) -> usize {
+                self.inner.block_size()
+            }
+            fn min_block_index(&self) -> usize {
+                self.inner.min_block_index()
+            }
+            fn max_block_index(&self) -> usize {
+                self.inner.max_block_index()
+            }
+        }
+
+        let mut storage = Lossy {
+            inner: RamStorage::new().expect("Can't create storage"),
+            syncs: 0,
+        };
+        let mut fs =
+            Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+        fs.barrier(true).expect("Barrier of empty fs failed");
+        fs.append(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+        fs.barrier(true).expect("Barrier failed");
+
+        fs.append(|blk_data| blk_data.fill(2))
+            .expect("Can't append");
+        fs.barrier(false).expect("Barrier without verify failed");
+        assert!(matches!(fs.barrier(true), Err(Error::BarrierFailed)));
+        assert_eq!(storage.syncs, 4);
+    }
+
+    #[test]
+    fn test_fs_write_amplification() {
+        use crate::storage::IoCounters;
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        const SECTOR: u64 = 2 * BLOCK_SIZE as u64;
+
+        /// Write of a block rewrites the whole sector of 2 blocks
+        struct Sectors {
+            inner: RamStorage<SIZE, BLOCK_SIZE>,
+            counters: IoCounters,
+        }
+
+        impl Storage for Sectors {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.inner.read(blk_idx, data)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                self.counters.erases += 1;
+                self.counters.programs += 1;
+                self.counters.programmed_bytes += SECTOR;
+                self.inner.write(blk_idx, data)
+            }
+            fn io_counters(&self) -> Option<IoCounters> {
+                Some(self.counters)
+            }
+            fn block_size(&self) -> usize {
+                self.inner.block_size()
+            }
+            fn min_block_index(&self) -> usize {
+                self.inner.min_block_index()
+            }
+            fn max_block_index(&self) -> usize {
+                self.inner.max_block_index()
+            }
+        }
+
+        let mut storage = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        let fs = Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert!(fs.write_amplification().is_none());
+
+        let mut storage = Sectors {
+            inner: RamStorage::new().expect("Can't create storage"),
+            counters: IoCounters::default(),
+        };
+        let mut fs =
+            Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..4 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        let data_size = fs.data_size() as u64;
+        let amplification = fs.write_amplification().expect("No counters");
+        let per_append = IoCounters {
+            programs: 1,
+            erases: 1,
+            programmed_bytes: SECTOR,
+        };
+        assert_eq!(amplification.appends, 4);
+        assert_eq!(amplification.last_append, per_append);
+        assert_eq!(amplification.io.programs, 4);
+        assert_eq!(amplification.programs_per_100_appends, 100);
+        assert_eq!(amplification.erases_per_100_appends, 100);
+        assert_eq!(amplification.bytes_amplification, SECTOR * 100 / data_size);
+    }
+
+    #[test]
+    fn test_fs_open_readonly() {
+        use core::cell::RefCell;
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        /// Writer and reader use the same storage, e.g. the same device opened by two processes
+        struct Shared<'s>(&'s RefCell<RamStorage<SIZE, BLOCK_SIZE>>);
+
+        impl Storage for Shared<'_> {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.0.borrow_mut().read(blk_idx, data)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                self.0.borrow_mut().write(blk_idx, data)
+            }
+            fn block_size(&self) -> usize {
+                BLOCK_SIZE
+            }
+            fn min_block_index(&self) -> usize {
+                0
+            }
+            fn max_block_index(&self) -> usize {
+                SIZE / BLOCK_SIZE
+            }
+        }
+
+        type Fs<'a, 's> = Filesystem<'a, Shared<'s>, BLOCK_SIZE>;
+
+        let ram = RefCell::new(RamStorage::new().expect("Can't create storage"));
+        let mut reader_storage = Shared(&ram);
+        assert!(matches!(
+            Fs::open_readonly(&mut reader_storage),
+            Err(Error::InvalidHeaderBlock)
+        ));
+
+        let mut writer_storage = Shared(&ram);
+        let mut writer = Fs::new(&mut writer_storage, FS_ID).expect("Can't create fs");
+        for i in 0..3 {
+            writer
+                .append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+
+        let config = ram.borrow().data[..BLOCK_SIZE * 2].to_vec();
+        let mut reader = Fs::open_readonly(&mut reader_storage).expect("Can't open fs");
+        assert!(reader.is_read_only());
+        assert_eq!(reader.used_blocks(), 3);
+        assert!(matches!(
+            reader.append(|blk_data| blk_data.fill(0)),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(reader.truncate_tail(1), Err(Error::ReadOnly)));
+        // boot counter isn't incremented by reader
+        assert!(slices_are_equal(
+            &ram.borrow().data[..BLOCK_SIZE * 2],
+            &config
+        ));
+
+        // blocks of the reader are overwritten by the newer ones
+        for i in 3..10 {
+            writer
+                .append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        assert!(matches!(reader.read(0, |_| {}), Err(Error::Lapped)));
+
+        reader.refresh().expect("Can't refresh");
+        assert_eq!(reader.used_blocks(), 7);
+        assert_eq!(reader.oldest_blk_id(), 3);
+        reader
+            .read(0, |blk_data| assert!(blk_data.iter().all(|v| *v == 3)))
+            .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_load_into() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+        type Fs<'a, S> = Filesystem<'a, S, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..10 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        assert!(matches!(
+            fs.load_into::<SIZE, 64>(),
+            Err(Error::InvalidBlockSizeForStorage)
+        ));
+        assert!(matches!(
+            fs.load_into::<{ BLOCK_SIZE * 4 }, BLOCK_SIZE>(),
+            Err(Error::TooSmallFilesystem)
+        ));
+
+        fn check<S: Storage>(copy: &mut Fs<'_, S>) {
+            assert_eq!((copy.used_blocks(), copy.oldest_blk_id()), (7, 3));
+            for offset in 0..7 {
+                copy.read(offset, |blk_data| {
+                    assert!(blk_data.iter().all(|v| *v == offset as u8 + 3))
+                })
+                .expect("Can't read");
+            }
+        }
+        let mut ram = fs.load_into::<SIZE, BLOCK_SIZE>().expect("Can't load fs");
+        check(&mut Fs::restore(&mut ram).expect("Can't restore copy"));
+
+        #[cfg(feature = "std")]
+        {
+            let mut image = fs.load_to_vec().expect("Can't load fs");
+            let mut slice = SliceStorage::new(&mut image, BLOCK_SIZE).expect("Can't open image");
+            check(&mut Fs::restore(&mut slice).expect("Can't restore copy"));
+        }
+    }
+
+    #[test]
+    fn test_fs_record_stream() {
+        use super::record::{RecordStream, RecordWriter, CHUNK_FIRST};
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let write = |fs: &mut Fs, records: &[(u8, usize)]| {
+            let mut writer = RecordWriter::new(fs);
+            let mut buf = [0_u8; 512];
+            for (seed, len) in records {
+                for (i, b) in buf[..*len].iter_mut().enumerate() {
+                    *b = seed.wrapping_add(i as u8);
+                }
+                writer.write(&buf[..*len]).expect("Can't write record");
+            }
+            writer.flush().expect("Can't flush records");
+        };
+        // (seed, len) of read records, content is checked
+        fn read<const R: usize>(fs: &mut Fs, out: &mut [(u8, usize)]) -> (usize, usize) {
+            let mut stream = RecordStream::<_, BLOCK_SIZE, R, _>::new(fs);
+            let mut count = 0;
+            while let Some(record) = stream.next_record().expect("Can't read record") {
+                let seed = record.first().map_or(0, |b| *b);
+                assert!(record
+                    .iter()
+                    .enumerate()
+                    .all(|(i, b)| *b == seed.wrapping_add(i as u8)));
+                out[count] = (seed, record.len());
+                count += 1;
+            }
+            (count, stream.dropped())
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        let data_size = fs.data_size();
+        // record of two and a half blocks spans three blocks
+        let records = [(1, 3), (2, data_size * 5 / 2), (0, 0), (4, 10), (5, 50)];
+        write(&mut fs, &records);
+        assert_eq!(fs.used_blocks(), 4);
+        let mut out = [(0, 0); 64];
+        assert_eq!(read::<512>(&mut fs, &mut out), (5, 0));
+        assert_eq!(out[..5], records);
+        // record bigger than buffer is dropped
+        assert_eq!(read::<64>(&mut fs, &mut out), (4, 1));
+        assert_eq!(out[..4], [(1, 3), (0, 0), (4, 10), (5, 50)]);
+
+        // wrap the ring, the oldest record in the ring begins in overwritten block, its tail is skipped
+        let records: [(u8, usize); 40] = core::array::from_fn(|i| (10 + i as u8, 40));
+        write(&mut fs, &records);
+        let (count, dropped) = read::<512>(&mut fs, &mut out);
+        assert_eq!(dropped, 0);
+        assert!(count > 0 && count < 40);
+        assert!(out[..count]
+            .iter()
+            .zip(&records[40 - count..])
+            .all(|(a, b)| a == b));
+
+        // power loss before the tail of the record was written
+        fs.append(|data| {
+            data.fill(0);
+            data[..2].copy_from_slice(&(CHUNK_FIRST | 10).to_le_bytes());
+        })
+        .expect("Can't append");
+        write(&mut fs, &[(100, 20)]);
+        let (count, dropped) = read::<512>(&mut fs, &mut out);
+        assert_eq!(dropped, 1);
+        assert_eq!(out[count - 1], (100, 20));
+    }
+
+    #[test]
+    fn test_fs_kv_overlay() {
+        use super::kv::{KvOverlay, KV_RECORD_TAG};
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+        type Kv<'f, 'a> =
+            KvOverlay<'f, 'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE, 64, 3, DefaultLayout>;
+
+        let check = |kv: &mut Kv, key: &[u8], expected: Option<&[u8]>| {
+            let mut is_equal = false;
+            let found = kv
+                .get(key, |v| is_equal = Some(v) == expected)
+                .expect("Can't get value");
+            assert_eq!(found, expected.is_some());
+            assert_eq!(is_equal, found);
+        };
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        let mut kv = Kv::new(&mut fs).expect("Can't create kv");
+        check(&mut kv, b"mode", None);
+        kv.set(b"mode", &[1]).expect("Can't set");
+        kv.set(b"name", b"dev").expect("Can't set");
+        kv.set(b"mode", &[2]).expect("Can't set");
+        check(&mut kv, b"mode", Some(&[2]));
+        check(&mut kv, b"name", Some(b"dev"));
+        let mut keys = 0;
+        kv.iter_keys(|key| {
+            assert!(key == b"mode" || key == b"name");
+            keys += 1;
+        });
+        assert_eq!(keys, 2);
+
+        assert!(matches!(kv.set(b"", &[1]), Err(Error::InvalidKey)));
+        assert!(matches!(kv.set(&[b'k'; 17], &[1]), Err(Error::InvalidKey)));
+        assert!(matches!(
+            kv.append_record(&[KV_RECORD_TAG, 0]),
+            Err(Error::ReservedRecordTag)
+        ));
+        kv.set(b"third", &[3]).expect("Can't set");
+        assert!(matches!(
+            kv.set(b"fourth", &[4]),
+            Err(Error::KeyTableIsFull)
+        ));
+
+        // event log wraps the ring many times, keys are rewritten before their blocks are overwritten
+        for i in 0..100u8 {
+            kv.append_record(&[i % 100; 30])
+                .expect("Can't append record");
+        }
+        kv.flush().expect("Can't flush");
+        check(&mut kv, b"mode", Some(&[2]));
+        check(&mut kv, b"name", Some(b"dev"));
+        check(&mut kv, b"third", Some(&[3]));
+
+        // keys are found by scan after restore
+        drop(kv);
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        let mut kv = Kv::new(&mut fs).expect("Can't create kv");
+        check(&mut kv, b"mode", Some(&[2]));
+        check(&mut kv, b"name", Some(b"dev"));
+    }
+
+    #[test]
+    fn test_fs_compact_into() {
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        const DEBUG: u32 = 1;
+        const ERROR: u32 = 2;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+            .expect("Can't create fs");
+        for i in 0..6u8 {
+            let header = HeaderV2 {
+                tag: if i % 3 == 0 { ERROR } else { DEBUG },
+                ..HeaderV2::default()
+            };
+            fs.append_with_header(header, |data| data.fill(i))
+                .expect("Can't append");
+        }
+
+        let mut archive_storage = RamStorage::new().expect("Can't create storage");
+        let mut archive = Fs::new_with_header(
+            &mut archive_storage,
+            FS_ID + 1,
+            CrcMode::Full,
+            HeaderVersion::V2,
+        )
+        .expect("Can't create archive");
+        archive
+            .append(|data| data.fill(0xAA))
+            .expect("Can't append");
+        let copied = fs
+            .compact_into(&mut archive, |info, _| {
+                info.header.is_some_and(|h| h.tag != DEBUG)
+            })
+            .expect("Can't compact");
+        assert_eq!(copied, 2);
+        assert_eq!(archive.used_blocks(), 3);
+        // ids are renumbered
+        for (blk_offset, fill) in [(1, 0), (2, 3)] {
+            archive
+                .read_with_info(blk_offset, |info, data| {
+                    assert_eq!(info.id, blk_offset as BlockId);
+                    assert_eq!(info.header.map(|h| h.tag), Some(ERROR));
+                    assert!(data.iter().all(|b| *b == fill));
+                })
+                .expect("Can't read archive");
+        }
+
+        // data of v1 blocks doesn't fit to v2 blocks of the same size
+        let mut v1_storage = RamStorage::new().expect("Can't create storage");
+        let mut v1 = Fs::new(&mut v1_storage, FS_ID + 2).expect("Can't create fs");
+        let res = v1.compact_into(&mut archive, |_, _| true);
+        assert!(matches!(res, Err(Error::InvalidBlockSizeForWrite)));
+        // v2 blocks are copied to v1 filesystem without header
+        let copied = fs
+            .compact_into(&mut v1, |_, _| true)
+            .expect("Can't compact to v1");
+        assert_eq!(copied, 6);
+        v1.read_with_info(5, |info, data| {
+            assert!(info.header.is_none());
+            assert!(data[..fs.data_size()].iter().all(|b| *b == 5));
+        })
+        .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_cursor() {
+        use super::cursor::Cursor;
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        let mut cursor = Cursor::new(0);
+        assert_eq!(cursor.peek(&mut fs, |_, _| {}).expect("Can't peek"), None);
+
+        for i in 0..3u8 {
+            fs.append(|data| data.fill(i)).expect("Can't append");
+        }
+        // not committed block is read again
+        for _ in 0..2 {
+            let id = cursor
+                .peek(&mut fs, |id, data| {
+                    assert_eq!(id, 0);
+                    assert!(data.iter().all(|b| *b == 0));
+                })
+                .expect("Can't peek");
+            assert_eq!(id, Some(0));
+        }
+        cursor.commit(0);
+        assert_eq!(
+            cursor.peek(&mut fs, |_, _| {}).expect("Can't peek"),
+            Some(1)
+        );
+        cursor.commit(2);
+        assert_eq!(cursor.next_blk_id(), 3);
+        assert_eq!(cursor.peek(&mut fs, |_, _| {}).expect("Can't peek"), None);
+
+        // cursor restored after the ring wrapped
+        for i in 3..12u8 {
+            fs.append(|data| data.fill(i)).expect("Can't append");
+        }
+        let mut cursor = Cursor::new(cursor.next_blk_id());
+        let id = cursor
+            .peek(&mut fs, |_, data| assert!(data.iter().all(|b| *b == 5)))
+            .expect("Can't peek");
+        assert_eq!(id, Some(5));
+        assert_eq!(cursor.missed(), 2);
+
+        // damaged block is skipped
+        let blk_idx = fs.blk_idx_of(1).expect("Can't get index");
+        fs.storage.data[blk_idx * BLOCK_SIZE + 10] ^= 0xFF;
+        cursor.commit(5);
+        assert_eq!(
+            cursor.peek(&mut fs, |_, _| {}).expect("Can't peek"),
+            Some(7)
+        );
+        assert_eq!(cursor.missed(), 3);
+    }
+
+    #[test]
+    fn test_fs_new_checked() {
+        const BLOCK_SIZE: usize = 128;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * 4 }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        // geometry is checked by compiler, e.g. `Filesystem<'_, RamStorage<384, 128>, 128>` or
+        // `Filesystem<'_, RamStorage<512, 128>, 64>` don't compile
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new_checked(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..3 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+
+        let fs = Fs::restore_checked(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.capacity(), fs.used_blocks()), (2, 2));
+    }
+
+    #[test]
+    fn test_fs_reserved_blocks() {
+        // config with reserved blocks doesn't fit to 128 byte block with all header features
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 9;
+        const RESERVED: usize = 2;
+        const MARK: u8 = 0x5a;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        assert!(matches!(
+            Fs::new_with_reserved(&mut storage, FS_ID, CrcMode::Full, 7),
+            Err(Error::TooSmallFilesystem)
+        ));
+        {
+            let mut fs = Fs::new_with_reserved(&mut storage, FS_ID, CrcMode::Full, RESERVED)
+                .expect("Can't create fs");
+            assert_eq!((fs.reserved_blocks(), fs.capacity()), (2..4, 5));
+            fs.write_reserved(1, &[MARK; BLOCK_SIZE])
+                .expect("Can't write reserved block");
+            assert!(matches!(
+                fs.write_reserved(RESERVED, &[MARK; BLOCK_SIZE]),
+                Err(Error::BlockOutOfRange)
+            ));
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            // ring of 5 blocks wrapped twice, the oldest block is the first one after reserved blocks
+            assert_eq!(fs.blk_idx_of(0).expect("Can't get index"), 4);
+            fs.secure_erase(0).expect("Can't erase fs");
+        }
+
+        // number of reserved blocks is restored from config block
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.reserved_blocks(), fs.used_blocks()), (2..4, 0));
+        let mut reserved = [0_u8; BLOCK_SIZE];
+        fs.read_reserved(1, |data| reserved.copy_from_slice(data))
+            .expect("Can't read reserved block");
+        assert!(reserved.iter().all(|b| *b == MARK));
+    }
+
+    #[test]
+    fn test_fs_append_with_flags() {
+        const BLOCK_SIZE: usize = 256;
+        const FLAGS: u8 = HeaderV2::FLAG_CRITICAL | HeaderV2::FLAG_COMPRESSED | 0x80;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * 8 }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            let res = fs.append_with_flags(FLAGS, |blk_data| blk_data.fill(1));
+            assert!(matches!(res, Err(Error::UnsupportedHeaderVersion)));
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append");
+            fs.read_with_info(0, |info, _| assert_eq!(info.flags(), 0))
+                .expect("Can't read");
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+            .expect("Can't create fs");
+        fs.append_with_flags(FLAGS, |blk_data| blk_data.fill(2))
+            .expect("Can't append");
+        fs.append(|blk_data| blk_data.fill(3))
+            .expect("Can't append");
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        let data_size = fs.data_size();
+        fs.read_with_info(0, |info, data| {
+            assert_eq!(info.flags(), FLAGS);
+            assert!(info.has_flags(HeaderV2::FLAG_CRITICAL | HeaderV2::FLAG_COMPRESSED));
+            assert!(!info.has_flags(HeaderV2::FLAG_ENCRYPTED));
+            assert_eq!(info.header.map(|h| h.length), Some(data_size as u16));
+            assert!(data.iter().all(|b| *b == 2));
+        })
+        .expect("Can't read");
+        fs.read_with_info(1, |info, _| assert_eq!(info.flags(), 0))
+            .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_read_policy() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        for crc_mode in [CrcMode::Full, CrcMode::Split] {
+            let mut storage = RamStorage::new().expect("Can't create storage");
+            {
+                let mut fs =
+                    Fs::new_with_crc_mode(&mut storage, FS_ID, crc_mode).expect("Can't create fs");
+                for i in 0..3 {
+                    fs.append(|blk_data| blk_data.fill(i))
+                        .expect("Can't append");
+                }
+            }
+            // the second block has bit rot in data
+            storage.data[3 * BLOCK_SIZE + fields::DATA_BEGIN] ^= 1;
+
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!(fs.read_policy(), ReadPolicy::Verify);
+            assert!(matches!(
+                fs.read(1, |_| {}),
+                Err(Error::NotValidBlockForRead)
+            ));
+
+            fs.set_read_policy(ReadPolicy::HeaderOnly);
+            let res = fs.read_with_info(1, |info, data| {
+                assert!(info.is_valid);
+                assert_eq!((info.id, data[0], data[1]), (1, 0, 1));
+            });
+            match crc_mode {
+                CrcMode::Split => assert!(res.is_ok()),
+                _ => assert!(matches!(res, Err(Error::NotValidBlockForRead))),
+            }
+
+            fs.set_read_policy(ReadPolicy::Report);
+            fs.read_with_info(1, |info, data| {
+                assert!(!info.is_valid);
+                assert_eq!((data[0], data[1]), (0, 1));
+            })
+            .expect("Can't read damaged block");
+            fs.read_with_info(2, |info, _| assert!(info.is_valid))
+                .expect("Can't read");
+            assert!(fs.health().crc_failures > 0);
+        }
+    }
+
+    #[test]
+    fn test_fs_metrics() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        #[derive(Default)]
+        struct Counters {
+            appends: u64,
+            read_errors: u64,
+            crc_failures: u64,
+            restores: u64,
+        }
+
+        impl MetricsSink for Counters {
+            fn add(&mut self, counter: Counter, value: u64) {
+                match counter {
+                    Counter::Appends => self.appends += value,
+                    Counter::ReadErrors => self.read_errors += value,
+                    Counter::CrcFailures => self.crc_failures += value,
+                    Counter::Restores => self.restores += value,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        storage.data[3 * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 1;
+
+        let mut counters = Counters::default();
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            fs.set_metrics(&mut counters);
+            fs.append(|blk_data| blk_data.fill(3))
+                .expect("Can't append");
+            assert!(fs.read(1, |_| {}).is_err());
+            assert!(fs.read(2, |_| {}).is_ok());
+            fs.refresh().expect("Can't refresh fs");
+        }
+        assert_eq!(
+            (
+                counters.appends,
+                counters.read_errors,
+                counters.crc_failures,
+                counters.restores
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(Counter::ReadErrors.name(), "read_errors");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_fs_tracing() {
+        use std::sync::{Arc, Mutex};
+        use std::vec::Vec;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        const BLOCK_SIZE: usize = 128;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * 8 }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        /// Name of span and value of its `blk_idx` field
+        type Span = (&'static str, Option<u64>);
+
+        #[derive(Clone, Default)]
+        struct Spans(Arc<Mutex<Vec<Span>>>);
+
+        struct BlkIdx<'s>(&'s mut Option<u64>);
+
+        impl Visit for BlkIdx<'_> {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "blk_idx" {
+                    *self.0 = Some(value);
+                }
+            }
+            fn record_debug(&mut self, _: &Field, _: &dyn core::fmt::Debug) {}
+        }
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+                let mut spans = self.0.lock().expect("Can't lock spans");
+                let mut blk_idx = None;
+                attrs.record(&mut BlkIdx(&mut blk_idx));
+                spans.push((attrs.metadata().name(), blk_idx));
+                Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.0.lock().expect("Can't lock spans");
+                let span = &mut spans[span.into_u64() as usize - 1];
+                values.record(&mut BlkIdx(&mut span.1));
+            }
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let spans = Spans::default();
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        tracing::subscriber::with_default(spans.clone(), || {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append");
+            fs.read(0, |_| {}).expect("Can't read");
+        });
+
+        let spans = spans.0.lock().expect("Can't lock spans");
+        assert_eq!(
+            &spans[..],
+            &[("init", None), ("append", Some(2)), ("read", Some(2))]
+        );
+    }
+
+    #[test]
+    fn test_fs_builder() {
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        #[derive(Default)]
+        struct Appends(u64);
+
+        impl MetricsSink for Appends {
+            fn add(&mut self, counter: Counter, value: u64) {
+                if counter == Counter::Appends {
+                    self.0 += value;
+                }
+            }
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        assert!(matches!(
+            Fs::builder(&mut storage).build(),
+            Err(Error::InvalidHeaderBlock)
+        ));
+        assert!(matches!(
+            Fs::builder(&mut storage)
+                .fs_id(FS_ID)
+                .read_only(true)
+                .build(),
+            Err(Error::InvalidHeaderBlock)
+        ));
+
+        let mut generated = 0;
+        let mut id_source = || {
+            generated += 1;
+            FS_ID
+        };
+        let mut appends = Appends::default();
+        {
+            let mut fs = Fs::builder(&mut storage)
+                .id_source(&mut id_source)
+                .crc_mode(CrcMode::Split)
+                .header(HeaderVersion::V2)
+                .reserved(1)
+                .read_policy(ReadPolicy::Report)
+                .quarantine(true)
+                .metrics(&mut appends)
+                .build()
+                .expect("Can't build fs");
+            assert_eq!(fs.id(), FS_ID);
+            assert_eq!(fs.read_policy(), ReadPolicy::Report);
+            assert_eq!(fs.reserved_blocks(), 2..3);
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        assert_eq!((generated, appends.0), (1, 3));
+
+        // restored filesystem keeps options of format, id source isn't called
+        let mut generated = 0;
+        let mut id_source = || {
+            generated += 1;
+            FS_ID + 1
+        };
+        {
+            let fs = Fs::builder(&mut storage)
+                .id_source(&mut id_source)
+                .crc_mode(CrcMode::Full)
+                .build()
+                .expect("Can't build fs");
+            assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 3));
+            assert_eq!(fs.crc_mode(), CrcMode::Split);
+            assert_eq!(fs.header_version(), HeaderVersion::V2);
+            assert_eq!(fs.read_policy(), ReadPolicy::Verify);
+        }
+        assert_eq!(generated, 0);
+
+        let mut buffer = [0_u8; BLOCK_SIZE];
+        {
+            let mut fs = Fs::builder(&mut storage)
+                .buffer(&mut buffer[..])
+                .validation(MountValidation::Full)
+                .build()
+                .expect("Can't build fs");
+            assert_eq!(fs.used_blocks(), 3);
+            fs.read(2, |blk_data| assert!(blk_data.iter().all(|b| *b == 2)))
+                .expect("Can't read");
+        }
+
+        assert!(matches!(
+            Fs::builder(&mut storage).fs_id(FS_ID + 1).build(),
+            Err(Error::ForeignFilesystem { found_fs_id: FS_ID })
+        ));
+        {
+            let fs = Fs::builder(&mut storage)
+                .fs_id(FS_ID + 1)
+                .force(true)
+                .format_policy(FormatPolicy::Wipe)
+                .build()
+                .expect("Can't build fs");
+            assert_eq!((fs.id(), fs.used_blocks()), (FS_ID + 1, 0));
+            assert_eq!(fs.crc_mode(), CrcMode::Full);
+        }
+        // blocks of the previous filesystem are destroyed
+        assert!(storage.data[2 * BLOCK_SIZE..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_fs_trim() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        struct Discarding {
+            inner: RamStorage<SIZE, BLOCK_SIZE>,
+            discarded: Option<Range<usize>>,
+        }
+
+        impl Storage for Discarding {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.inner.read(blk_idx, data)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                self.inner.write(blk_idx, data)
+            }
+            fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+                self.discarded = Some(blocks);
+                Ok(())
+            }
+            fn block_size(&self) -> usize {
+                self.inner.block_size()
+            }
+            fn min_block_index(&self) -> usize {
+                self.inner.min_block_index()
+            }
+            fn max_block_index(&self) -> usize {
+                self.inner.max_block_index()
+            }
+        }
+
+        let mut storage = Discarding {
+            inner: RamStorage::new().expect("Can't create storage"),
+            discarded: None,
+        };
+        {
+            let mut fs =
+                Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.trim().expect("Can't trim"), 4);
+        }
+        assert_eq!(storage.discarded.take(), Some(5..9));
+
+        let mut fs =
+            Filesystem::<_, BLOCK_SIZE>::new(&mut storage, FS_ID).expect("Can't restore fs");
+        for i in 0..10 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        assert_eq!(fs.trim().expect("Can't trim full fs"), 0);
+    }
+
+    #[test]
+    fn test_fs_wear_stats() {
+        // boot counter doesn't fit to config of 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+        const NEW_FS_ID: FsId = 1585159336;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for _ in 0..10 {
+                fs.append(|blk_data| blk_data.fill(1))
+                    .expect("Can't append");
+            }
+            // config blocks written on format and 10 appends, capacity is 7 blocks
+            assert_eq!(
+                fs.wear_stats(),
+                WearStats {
+                    total_writes: 12,
+                    run_writes: 12,
+                    avg_writes_per_block: 1,
+                    ring_cycles: 1,
+                }
+            );
+        }
+        {
+            // writes of previous fs are kept on format
+            let mut fs = Fs::new_with_force(&mut storage, NEW_FS_ID, CrcMode::Full, true)
+                .expect("Can't format fs");
+            for _ in 0..3 {
+                fs.append(|blk_data| blk_data.fill(2))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.wear_stats().total_writes, 17);
+            assert_eq!(fs.wear_stats().run_writes, 5);
+        }
+
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.wear_stats().total_writes, 17);
+        assert_eq!(fs.wear_stats().run_writes, 0);
+        assert_eq!(fs.boot_count(), 0);
+
+        // config is rewritten only by writer mount
+        let fs = Fs::mount_for_write(&mut storage).expect("Can't mount fs");
+        assert_eq!(fs.wear_stats().run_writes, 1);
+        assert_eq!(fs.boot_count(), 1);
+    }
+
+    #[test]
+    fn test_fs_append_seq() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        // sequence is stored in v2 header
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert!(matches!(
+            fs.append_seq(1, |blk_data| blk_data.fill(1)),
+            Err(Error::UnsupportedHeaderVersion)
+        ));
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+                .expect("Can't create fs");
+            assert!(matches!(
+                fs.append_with_flags(HeaderV2::FLAG_SEQ, |blk_data| blk_data.fill(1)),
+                Err(Error::ReservedFlag)
+            ));
+            for seq in [1, 1, 2, 2, 2] {
+                fs.append_seq(seq, |blk_data| blk_data.fill(seq as u8))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.used_blocks(), 2);
+            fs.append(|blk_data| blk_data.fill(0))
+                .expect("Can't append");
+            fs.append_seq(2, |blk_data| blk_data.fill(2))
+                .expect("Can't append");
+            assert_eq!(fs.used_blocks(), 4);
+        }
+
+        // retry after restart
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        fs.append_seq(2, |blk_data| blk_data.fill(2))
+            .expect("Can't append");
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (4, 4));
+        fs.read_with_info(3, |info, blk_data| {
+            assert_eq!(
+                (info.seq, info.flags(), blk_data[0]),
+                (2, HeaderV2::FLAG_SEQ, 2)
+            );
+        })
+        .expect("Can't read");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_fs_append_dedup() {
+        use std::io::Read;
+
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 12;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        // marker is flagged in v2 header
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert!(matches!(
+            fs.append_dedup(|blk_data| blk_data.fill(1)),
+            Err(Error::UnsupportedHeaderVersion)
+        ));
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+                .expect("Can't create fs");
+            assert!(matches!(
+                fs.append_with_flags(HeaderV2::FLAG_REPEAT, |blk_data| blk_data.fill(1)),
+                Err(Error::ReservedFlag)
+            ));
+            for value in [1, 1, 1, 2, 1, 1] {
+                fs.append_dedup(|blk_data| blk_data.fill(value))
+                    .expect("Can't append");
+            }
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (6, 6));
+
+            let mut read = [(0, 0); 6];
+            for (offset, read) in read.iter_mut().enumerate() {
+                fs.read_with_info(offset, |info, blk_data| *read = (info.repeat, blk_data[0]))
+                    .expect("Can't read");
+            }
+            assert_eq!(read, [(0, 1), (1, 1), (2, 1), (0, 2), (0, 1), (1, 1)]);
+        }
+
+        // markers survive restart, they are expanded by reader
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        fs.append_dedup(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+        fs.append(|blk_data| blk_data.fill(1))
+            .expect("Can't append");
+        assert_eq!(fs.used_blocks(), 8);
+        fs.read_with_info(6, |info, _| assert_eq!(info.repeat, 2))
+            .expect("Can't read");
+
+        let data_size = fs.data_size();
+        let mut data = std::vec::Vec::new();
+        crate::io::FsReader::new(&mut fs)
+            .read_to_end(&mut data)
+            .expect("Can't read");
+        let values: std::vec::Vec<u8> = data.chunks(data_size).map(|blk| blk[0]).collect();
+        assert_eq!(values, [1, 1, 1, 2, 1, 1, 1, 1]);
+
+        // marker can't be read after the referred block is overwritten
+        for value in 3..6 {
+            fs.append(|blk_data| blk_data.fill(value))
+                .expect("Can't append");
+        }
+        assert_eq!(fs.oldest_blk_id(), 1);
+        assert!(matches!(
+            fs.read(0, |_| {}),
+            Err(Error::NotValidBlockForRead)
+        ));
+        fs.read(2, |blk_data| assert_eq!(blk_data[0], 2))
+            .expect("Can't read");
+
+        // block with flags isn't referred, its flags would be lost
+        fs.append_with_flags(HeaderV2::FLAG_CRITICAL, |blk_data| blk_data.fill(7))
+            .expect("Can't append");
+        fs.append_dedup(|blk_data| blk_data.fill(7))
+            .expect("Can't append");
+        let newest = fs.used_blocks() - 1;
+        fs.read_with_info(newest, |info, _| assert_eq!(info.repeat, 0))
+            .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_read_with_buf() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+            .expect("Can't create fs");
+        for i in 0..3 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        // marker of the block 2
+        fs.append_dedup(|blk_data| blk_data.fill(2))
+            .expect("Can't append");
+
+        {
+            // several readers of shared reference
+            let fs = &fs;
+            let (mut first, mut second) = ([0_u8; BLOCK_SIZE], [0_u8; BLOCK_SIZE]);
+            let mut read = [0; 3];
+            fs.read_with_buf(&mut first, 1, |blk_data| read[0] = blk_data[0])
+                .expect("Can't read");
+            fs.read_with_buf(&mut second, 2, |blk_data| read[1] = blk_data[0])
+                .expect("Can't read");
+            fs.read_with_buf(&mut first, 3, |blk_data| read[2] = blk_data[0])
+                .expect("Can't read marker");
+            assert_eq!(read, [1, 2, 2]);
+
+            assert!(matches!(
+                fs.read_with_buf(&mut first[..BLOCK_SIZE - 1], 0, |_| {}),
+                Err(Error::TooSmallBuffer)
+            ));
+        }
+
+        // block quarantined by `read` isn't returned even after it's valid again
+        fs.set_quarantine(true);
+        let blk_idx = fs.blk_idx_of(0).expect("Can't get block index");
+        fs.storage.data[blk_idx * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 0xff;
+        assert!(fs.read(0, |_| {}).is_err());
+        fs.storage.data[blk_idx * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 0xff;
+        let mut buf = [0_u8; BLOCK_SIZE];
+        assert!(matches!(
+            fs.read_with_buf(&mut buf, 0, |_| {}),
+            Err(Error::NotValidBlockForRead)
+        ));
+    }
+
+    #[test]
+    fn test_fs_new_with_buffer() {
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+        type BorrowedFs<'a> =
+            Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE, DefaultLayout, &'a mut [u8]>;
+        assert_eq!(
+            core::mem::size_of::<Fs>() - core::mem::size_of::<BorrowedFs>(),
+            BLOCK_SIZE - core::mem::size_of::<&mut [u8]>()
+        );
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut buffer = [0_u8; BLOCK_SIZE];
+        assert!(matches!(
+            BorrowedFs::new_with_buffer(&mut storage, FS_ID, &mut buffer[..BLOCK_SIZE - 1]),
+            Err(Error::TooSmallBuffer)
+        ));
+        {
+            let mut fs = BorrowedFs::new_with_buffer(&mut storage, FS_ID, &mut buffer[..])
+                .expect("Can't create fs");
+            fs.append(|blk_data| blk_data.fill(3))
+                .expect("Can't append");
+        }
+
+        let mut fs = BorrowedFs::restore_with_buffer(&mut storage, &mut buffer[..])
+            .expect("Can't restore fs");
+        assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 1));
+        fs.read(0, |blk_data| assert!(blk_data.iter().all(|b| *b == 3)))
+            .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_restore_with_progress() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 64;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..40 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut reports = 0;
+        let mut last = None;
+        let mut progress = |p: MountProgress| {
+            reports += 1;
+            last = Some(p);
+        };
+        {
+            let fs =
+                Fs::restore_with_progress(&mut storage, &mut progress).expect("Can't restore fs");
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (40, 40));
+        }
+        // config blocks, both ends and binary search over 62 blocks
+        let last = last.expect("Progress wasn't reported");
+        assert_eq!((last.probed, reports), (11, 11));
+        assert_eq!(last.remaining, 0);
+    }
+
+    #[test]
+    fn test_fs_restore_with_trail() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 64;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..40 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut steps = [None; 32];
+        let mut len = 0;
+        let mut trail = |step: RestoreStep| {
+            steps[len] = Some(step);
+            len += 1;
+        };
+        {
+            let fs = Fs::restore_with_trail(&mut storage, &mut trail).expect("Can't restore fs");
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (40, 40));
+        }
+        let steps = &steps[..len];
+        // the same blocks as reported by progress
+        let probed = steps
+            .iter()
+            .filter(|s| matches!(s, Some(RestoreStep::Probed { .. })))
+            .count();
+        assert_eq!(probed, 11);
+        // both config blocks, the newest one holds the config
+        for (step, idx) in steps[..2].iter().zip(0..) {
+            assert!(matches!(
+                step,
+                Some(RestoreStep::Probed {
+                    blk_idx,
+                    fs_id: FS_ID,
+                    ..
+                }) if *blk_idx == idx
+            ));
+        }
+        assert_eq!(
+            steps[2],
+            Some(RestoreStep::Probed {
+                blk_idx: 2,
+                fs_id: FS_ID,
+                id: Some(0)
+            })
+        );
+        // ring isn't full, the last block isn't written
+        assert!(matches!(
+            steps[3],
+            Some(RestoreStep::Probed {
+                blk_idx: 63,
+                id: None,
+                ..
+            })
+        ));
+        assert!(steps.iter().any(|s| matches!(
+            s,
+            Some(RestoreStep::Decision(RestoreDecision::SearchLeft { .. }))
+        )));
+        assert!(steps.iter().any(|s| matches!(
+            s,
+            Some(RestoreStep::Decision(RestoreDecision::SearchRight { .. }))
+        )));
+        // block 40 is after config blocks
+        assert_eq!(
+            steps[len - 1],
+            Some(RestoreStep::Decision(RestoreDecision::Head {
+                blk_idx: 42,
+                next_id: 40
+            }))
+        );
+    }
+
+    #[test]
+    fn test_fs_restore_with_validation() {
+        // config of 256 byte block has boot counter
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let damaged = {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            fs.blk_idx_of(3).expect("Can't get block index")
+        };
+        storage.data[damaged * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 0xff;
+        let image = storage.data;
+
+        for validation in [MountValidation::Fast, MountValidation::Newest(6)] {
+            let fs = Fs::restore_with_validation(&mut storage, validation).expect("Can't restore");
+            assert_eq!(fs.used_blocks(), 10);
+        }
+        for validation in [MountValidation::Newest(7), MountValidation::Full] {
+            assert!(matches!(
+                Fs::restore_with_validation(&mut storage, validation),
+                Err(Error::MountValidationFailed)
+            ));
+        }
+        // restore doesn't write, boot is counted only by writer mount
+        assert!(slices_are_equal(&storage.data, &image));
+        let fs = Fs::mount_for_write(&mut storage).expect("Can't mount fs");
+        assert_eq!(fs.boot_count(), 1);
+    }
+
+    #[test]
+    fn test_fs_block_info() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 5;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        for i in 0..5 {
+            let appended = fs
+                .append(|blk_data| blk_data.fill(i as u8))
+                .expect("Can't append");
+            // config blocks are at 0 and 1, 3 data blocks
+            assert_eq!((appended.id, appended.offset), (i, 2 + i as usize % 3));
+        }
+
+        let mut read = (0, 0);
+        fs.read_with_info(0, |info, blk_data| read = (info.id, blk_data[0]))
+            .expect("Can't read");
+        assert_eq!(read, (2, 2));
+
+        let blk_idx = fs.blk_idx_of(0).expect("Can't get block index");
+        let mut raw = [0_u8; BLOCK_SIZE];
+        let read = fs.read_raw(blk_idx, |blk| raw.copy_from_slice(blk));
+        assert_eq!(read.ok(), Some(BLOCK_SIZE));
+        let block = Block::<BLOCK_SIZE>::from_buffer(&raw);
+        assert_eq!((block.id(), block.fs_id()), (2, FS_ID));
+        assert!(matches!(
+            fs.read_raw(fs.storage().max_block_index(), |_| {}),
+            Err(Error::BlockOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_fs_block_layout() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        #[derive(Debug)]
+        struct ChannelLayout;
+        impl BlockLayout for ChannelLayout {
+            const METADATA_LEN: usize = 2;
+        }
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE, ChannelLayout>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert_eq!(fs.data_size(), Fs::data_block_size());
+            assert_eq!(
+                fs.data_size(),
+                BLOCK_SIZE - Block::<BLOCK_SIZE>::attributes_size() - 2
+            );
+            for i in 0..3_u8 {
+                fs.append_with_metadata(|metadata, blk_data| {
+                    metadata.copy_from_slice(&[0xc0, i]);
+                    blk_data.fill(i);
+                })
+                .expect("Can't append");
+            }
+            fs.append(|blk_data| blk_data.fill(3))
+                .expect("Can't append");
+        }
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.used_blocks(), 4);
+        for i in 0..4_u8 {
+            let mut read = ([0_u8; 2], 0);
+            fs.read_with_metadata(i as usize, |metadata, blk_data| {
+                read = ([metadata[0], metadata[1]], blk_data[0]);
+            })
+            .expect("Can't read");
+            let expected = if i < 3 { [0xc0, i] } else { [0, 0] };
+            assert_eq!(read, (expected, i));
+        }
+
+        let mut metadata = [0_u8; 2];
+        fs.read_with_info(1, |info, _| metadata.copy_from_slice(info.metadata()))
+            .expect("Can't read");
+        assert_eq!(metadata, [0xc0, 1]);
+
+        // metadata of a buffer shorter than the header is cut
+        let data_begin = fields::DATA_BEGIN + HeaderVersion::V2.extension_len();
+        for len in [fields::DATA_BEGIN, data_begin + 1] {
+            let info = BlockInfo::<BLOCK_SIZE>::from_buffer_with_header::<ChannelLayout>(
+                &storage.data[BLOCK_SIZE..BLOCK_SIZE + len],
+                CrcMode::Full,
+                HeaderVersion::V2,
+            );
+            assert!(!info.is_valid);
+            assert!(info.metadata().len() <= 1);
+        }
+
+        // metadata is covered by header crc in all crc modes,
+        // config with crc mode doesn't fit to 64 byte block with all header features
+        const CONFIG_BLOCK_SIZE: usize = 128;
+        type HeaderOnlyFs<'a> = Filesystem<
+            'a,
+            RamStorage<{ CONFIG_BLOCK_SIZE * 8 }, CONFIG_BLOCK_SIZE>,
+            CONFIG_BLOCK_SIZE,
+            ChannelLayout,
+        >;
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = HeaderOnlyFs::new_with_crc_mode(&mut storage, FS_ID, CrcMode::HeaderOnly)
+                .expect("Can't create fs");
+            for i in 0..3_u8 {
+                fs.append_with_metadata(|metadata, blk_data| {
+                    metadata.copy_from_slice(&[0xc0, i]);
+                    blk_data.fill(i);
+                })
+                .expect("Can't append");
+            }
+        }
+        storage.data[3 * CONFIG_BLOCK_SIZE + fields::DATA_BEGIN + 1] ^= 0xff;
+        storage.data[4 * CONFIG_BLOCK_SIZE + fields::DATA_BEGIN + 2] ^= 0xff;
+        let mut fs = HeaderOnlyFs::restore(&mut storage).expect("Can't restore fs");
+        assert!(matches!(
+            fs.read(1, |_| {}),
+            Err(Error::NotValidBlockForRead)
+        ));
+        assert!(fs.read(2, |_| {}).is_ok());
+    }
+
+    #[test]
+    fn test_fs_crc_modes() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        for (crc_mode, detects_data_damage) in [
+            (CrcMode::Full, true),
+            (CrcMode::HeaderOnly, false),
+            (CrcMode::Split, true),
+        ] {
+            let mut storage = RamStorage::new().expect("Can't create storage");
+            {
+                let mut fs =
+                    Fs::new_with_crc_mode(&mut storage, FS_ID, crc_mode).expect("Can't create fs");
+                assert_eq!(
+                    fs.data_size(),
+                    Fs::data_block_size() - crc_mode.trailer_len()
+                );
+                for i in 0..3 {
+                    fs.append(|blk_data| blk_data.fill(i))
+                        .expect("Can't append");
+                }
+            }
+
+            // crc mode is restored from config
+            let fs = Fs::new_with_crc_mode(&mut storage, FS_ID, CrcMode::Full)
+                .expect("Can't restore fs");
+            assert_eq!(fs.crc_mode(), crc_mode);
+
+            storage.data[3 * BLOCK_SIZE + fields::DATA_BEGIN] ^= 0xff;
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!((fs.crc_mode(), fs.used_blocks()), (crc_mode, 3));
+            let mut data = [0_u8; BLOCK_SIZE];
+            let read = fs.read(2, |blk_data| {
+                data[..blk_data.len()].copy_from_slice(blk_data)
+            });
+            assert!(read.is_ok(), "Can't read block: {:?}", read);
+            assert!(data[..fs.data_size()].iter().all(|b| *b == 2));
+            assert_eq!(fs.read(1, |_| {}).is_err(), detects_data_damage);
+        }
+    }
+
+    #[test]
+    fn test_fs_restore_reads_headers() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        /// Partial read leaves garbage in the rest of the buffer
+        struct Prefixes {
+            inner: RamStorage<SIZE, BLOCK_SIZE>,
+            reads: usize,
+        }
+
+        impl Storage for Prefixes {
+            fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+                self.inner.read(blk_idx, data)
+            }
+            fn read_prefix(
+                &mut self,
+                blk_idx: usize,
+                len: usize,
+                data: &mut [u8],
+            ) -> Result<usize, Error> {
+                self.reads += 1;
+                self.inner.read(blk_idx, data)?;
+                data[len..BLOCK_SIZE].fill(0xaa);
+                Ok(len)
+            }
+            fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+                self.inner.write(blk_idx, data)
+            }
+            fn block_size(&self) -> usize {
+                self.inner.block_size()
+            }
+            fn min_block_index(&self) -> usize {
+                self.inner.min_block_index()
+            }
+            fn max_block_index(&self) -> usize {
+                self.inner.max_block_index()
+            }
+        }
+
+        for crc_mode in [CrcMode::Full, CrcMode::HeaderOnly, CrcMode::Split] {
+            let mut storage = Prefixes {
+                inner: RamStorage::new().expect("Can't create storage"),
+                reads: 0,
+            };
+            {
+                let mut fs =
+                    Filesystem::<_, BLOCK_SIZE>::new_with_crc_mode(&mut storage, FS_ID, crc_mode)
+                        .expect("Can't create fs");
+                for i in 0..10 {
+                    fs.append(|blk_data| blk_data.fill(i))
+                        .expect("Can't append");
+                }
+            }
+            storage.reads = 0;
+
+            // only header is read in case data isn't checked on restore
+            let mut fs =
+                Filesystem::<_, BLOCK_SIZE>::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!(fs.next_blk_id(), 10);
+            let newest = fs.used_blocks() - 1;
+            let read = fs.read(newest, |blk_data| assert!(blk_data.iter().all(|b| *b == 9)));
+            assert!(read.is_ok(), "Can't read block: {:?}", read);
+            assert_eq!(storage.reads > 0, crc_mode == CrcMode::HeaderOnly);
+        }
+    }
+
+    #[test]
+    fn test_fs_id_high_water() {
+        // non zero high-water mark doesn't fit to config of 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.set_id_reserve(4);
+            for i in 0..6 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.id_high_water(), 8);
+        }
+
+        // block with id 5 is torn, its id must not be used again
+        storage.data[7 * BLOCK_SIZE + fields::DATA_BEGIN] ^= 0xff;
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!((fs.id_high_water(), fs.next_blk_id()), (8, 8));
+            fs.set_id_reserve(4);
+            let appended = fs
+                .append(|blk_data| blk_data.fill(0xaa))
+                .expect("Can't append");
+            assert_eq!((appended.id, appended.offset), (8, 7));
+            assert_eq!(fs.id_high_water(), 12);
+        }
+
+        // restore without damaged blocks doesn't skip ids
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.id_high_water(), fs.next_blk_id()), (12, 9));
+        assert_eq!(
+            fs.find_first_invalid_offset().expect("Can't check fs"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fs_find_block() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let timestamp = |data: &[u8]| u32::from_le_bytes(data[..4].try_into().unwrap());
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert_eq!(fs.find_block(|_| true).expect("Can't find"), 0);
+
+        // wrap the ring, timestamps of blocks available for read are 100, 110, ..
+        for i in 0..20u32 {
+            fs.append(|data| data[..4].copy_from_slice(&(i * 10).to_le_bytes()))
+                .expect("Can't append");
+        }
+        let used = fs.used_blocks();
+        let first_ts = (20 - used as u32) * 10;
+
+        for (ts, expected) in [
+            (0, 0),
+            (first_ts, 0),
+            (first_ts + 1, 1),
+            (first_ts + 30, 3),
+            (190, used - 1),
+            (191, used),
+        ] {
+            let offset = fs
+                .find_block(|data| timestamp(data) < ts)
+                .expect("Can't find");
+            assert_eq!(offset, expected, "timestamp {}", ts);
+        }
+    }
+
+    #[test]
+    fn test_fs_find() {
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        assert_eq!(fs.find(0, |_| true).expect("Can't find"), None);
+
+        // markers are every third block
+        for i in 0..10u8 {
+            fs.append(|data| {
+                data[0] = i;
+                data[1] = u8::from(i % 3 == 0);
+            })
+            .expect("Can't append");
+        }
+        // blocks 3..10 are available
+        let first = fs.find(0, |data| data[1] == 1).expect("Can't find");
+        let first = first.expect("Marker not found");
+        assert_eq!(first.blk_offset, 0);
+        let next = fs
+            .find(first.blk_offset + 1, |data| data[1] == 1)
+            .expect("Can't find")
+            .expect("Marker not found");
+        assert_eq!(next.blk_offset, 3);
+        assert_eq!(next.id, first.id + 3);
+
+        let last = fs.rfind(|data| data[1] == 1).expect("Can't find");
+        assert_eq!(last.map(|blk| blk.blk_offset), Some(6));
+
+        let (found, value) = fs
+            .find_map(0, |data| (data[0] > 7).then_some(data[0]))
+            .expect("Can't find")
+            .expect("Block not found");
+        assert_eq!((found.blk_offset, value), (5, 8));
+        assert_eq!(fs.find(0, |data| data[0] == 1).expect("Can't find"), None);
+        assert_eq!(fs.find(100, |_| true).expect("Can't find"), None);
+
+        // damaged block is skipped
+        let blk_idx = fs.blk_idx_of(3).expect("Can't get index");
+        fs.storage.data[blk_idx * BLOCK_SIZE + 10] ^= 0xFF;
+        let next = fs.find(1, |data| data[1] == 1).expect("Can't find");
+        assert_eq!(next.map(|blk| blk.blk_offset), Some(6));
+    }
+
+    #[test]
+    fn test_fs_restore_or_format() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::restore_or_format(&mut storage, || FS_ID, FormatPolicy::Preserve)
+                .expect("Can't format fs");
+            assert_eq!(fs.id(), FS_ID);
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append");
+        }
+
+        let fs = Fs::restore_or_format(&mut storage, || unreachable!(), FormatPolicy::Wipe)
+            .expect("Can't restore fs");
+        assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 1));
+
+        let copy = |storage: &RamStorage<SIZE, BLOCK_SIZE>| {
+            let mut copy = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+            copy.data.copy_from_slice(&storage.data);
+            copy
+        };
+        let blocks_of = |storage: &mut RamStorage<SIZE, BLOCK_SIZE>, fs_id: FsId| {
+            let mut ids = [FsIdCount::default(); 2];
+            let report = Fs::count_fs_ids(storage, 1, &mut ids).expect("Can't count ids");
+            ids[..report.ids_found]
+                .iter()
+                .find(|count| count.fs_id == fs_id)
+                .map_or(0, |count| count.blocks)
+        };
+
+        // damaged config blocks of the filesystem, storage isn't formatted
+        let mut damaged = copy(&storage);
+        damaged.data[0] ^= 0xFF;
+        damaged.data[BLOCK_SIZE] ^= 0xFF;
+        assert!(matches!(
+            Fs::restore_or_format(&mut damaged, || unreachable!(), FormatPolicy::Wipe),
+            Err(Error::DamagedConfig)
+        ));
+        assert!(matches!(
+            Fs::new(&mut damaged, FS_ID),
+            Err(Error::DamagedConfig)
+        ));
+        assert_eq!(blocks_of(&mut damaged, FS_ID), 1);
+
+        for (fs_id, policy, previous) in [
+            (FS_ID + 1, FormatPolicy::Preserve, 1),
+            (FS_ID + 1, FormatPolicy::Wipe, 0),
+            // block with the same id isn't foreign, it's kept with new config blocks
+            (FS_ID, FormatPolicy::Wipe, 3),
+        ] {
+            // erased config blocks, data block of previous fs is still in storage
+            let mut storage = copy(&storage);
+            storage.data[..BLOCK_SIZE * 2].fill(0);
+            let fs =
+                Fs::restore_or_format(&mut storage, || fs_id, policy).expect("Can't format fs");
+            assert_eq!((fs.id(), fs.used_blocks()), (fs_id, 0));
+            assert_eq!(blocks_of(&mut storage, FS_ID), previous);
+        }
+
+        // blocks of another fs are after the block with the new id
+        let mut mixed = copy(&storage);
+        {
+            let mut other = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+            let mut fs = Fs::new(&mut other, FS_ID + 1).expect("Can't create fs");
+            for _ in 0..3 {
+                fs.append(|blk_data| blk_data.fill(2))
+                    .expect("Can't append");
+            }
+            mixed.data[BLOCK_SIZE * 3..BLOCK_SIZE * 5]
+                .copy_from_slice(&other.data[BLOCK_SIZE * 3..BLOCK_SIZE * 5]);
+        }
+        mixed.data[..BLOCK_SIZE * 2].fill(0);
+        assert_eq!(blocks_of(&mut mixed, FS_ID + 1), 2);
+        let data_block = mixed.data[BLOCK_SIZE * 2..BLOCK_SIZE * 3].to_vec();
+        let fs = Fs::restore_or_format(&mut mixed, || FS_ID, FormatPolicy::Wipe)
+            .expect("Can't format fs");
+        assert_eq!(fs.wear_stats().run_writes, 4);
+        assert_eq!(blocks_of(&mut mixed, FS_ID + 1), 0);
+        // config blocks and the kept data block
+        assert_eq!(blocks_of(&mut mixed, FS_ID), 3);
+        assert!(slices_are_equal(
+            &mixed.data[BLOCK_SIZE * 2..BLOCK_SIZE * 3],
+            &data_block
+        ));
+    }
+
+    #[test]
+    fn test_fs_block_time() {
+        use crate::time::{FnTimeSource, TimeSource, Timestamp};
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+
+        struct WallClock;
+        impl TimeSource for WallClock {
+            fn ticks(&mut self) -> Timestamp {
+                10
+            }
+            fn wall_clock(&mut self) -> Option<Timestamp> {
+                Some(1_000_000)
+            }
+        }
+
+        fn timestamps(fs: &mut Fs) -> [Timestamp; 4] {
+            let mut timestamps = [0; 4];
+            for (i, timestamp) in timestamps.iter_mut().enumerate() {
+                fs.read_with_info(i, |info, _| {
+                    *timestamp = info.header.expect("Block has no v2 header").timestamp
+                })
+                .expect("Can't read block");
+            }
+            timestamps
+        }
+
+        // timestamps are stored in v2 header
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            let mut source = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            assert!(matches!(
+                fs.set_time_source(&mut source),
+                Err(Error::UnsupportedHeaderVersion)
+            ));
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+                .expect("Can't create fs");
+            assert_eq!(fs.now(), None);
+            fs.append(|blk_data| blk_data.fill(0))
+                .expect("Can't append");
+
+            let mut source = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            fs.set_time_source(&mut source)
+                .expect("Can't set time source");
+            for ticks in [100, 200, 300] {
+                TICKS.store(ticks, Ordering::Relaxed);
+                fs.append(|blk_data| blk_data.fill(0))
+                    .expect("Can't append");
+            }
+            assert_eq!(timestamps(&mut fs), [0, 100, 200, 300]);
+            assert_eq!(fs.truncate_older_than(150).expect("Can't truncate"), 2);
+            assert_eq!(fs.used_blocks(), 2);
+            assert_eq!(fs.truncate_older_than(1000).expect("Can't truncate"), 0);
+        }
+
+        // without wall clock timestamps continue from the newest block
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            TICKS.store(50, Ordering::Relaxed);
+            let mut source = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            fs.set_time_source(&mut source)
+                .expect("Can't set time source");
+            assert_eq!((fs.boot_time(), fs.now()), (300, Some(350)));
+        }
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.boot_time(), 300);
+        let mut source = WallClock;
+        fs.set_time_source(&mut source)
+            .expect("Can't set time source");
+        assert_eq!((fs.boot_time(), fs.now()), (999_990, Some(1_000_000)));
+    }
+
+    #[test]
+    fn test_fs_export_range() {
+        use crate::time::FnTimeSource;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut source = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+        let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+            .expect("Can't create fs");
+        fs.set_time_source(&mut source)
+            .expect("Can't set time source");
+        for i in 1..=6u8 {
+            TICKS.store(u64::from(i) * 100, Ordering::Relaxed);
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+
+        let mut dst = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        assert_eq!(
+            fs.export_range(200, 400, &mut dst).expect("Can't export"),
+            2
+        );
+        let mut copy = Fs::restore(&mut dst).expect("Can't restore copy");
+        assert_eq!(copy.used_blocks(), 2);
+        for (blk_offset, fill) in [(0, 2), (1, 3)] {
+            copy.read(blk_offset, |data| assert!(data.iter().all(|b| *b == fill)))
+                .expect("Can't read copy");
+        }
+
+        // empty window and window after the newest block
+        let mut dst = RamStorage::<SIZE, BLOCK_SIZE>::new().expect("Can't create storage");
+        assert_eq!(
+            fs.export_range(250, 290, &mut dst).expect("Can't export"),
+            0
+        );
+        assert_eq!(
+            fs.export_range(900, 1000, &mut dst).expect("Can't export"),
+            0
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let mut out = std::vec::Vec::new();
+            let written = fs
+                .export_range_to_writer(300, 600, &mut out)
+                .expect("Can't export to writer");
+            assert_eq!(written, 3);
+            assert_eq!(out.len(), 3 * fs.data_size());
+            assert!(out[..fs.data_size()].iter().all(|b| *b == 3));
+            assert!(out[2 * fs.data_size()..].iter().all(|b| *b == 5));
+        }
+    }
+
+    #[test]
+    fn test_fs_group_commit() {
+        use super::group_commit::{CommitThresholds, GroupCommitFs};
+        use crate::time::FnTimeSource;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        let thresholds = CommitThresholds {
+            blocks: 3,
+            max_delay: 1000,
+        };
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        {
+            let time = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            let mut group = GroupCommitFs::<_, BLOCK_SIZE, 4, _, _>::new(&mut fs, time, thresholds);
+            for value in 0..2 {
+                let blk = group
+                    .append(|blk_data| blk_data.fill(value))
+                    .expect("Can't append");
+                assert!(blk.is_none());
+            }
+            let blk = group
+                .append(|blk_data| blk_data.fill(2))
+                .expect("Can't append")
+                .expect("Blocks are not written");
+            assert_eq!(blk.id, 2);
+            assert_eq!(group.staged(), 0);
+
+            group
+                .append(|blk_data| blk_data.fill(3))
+                .expect("Can't append");
+            TICKS.store(500, Ordering::Relaxed);
+            assert!(group.poll().expect("Can't poll").is_none());
+            TICKS.store(1000, Ordering::Relaxed);
+            assert!(group.poll().expect("Can't poll").is_some());
+
+            group
+                .append(|blk_data| blk_data.fill(4))
+                .expect("Can't append");
+            assert_eq!(group.staged(), 1);
+            assert!(group.flush().expect("Can't flush").is_some());
+            assert!(group.flush().expect("Can't flush").is_none());
+        }
+        assert_eq!(fs.used_blocks(), 5);
+        for offset in 0..5 {
+            fs.read(offset, |blk_data| {
+                assert!(blk_data.iter().all(|v| *v == offset as u8))
+            })
+            .expect("Can't read");
+        }
+    }
+
+    #[test]
+    fn test_fs_recommend_block_size() {
+        use super::sizing::recommend_block_size;
+        use super::MAX_BLOCK_SIZE;
+        use crate::storage::{Capabilities, Geometry};
+
+        let sd = Geometry {
+            block_size: 512,
+            min_block_index: 0,
+            max_block_index: 1024,
+            erase_size: None,
+            read_size: 512,
+            write_size: 512,
+            capabilities: Capabilities::default(),
+        };
+        let advice =
+            recommend_block_size(&sd, 400, CrcMode::Full, HeaderVersion::V1, MAX_BLOCK_SIZE)
+                .expect("No block size");
+        // 4096 byte block has the same overhead, but loses more records on torn write
+        assert_eq!((advice.block_size, advice.records_per_block), (2048, 5));
+        assert_eq!(advice.data_size, 2048 - fields::DATA_BEGIN);
+        assert_eq!(advice.write_amplification, 102);
+        assert!(
+            recommend_block_size(&sd, 5000, CrcMode::Full, HeaderVersion::V1, MAX_BLOCK_SIZE)
+                .is_none()
+        );
+
+        // every write of smaller block erases the whole sector
+        let nor = Geometry {
+            erase_size: Some(4096),
+            read_size: 1,
+            write_size: 4,
+            ..sd
+        };
+        let advice =
+            recommend_block_size(&nor, 16, CrcMode::Split, HeaderVersion::V2, MAX_BLOCK_SIZE)
+                .expect("No block size");
+        assert_eq!(advice.block_size, 4096);
+    }
+
+    #[test]
+    fn test_fs_throttle() {
+        use super::throttle::{AppendBudget, ThrottlePolicy, ThrottledFs};
+        use crate::time::FnTimeSource;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        let budget = AppendBudget {
+            appends: 2,
+            period: 1000,
+        };
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+        {
+            let time = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            let mut throttled = ThrottledFs::new(&mut fs, time, budget, ThrottlePolicy::Reject);
+            for _ in 0..2 {
+                let blk = throttled
+                    .append(|blk_data| blk_data.fill(1))
+                    .expect("Can't append");
+                assert!(blk.is_some());
+            }
+            assert!(matches!(
+                throttled.append(|blk_data| blk_data.fill(1)),
+                Err(Error::Throttled)
+            ));
+
+            TICKS.store(1000, Ordering::Relaxed);
+            assert!(throttled
+                .append(|blk_data| blk_data.fill(1))
+                .expect("Can't append")
+                .is_some());
+        }
+        assert_eq!(fs.used_blocks(), 3);
+
+        {
+            let time = FnTimeSource(|| TICKS.load(Ordering::Relaxed));
+            let mut throttled = ThrottledFs::new(&mut fs, time, budget, ThrottlePolicy::Coalesce);
+            for value in 2..4 {
+                throttled
+                    .append(|blk_data| blk_data.fill(value))
+                    .expect("Can't append");
+            }
+            for value in 4..7 {
+                let blk = throttled
+                    .append(|blk_data| blk_data.fill(value))
+                    .expect("Can't append");
+                assert!(blk.is_none());
+            }
+            assert!(throttled.flush().expect("Can't flush").is_none());
+
+            // only the latest coalesced data is written
+            TICKS.store(2000, Ordering::Relaxed);
+            assert!(throttled.flush().expect("Can't flush").is_some());
+            assert!(!throttled.has_pending());
+        }
+        assert_eq!(fs.used_blocks(), 6);
+        fs.read(5, |blk_data| assert!(blk_data.iter().all(|v| *v == 6)))
+            .expect("Can't read");
+    }
+
+    #[test]
+    fn test_fs_overflow() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.append(|blk_data| blk_data.fill(1))
+                .expect("Can't append");
+            assert!(matches!(
+                fs.read(usize::MAX, |_| {}),
+                Err(Error::ArithmeticOverflow)
+            ));
+        }
+
+        // the last id is exhausted by append, existing blocks are still readable
+        let blk = &mut storage.data[BLOCK_SIZE * 2..BLOCK_SIZE * 3];
+        Block::<BLOCK_SIZE>::set_id(blk, MAX_BLOCK_ID - 1);
+        Block::<BLOCK_SIZE>::set_crc(blk);
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.blocks_until_id_exhaustion(), 1);
+        fs.append(|blk_data| blk_data.fill(2))
+            .expect("Can't append");
+        assert_eq!(fs.blocks_until_id_exhaustion(), 0);
+        assert!(matches!(
+            fs.append(|blk_data| blk_data.fill(3)),
+            Err(Error::BlockIdsExhausted)
+        ));
+        assert_eq!(fs.used_blocks(), 2);
+        fs.read(1, |data| assert!(data.iter().all(|v| *v == 2)))
+            .expect("Can't read");
+
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.next_blk_id(), MAX_BLOCK_ID + 1);
+        assert_eq!(fs.blocks_until_id_exhaustion(), 0);
+    }
+
+    #[test]
+    fn test_fs_count_ids() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 16;
+        const NEW_FS_ID: FsId = 1585159336;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for _ in 0..10 {
+                fs.append(|blk_data| blk_data.fill(1))
+                    .expect("Can't append");
+            }
+        }
+        {
+            // reformat, config blocks and 3 blocks of old fs are overwritten
+            let mut fs = Fs::new_with_force(&mut storage, NEW_FS_ID, CrcMode::Full, true)
+                .expect("Can't create new fs");
+            for _ in 0..3 {
+                fs.append(|blk_data| blk_data.fill(2))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut ids = [FsIdCount::default(); 4];
+        let report = Fs::count_fs_ids(&mut storage, 1, &mut ids).expect("Can't count ids");
+        assert_eq!(
+            report,
+            FsIdsReport {
+                checked_blocks: 16,
+                valid_blocks: 12,
+                ids_found: 2,
+                untracked_blocks: 0,
+            }
+        );
+        assert_eq!(
+            &ids[..2],
+            &[
+                FsIdCount {
+                    fs_id: NEW_FS_ID,
+                    blocks: 5
+                },
+                FsIdCount {
+                    fs_id: FS_ID,
+                    blocks: 7
+                },
+            ]
+        );
+
+        let mut ids = [FsIdCount::default(); 1];
+        let report = Fs::count_fs_ids(&mut storage, 2, &mut ids).expect("Can't sample ids");
+        assert_eq!(report.checked_blocks, 8);
+        assert_eq!(report.valid_blocks, 6);
+        assert_eq!(report.ids_found, 1);
+        assert_eq!(report.untracked_blocks, 3);
+        assert_eq!(ids[0].blocks, 3);
+    }
+
+    #[test]
+    fn test_fs_recover_blocks() {
+        // config doesn't fit to 64 byte block with all header features
+        const BLOCK_SIZE: usize = 128;
+        const SIZE: usize = BLOCK_SIZE * 17;
+        const NEW_FS_ID: FsId = 1585159336;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            // 20 blocks with wraparound, blocks 5..20 are left, block 15 is the first data block
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..20 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        {
+            // reformat overwrites 3 blocks after config blocks, blocks 15..18 are lost
+            let mut fs = Fs::new_with_force(&mut storage, NEW_FS_ID, CrcMode::Full, true)
+                .expect("Can't create new fs");
+            for _ in 0..3 {
+                fs.append(|blk_data| blk_data.fill(0xff))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut ids = [0; 12];
+        let mut pos = 0;
+        let read = Fs::recover_blocks(&mut storage, FS_ID, |id, blk_data| {
+            assert!(blk_data.iter().all(|b| *b as u64 == id));
+            ids[pos] = id;
+            pos += 1;
+            true
+        })
+        .expect("Can't recover blocks");
+        assert_eq!(read, 12);
+        assert_eq!(ids, [5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 18, 19]);
+
+        let mut count = 0;
+        let read = Fs::recover_blocks(&mut storage, FS_ID, |_, _| {
+            count += 1;
+            count < 5
+        })
+        .expect("Can't recover part of blocks");
+        assert_eq!(read, 5);
+
+        let read = Fs::recover_blocks(&mut storage, 1, |_, _| true).expect("Can't recover");
+        assert_eq!(read, 0);
+    }
+
+    #[test]
+    fn test_generate_fs_id() {
+        let hw_id = [0x12_u8, 0x34, 0x56, 0x78, 0x9a, 0xbc];
+
+        assert_eq!(generate_fs_id(&hw_id, 42), generate_fs_id(&hw_id, 42));
+        assert_ne!(generate_fs_id(&hw_id, 42), generate_fs_id(&hw_id, 43));
+        assert_ne!(generate_fs_id(&hw_id, 42), generate_fs_id(&hw_id[1..], 42));
+
+        let mut storage = RamStorage::<{ 512 * 8 }, 512>::new().expect("Can't create storage");
+        let fs_id = generate_fs_id(&hw_id, 42);
+        let fs = Filesystem::<_, 512>::new(&mut storage, fs_id).expect("Can't create fs");
+        assert_eq!(fs.stats().id, fs_id);
+    }
+
+    #[test]
+    fn test_derive_fs_id() {
+        let hw_id = [0x12_u8, 0x34, 0x56, 0x78, 0x9a, 0xbc];
+
+        assert_eq!(derive_fs_id(&hw_id, b"log"), derive_fs_id(&hw_id, b"log"));
+        assert_ne!(derive_fs_id(&hw_id, b"log"), derive_fs_id(&hw_id, b"cfg"));
+        assert_ne!(
+            derive_fs_id(&hw_id, b"log"),
+            derive_fs_id(&hw_id[1..], b"log")
+        );
+        assert_ne!(derive_fs_id(b"ab", b"c"), derive_fs_id(b"a", b"bc"));
+        // documented scheme, ids of deployed devices must never change
+        assert_eq!(derive_fs_id(b"abc", b"xyz"), 0x12885b67);
+        assert_eq!(
+            derive_wide_fs_id(b"abc", b"xyz"),
+            0xcfacb9da808e9255c4d5e90d12885b67
+        );
+    }
+
+    #[test]
+    fn test_fs_wide_fs_id() {
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let wide_id = derive_wide_fs_id(b"abc", b"xyz");
+        let narrow_id = wide_id & NARROW_FS_ID_MAX;
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new_with_header(
+                &mut storage,
+                wide_id,
+                CrcMode::HeaderOnly,
+                HeaderVersion::V2,
+            )
+            .expect("Can't create fs");
+            assert_eq!(fs.data_size(), Fs::data_block_size() - HeaderV2::LEN - 12);
+            // ring wraps, head is found by ids of blocks with the whole fs id
+            for value in 0..10 {
+                fs.append(|blk_data| blk_data.fill(value))
+                    .expect("Can't append");
+            }
+        }
+
+        // width of the id is stored in config, restore doesn't need it
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!(fs.id(), wide_id);
+            assert_eq!((fs.next_blk_id(), fs.used_blocks()), (10, 6));
+            fs.read_with_info(5, |info, blk_data| {
+                assert_eq!((info.fs_id, blk_data[0]), (wide_id, 9));
+            })
+            .expect("Can't read");
+        }
+
+        let mut found = [0; 1];
+        Fs::scan_for_fs(&mut storage, |candidate| {
+            found[0] = candidate.fs_id;
+            true
+        })
+        .expect("Can't scan");
+        assert_eq!(found, [wide_id]);
+
+        // blocks of the same low bits don't belong to narrow filesystem
+        assert!(matches!(
+            Fs::new(&mut storage, narrow_id),
+            Err(Error::ForeignFilesystem { found_fs_id }) if found_fs_id == wide_id
+        ));
+
+        // config block must have space for the whole id
+        let mut storage = RamStorage::<{ 64 * 8 }, 64>::new().expect("Can't create storage");
+        assert!(matches!(
+            Filesystem::<_, 64>::new(&mut storage, wide_id),
+            Err(Error::InvalidBlockSizeForStorage)
+        ));
+    }
+}
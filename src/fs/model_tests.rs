@@ -0,0 +1,129 @@
+//! Model based tests: random sequences of appends, restores and power cuts are applied to the filesystem
+//! and to a reference model (deque of the newest blocks, `None` is damaged block), restored filesystem must match
+//! the model.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use proptest::prelude::*;
+
+use super::config_block::CONFIG_COPIES;
+use super::Filesystem;
+use crate::block::FsId;
+use crate::error::Error;
+use crate::storage::ram::RamStorage;
+use crate::storage::Storage;
+
+// config doesn't fit to 64 byte block with all header features
+const BLOCK_SIZE: usize = 128;
+const MAX_BLOCKS: usize = 12;
+const FS_ID: FsId = 871349305;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Append(u8),
+    Restore,
+    /// Append is interrupted after header of the block is written, its data is damaged
+    PowerCut(u8),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        6 => any::<u8>().prop_map(Op::Append),
+        2 => Just(Op::Restore),
+        1 => any::<u8>().prop_map(Op::PowerCut),
+    ]
+}
+
+/// Ring of `blocks` blocks, the first ones are config blocks
+struct Ring {
+    inner: RamStorage<{ BLOCK_SIZE * MAX_BLOCKS }, BLOCK_SIZE>,
+    blocks: usize,
+}
+
+impl Storage for Ring {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.inner.read(blk_idx, data)
+    }
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        self.inner.write(blk_idx, data)
+    }
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+    fn min_block_index(&self) -> usize {
+        0
+    }
+    fn max_block_index(&self) -> usize {
+        self.blocks
+    }
+}
+
+type Fs<'a> = Filesystem<'a, Ring, BLOCK_SIZE>;
+
+fn check(fs: &mut Fs, model: &VecDeque<Option<u8>>) -> Result<(), TestCaseError> {
+    prop_assert_eq!(fs.used_blocks(), model.len());
+    prop_assert_eq!(fs.is_empty(), model.is_empty());
+    let mut blocks = Vec::new();
+    for blk_offset in 0..fs.used_blocks() {
+        let mut value = None;
+        match fs.read(blk_offset, |blk_data| value = Some(blk_data[0])) {
+            Ok(_) | Err(Error::NotValidBlockForRead) => blocks.push(value),
+            Err(e) => return Err(TestCaseError::fail(std::format!("Can't read: {:?}", e))),
+        }
+    }
+    prop_assert!(blocks.iter().eq(model.iter()));
+    Ok(())
+}
+
+fn run(blocks: usize, ops: &[Op]) -> Result<(), TestCaseError> {
+    let capacity = blocks - CONFIG_COPIES;
+    let mut storage = Ring {
+        inner: RamStorage::new().expect("Can't create storage"),
+        blocks,
+    };
+    let mut model = VecDeque::new();
+    let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+    for op in ops {
+        match op {
+            Op::Append(value) => {
+                fs.append(|blk_data| blk_data.fill(*value))
+                    .expect("Can't append");
+                if model.len() == capacity {
+                    model.pop_front();
+                }
+                model.push_back(Some(*value));
+            }
+            Op::Restore => {
+                fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            }
+            Op::PowerCut(value) => {
+                let appended = fs
+                    .append(|blk_data| blk_data.fill(*value))
+                    .expect("Can't append");
+                let end = (appended.offset + 1) * BLOCK_SIZE;
+                storage.inner.data[end - 1] ^= 0xFF;
+                // the oldest block was overwritten by the damaged one, it's treated as the oldest block
+                // till the next append overwrites it, damaged block at the end of the ring is dropped
+                if model.len() == capacity {
+                    model.pop_front();
+                    if appended.offset + 1 < blocks {
+                        model.push_front(None);
+                    }
+                }
+                fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            }
+        }
+        check(&mut fs, &model)?;
+    }
+
+    let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+    check(&mut fs, &model)
+}
+
+proptest! {
+    #[test]
+    fn test_fs_model(blocks in CONFIG_COPIES + 1..=MAX_BLOCKS, ops in proptest::collection::vec(op(), 0..40)) {
+        run(blocks, &ops)?;
+    }
+}
@@ -0,0 +1,55 @@
+//! Hooks to get notified about filesystem events, set observer with [`super::Filesystem::set_observer`].
+
+use core::fmt;
+
+use super::FsStats;
+use crate::block::BlockId;
+
+/// All methods have empty default implementation, implement only needed ones
+pub trait FsObserver {
+    /// Called by `append` when used blocks reach `percent` of capacity, 100 means storage is full
+    /// and next append will overwrite the oldest block.
+    /// Only crossing by `append` is reported, it is not called on restore of already filled storage.
+    fn on_watermark(&mut self, _percent: u8, _stats: &FsStats) {}
+
+    /// Return true to get [`FsObserver::on_evict`] calls, it costs additional read for every append to full storage
+    fn wants_evicted_blocks(&self) -> bool {
+        false
+    }
+
+    /// Called by `append` to full storage with id and data of the oldest block right before it is overwritten,
+    /// use it to archive or summarize old data. Not called in case the oldest block is not valid.
+    fn on_evict(&mut self, _id: BlockId, _data: &[u8]) {}
+}
+
+pub(crate) struct Observer<'a> {
+    pub(crate) observer: &'a mut (dyn FsObserver + Send),
+    /// Percents of capacity
+    pub(crate) watermarks: &'a [u8],
+}
+
+impl<'a> Observer<'a> {
+    pub(crate) fn notify_usage(&mut self, used_before: usize, stats: &FsStats) {
+        for percent in self.watermarks.iter().copied() {
+            if percent == 0 || percent > 100 {
+                continue;
+            }
+
+            // compare used / capacity with percent / 100 without rounding and overflow
+            let threshold = stats.capacity as u128 * percent as u128;
+            if (used_before as u128) * 100 < threshold
+                && (stats.used_blocks as u128) * 100 >= threshold
+            {
+                self.observer.on_watermark(percent, stats);
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Observer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observer")
+            .field("watermarks", &self.watermarks)
+            .finish()
+    }
+}
@@ -0,0 +1,455 @@
+//! Blocks kept out of the ring (pins) and eviction by priority, see [`Filesystem::pin`].
+
+use super::metrics::Counter;
+use super::{config_block, AppendedBlock, Filesystem};
+use crate::block::{BlockLayout, HeaderV2, HeaderVersion};
+use crate::error::Error;
+use crate::logging::log;
+use crate::storage::Storage;
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    /// Read data of pinned block, `blk_idx` is storage index returned by `pin`
+    pub fn read_pinned<F>(&mut self, blk_idx: usize, reader: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&[u8]),
+    {
+        if !self.is_pinned(blk_idx) {
+            return Err(Error::BlockIsNotPinned);
+        }
+
+        self.read_blk_idx(blk_idx, |_, _, data| reader(data))
+    }
+
+    /// Pin block at `blk_offset`, write head skips it on wraparound until it is unpinned, so pinned data
+    /// (e.g. panic record) is not overwritten. Pinned block is excluded from `read` offsets and capacity,
+    /// read it with `read_pinned`. Returns storage index of the block.
+    /// Pins are kept in the table passed to `set_pins`.
+    pub fn pin(&mut self, blk_offset: usize) -> Result<usize, Error> {
+        if blk_offset >= self.used_blocks() {
+            return Err(Error::NotValidBlockForRead);
+        }
+        // at least one block must be left for writes
+        if self.capacity() < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        let slot = self
+            .pins
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::PinTableIsFull)?;
+        let blk_idx = self.blk_idx_of(blk_offset)?;
+        log!(debug, "Pin block {} at {}", blk_offset, blk_idx);
+        self.pins[slot] = Some(Pin {
+            blk_idx,
+            released: false,
+        });
+
+        // the oldest block of full storage is the next one to write
+        if self.offset == blk_idx {
+            self.offset = self.skip_pinned(self.offset, 0)?;
+        }
+        #[cfg(feature = "strict_invariants")]
+        self.check_invariants();
+
+        Ok(blk_idx)
+    }
+
+    /// Unpin block pinned with `pin` or appended with `Priority::High`, block returns to the ring (and will be
+    /// overwritten) when write head reaches it, till then it is still skipped and can be read with `read_pinned`.
+    pub fn unpin(&mut self, blk_idx: usize) -> Result<(), Error> {
+        if let Some(pin) = self
+            .pins
+            .iter_mut()
+            .flatten()
+            .find(|p| p.blk_idx == blk_idx)
+        {
+            pin.released = true;
+            return Ok(());
+        }
+
+        match self
+            .priority
+            .iter_mut()
+            .flatten()
+            .find(|p| p.blk_idx == blk_idx)
+        {
+            Some(pin) => {
+                pin.released = true;
+                self.write_config()
+            }
+            None => Err(Error::BlockIsNotPinned),
+        }
+    }
+
+    /// Set table of pinned blocks and reload filesystem attributes. Filesystem doesn't persist pins,
+    /// application has to store the table (see `pins`) after every `pin`, `unpin` and `append`
+    /// (released pins are removed from the table by `append`) and set it after every restart.
+    pub fn set_pins(&mut self, pins: &'a mut [Option<Pin>]) -> Result<(), Error> {
+        self.pins = pins;
+        self.init()?;
+        self.count(Counter::Restores);
+        Ok(())
+    }
+
+    /// Table of pinned blocks
+    pub fn pins(&self) -> &[Option<Pin>] {
+        self.pins
+    }
+
+    /// Same as `append`, block with `Priority::High` (e.g. crash dump) is kept out of the ring like pinned block
+    /// (see `pin`), so routine blocks are overwritten on wraparound before it, read it with `read_pinned`. Block is
+    /// flagged in v2 header (see [`HeaderV2::FLAG_PRIORITY`]) and its index is stored in config block, restore
+    /// keeps it out of the ring without `set_pins`. Filesystem with v1 header returns `UnsupportedHeaderVersion`,
+    /// config of small blocks has no space for the table (`CanNotWriteConfig`). In case the table is full
+    /// (see [`config_block::MAX_PRIORITY_BLOCKS`]), block isn't written and `PinTableIsFull` is returned, `unpin`
+    /// old blocks (slot is free when write head reaches unpinned block) or write it with `Priority::Normal`.
+    pub fn append_with_priority<F>(
+        &mut self,
+        priority: Priority,
+        writer: F,
+    ) -> Result<AppendedBlock, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if priority == Priority::Normal {
+            return self.append(writer);
+        }
+        if self.header != HeaderVersion::V2 {
+            return Err(Error::UnsupportedHeaderVersion);
+        }
+        // block of transaction isn't available for read, so it can't be pinned
+        if self.in_tx {
+            return Err(Error::TransactionIsOpen);
+        }
+        if self.config_size() < config_block::PRIORITY_END {
+            log!(warn, "Block is too small for the table of priority blocks");
+            return Err(Error::CanNotWriteConfig);
+        }
+        // at least one block must be left for writes
+        if self.capacity() < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+        let slot = self
+            .priority
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::PinTableIsFull)?;
+
+        let header = HeaderV2 {
+            flags: HeaderV2::FLAG_PRIORITY,
+            length: u16::try_from(self.data_size()).unwrap_or(u16::MAX),
+            ..HeaderV2::default()
+        };
+        let blk = self.append_block(Some(header), |metadata, data| {
+            metadata.fill(0);
+            writer(data);
+        })?;
+        log!(debug, "Keep high priority block at {}", blk.offset);
+        self.priority[slot] = Some(Pin {
+            blk_idx: blk.offset,
+            released: false,
+        });
+        // the oldest block of full storage is the next one to write
+        if self.offset == blk.offset {
+            self.offset = self.skip_pinned(self.offset, 0)?;
+        }
+        #[cfg(feature = "strict_invariants")]
+        self.check_invariants();
+        self.write_config()?;
+
+        Ok(blk)
+    }
+
+    /// Blocks appended with `Priority::High` which are kept out of the ring, the table is stored in config block
+    pub fn priority_blocks(&self) -> &[Option<Pin>] {
+        &self.priority
+    }
+
+    fn is_pinned(&self, blk_idx: usize) -> bool {
+        self.pins
+            .iter()
+            .chain(&self.priority)
+            .flatten()
+            .any(|p| p.blk_idx == blk_idx)
+    }
+
+    /// Table of high priority blocks from `config`, entries out of data range and entries of blocks which
+    /// were overwritten after config was written (they don't have `FLAG_PRIORITY`) are ignored
+    pub(super) fn priority_of(
+        &mut self,
+        config: &config_block::FsConfigBlock,
+        read_buf: &mut [u8],
+    ) -> Result<[Option<Pin>; config_block::MAX_PRIORITY_BLOCKS], Error> {
+        let mut priority = [None; config_block::MAX_PRIORITY_BLOCKS];
+        if !config.has_fields() {
+            return Ok(priority);
+        }
+
+        let data_blocks = self.data_blk_offset()..self.storage.max_block_index();
+        for (pin, entry) in priority.iter_mut().zip(config.priority) {
+            let Some(blk_idx) = (entry & !config_block::PRIORITY_RELEASED)
+                .checked_sub(1)
+                .and_then(|blk_idx| usize::try_from(blk_idx).ok())
+                .filter(|blk_idx| data_blocks.contains(blk_idx))
+            else {
+                continue;
+            };
+            self.storage.read(blk_idx, read_buf)?;
+            let info = Self::info_of(read_buf, self.crc_mode, self.header, self.id);
+            if !info.is_valid || info.fs_id != self.id || !info.has_flags(HeaderV2::FLAG_PRIORITY) {
+                log!(debug, "Block at {} isn't high priority anymore", blk_idx);
+                continue;
+            }
+            *pin = Some(Pin {
+                blk_idx,
+                released: entry & config_block::PRIORITY_RELEASED != 0,
+            });
+        }
+
+        Ok(priority)
+    }
+
+    /// Storage index of the `count`-th not pinned data block starting from `from`, wraps around the end.
+    /// `count` must be less than `capacity`.
+    pub(super) fn skip_pinned(&self, from: usize, count: usize) -> Result<usize, Error> {
+        let begin = self.data_blk_offset();
+        let end = self.storage.max_block_index();
+        let len = end - begin;
+        let distance = |blk_idx: usize| {
+            if blk_idx >= from {
+                blk_idx - from
+            } else {
+                len - (from - blk_idx)
+            }
+        };
+
+        // every pinned block on the way moves the result one block further,
+        // smallest distance which isn't changed by pinned blocks is never pinned itself
+        let mut dist = count;
+        loop {
+            let skipped = self
+                .skipped_blocks()
+                .filter(|blk_idx| distance(*blk_idx) <= dist)
+                .count();
+            let next = count
+                .checked_add(skipped)
+                .ok_or(Error::ArithmeticOverflow)?;
+            if next == dist {
+                break;
+            }
+            dist = next;
+        }
+
+        if dist >= len {
+            return Err(Error::ArithmeticOverflow);
+        }
+        if dist >= end - from {
+            Ok(begin + (dist - (end - from)))
+        } else {
+            Ok(from + dist)
+        }
+    }
+}
+
+/// Block pinned with [`Filesystem::pin`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    /// Storage index of the block
+    pub blk_idx: usize,
+    /// Block was unpinned, but write head hasn't reached it yet
+    pub released: bool,
+}
+
+/// Priority of block written by [`Filesystem::append_with_priority`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// Block is kept while routine blocks are overwritten
+    High,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pin, Priority};
+    use crate::block::{CrcMode, FsId, HeaderV2, HeaderVersion};
+    use crate::error::Error;
+    use crate::fs::config_block::MAX_PRIORITY_BLOCKS;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_pins() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        fn read_all(fs: &mut Fs) -> [u8; 8] {
+            let mut ids = [u8::MAX; 8];
+            for (i, id) in ids.iter_mut().enumerate().take(fs.used_blocks()) {
+                fs.read(i, |blk_data| *id = blk_data[0])
+                    .expect("Can't read block");
+            }
+            ids
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut pins = [None; 2];
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.set_pins(&mut pins).expect("Can't set pins");
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+
+            let pinned = fs.pin(1).expect("Can't pin block");
+            assert_eq!(pinned, 3);
+            assert_eq!((fs.capacity(), fs.used_blocks()), (6, 4));
+            assert_eq!(read_all(&mut fs)[..4], [0, 2, 3, 4]);
+
+            // wraparound skips pinned block
+            for i in 5..11 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(read_all(&mut fs)[..6], [5, 6, 7, 8, 9, 10]);
+            let mut data = 0;
+            fs.read_pinned(pinned, |blk_data| data = blk_data[0])
+                .expect("Can't read pinned block");
+            assert_eq!(data, 1);
+        }
+
+        let mut restored_pins = pins;
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't restore fs");
+            fs.set_pins(&mut restored_pins).expect("Can't set pins");
+            assert_eq!(
+                (fs.next_blk_id(), fs.offset(), fs.used_blocks()),
+                (11, 7, 6)
+            );
+            assert_eq!(read_all(&mut fs)[..6], [5, 6, 7, 8, 9, 10]);
+
+            fs.unpin(3).expect("Can't unpin block");
+            assert!(matches!(fs.unpin(4), Err(Error::BlockIsNotPinned)));
+            for i in 11..13 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            // released block is still skipped until write head reaches it
+            assert!(fs.read_pinned(3, |_| {}).is_ok());
+            for i in 13..15 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert!(matches!(
+                fs.read_pinned(3, |_| {}),
+                Err(Error::BlockIsNotPinned)
+            ));
+            assert_eq!(fs.pins(), &[None, None]);
+            assert_eq!(fs.capacity(), 7);
+            assert_eq!(read_all(&mut fs)[..7], [8, 9, 10, 11, 12, 13, 14]);
+        }
+
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs without pins");
+        assert_eq!((fs.next_blk_id(), fs.used_blocks()), (15, 7));
+    }
+
+    #[test]
+    fn test_fs_append_with_priority() {
+        // config of 256 byte block has the table of priority blocks
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 11;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert!(matches!(
+                fs.append_with_priority(Priority::High, |_| {}),
+                Err(Error::UnsupportedHeaderVersion)
+            ));
+        }
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let mut dumps = [0; MAX_PRIORITY_BLOCKS];
+        {
+            let mut fs = Fs::new_with_header(&mut storage, FS_ID, CrcMode::Full, HeaderVersion::V2)
+                .expect("Can't create fs");
+            for (i, dump) in dumps.iter_mut().enumerate() {
+                fs.append(|blk_data| blk_data.fill(0))
+                    .expect("Can't append");
+                *dump = fs
+                    .append_with_priority(Priority::High, |blk_data| blk_data.fill(i as u8 + 1))
+                    .expect("Can't append")
+                    .offset;
+            }
+            let mut info = None;
+            fs.read_raw(dumps[0], |block| {
+                info = Some(Fs::info_of(block, CrcMode::Full, HeaderVersion::V2, FS_ID))
+            })
+            .expect("Can't read block");
+            assert!(info
+                .expect("Block wasn't read")
+                .has_flags(HeaderV2::FLAG_PRIORITY));
+            let next_id = fs.next_blk_id();
+            assert!(matches!(
+                fs.append_with_priority(Priority::High, |_| {}),
+                Err(Error::PinTableIsFull)
+            ));
+            assert_eq!(fs.next_blk_id(), next_id);
+
+            fs.begin_tx().expect("Can't begin transaction");
+            assert!(matches!(
+                fs.append_with_priority(Priority::High, |_| {}),
+                Err(Error::TransactionIsOpen)
+            ));
+            fs.rollback_tx().expect("Can't rollback transaction");
+        }
+
+        // restore keeps dumps out of the ring without table of pins
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(
+            fs.priority_blocks(),
+            dumps.map(|blk_idx| Some(Pin {
+                blk_idx,
+                released: false
+            }))
+        );
+        assert_eq!(fs.capacity(), 5);
+        for _ in 0..20 {
+            fs.append(|blk_data| blk_data.fill(0))
+                .expect("Can't append");
+        }
+        for (i, blk_idx) in dumps.iter().enumerate() {
+            let mut data = 0;
+            fs.read_pinned(*blk_idx, |blk_data| data = blk_data[0])
+                .expect("Can't read pinned block");
+            assert_eq!(data, i as u8 + 1);
+        }
+
+        // unpinned dump is overwritten by write head after restore
+        fs.unpin(dumps[0]).expect("Can't unpin block");
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert!(fs.priority_blocks()[0].is_some_and(|pin| pin.released));
+        for _ in 0..6 {
+            fs.append(|blk_data| blk_data.fill(0))
+                .expect("Can't append");
+        }
+        assert!(matches!(
+            fs.read_pinned(dumps[0], |_| {}),
+            Err(Error::BlockIsNotPinned)
+        ));
+        assert_eq!(fs.capacity(), 6);
+
+        // entry of overwritten block is dropped by restore
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.priority_blocks()[0], None);
+        assert_eq!(fs.capacity(), 6);
+    }
+}
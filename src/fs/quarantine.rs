@@ -0,0 +1,163 @@
+//! Blocks which failed crc on read are skipped until they are rewritten, see [`Filesystem::set_quarantine`].
+
+use super::{config_block, Filesystem};
+use crate::block::BlockLayout;
+use crate::error::Error;
+use crate::logging::log;
+use crate::storage::Storage;
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    /// Record blocks with wrong crc found by reads and `scrub` in quarantine (it's stored in config block, up to
+    /// `MAX_QUARANTINED` blocks, it isn't stored in case config block is too small), so one bad sector
+    /// doesn't produce errors on every cycle of the ring. Reads of quarantined block fail with
+    /// `NotValidBlockForRead` without reading the storage (except `ReadPolicy::Report`), write head marks it
+    /// as bad (see `mark_bad`) instead of writing to it. Quarantine isn't recorded by read-only mount.
+    pub fn set_quarantine(&mut self, enabled: bool) {
+        self.quarantine_enabled = enabled;
+    }
+
+    /// Storage indexes of quarantined blocks, see `set_quarantine`
+    pub fn quarantined(&self) -> impl Iterator<Item = usize> + '_ {
+        self.quarantine.iter().flatten().copied()
+    }
+
+    pub(super) fn is_quarantined(&self, blk_idx: usize) -> bool {
+        self.quarantine.contains(&Some(blk_idx))
+    }
+
+    pub(super) fn quarantine_blk(&mut self, blk_idx: usize) -> Result<(), Error> {
+        if !self.quarantine_enabled || self.read_only || self.is_quarantined(blk_idx) {
+            return Ok(());
+        }
+        let Some(slot) = self.quarantine.iter().position(Option::is_none) else {
+            log!(
+                warn,
+                "Quarantine is full, block at {} isn't recorded",
+                blk_idx
+            );
+            return Ok(());
+        };
+
+        log!(warn, "Quarantine block at {}", blk_idx);
+        self.quarantine[slot] = Some(blk_idx);
+        self.write_config()
+    }
+
+    /// Write head reached quarantined block, it's marked as bad, so it's skipped by the next writes
+    pub(super) fn retire_quarantined(&mut self) -> Result<(), Error> {
+        while let Some(slot) = self.quarantine.iter().position(|q| *q == Some(self.offset)) {
+            self.quarantine[slot] = None;
+            let blk_idx = self.offset;
+            match self.mark_bad(blk_idx) {
+                Ok(()) => {}
+                // block is reused, the table keeps its free entry
+                Err(Error::BadBlockTableIsFull | Error::TooSmallFilesystem) => {
+                    log!(
+                        warn,
+                        "Block at {} isn't marked as bad, it's reused",
+                        blk_idx
+                    );
+                    self.write_config()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Table of quarantined blocks from `config`, entries out of data range are ignored
+    pub(super) fn quarantine_of(
+        &self,
+        config: &config_block::FsConfigBlock,
+    ) -> [Option<usize>; config_block::MAX_QUARANTINED] {
+        let mut quarantine = [None; config_block::MAX_QUARANTINED];
+        if !config.has_fields() {
+            return quarantine;
+        }
+
+        let data_blocks = self.data_blk_offset()..self.storage.max_block_index();
+        for (quarantined, entry) in quarantine.iter_mut().zip(config.quarantine) {
+            *quarantined = entry
+                .checked_sub(1)
+                .and_then(|blk_idx| usize::try_from(blk_idx).ok())
+                .filter(|blk_idx| data_blocks.contains(blk_idx));
+        }
+
+        quarantine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::FsId;
+    use crate::error::Error;
+    use crate::fs::{Filesystem, ReadPolicy};
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_quarantine() {
+        // quarantine doesn't fit to config of 128 byte block with all header features
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 9;
+        const DAMAGED: usize = 4;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        // bit rot in data of block 2
+        storage.data[DAMAGED * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 1;
+        {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert!(fs.read(2, |_| {}).is_err());
+            assert_eq!(fs.quarantined().count(), 0);
+
+            fs.set_quarantine(true);
+            assert!(matches!(
+                fs.read(2, |_| {}),
+                Err(Error::NotValidBlockForRead)
+            ));
+            assert!(fs.quarantined().eq([DAMAGED]));
+        }
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert!(fs.quarantined().eq([DAMAGED]));
+        assert!(matches!(
+            fs.read(2, |_| {}),
+            Err(Error::NotValidBlockForRead)
+        ));
+        fs.set_read_policy(ReadPolicy::Report);
+        fs.read_with_info(2, |info, _| assert!(!info.is_valid))
+            .expect("Can't read damaged block");
+
+        // write head skips damaged block after the ring wraps around
+        let offsets: [usize; 5] = core::array::from_fn(|i| {
+            fs.append(|blk_data| blk_data.fill(5 + i as u8))
+                .expect("Can't append")
+                .offset
+        });
+        assert_eq!(offsets, [7, 8, 2, 3, 5]);
+        assert_eq!(fs.quarantined().count(), 0);
+        assert!(fs.bad_blocks().eq([DAMAGED]));
+        assert_eq!(fs.health().remapped_blocks, 1);
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert!(fs.bad_blocks().eq([DAMAGED]));
+        assert_eq!((fs.used_blocks(), fs.oldest_blk_id()), (6, 4));
+        for blk_offset in 0..6 {
+            fs.read(blk_offset, |blk_data| {
+                assert_eq!(blk_data[0], blk_offset as u8 + 4)
+            })
+            .expect("Can't read");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Records of any size packed to blocks: [`RecordWriter`] splits records to chunks, [`RecordStream`] reassembles them.
+//!
+//! Data of a block is a sequence of chunks, every chunk is `u16` little endian header followed by chunk data.
+//! Header has [`CHUNK_FIRST`] and [`CHUNK_LAST`] flags and length of the chunk data, zero header is padding
+//! till the end of the block. Record which doesn't fit to the rest of the block continues in the next one.
+
+use super::Filesystem;
+use crate::block::{BlockId, BlockLayout};
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+
+/// Length of chunk header
+pub const CHUNK_HEADER_LEN: usize = 2;
+/// Chunk is the beginning of a record
+pub const CHUNK_FIRST: u16 = 0x8000;
+/// Chunk is the end of a record
+pub const CHUNK_LAST: u16 = 0x4000;
+/// The biggest length of chunk data
+pub const MAX_CHUNK_LEN: usize = 0x3FFF;
+
+/// Packs records to blocks, a block is appended when it's full. Not full block is appended (padded with zeroes)
+/// on `flush` or when the writer is dropped.
+pub struct RecordWriter<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    buf: [u8; BS],
+    len: usize,
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>
+    RecordWriter<'f, 'a, S, BS, L, B>
+{
+    pub fn new(fs: &'f mut Filesystem<'a, S, BS, L, B>) -> Self {
+        Self {
+            fs,
+            buf: [0_u8; BS],
+            len: 0,
+        }
+    }
+
+    /// Write `record`, it's split to chunks in case it doesn't fit to the rest of the current block.
+    /// Blocks filled by the record are appended, its tail stays in the writer till the block is full or flushed.
+    pub fn write(&mut self, record: &[u8]) -> Result<(), Error> {
+        let data_size = self.fs.data_size();
+        if data_size <= CHUNK_HEADER_LEN {
+            return Err(Error::TooSmallBuffer);
+        }
+
+        let mut rest = record;
+        let mut flags = CHUNK_FIRST;
+        loop {
+            let space = data_size - self.len - CHUNK_HEADER_LEN;
+            let len = rest.len().min(space).min(MAX_CHUNK_LEN);
+            if len == rest.len() {
+                flags |= CHUNK_LAST;
+            }
+            let header = flags | len as u16;
+            self.buf[self.len..self.len + CHUNK_HEADER_LEN].copy_from_slice(&header.to_le_bytes());
+            self.len += CHUNK_HEADER_LEN;
+            self.buf[self.len..self.len + len].copy_from_slice(&rest[..len]);
+            self.len += len;
+            rest = &rest[len..];
+            flags = 0;
+
+            // chunk needs at least one byte of data
+            if data_size - self.len <= CHUNK_HEADER_LEN {
+                self.append_buf()?;
+            }
+            if header & CHUNK_LAST != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Append not full block, the rest of it is padding
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        self.append_buf()
+    }
+
+    /// Bytes of the current block which are not appended yet
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn fs(&mut self) -> &mut Filesystem<'a, S, BS, L, B> {
+        self.fs
+    }
+
+    fn append_buf(&mut self) -> Result<(), Error> {
+        let buf = &self.buf;
+        let len = self.len;
+        self.fs.append(|blk_data| {
+            blk_data[..len].copy_from_slice(&buf[..len]);
+            blk_data[len..].fill(0);
+        })?;
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Drop
+    for RecordWriter<'f, 'a, S, BS, L, B>
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Pull based reader of records written by [`RecordWriter`], from the oldest block to the newest one.
+/// Record is reassembled in the internal buffer of `R` bytes, so memory use is bounded and nothing is allocated.
+/// Tail of the oldest record which beginning was overwritten by the ring is skipped. Incomplete records are dropped
+/// and counted in `dropped`:
+/// - record which blocks were overwritten while the stream was reading it (the stream continues from the oldest block),
+/// - record interrupted by invalid block or by a new record (e.g. power loss before the tail was written),
+/// - record bigger than `R`.
+///
+/// Record which tail isn't written yet is kept, `next_record` returns it after the tail is appended.
+pub struct RecordStream<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    const R: usize,
+    L: BlockLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    /// Id of the next block to read, `None` to begin with the oldest block
+    next_id: Option<BlockId>,
+    /// Id of the block in the buffer
+    blk_id: BlockId,
+    /// Id of the block where the current record begins
+    record_id: BlockId,
+    blk: [u8; BS],
+    blk_len: usize,
+    pos: usize,
+    record: [u8; R],
+    record_len: usize,
+    in_record: bool,
+    overflow: bool,
+    dropped: usize,
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, const R: usize, L: BlockLayout, B: AsMut<[u8]>>
+    RecordStream<'f, 'a, S, BS, R, L, B>
+{
+    pub fn new(fs: &'f mut Filesystem<'a, S, BS, L, B>) -> Self {
+        Self::with_next_id(fs, None)
+    }
+
+    /// Stream which begins with block `id`, tail of the record which begins before it is skipped
+    pub fn from_blk_id(fs: &'f mut Filesystem<'a, S, BS, L, B>, id: BlockId) -> Self {
+        Self::with_next_id(fs, Some(id))
+    }
+
+    fn with_next_id(fs: &'f mut Filesystem<'a, S, BS, L, B>, next_id: Option<BlockId>) -> Self {
+        Self {
+            fs,
+            next_id,
+            blk_id: 0,
+            record_id: 0,
+            blk: [0_u8; BS],
+            blk_len: 0,
+            pos: 0,
+            record: [0_u8; R],
+            record_len: 0,
+            in_record: false,
+            overflow: false,
+            dropped: 0,
+        }
+    }
+
+    /// The next complete record, `None` in case the newest block is reached
+    pub fn next_record(&mut self) -> Result<Option<&[u8]>, Error> {
+        loop {
+            if self.blk_len - self.pos <= CHUNK_HEADER_LEN || self.chunk_header() == 0 {
+                if !self.next_block()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let header = self.chunk_header();
+            let begin = self.pos + CHUNK_HEADER_LEN;
+            let end = begin + (header as usize & MAX_CHUNK_LEN);
+            if end > self.blk_len {
+                log!(warn, "Chunk at {} is out of block", self.pos);
+                self.pos = self.blk_len;
+                self.drop_record();
+                continue;
+            }
+            self.pos = end;
+
+            if header & CHUNK_FIRST != 0 {
+                self.drop_record();
+                self.in_record = true;
+                self.record_id = self.blk_id;
+            } else if !self.in_record {
+                // tail of dropped record
+                continue;
+            }
+
+            let len = end - begin;
+            if self.record_len + len <= R {
+                self.record[self.record_len..self.record_len + len]
+                    .copy_from_slice(&self.blk[begin..end]);
+                self.record_len += len;
+            } else {
+                self.overflow = true;
+            }
+
+            if header & CHUNK_LAST != 0 {
+                let (len, overflow) = (self.record_len, self.overflow);
+                self.in_record = false;
+                self.record_len = 0;
+                self.overflow = false;
+                if overflow {
+                    log!(debug, "Record is bigger than {} bytes", R);
+                    self.dropped += 1;
+                    continue;
+                }
+
+                return Ok(Some(&self.record[..len]));
+            }
+        }
+    }
+
+    /// Id of the block where the record returned by `next_record` begins
+    pub fn record_blk_id(&self) -> BlockId {
+        self.record_id
+    }
+
+    /// Number of incomplete records which were skipped
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn chunk_header(&self) -> u16 {
+        u16::from_le_bytes([self.blk[self.pos], self.blk[self.pos + 1]])
+    }
+
+    fn drop_record(&mut self) {
+        if self.in_record {
+            self.dropped += 1;
+        }
+        self.in_record = false;
+        self.record_len = 0;
+        self.overflow = false;
+    }
+
+    /// Read the next block to the buffer, `false` in case there is no block after the newest one
+    fn next_block(&mut self) -> Result<bool, Error> {
+        loop {
+            let oldest = self.fs.oldest_blk_id();
+            let id = self.next_id.unwrap_or(oldest);
+            if id >= self.fs.next_blk_id() {
+                return Ok(false);
+            }
+            let Some(blk_offset) = self.fs.blk_offset_of(id) else {
+                log!(
+                    debug,
+                    "Block {} was overwritten, continue from {}",
+                    id,
+                    oldest
+                );
+                self.drop_record();
+                self.next_id = Some(oldest);
+                continue;
+            };
+
+            let blk = &mut self.blk;
+            let mut len = 0;
+            let mut blk_id = id;
+            let res = self.fs.read_with_info(blk_offset, |info, data| {
+                blk_id = info.id;
+                len = data.len();
+                blk[..len].copy_from_slice(data);
+            });
+            self.next_id = Some(id + 1);
+            match res {
+                // stale block of the previous lap
+                Ok(_) if blk_id != id => self.drop_record(),
+                Ok(_) => {
+                    self.blk_id = id;
+                    self.blk_len = len;
+                    self.pos = 0;
+                    return Ok(true);
+                }
+                Err(Error::NotValidBlockForRead | Error::Lapped) => self.drop_record(),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
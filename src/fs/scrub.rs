@@ -0,0 +1,207 @@
+//! Background checks of stored blocks, see [`Filesystem::scrub`].
+
+use core::ops::Range;
+
+use super::metrics::Counter;
+use super::Filesystem;
+use crate::block::{BlockId, BlockLayout};
+use crate::error::Error;
+use crate::logging::log;
+use crate::storage::Storage;
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    /// Check ids of all used blocks, block at offset `i` must have id `oldest_blk_id() + i`.
+    /// `on_gap` is called for every range of missing ids (blocks are corrupted or overwritten by other data),
+    /// check stops when it returns false. Returns number of missing ids.
+    pub fn find_gaps<F>(&mut self, mut on_gap: F) -> Result<usize, Error>
+    where
+        F: FnMut(Range<BlockId>) -> bool,
+    {
+        let oldest = self.oldest_blk_id();
+        let mut missing = 0;
+        let mut gap_begin = None;
+        for blk_offset in 0..self.used_blocks() {
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let info = self.read_info(blk_idx)?;
+
+            let expected = oldest + blk_offset as BlockId;
+            let is_present = info.is_valid && info.fs_id == self.id && info.id == expected;
+            match (is_present, gap_begin) {
+                (false, None) => {
+                    log!(debug, "Block {} at {} is missing", expected, blk_idx);
+                    gap_begin = Some(expected);
+                    missing += 1;
+                }
+                (false, Some(_)) => missing += 1,
+                (true, Some(begin)) => {
+                    gap_begin = None;
+                    if !on_gap(begin..expected) {
+                        return Ok(missing);
+                    }
+                }
+                (true, None) => {}
+            }
+        }
+
+        if let Some(begin) = gap_begin {
+            on_gap(begin..self.next_blk_id());
+        }
+
+        Ok(missing)
+    }
+
+    /// Verify up to `step` used blocks, every call continues after the block checked by the previous one and wraps
+    /// around to the oldest block, so periodic calls (e.g. from idle task) continuously check the whole ring without
+    /// long pause. Damaged blocks are reported, not repaired: storages don't expose correctable ECC errors.
+    pub fn scrub(&mut self, step: usize) -> Result<ScrubReport, Error> {
+        let mut report = ScrubReport::default();
+        let used = self.used_blocks();
+        for _ in 0..step.min(used) {
+            let blk_offset = match self.blk_offset_of(self.scrub_id) {
+                Some(blk_offset) if blk_offset < used => blk_offset,
+                // checked block was overwritten or dropped
+                _ => 0,
+            };
+            let expected = self.oldest_blk_id() + blk_offset as BlockId;
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let info = self.read_info(blk_idx)?;
+            if !info.is_valid || info.fs_id != self.id || info.id != expected {
+                log!(warn, "Scrub: block {} at {} is damaged", expected, blk_idx);
+                report.damaged += 1;
+                if !info.is_valid && info.fs_id == self.id {
+                    self.health.crc_failures = self.health.crc_failures.saturating_add(1);
+                    self.count(Counter::CrcFailures);
+                    self.quarantine_blk(blk_idx)?;
+                }
+            }
+
+            report.checked += 1;
+            self.scrub_id = expected + 1;
+            if blk_offset + 1 == used {
+                report.completed_pass = true;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Offset of the first used block which is not valid (or its id isn't bigger than id of the previous block),
+    /// it's the place where valid data ends, e.g. after power loss during write. `None` if all used blocks are valid.
+    /// Ids skipped on restore (see `set_id_reserve`) are not treated as invalid blocks.
+    pub fn find_first_invalid_offset(&mut self) -> Result<Option<usize>, Error> {
+        self.first_invalid_offset_from(0)
+    }
+
+    pub(super) fn first_invalid_offset_from(
+        &mut self,
+        begin: usize,
+    ) -> Result<Option<usize>, Error> {
+        let mut prev_id = None;
+        for blk_offset in begin..self.used_blocks() {
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let info = self.read_info(blk_idx)?;
+            let is_increasing = prev_id.is_none_or(|prev| info.id > prev);
+            if !info.is_valid || info.fs_id != self.id || !is_increasing {
+                return Ok(Some(blk_offset));
+            }
+            prev_id = Some(info.id);
+        }
+
+        Ok(None)
+    }
+}
+
+/// Result of [`Filesystem::scrub`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub damaged: usize,
+    /// The newest block was checked, the next call starts from the oldest one
+    pub completed_pass: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScrubReport;
+    use crate::block::{fields, FsId};
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_scrub() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let damaged = {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert_eq!(fs.scrub(4).expect("Can't scrub"), ScrubReport::default());
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            // block isn't probed by restore
+            fs.blk_idx_of(1).expect("Can't get block index")
+        };
+        storage.data[damaged * BLOCK_SIZE + BLOCK_SIZE - 1] ^= 0xff;
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.used_blocks(), 5);
+        let report = |checked, damaged, completed_pass| ScrubReport {
+            checked,
+            damaged,
+            completed_pass,
+        };
+        assert_eq!(fs.scrub(3).expect("Can't scrub"), report(3, 1, false));
+        assert_eq!(fs.scrub(3).expect("Can't scrub"), report(3, 0, true));
+        // damaged block is overwritten, scrub continues from the new oldest block
+        for i in 5..9 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        assert_eq!(fs.scrub(7).expect("Can't scrub"), report(7, 0, true));
+    }
+
+    #[test]
+    fn test_fs_gaps() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..12 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            let missing = fs
+                .find_gaps(|_| panic!("No gaps expected"))
+                .expect("Can't find gaps");
+            assert_eq!(missing, 0);
+        }
+
+        // id of the block is its index - 2, corrupted blocks must not be visited by restore
+        for blk_idx in [4, 5, 11] {
+            storage.data[blk_idx * BLOCK_SIZE + fields::DATA_BEGIN] ^= 0xff;
+        }
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't restore fs");
+        let mut gaps = [0..0, 0..0, 0..0];
+        let mut len = 0;
+        let missing = fs
+            .find_gaps(|gap| {
+                gaps[len] = gap;
+                len += 1;
+                true
+            })
+            .expect("Can't find gaps");
+        assert_eq!(missing, 3);
+        assert_eq!(&gaps[..len], &[2..4, 9..10]);
+    }
+}
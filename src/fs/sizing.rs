@@ -0,0 +1,63 @@
+//! Choose block size for a storage and typical record size, see [`recommend_block_size`].
+
+use crate::block::{fields, CrcMode, HeaderVersion};
+use crate::storage::Geometry;
+
+/// Smallest block size which is considered
+pub const MIN_RECOMMENDED_BLOCK_SIZE: usize = 64;
+/// Larger block is recommended only in case it lowers write amplification by more than this (percent points)
+pub const AMPLIFICATION_TOLERANCE: u32 = 10;
+
+/// Block size chosen by [`recommend_block_size`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockSizeAdvice {
+    pub block_size: usize,
+    /// Size of data passed to `append`
+    pub data_size: usize,
+    /// Records which fit to data of one block
+    pub records_per_block: usize,
+    /// Bytes written to the medium (whole erase unit in case block is smaller) per 100 bytes of records,
+    /// 100 means no overhead
+    pub write_amplification: u32,
+}
+
+/// Recommend block size (power of two up to `max_block_size`, e.g. [`super::MAX_BLOCK_SIZE`] of
+/// [`super::DynFilesystem`]) for storage with `geometry` and records of `record_size` bytes packed to blocks.
+/// Bigger blocks have lower header overhead, but take more RAM and lose more records on torn write, so
+/// the smallest block within [`AMPLIFICATION_TOLERANCE`] of the lowest write amplification is chosen.
+/// Blocks must be multiple of write size of the storage. `None` in case record doesn't fit to any block.
+/// Pass chosen size to the storage (e.g. `block_size` of `FileStorage`), the same size must be used on restore.
+pub fn recommend_block_size(
+    geometry: &Geometry,
+    record_size: usize,
+    crc_mode: CrcMode,
+    header: HeaderVersion,
+    max_block_size: usize,
+) -> Option<BlockSizeAdvice> {
+    let record_size = record_size.max(1);
+    let header_len = fields::DATA_BEGIN + header.extension_len() + crc_mode.trailer_len();
+    let mut candidates =
+        core::iter::successors(Some(MIN_RECOMMENDED_BLOCK_SIZE), |size| size.checked_mul(2))
+            .take_while(|size| *size <= max_block_size)
+            .filter(|size| *size % geometry.write_size.max(1) == 0)
+            .filter_map(|block_size| {
+                let data_size = block_size.checked_sub(header_len)?;
+                let records_per_block = data_size / record_size;
+                if records_per_block == 0 {
+                    return None;
+                }
+
+                let written = block_size.max(geometry.erase_size.unwrap_or(0)) as u64;
+                let payload = (records_per_block * record_size) as u64;
+                Some(BlockSizeAdvice {
+                    block_size,
+                    data_size,
+                    records_per_block,
+                    write_amplification: u32::try_from(written * 100 / payload).unwrap_or(u32::MAX),
+                })
+            });
+
+    let lowest = candidates.clone().map(|a| a.write_amplification).min()?;
+    candidates.find(|a| a.write_amplification <= lowest.saturating_add(AMPLIFICATION_TOLERANCE))
+}
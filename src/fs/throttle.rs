@@ -0,0 +1,137 @@
+//! Cap write frequency of battery or wear constrained devices, see [`ThrottledFs`].
+
+use super::{AppendedBlock, Filesystem};
+use crate::block::BlockLayout;
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+use crate::time::{TimeSource, Timestamp};
+
+/// At most `appends` blocks are written during every `period` milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendBudget {
+    pub appends: u32,
+    pub period: Timestamp,
+}
+
+/// What happens with append over budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThrottlePolicy {
+    /// Append fails with `Error::Throttled`
+    #[default]
+    Reject,
+    /// Data is kept in memory as pending block (it replaces previous pending one), the newest data is written
+    /// by `flush` or by the next append within budget, so only the latest value (e.g. status) is kept
+    Coalesce,
+}
+
+/// Wrapper of filesystem which limits number of appends with [`AppendBudget`]
+pub struct ThrottledFs<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout,
+    T: TimeSource,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    time: T,
+    budget: AppendBudget,
+    policy: ThrottlePolicy,
+    window_start: Timestamp,
+    appends: u32,
+    pending: [u8; BS],
+    has_pending: bool,
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, T: TimeSource, B: AsMut<[u8]>>
+    ThrottledFs<'f, 'a, S, BS, L, T, B>
+{
+    /// `time` ticks are used to measure `budget` period
+    pub fn new(
+        fs: &'f mut Filesystem<'a, S, BS, L, B>,
+        mut time: T,
+        budget: AppendBudget,
+        policy: ThrottlePolicy,
+    ) -> Self {
+        let window_start = time.ticks();
+        Self {
+            fs,
+            time,
+            budget,
+            policy,
+            window_start,
+            appends: 0,
+            pending: [0_u8; BS],
+            has_pending: false,
+        }
+    }
+
+    /// Same as [`Filesystem::append`] within budget. Over budget returns `Error::Throttled`
+    /// or `None` in case data was coalesced, depends on [`ThrottlePolicy`].
+    pub fn append<F>(&mut self, writer: F) -> Result<Option<AppendedBlock>, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let data_size = self.fs.data_size();
+        if !self.take_budget() {
+            return match self.policy {
+                ThrottlePolicy::Reject => Err(Error::Throttled),
+                ThrottlePolicy::Coalesce => {
+                    writer(&mut self.pending[..data_size]);
+                    self.has_pending = true;
+                    Ok(None)
+                }
+            };
+        }
+
+        // pending data is older, it's replaced by the new one
+        self.has_pending = false;
+        self.fs.append(writer).map(Some)
+    }
+
+    /// Write pending block in case budget allows it, returns `None` if there is no pending block or
+    /// it's still throttled. Call it periodically with `Coalesce` policy.
+    pub fn flush(&mut self) -> Result<Option<AppendedBlock>, Error> {
+        if !self.has_pending || !self.take_budget() {
+            return Ok(None);
+        }
+
+        let data_size = self.fs.data_size();
+        let pending = &self.pending[..data_size];
+        let blk = self
+            .fs
+            .append(|blk_data| blk_data.copy_from_slice(pending))?;
+        self.has_pending = false;
+        Ok(Some(blk))
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.has_pending
+    }
+
+    pub fn into_inner(self) -> &'f mut Filesystem<'a, S, BS, L, B> {
+        self.fs
+    }
+
+    fn take_budget(&mut self) -> bool {
+        let now = self.time.ticks();
+        if now.saturating_sub(self.window_start) >= self.budget.period {
+            self.window_start = now;
+            self.appends = 0;
+        }
+
+        if self.appends >= self.budget.appends {
+            log!(
+                trace,
+                "Append is throttled till {}",
+                self.window_start + self.budget.period
+            );
+            return false;
+        }
+
+        self.appends += 1;
+        true
+    }
+}
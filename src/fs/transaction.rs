@@ -0,0 +1,141 @@
+//! Blocks appended between `begin_tx` and `commit_tx` are dropped on restore unless committed.
+
+use super::Filesystem;
+use crate::block::BlockLayout;
+use crate::error::Error;
+use crate::logging::log;
+use crate::storage::Storage;
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    /// Start transaction, blocks appended till `commit_tx` are not available for read and are dropped
+    /// on restore in case transaction isn't committed (e.g. after power loss), so record of several blocks
+    /// is never half visible.
+    pub fn begin_tx(&mut self) -> Result<(), Error> {
+        if self.in_tx {
+            return Err(Error::TransactionIsOpen);
+        }
+
+        log!(debug, "Begin transaction at block {}", self.next_blk_id());
+        self.in_tx = true;
+        self.blk_factory.set_pending(true);
+        Ok(())
+    }
+
+    /// Append the last block of transaction, it is written without pending flag and works as commit marker:
+    /// once it is written, all blocks of transaction become available for read.
+    /// Returns number of blocks in transaction.
+    pub fn commit_tx<F>(&mut self, writer: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if !self.in_tx {
+            return Err(Error::TransactionIsNotOpen);
+        }
+
+        let blocks = self.uncommitted + 1;
+        self.in_tx = false;
+        self.blk_factory.set_pending(false);
+        if let Err(e) = self.append(writer) {
+            self.in_tx = true;
+            self.blk_factory.set_pending(true);
+            return Err(e);
+        }
+
+        log!(debug, "Committed transaction of {} blocks", blocks);
+        Ok(blocks)
+    }
+
+    /// Drop all blocks appended since `begin_tx`, returns number of dropped blocks
+    pub fn rollback_tx(&mut self) -> Result<usize, Error> {
+        if !self.in_tx {
+            return Err(Error::TransactionIsNotOpen);
+        }
+
+        self.truncate_tail(self.used_blocks())
+    }
+
+    /// Transaction is started with `begin_tx` and not committed yet
+    pub fn in_tx(&self) -> bool {
+        self.in_tx
+    }
+
+    /// Number of the newest blocks with pending flag, they belong to not committed transaction
+    pub(super) fn count_uncommitted(&mut self) -> Result<usize, Error> {
+        let mut count = 0;
+        for blk_offset in (0..self.used_blocks()).rev() {
+            let blk_idx = self.blk_idx_of(blk_offset)?;
+            let info = self.read_info(blk_idx)?;
+            if !info.is_pending || info.fs_id != self.id {
+                break;
+            }
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::FsId;
+    use crate::error::Error;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_transactions() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 9;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert!(matches!(
+                fs.commit_tx(|_| {}),
+                Err(Error::TransactionIsNotOpen)
+            ));
+            fs.append(|blk_data| blk_data.fill(0))
+                .expect("Can't append");
+
+            fs.begin_tx().expect("Can't begin tx");
+            assert!(matches!(fs.begin_tx(), Err(Error::TransactionIsOpen)));
+            for i in 1..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (1, 3));
+            let blocks = fs
+                .commit_tx(|blk_data| blk_data.fill(3))
+                .expect("Can't commit tx");
+            assert_eq!(blocks, 3);
+            assert_eq!(fs.used_blocks(), 4);
+
+            fs.begin_tx().expect("Can't begin tx");
+            fs.append(|blk_data| blk_data.fill(4))
+                .expect("Can't append");
+            assert_eq!(fs.rollback_tx().expect("Can't rollback"), 1);
+            assert!(!fs.in_tx());
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (4, 4));
+
+            // power loss in the middle of transaction
+            fs.begin_tx().expect("Can't begin tx");
+            for i in 4..6 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (4, 4));
+        fs.append(|blk_data| blk_data.fill(0xaa))
+            .expect("Can't append");
+        let mut newest = 0;
+        fs.read(4, |blk_data| newest = blk_data[0])
+            .expect("Can't read");
+        assert_eq!(newest, 0xaa);
+    }
+}
@@ -0,0 +1,273 @@
+//! Dropping of the oldest and the newest blocks.
+
+use super::{config_block, Filesystem};
+use crate::block::{BlockId, BlockLayout};
+use crate::error::Error;
+use crate::logging::log;
+use crate::storage::Storage;
+use crate::time::Timestamp;
+
+impl<'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Filesystem<'a, S, BS, L, B> {
+    /// Drop blocks appended more than `max_age` milliseconds ago with `truncate_front`, returns number
+    /// of dropped blocks. Blocks are found by binary search of timestamps, nothing is dropped without time source.
+    pub fn truncate_older_than(&mut self, max_age: Timestamp) -> Result<usize, Error> {
+        let Some(now) = self.now() else {
+            return Ok(0);
+        };
+        let oldest_kept = now.saturating_sub(max_age);
+        let begin = self.first_blk_at(oldest_kept)?;
+        self.truncate_front(begin)
+    }
+
+    /// Drop blocks starting from `blk_offset` to the newest one: blocks are overwritten with zeroes
+    /// and write head is moved to `blk_offset`, so the next append continues from there.
+    /// In full filesystem dropped blocks are left as invalid blocks before the oldest one till they are
+    /// overwritten by new appends. Blocks of not committed transaction are always dropped and transaction
+    /// is closed. Returns number of dropped blocks.
+    pub fn truncate_tail(&mut self, blk_offset: usize) -> Result<usize, Error> {
+        self.check_writable()?;
+        let blk_offset = blk_offset.min(self.used_blocks());
+        let used = self.used_blocks() + self.uncommitted;
+        self.in_tx = false;
+        self.uncommitted = 0;
+        self.blk_factory.set_pending(false);
+        if blk_offset >= used {
+            return Ok(0);
+        }
+
+        let head = self.blk_idx_of(blk_offset)?;
+        let next_id = self.oldest_blk_id() + blk_offset as BlockId;
+        for offset in blk_offset..used {
+            let blk_idx = self.blk_idx_of(offset)?;
+            let data_buf = &mut self.buffer.as_mut()[..self.storage.block_size()];
+            data_buf.fill(0);
+            self.storage.write(blk_idx, data_buf)?;
+            self.run_writes += 1;
+        }
+
+        log!(
+            info,
+            "Truncated {} blocks, next block {} at {}",
+            used - blk_offset,
+            next_id,
+            head
+        );
+        // blocks dropped by `truncate_front` stay in the ring before the head
+        let is_empty = !self.is_full && head == self.data_blk_offset();
+        let is_full = self.is_full;
+        self.setup_attributes(head, next_id, is_empty, is_full);
+        #[cfg(feature = "strict_invariants")]
+        self.check_invariants();
+
+        Ok(used - blk_offset)
+    }
+
+    /// Undo the last `append` (e.g. application detected it wrote garbage): the newest block is overwritten
+    /// with zeroes and write head steps back to it. Block overwritten by reverted append is not restored.
+    /// Use `rollback_tx` to drop blocks of transaction.
+    pub fn revert_last(&mut self) -> Result<(), Error> {
+        if self.in_tx {
+            return Err(Error::TransactionIsOpen);
+        }
+        let used = self.used_blocks();
+        if used == 0 {
+            return Err(Error::BlockOutOfRange);
+        }
+
+        self.truncate_tail(used - 1)?;
+        Ok(())
+    }
+
+    /// Drop `count` oldest blocks (e.g. data which was already uploaded), they are not available
+    /// for `read` anymore, though they stay in storage till write head overwrites them.
+    /// Returns number of dropped blocks. Position of the first block is written to config block, so it survives
+    /// restart. Config of small blocks (and of filesystem of version 0x1) has no space for it, application has to
+    /// store `front_blk_id` and set it with `set_front_blk_id` after restart. Front of read only filesystem is
+    /// kept only in RAM.
+    pub fn truncate_front(&mut self, count: usize) -> Result<usize, Error> {
+        let count = count.min(self.used_blocks());
+        self.set_front_blk_id(self.oldest_blk_id() + count as BlockId)?;
+
+        Ok(count)
+    }
+
+    /// Id of the first block available for read, set by `truncate_front`
+    pub fn front_blk_id(&self) -> BlockId {
+        self.front_id
+    }
+
+    /// Restore position of the first block saved after `truncate_front`, blocks with smaller ids are not available
+    /// for read. Id bigger than next block id drops all blocks.
+    pub fn set_front_blk_id(&mut self, id: BlockId) -> Result<(), Error> {
+        if id == self.front_id {
+            return Ok(());
+        }
+        self.front_id = id;
+        log!(debug, "Truncate front to {}", id);
+        if self.read_only || self.config_size() < config_block::FRONT_ID_END {
+            return Ok(());
+        }
+
+        self.write_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{fields, FsId};
+    use crate::error::Error;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 522285587;
+
+    #[test]
+    fn test_fs_truncate_tail() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 16;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(
+                fs.find_first_invalid_offset().expect("Can't check fs"),
+                None
+            );
+        }
+
+        // half written block 6 and stale blocks after it
+        storage.data[8 * BLOCK_SIZE + fields::DATA_BEGIN] ^= 0xff;
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't restore fs");
+        let invalid = fs.find_first_invalid_offset().expect("Can't check fs");
+        assert_eq!(invalid, Some(6));
+        assert_eq!(fs.truncate_tail(6).expect("Can't truncate"), 4);
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (6, 6));
+        assert_eq!(
+            fs.find_first_invalid_offset().expect("Can't check fs"),
+            None
+        );
+
+        fs.append(|blk_data| blk_data.fill(0xaa))
+            .expect("Can't append");
+        let head = {
+            let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (7, 7));
+
+            // blocks dropped by truncate_front are still in the ring, it isn't empty after the rest is truncated
+            assert_eq!(fs.truncate_front(3).expect("Can't truncate"), 3);
+            assert_eq!(fs.truncate_tail(0).expect("Can't truncate"), 4);
+            assert!(!fs.is_empty());
+            (fs.is_empty(), fs.offset(), fs.next_blk_id())
+        };
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.is_empty(), fs.offset(), fs.next_blk_id()), head);
+    }
+
+    #[test]
+    fn test_fs_revert_last() {
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            assert!(matches!(fs.revert_last(), Err(Error::BlockOutOfRange)));
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            fs.revert_last().expect("Can't revert");
+            assert_eq!((fs.used_blocks(), fs.next_blk_id()), (2, 2));
+            fs.append(|blk_data| blk_data.fill(0xaa))
+                .expect("Can't append");
+        }
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (3, 3));
+        let mut newest = 0;
+        fs.read(2, |blk_data| newest = blk_data[0])
+            .expect("Can't read");
+        assert_eq!(newest, 0xaa);
+
+        for _ in 0..3 {
+            fs.revert_last().expect("Can't revert");
+        }
+        assert!(fs.is_empty());
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (0, 0));
+    }
+
+    #[test]
+    fn test_fs_truncate_front() {
+        // config of 256 byte block has front id
+        const BLOCK_SIZE: usize = 256;
+        const SIZE: usize = BLOCK_SIZE * 17;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.truncate_front(4).expect("Can't truncate"), 4);
+            assert_eq!((fs.used_blocks(), fs.oldest_blk_id()), (6, 4));
+            let mut oldest = 0;
+            fs.read(0, |blk_data| oldest = blk_data[0])
+                .expect("Can't read");
+            assert_eq!(oldest, 4);
+        }
+
+        // front is restored from config block
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.oldest_blk_id()), (6, 4));
+        assert_eq!(fs.front_blk_id(), 4);
+
+        // dropped blocks are overwritten first, then storage is full again
+        for i in 10..22 {
+            fs.append(|blk_data| blk_data.fill(i))
+                .expect("Can't append");
+        }
+        assert_eq!((fs.used_blocks(), fs.oldest_blk_id()), (15, 7));
+        assert_eq!(fs.truncate_front(100).expect("Can't truncate"), 15);
+        assert_eq!(fs.used_blocks(), 0);
+
+        let fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.next_blk_id()), (0, 22));
+    }
+
+    #[test]
+    fn test_fs_truncate_front_small_block() {
+        // config of 64 byte block has no space for front id
+        const BLOCK_SIZE: usize = 64;
+        const SIZE: usize = BLOCK_SIZE * 8;
+
+        type Fs<'a> = Filesystem<'a, RamStorage<SIZE, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage");
+        let front = {
+            let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            assert_eq!(fs.truncate_front(2).expect("Can't truncate"), 2);
+            fs.front_blk_id()
+        };
+
+        let mut fs = Fs::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.used_blocks(), 5);
+        fs.set_front_blk_id(front).expect("Can't set front");
+        assert_eq!((fs.used_blocks(), fs.oldest_blk_id()), (3, 2));
+    }
+}
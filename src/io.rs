@@ -0,0 +1,220 @@
+use std::format;
+use std::io;
+
+use crate::block::{BlockLayout, DefaultLayout};
+use crate::error::Error;
+use crate::fs::Filesystem;
+use crate::storage::Storage;
+
+fn to_io_error(e: Error) -> io::Error {
+    io::Error::other(format!("appendfs error: {:?}", e))
+}
+
+/// `std::io::Read` adapter, reads data of all blocks from the oldest one to the newest one.
+/// Reading stops at the first block which is not valid (or after the last written block).
+pub struct FsReader<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout = DefaultLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    blk_offset: usize,
+    end_offset: usize,
+    buf: [u8; BS],
+    pos: usize,
+    len: usize,
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>
+    FsReader<'f, 'a, S, BS, L, B>
+{
+    pub fn new(fs: &'f mut Filesystem<'a, S, BS, L, B>) -> Self {
+        Self::with_range(fs, 0, usize::MAX)
+    }
+
+    /// Read at most `count` blocks starting from `blk_offset` (0 is the oldest block)
+    pub fn with_range(
+        fs: &'f mut Filesystem<'a, S, BS, L, B>,
+        blk_offset: usize,
+        count: usize,
+    ) -> Self {
+        Self {
+            fs,
+            blk_offset,
+            end_offset: blk_offset.saturating_add(count),
+            buf: [0_u8; BS],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Offset of the next block to read
+    pub fn blk_offset(&self) -> usize {
+        self.blk_offset
+    }
+
+    fn fill_buf(&mut self) -> Result<bool, Error> {
+        if self.blk_offset >= core::cmp::min(self.end_offset, self.fs.used_blocks()) {
+            return Ok(false);
+        }
+
+        let buf = &mut self.buf;
+        let mut len = 0;
+        match self.fs.read(self.blk_offset, |blk_data| {
+            len = blk_data.len();
+            buf[..len].copy_from_slice(blk_data);
+        }) {
+            Ok(_) => {}
+            Err(Error::NotValidBlockForRead) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        self.blk_offset += 1;
+        self.pos = 0;
+        self.len = len;
+        Ok(true)
+    }
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> io::Read
+    for FsReader<'f, 'a, S, BS, L, B>
+{
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len && !self.fill_buf().map_err(to_io_error)? {
+            return Ok(0);
+        }
+
+        let to_copy = core::cmp::min(out.len(), self.len - self.pos);
+        out[..to_copy].copy_from_slice(&self.buf[self.pos..self.pos + to_copy]);
+        self.pos += to_copy;
+
+        Ok(to_copy)
+    }
+}
+
+/// `std::io::Write` adapter, collects data until it fills a whole block and appends it.
+/// Not full block is appended (padded with zeroes) on `flush` or when the writer is dropped.
+pub struct FsWriter<
+    'f,
+    'a,
+    S: Storage,
+    const BS: usize,
+    L: BlockLayout = DefaultLayout,
+    B: AsMut<[u8]> = [u8; BS],
+> {
+    fs: &'f mut Filesystem<'a, S, BS, L, B>,
+    buf: [u8; BS],
+    len: usize,
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>
+    FsWriter<'f, 'a, S, BS, L, B>
+{
+    pub fn new(fs: &'f mut Filesystem<'a, S, BS, L, B>) -> Self {
+        Self {
+            fs,
+            buf: [0_u8; BS],
+            len: 0,
+        }
+    }
+
+    fn append_buf(&mut self) -> Result<(), Error> {
+        let buf = &self.buf;
+        let len = self.len;
+        self.fs.append(|blk_data| {
+            let to_copy = core::cmp::min(blk_data.len(), len);
+            blk_data[..to_copy].copy_from_slice(&buf[..to_copy]);
+            blk_data[to_copy..].fill(0);
+        })?;
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> io::Write
+    for FsWriter<'f, 'a, S, BS, L, B>
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let blk_len = self.fs.data_size();
+        let to_copy = core::cmp::min(data.len(), blk_len - self.len);
+        self.buf[self.len..self.len + to_copy].copy_from_slice(&data[..to_copy]);
+        self.len += to_copy;
+
+        if self.len == blk_len {
+            self.append_buf().map_err(to_io_error)?;
+        }
+
+        Ok(to_copy)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        self.append_buf().map_err(to_io_error)
+    }
+}
+
+impl<'f, 'a, S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>> Drop
+    for FsWriter<'f, 'a, S, BS, L, B>
+{
+    fn drop(&mut self) {
+        let _ = io::Write::flush(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::vec::Vec;
+
+    use super::{FsReader, FsWriter};
+    use crate::block::FsId;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 1045871;
+
+    #[test]
+    fn test_io_copy() {
+        const BLOCK_SIZE: usize = 64;
+        const BLOCK_COUNT: usize = 16;
+
+        type Fs<'a> =
+            Filesystem<'a, RamStorage<{ BLOCK_SIZE * BLOCK_COUNT }, BLOCK_SIZE>, BLOCK_SIZE>;
+
+        let mut storage = RamStorage::new().expect("Can't create storage for test_io_copy");
+        let mut fs = Fs::new(&mut storage, FS_ID).expect("Can't create fs for test_io_copy");
+
+        // 3.5 blocks, last block must be padded with zeroes
+        let data_len = Fs::data_block_size() * 7 / 2;
+        let expected: Vec<u8> = (0..data_len).map(|i| (i % 251) as u8).collect();
+        {
+            let mut writer = FsWriter::new(&mut fs);
+            writer.write_all(&expected[..]).expect("Can't write data");
+            writer.flush().expect("Can't flush data");
+        }
+        assert_eq!(fs.used_blocks(), 4);
+
+        let mut actual = Vec::new();
+        FsReader::new(&mut fs)
+            .read_to_end(&mut actual)
+            .expect("Can't read data");
+        assert_eq!(actual.len(), Fs::data_block_size() * 4);
+        assert_eq!(&actual[..data_len], &expected[..]);
+        assert!(actual[data_len..].iter().all(|b| *b == 0));
+
+        let mut actual = Vec::new();
+        FsReader::with_range(&mut fs, 1, 2)
+            .read_to_end(&mut actual)
+            .expect("Can't read range");
+        let begin = Fs::data_block_size();
+        let end = begin + 2 * Fs::data_block_size();
+        assert_eq!(&actual[..], &expected[begin..end]);
+    }
+}
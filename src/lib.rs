@@ -1,8 +1,21 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "std")]
+pub mod archive;
 pub mod block;
 pub mod error;
+pub mod format;
 pub mod fs;
+#[cfg(feature = "std")]
+pub mod io;
 pub mod logging;
 pub mod storage;
+#[cfg(feature = "test_support")]
+pub mod test_support;
+pub mod time;
+#[cfg(feature = "tokio")]
+pub mod tokio_fs;
 pub mod utils;
@@ -1,10 +1,47 @@
 pub fn init() {
     #[cfg(feature = "logging")]
-    env_logger::init();
+    let _ = env_logger::try_init();
 }
 
+/// Targets of log records of subsystems, they are filtered independently of other records of the crate, e.g.
+/// `RUST_LOG=error,appendfs::init=debug` logs errors and restore scan without block io of big storage
+pub mod target {
+    /// Restore scan: config block, search of the newest block and attributes of restored filesystem
+    pub const INIT: &str = "appendfs::init";
+    /// Block reads and writes of the filesystem and storages
+    pub const IO: &str = "appendfs::io";
+}
+
+/// `log!(level, ..)` logs with target of the module, `log!(target: ..., level, ..)` with target of subsystem,
+/// see [`target`]
 #[macro_export]
 macro_rules! log {
+    (target: $target:expr, $level:tt, $arg:expr) => {
+        {
+            #[cfg(feature="logging")]
+            {
+                log::$level!(target: $target, $arg);
+            }
+            #[cfg(not(feature="logging"))]
+            {
+                let _ = $target;
+            }
+        }
+    };
+
+    (target: $target:expr, $level:tt, $arg:expr, $($args:expr),+) => {
+        {
+            #[cfg(feature="logging")]
+            {
+                log::$level!(target: $target, $arg, $($args),+);
+            }
+            #[cfg(not(feature="logging"))]
+            {
+                let _ = ($target, $(&$args),+);
+            }
+        }
+    };
+
     ($level:tt, $arg:expr) => {
         {
             #[cfg(feature="logging")]
@@ -20,6 +57,11 @@ macro_rules! log {
             {
                 log::$level!($arg, $($args),+);
             }
+            // args must be used even without logging to avoid unused variable warnings
+            #[cfg(not(feature="logging"))]
+            {
+                let _ = ($(&$args),+);
+            }
         }
     };
 }
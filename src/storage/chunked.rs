@@ -0,0 +1,129 @@
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::storage::{Capabilities, Geometry, IoCounters, Storage};
+use crate::utils::validate_block_index;
+
+/// Storage which joins `chunks` consecutive blocks of `inner` into one logical block, so filesystem uses blocks
+/// bigger than block of the device (e.g. 4 KB blocks over 512 B sectors of SD card). Logical block `i` is
+/// physical blocks `inner.min_block_index() + i * chunks..` and logical indexes start from 0.
+/// Chunks are written from the last to the first one, so header of the block is written last and
+/// interrupted write leaves header of the previous block, which fails crc check of `CrcMode::Full` with new data.
+#[derive(Debug)]
+pub struct ChunkedStorage<S: Storage> {
+    inner: S,
+    chunks: usize,
+}
+
+impl<S: Storage> ChunkedStorage<S> {
+    /// `inner` must have at least 2 logical blocks, physical blocks after the last whole logical block aren't used
+    pub fn new(inner: S, chunks: usize) -> Result<Self, Error> {
+        if chunks == 0 {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        let blocks = inner.max_block_index() - inner.min_block_index();
+        if blocks / chunks < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        Ok(Self { inner, chunks })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn chunk_idx(&self, blk_idx: usize) -> usize {
+        self.inner.min_block_index() + blk_idx * self.chunks
+    }
+}
+
+impl<S: Storage> Storage for ChunkedStorage<S> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        let block_size = self.block_size();
+        if data.len() < block_size {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let begin = self.chunk_idx(blk_idx);
+        let chunk_size = self.inner.block_size();
+        for (i, chunk) in data[..block_size].chunks_mut(chunk_size).enumerate() {
+            self.inner.read(begin + i, chunk)?;
+        }
+
+        Ok(block_size)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() != self.block_size() {
+            return Err(Error::DataLenNotEqualToBlockSize);
+        }
+
+        let begin = self.chunk_idx(blk_idx);
+        let chunk_size = self.inner.block_size();
+        for (i, chunk) in data.chunks(chunk_size).enumerate().rev() {
+            self.inner.write(begin + i, chunk)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size() * self.chunks
+    }
+
+    fn min_block_index(&self) -> usize {
+        0
+    }
+
+    fn max_block_index(&self) -> usize {
+        (self.inner.max_block_index() - self.inner.min_block_index()) / self.chunks
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        validate_block_index(self, blk_idx)?;
+
+        let begin = self.chunk_idx(blk_idx);
+        for chunk_idx in begin..begin + self.chunks {
+            self.inner.erase(chunk_idx)?;
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        self.inner
+            .discard(self.chunk_idx(blocks.start)..self.chunk_idx(blocks.end))
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.inner.sync()
+    }
+
+    fn io_counters(&self) -> Option<IoCounters> {
+        self.inner.io_counters()
+    }
+
+    fn geometry(&self) -> Geometry {
+        let inner = self.inner.geometry();
+        Geometry {
+            block_size: self.block_size(),
+            min_block_index: self.min_block_index(),
+            max_block_index: self.max_block_index(),
+            capabilities: Capabilities {
+                write_in_place: false,
+                ..inner.capabilities
+            },
+            ..inner
+        }
+    }
+}
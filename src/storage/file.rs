@@ -1,14 +1,20 @@
 extern crate std;
 
-use std::fs::File;
+use std::format;
 use std::fs::OpenOptions;
+use std::fs::{File, TryLockError};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::string::{String, ToString};
 
-use crate::block::fields;
+use core::ops::Range;
+
+use crate::block::{fields, FsId};
 use crate::error::Error;
+use crate::fs::DynFilesystem;
 use crate::log;
-use crate::storage::Storage;
+use crate::logging::target;
+use crate::storage::{SharedRead, Storage};
 use crate::utils::validate_block_index;
 
 const DEFAULT_RETRIES: u16 = 4;
@@ -43,23 +49,75 @@ impl FileStorage {
             file,
         })
     }
+
+    /// Create image file of `blocks` blocks (existing file is truncated) and format filesystem with `fs_id` in it,
+    /// e.g. for tests and tooling without real device. File is sparse on filesystems which support it,
+    /// only written blocks take space.
+    pub fn create_image<P: AsRef<Path>>(
+        path: P,
+        blocks: u32,
+        block_size: u32,
+        fs_id: FsId,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        File::create(path)
+            .and_then(|f| f.set_len(blocks as u64 * block_size as u64))
+            .map_err(|e| e.to_string())?;
+
+        let mut storage = Self::new(
+            path.to_string_lossy().to_string(),
+            0,
+            blocks,
+            block_size,
+            None,
+        )?;
+        DynFilesystem::new(&mut storage, fs_id)
+            .map_err(|e| format!("Can't format image, err: {:?}", e))?;
+
+        Ok(storage)
+    }
+
+    /// Take advisory exclusive lock (flock) of the device file, so two processes (e.g. writer daemon and
+    /// `appendfs-cli write`) don't mount it read-write at the same time and corrupt the head. The whole file
+    /// is locked, not only the block range. Fails with `StorageIsLocked` in case another process holds the lock,
+    /// it's released by `unlock` or when the storage is dropped. Readers don't need the lock.
+    pub fn lock(&self) -> Result<(), Error> {
+        self.file.try_lock().map_err(|e| match e {
+            TryLockError::WouldBlock => Error::StorageIsLocked,
+            TryLockError::Error(e) => {
+                log!(error, "Can't lock file: {:?}", e);
+                Error::CanNotPerformWrite
+            }
+        })
+    }
+
+    pub fn unlock(&self) -> Result<(), Error> {
+        self.file.unlock().map_err(|e| {
+            log!(error, "Can't unlock file: {:?}", e);
+            Error::CanNotPerformWrite
+        })
+    }
 }
 
 impl Storage for FileStorage {
     fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.read_prefix(blk_idx, self.block_size(), data)
+    }
+
+    fn read_prefix(&mut self, blk_idx: usize, len: usize, data: &mut [u8]) -> Result<usize, Error> {
         validate_block_index(self, blk_idx)?;
 
         if data.len() < self.block_size() {
             return Err(Error::NotEnoughSpaceForRead);
         }
 
-        let offset = blk_idx * self.block_size();
-        log!(trace, "Read at {}", offset);
+        let offset = self.blk_byte_offset(blk_idx);
+        log!(target: target::IO, trace, "Read at {}", offset);
         self.file
-            .seek(SeekFrom::Start(offset as u64))
+            .seek(SeekFrom::Start(offset))
             .map_err(|_e| Error::CanNotSeekForRead)?;
 
-        let data = &mut data[..self.block_size()];
+        let data = &mut data[..len.min(self.block_size())];
         for i in 0..self.retries {
             let res = self.file.read_exact(data);
             if res.is_ok() {
@@ -78,9 +136,10 @@ impl Storage for FileStorage {
             }
         }
 
-        log!(trace, "Read header: {:?}", &data[..fields::DATA_BEGIN]);
+        let header_len = fields::DATA_BEGIN.min(data.len());
+        log!(target: target::IO, trace, "Read header: {:?}", &data[..header_len]);
 
-        Ok(self.block_size())
+        Ok(data.len())
     }
 
     fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
@@ -89,15 +148,16 @@ impl Storage for FileStorage {
             return Err(Error::DataLenNotEqualToBlockSize);
         }
 
-        let offset = blk_idx * self.block_size();
+        let offset = self.blk_byte_offset(blk_idx);
         log!(
+            target: target::IO,
             trace,
             "Write at {}, header: {:?}",
             offset,
             &data[..fields::DATA_BEGIN]
         );
         self.file
-            .seek(SeekFrom::Start(offset as u64))
+            .seek(SeekFrom::Start(offset))
             .map_err(|_e| Error::CanNotSeekForWrite)?;
 
         for i in 0..self.retries {
@@ -114,6 +174,20 @@ impl Storage for FileStorage {
         Ok(self.block_size())
     }
 
+    /// Neither hole punching of image files nor discard of block devices is available without platform calls,
+    /// so blocks are never discarded silently
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        log!(warn, "Discard of blocks {:?} isn't supported", blocks);
+        Err(Error::UnsupportedOperation)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data().map_err(|e| {
+            log!(error, "Can't sync file: {:?}", e);
+            Error::CanNotPerformWrite
+        })
+    }
+
     fn block_size(&self) -> usize {
         self.block_size as usize
     }
@@ -126,3 +200,40 @@ impl Storage for FileStorage {
         self.end_block as usize
     }
 }
+
+/// Positioned read, it doesn't move cursor of the file
+#[cfg(unix)]
+impl SharedRead for FileStorage {
+    fn read_shared(&self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        use std::os::unix::fs::FileExt;
+
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() < self.block_size() {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let offset = self.blk_byte_offset(blk_idx);
+        log!(target: target::IO, trace, "Shared read at {}", offset);
+        let data = &mut data[..self.block_size()];
+        for i in 0..self.retries {
+            let res = self.file.read_exact_at(data, offset);
+            if res.is_ok() {
+                break;
+            }
+
+            if i + 1 == self.retries && res.is_err() {
+                log!(
+                    error,
+                    "Can't perform read, offset: {}, data_len: {}, err: {:?}",
+                    offset,
+                    data.len(),
+                    res
+                );
+                return Err(Error::CanNotPerformRead);
+            }
+        }
+
+        Ok(self.block_size())
+    }
+}
@@ -1,10 +1,28 @@
+use core::ops::Range;
+
 use crate::error::Error;
 
+pub mod chunked;
+pub mod paged;
 pub mod ram;
+pub mod replicated;
+pub mod slice;
 
 #[cfg(feature = "file_storage")]
 pub mod file;
 
+#[cfg(feature = "std")]
+pub mod net;
+
+#[cfg(feature = "std")]
+pub mod trace;
+
+#[cfg(feature = "embassy")]
+pub mod nor_flash;
+
+#[cfg(all(feature = "opfs", target_arch = "wasm32"))]
+pub mod opfs;
+
 pub trait Storage {
     fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error>;
     fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error>;
@@ -13,12 +31,214 @@ pub trait Storage {
     fn block_size(&self) -> usize;
     fn min_block_index(&self) -> usize;
     fn max_block_index(&self) -> usize;
+
+    /// Read at least the first `len` bytes of block `blk_idx` (e.g. header of the block probed by restore),
+    /// `data` must have space for the whole block. Storages which can read a part of the block (e.g. file on
+    /// SD card) override it to read less, default implementation reads the whole block. Returns read length.
+    fn read_prefix(
+        &mut self,
+        blk_idx: usize,
+        _len: usize,
+        data: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.read(blk_idx, data)
+    }
+
+    /// Erase block on flash backed storage (e.g. erase NOR sector or issue TRIM), storages without
+    /// erase operation don't need to implement it
+    fn erase(&mut self, _blk_idx: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Tell the device that data of `blocks` is not needed anymore (TRIM/discard on SSD, eMMC or SD card),
+    /// so its flash translation layer doesn't have to keep it. Content of discarded blocks is undefined.
+    /// Default does nothing (e.g. RAM), storage of medium with discard which can't issue it returns
+    /// `UnsupportedOperation`.
+    fn discard(&mut self, _blocks: Range<usize>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Make all written blocks durable (e.g. fsync of file or flush of write cache), storages which write
+    /// through don't need to implement it, see [`crate::fs::Filesystem::barrier`]
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Storage keeps blocks in memory (RAM or mmap), so `append` fills block with `write_in_place` without
+    /// intermediate buffer
+    fn can_write_in_place(&self) -> bool {
+        false
+    }
+
+    /// Let `writer` fill the whole block `blk_idx` in memory of the storage, it's called only in case
+    /// `can_write_in_place` returns true. Block which can't be written fails with `CanNotPerformWrite` before
+    /// `writer` is called, so the filesystem marks it as bad and writes the block to the next one.
+    fn write_in_place<F>(&mut self, _blk_idx: usize, _writer: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&mut [u8]),
+        Self: Sized,
+    {
+        Err(Error::CanNotPerformWrite)
+    }
+
+    /// Physical operations performed by the storage since it was created, storages which don't track them
+    /// (e.g. RAM or files) return `None`, see [`crate::fs::Filesystem::write_amplification`]
+    fn io_counters(&self) -> Option<IoCounters> {
+        None
+    }
+
+    /// Layout and capabilities of the storage, storages with erase units or io granularity (flash) override it
+    fn geometry(&self) -> Geometry {
+        Geometry {
+            block_size: self.block_size(),
+            min_block_index: self.min_block_index(),
+            max_block_index: self.max_block_index(),
+            erase_size: None,
+            read_size: 1,
+            write_size: 1,
+            capabilities: Capabilities {
+                write_in_place: self.can_write_in_place(),
+                ..Capabilities::default()
+            },
+        }
+    }
+
+    /// Offset of the block in bytes, computed in u64 so storage bigger than 4 GB works on 32 bit targets
+    fn blk_byte_offset(&self, blk_idx: usize) -> u64 {
+        blk_idx as u64 * self.block_size() as u64
+    }
+}
+
+/// Storage layout returned by [`Storage::geometry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub block_size: usize,
+    pub min_block_index: usize,
+    pub max_block_index: usize,
+    /// Size of erase unit in bytes, `None` for storages without erase (RAM, files)
+    pub erase_size: Option<usize>,
+    /// Granularity of reads of the medium in bytes
+    pub read_size: usize,
+    /// Granularity of writes of the medium in bytes
+    pub write_size: usize,
+    pub capabilities: Capabilities,
+}
+
+/// Optional operations implemented by the storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `erase` isn't no-op (flash)
+    pub erase: bool,
+    /// `discard` isn't no-op (TRIM of SSD, eMMC or SD card)
+    pub discard: bool,
+    /// See [`Storage::write_in_place`]
+    pub write_in_place: bool,
+}
+
+/// Physical operations of erase-aware storage, see [`Storage::io_counters`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoCounters {
+    /// Program (write) operations of the medium
+    pub programs: u64,
+    /// Erase operations of the medium (e.g. erase of NOR sector)
+    pub erases: u64,
+    pub programmed_bytes: u64,
+}
+
+impl IoCounters {
+    /// Operations performed after `base` was taken
+    pub fn since(&self, base: &IoCounters) -> IoCounters {
+        IoCounters {
+            programs: self.programs.saturating_sub(base.programs),
+            erases: self.erases.saturating_sub(base.erases),
+            programmed_bytes: self.programmed_bytes.saturating_sub(base.programmed_bytes),
+        }
+    }
+}
+
+/// Storage which can be read through shared reference, see [`crate::fs::Filesystem::read_with_buf`]
+pub trait SharedRead: Storage {
+    fn read_shared(&self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// Storage with geometry known at compile time (e.g. [`ram::RamStorage`]), so it's validated by compiler,
+/// see [`crate::fs::Filesystem::new_checked`]
+pub trait StaticGeometry: Storage {
+    const BLOCK_SIZE: usize;
+    /// Number of blocks including config block
+    const BLOCK_COUNT: usize;
+}
+
+/// Storage chosen at runtime, `Filesystem<'_, &mut dyn Storage, BS>` is compiled once for all backends.
+/// `write_in_place` isn't object safe, so blocks are always written from buffer of the filesystem.
+impl Storage for &mut dyn Storage {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        (**self).read(blk_idx, data)
+    }
+
+    fn read_prefix(&mut self, blk_idx: usize, len: usize, data: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_prefix(blk_idx, len, data)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        (**self).write(blk_idx, data)
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn min_block_index(&self) -> usize {
+        (**self).min_block_index()
+    }
+
+    fn max_block_index(&self) -> usize {
+        (**self).max_block_index()
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        (**self).erase(blk_idx)
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        (**self).discard(blocks)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        (**self).sync()
+    }
+
+    fn io_counters(&self) -> Option<IoCounters> {
+        (**self).io_counters()
+    }
+
+    fn geometry(&self) -> Geometry {
+        let geometry = (**self).geometry();
+        Geometry {
+            capabilities: Capabilities {
+                write_in_place: false,
+                ..geometry.capabilities
+            },
+            ..geometry
+        }
+    }
+
+    fn blk_byte_offset(&self, blk_idx: usize) -> u64 {
+        (**self).blk_byte_offset(blk_idx)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ram::RamStorage, Storage};
-    use crate::utils::slices_are_equal;
+    use super::{
+        chunked::ChunkedStorage, paged::PagedStorage, ram::RamStorage,
+        replicated::ReplicatedStorage, slice::SliceStorage, Capabilities, Geometry, Storage,
+    };
+    use crate::block::FsId;
+    use crate::error::Error;
+    use crate::fs::{DynFilesystem, DynStorageFilesystem, Filesystem};
+    use crate::utils::{slices_are_equal, trim_block_idx_with_wraparound};
 
     #[test]
     fn test_ram_storage() {
@@ -82,4 +302,413 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_slice_storage() {
+        const BLOCK: usize = 512;
+        const FS_ID: FsId = 283749;
+
+        let mut image = [0_u8; BLOCK * 8];
+        assert!(matches!(
+            SliceStorage::new(&mut image[..BLOCK * 2 + 1], BLOCK),
+            Err(Error::InvalidBlockSizeForStorage)
+        ));
+
+        {
+            let mut storage =
+                SliceStorage::new(&mut image[..], BLOCK).expect("Can't create storage");
+            assert_eq!(storage.max_block_index(), 8);
+
+            let mut fs = DynFilesystem::new(&mut storage, FS_ID).expect("Can't create fs");
+            fs.append(|blk_data| blk_data.fill(7))
+                .expect("Can't append");
+        }
+
+        // image can be parsed again, e.g. after it was saved and loaded back
+        let mut copy = image;
+        let mut storage = SliceStorage::new(&mut copy[..], BLOCK).expect("Can't create storage");
+        let mut fs = DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 1));
+        assert!(fs
+            .read(0, |blk_data| assert!(blk_data.iter().all(|b| *b == 7)))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_blk_offsets() {
+        const BLOCK: usize = 512;
+
+        let mut image = [0_u8; BLOCK * 2];
+        let storage = SliceStorage::new(&mut image[..], BLOCK).expect("Can't create storage");
+        // offset of the block of 8 GB storage doesn't fit to u32
+        let blk_idx = 16 * 1024 * 1024 - 1;
+        assert_eq!(
+            storage.blk_byte_offset(blk_idx),
+            8 * 1024 * 1024 * 1024 - 512
+        );
+
+        assert_eq!(trim_block_idx_with_wraparound(0, 2048, 4096), 2048);
+        assert_eq!(trim_block_idx_with_wraparound(3000, 2048, 4096), 3000);
+        assert_eq!(trim_block_idx_with_wraparound(4096, 2048, 4096), 2048);
+        assert_eq!(trim_block_idx_with_wraparound(6143, 2048, 4096), 4095);
+        assert_eq!(trim_block_idx_with_wraparound(6144, 2048, 4096), 2048);
+        assert_eq!(
+            trim_block_idx_with_wraparound(usize::MAX, usize::MAX - 4, usize::MAX),
+            usize::MAX - 4
+        );
+    }
+
+    #[test]
+    fn test_replicated_storage() {
+        const BLOCK: usize = 256;
+        const FS_ID: FsId = 283749;
+
+        let mut image = [0_u8; BLOCK * 8];
+        assert!(matches!(
+            ReplicatedStorage::new(
+                RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create primary"),
+                SliceStorage::new(&mut image[..BLOCK * 4], BLOCK).expect("Can't create replica"),
+            ),
+            Err(Error::TooSmallFilesystem)
+        ));
+
+        let mut storage = ReplicatedStorage::new(
+            RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create primary"),
+            SliceStorage::new(&mut image[..], BLOCK).expect("Can't create replica"),
+        )
+        .expect("Can't create replicated storage");
+        {
+            let mut fs = DynFilesystem::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        // config blocks written on format and 3 data blocks
+        assert_eq!(storage.stats().replicated_blocks, 5);
+        assert_eq!(storage.stats().failed_blocks, 0);
+
+        let (_, mut replica) = storage.into_inner();
+        let mut fs = DynFilesystem::restore(&mut replica).expect("Can't restore replica");
+        assert_eq!((fs.id(), fs.used_blocks()), (FS_ID, 3));
+        assert!(fs
+            .read(2, |blk_data| assert!(blk_data.iter().all(|b| *b == 2)))
+            .is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_trace_replay() {
+        use super::trace::{ReplayStorage, TraceDetail, TraceStorage};
+
+        const BLOCK: usize = 128;
+        const FS_ID: FsId = 283749;
+
+        let ram = RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create storage");
+        let mut storage = TraceStorage::new(ram, TraceDetail::Data);
+        {
+            let mut fs = DynFilesystem::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            fs.barrier(true).expect("Barrier failed");
+        }
+        let recorded = storage.events().len();
+        DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+
+        let mut trace = std::vec::Vec::new();
+        storage.write_trace(&mut trace).expect("Can't write trace");
+        let mut replay = ReplayStorage::from_reader(&trace[..]).expect("Can't parse trace");
+        {
+            let mut fs = DynFilesystem::new(&mut replay, FS_ID).expect("Can't replay fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't replay append");
+            }
+            fs.barrier(true).expect("Can't replay barrier");
+        }
+        assert_eq!(replay.position(), recorded);
+        let fs = DynFilesystem::restore(&mut replay).expect("Can't replay restore");
+        assert_eq!(fs.used_blocks(), 6);
+        assert!(replay.is_finished());
+        assert_eq!(replay.divergences(), 0);
+
+        // write of other data diverges from the trace
+        let mut replay = ReplayStorage::from_reader(&trace[..]).expect("Can't parse trace");
+        let mut fs = DynFilesystem::new(&mut replay, FS_ID).expect("Can't replay fs");
+        assert!(fs.append(|blk_data| blk_data.fill(42)).is_err());
+        assert!(replay.divergences() > 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_net_storage() {
+        use super::net::{serve, NetStorage};
+        use std::net::TcpListener;
+
+        const BLOCK: usize = 128;
+        const FS_ID: FsId = 583921;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Can't bind");
+        let addr = listener.local_addr().expect("Can't get address");
+        let server = std::thread::spawn(move || {
+            let mut ram = RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create storage");
+            let (stream, _) = listener.accept().expect("Can't accept");
+            serve(&mut ram, stream).expect("Can't serve");
+            ram
+        });
+
+        let mut storage = NetStorage::connect(addr).expect("Can't connect");
+        assert_eq!(
+            (
+                storage.block_size(),
+                storage.min_block_index(),
+                storage.max_block_index()
+            ),
+            (BLOCK, 0, 8)
+        );
+        {
+            let mut fs = DynFilesystem::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            fs.barrier(true).expect("Barrier failed");
+        }
+        let mut fs = DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.used_blocks(), 6);
+        fs.read(5, |blk_data| assert!(blk_data.iter().all(|b| *b == 9)))
+            .expect("Can't read");
+        assert!(storage.read(100, &mut [0_u8; BLOCK]).is_err());
+        drop(storage);
+
+        let mut ram = server.join().expect("Server failed");
+        let fs = DynFilesystem::restore(&mut ram).expect("Can't restore served storage");
+        assert_eq!(fs.used_blocks(), 6);
+    }
+
+    #[test]
+    fn test_geometry() {
+        const BLOCK: usize = 128;
+
+        let ram = RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create storage");
+        let geometry = Geometry {
+            block_size: BLOCK,
+            min_block_index: 0,
+            max_block_index: 8,
+            erase_size: None,
+            read_size: 1,
+            write_size: 1,
+            capabilities: Capabilities {
+                write_in_place: true,
+                ..Capabilities::default()
+            },
+        };
+        assert_eq!(ram.geometry(), geometry);
+
+        let mut image = [0_u8; BLOCK * 8];
+        let replicated = ReplicatedStorage::new(
+            ram,
+            SliceStorage::new(&mut image[..], BLOCK).expect("Can't create replica"),
+        )
+        .expect("Can't create replicated storage");
+        // blocks are copied to replica from buffer
+        let geometry = Geometry {
+            capabilities: Capabilities::default(),
+            ..geometry
+        };
+        assert_eq!(replicated.geometry(), geometry);
+    }
+
+    #[cfg(feature = "file_storage")]
+    #[test]
+    fn test_file_image() {
+        use super::file::FileStorage;
+
+        const FS_ID: FsId = 283749;
+
+        let path = std::env::temp_dir().join("appendfs-test-image");
+        let mut storage =
+            FileStorage::create_image(&path, 16, 512, FS_ID).expect("Can't create image");
+        assert_eq!(
+            std::fs::metadata(&path).expect("Can't stat image").len(),
+            16 * 512
+        );
+        {
+            let mut fs = DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!((fs.id(), fs.capacity()), (FS_ID, 14));
+            fs.append(|blk_data| blk_data.fill(7))
+                .expect("Can't append");
+        }
+
+        // existing image is replaced
+        let mut storage =
+            FileStorage::create_image(&path, 8, 512, FS_ID).expect("Can't create image");
+        let mut fs = DynFilesystem::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.used_blocks(), fs.capacity()), (0, 6));
+        // blocks of image aren't discarded silently
+        assert!(matches!(fs.trim(), Err(Error::UnsupportedOperation)));
+        std::fs::remove_file(&path).expect("Can't remove image");
+    }
+
+    #[cfg(feature = "file_storage")]
+    #[test]
+    fn test_file_lock() {
+        use super::file::FileStorage;
+
+        const FS_ID: FsId = 283749;
+
+        let path = std::env::temp_dir().join("appendfs-test-lock");
+        let writer = FileStorage::create_image(&path, 8, 512, FS_ID).expect("Can't create image");
+        let open = || {
+            let path = path.to_string_lossy().into_owned();
+            FileStorage::new(path, 0, 8, 512, None).expect("Can't open image")
+        };
+        writer.lock().expect("Can't lock");
+        let other = open();
+        assert!(matches!(other.lock(), Err(Error::StorageIsLocked)));
+
+        writer.unlock().expect("Can't unlock");
+        other.lock().expect("Can't lock");
+        drop(other);
+        open().lock().expect("Lock isn't released on drop");
+        std::fs::remove_file(&path).expect("Can't remove image");
+    }
+
+    #[test]
+    fn test_chunked_storage() {
+        const BLOCK: usize = 64;
+        const FS_ID: FsId = 283749;
+
+        let ram = RamStorage::<{ BLOCK * 9 }, BLOCK>::new().expect("Can't create storage");
+        let mut storage = ChunkedStorage::new(ram, 2).expect("Can't create chunked storage");
+        assert_eq!(
+            (storage.block_size(), storage.max_block_index()),
+            (BLOCK * 2, 4)
+        );
+        assert!(matches!(
+            Filesystem::<_, BLOCK>::new(&mut storage, FS_ID),
+            Err(Error::BlockSizeMismatch {
+                block_size: 128,
+                max_block_size: BLOCK
+            })
+        ));
+
+        {
+            let mut fs =
+                Filesystem::<_, { BLOCK * 2 }>::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        let mut fs =
+            Filesystem::<_, { BLOCK * 2 }>::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!((fs.oldest_blk_id(), fs.next_blk_id()), (3, 5));
+        fs.read(1, |blk_data| assert!(blk_data.iter().all(|b| *b == 4)))
+            .expect("Can't read");
+
+        // the last physical block isn't used
+        let ram = storage.into_inner();
+        assert!(ram.data[BLOCK * 8..].iter().all(|b| *b == 0));
+        assert!(matches!(
+            ChunkedStorage::new(ram, 5),
+            Err(Error::TooSmallFilesystem)
+        ));
+    }
+
+    #[test]
+    fn test_paged_storage() {
+        const PAGE: usize = 512;
+        const FS_ID: FsId = 283749;
+
+        let ram = RamStorage::<{ PAGE * 4 }, PAGE>::new().expect("Can't create storage");
+        let mut storage = PagedStorage::<_, PAGE>::new(ram, 4).expect("Can't create paged storage");
+        assert_eq!((storage.block_size(), storage.max_block_index()), (128, 16));
+        assert_eq!(storage.geometry().write_size, PAGE);
+        {
+            let mut fs = Filesystem::<_, 128>::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..5 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+            // pending blocks are read from RAM
+            fs.read(4, |blk_data| assert!(blk_data.iter().all(|b| *b == 4)))
+                .expect("Can't read");
+        }
+        // the first page (config blocks and 2 blocks) is written, the second one is pending
+        assert_eq!(storage.pending_page(), Some(1));
+        let ram = storage.into_inner();
+        assert!(ram.data[PAGE..].iter().all(|b| *b == 0));
+
+        let mut storage = PagedStorage::<_, PAGE>::new(ram, 4).expect("Can't create paged storage");
+        {
+            let mut fs = Filesystem::<_, 128>::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!(fs.next_blk_id(), 2);
+            fs.append(|blk_data| blk_data.fill(2))
+                .expect("Can't append");
+        }
+        storage.flush().expect("Can't flush");
+        assert_eq!(storage.pending_page(), None);
+        let fs = Filesystem::<_, 128>::restore(&mut storage).expect("Can't restore fs");
+        assert_eq!(fs.next_blk_id(), 3);
+    }
+
+    #[test]
+    fn test_dyn_storage() {
+        const BLOCK: usize = 128;
+        const FS_ID: FsId = 283749;
+
+        let mut ram = RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create storage");
+        let mut image = [0_u8; BLOCK * 8];
+        let mut slice = SliceStorage::new(&mut image[..], BLOCK).expect("Can't create storage");
+        for use_ram in [true, false] {
+            let mut storage: &mut dyn Storage = if use_ram { &mut ram } else { &mut slice };
+            assert!(!storage.geometry().capabilities.write_in_place);
+            assert_eq!(storage.max_block_index(), 8);
+
+            let mut fs = DynStorageFilesystem::new(&mut storage, FS_ID).expect("Can't create fs");
+            for i in 0..3 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+
+        for storage in [&mut ram as &mut dyn Storage, &mut slice] {
+            let mut storage = storage;
+            let fs = DynStorageFilesystem::restore(&mut storage).expect("Can't restore fs");
+            assert_eq!(fs.used_blocks(), 3);
+        }
+    }
+
+    #[test]
+    fn test_write_in_place() {
+        const BLOCK: usize = 128;
+        const FS_ID: FsId = 283749;
+
+        let mut in_place = RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create storage");
+        assert!(in_place.can_write_in_place());
+        // replicated storage writes blocks from buffer of the filesystem
+        let mut image = [0_u8; BLOCK * 8];
+        let mut copied = ReplicatedStorage::new(
+            RamStorage::<{ BLOCK * 8 }, BLOCK>::new().expect("Can't create primary"),
+            SliceStorage::new(&mut image[..], BLOCK).expect("Can't create replica"),
+        )
+        .expect("Can't create replicated storage");
+        assert!(!copied.can_write_in_place());
+
+        fn append_blocks<S: Storage>(storage: &mut S) {
+            let mut fs = DynFilesystem::new(storage, FS_ID).expect("Can't create fs");
+            for i in 0..10 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+            }
+        }
+        append_blocks(&mut in_place);
+        append_blocks(&mut copied);
+
+        let (_, replica) = copied.into_inner();
+        assert!(slices_are_equal(&in_place.data[..], replica.data()));
+    }
 }
@@ -0,0 +1,250 @@
+//! Remote storage over TCP: [`serve`] exposes a storage (e.g. file or block device of a host), [`NetStorage`]
+//! is a client which implements [`Storage`], so a device with network and without local flash can log
+//! to remote appendfs.
+//!
+//! Protocol is request-response over one connection, integers are little endian.
+//! Request is `<op: u8> <blk_idx: u32> <len: u32> <payload>`, response is `<status: u8> <len: u32> <payload>`,
+//! status 0 is success. `len` of request is block size for `read`, size of payload for `write` and number of blocks
+//! for `discard`. Response to `geometry` is `<block_size: u32> <min_block_index: u32> <max_block_index: u32>`,
+//! response to `write` is written length.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::vec;
+use std::vec::Vec;
+
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+
+const OP_GEOMETRY: u8 = 0;
+const OP_READ: u8 = 1;
+const OP_WRITE: u8 = 2;
+const OP_ERASE: u8 = 3;
+const OP_DISCARD: u8 = 4;
+const OP_SYNC: u8 = 5;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+const HEADER_LEN: usize = 9;
+
+/// Storage of a remote host which runs [`serve`], every operation is one round trip
+pub struct NetStorage<T: Read + Write = TcpStream> {
+    stream: T,
+    block_size: usize,
+    min_block_index: usize,
+    max_block_index: usize,
+}
+
+impl NetStorage<TcpStream> {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).map_err(|e| {
+            log!(error, "Can't connect to block server: {}", e);
+            Error::CanNotPerformRead
+        })?;
+        // requests are small and every one waits for response
+        let _ = stream.set_nodelay(true);
+        Self::new(stream)
+    }
+}
+
+impl<T: Read + Write> NetStorage<T> {
+    /// Client over connected `stream`, geometry of the remote storage is requested once
+    pub fn new(stream: T) -> Result<Self, Error> {
+        let mut storage = Self {
+            stream,
+            block_size: 0,
+            min_block_index: 0,
+            max_block_index: 0,
+        };
+        let mut geometry = [0_u8; 12];
+        storage
+            .call(OP_GEOMETRY, 0, 0, &[], &mut geometry)
+            .ok_or(Error::CanNotPerformRead)?;
+        let field = |i: usize| u32::from_le_bytes(geometry[i * 4..i * 4 + 4].try_into().unwrap());
+        storage.block_size = field(0) as usize;
+        storage.min_block_index = field(1) as usize;
+        storage.max_block_index = field(2) as usize;
+
+        Ok(storage)
+    }
+
+    /// Send request and read response payload to `out`, returns payload length, `None` on error
+    fn call(
+        &mut self,
+        op: u8,
+        blk_idx: usize,
+        len: usize,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Option<usize> {
+        let res = self.exchange(op, blk_idx, len, payload, out);
+        match res {
+            Ok(Some(len)) => Some(len),
+            Ok(None) => {
+                log!(debug, "Block server failed op {} at {}", op, blk_idx);
+                None
+            }
+            Err(e) => {
+                log!(error, "Block server io error: {}", e);
+                None
+            }
+        }
+    }
+
+    fn exchange(
+        &mut self,
+        op: u8,
+        blk_idx: usize,
+        len: usize,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> io::Result<Option<usize>> {
+        let mut header = [0_u8; HEADER_LEN];
+        header[0] = op;
+        header[1..5].copy_from_slice(&to_u32(blk_idx)?.to_le_bytes());
+        header[5..9].copy_from_slice(&to_u32(len)?.to_le_bytes());
+        self.stream.write_all(&header)?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()?;
+
+        let mut response = [0_u8; 5];
+        self.stream.read_exact(&mut response)?;
+        let len = u32::from_le_bytes(response[1..5].try_into().unwrap()) as usize;
+        if len > out.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response is longer than buffer",
+            ));
+        }
+        self.stream.read_exact(&mut out[..len])?;
+
+        Ok((response[0] == STATUS_OK).then_some(len))
+    }
+}
+
+impl<T: Read + Write> Storage for NetStorage<T> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        let len = data.len().min(self.block_size);
+        self.call(OP_READ, blk_idx, len, &[], data)
+            .ok_or(Error::CanNotPerformRead)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        let mut written = [0_u8; 4];
+        self.call(OP_WRITE, blk_idx, data.len(), data, &mut written)
+            .ok_or(Error::CanNotPerformWrite)?;
+        Ok(u32::from_le_bytes(written) as usize)
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        self.call(OP_ERASE, blk_idx, 0, &[], &mut [])
+            .ok_or(Error::CanNotPerformWrite)
+            .map(|_| ())
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        self.call(OP_DISCARD, blocks.start, blocks.len(), &[], &mut [])
+            .ok_or(Error::CanNotPerformWrite)
+            .map(|_| ())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.call(OP_SYNC, 0, 0, &[], &mut [])
+            .ok_or(Error::CanNotPerformWrite)
+            .map(|_| ())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn min_block_index(&self) -> usize {
+        self.min_block_index
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.max_block_index
+    }
+}
+
+fn to_u32(value: usize) -> io::Result<u32> {
+    u32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "value exceeds u32"))
+}
+
+/// Serve requests of one [`NetStorage`] client with `storage` till the client closes connection
+pub fn serve<S: Storage, T: Read + Write>(storage: &mut S, mut stream: T) -> io::Result<()> {
+    let block_size = storage.block_size();
+    let mut buf = vec![0_u8; block_size];
+    loop {
+        let mut header = [0_u8; HEADER_LEN];
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let blk_idx = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(header[5..9].try_into().unwrap()) as usize;
+
+        let response: Result<Vec<u8>, Error> = match header[0] {
+            OP_GEOMETRY => {
+                let mut geometry = Vec::with_capacity(12);
+                for value in [
+                    block_size,
+                    storage.min_block_index(),
+                    storage.max_block_index(),
+                ] {
+                    geometry.extend_from_slice(&to_u32(value)?.to_le_bytes());
+                }
+                Ok(geometry)
+            }
+            OP_READ if len <= block_size => storage
+                .read(blk_idx, &mut buf[..len])
+                .map(|read| buf[..read].to_vec()),
+            OP_WRITE if len <= block_size => {
+                stream.read_exact(&mut buf[..len])?;
+                storage
+                    .write(blk_idx, &buf[..len])
+                    .map(|written| (written as u32).to_le_bytes().to_vec())
+            }
+            OP_ERASE => storage.erase(blk_idx).map(|_| Vec::new()),
+            OP_DISCARD => storage
+                .discard(blk_idx..blk_idx.saturating_add(len))
+                .map(|_| Vec::new()),
+            OP_SYNC => storage.sync().map(|_| Vec::new()),
+            op => {
+                // payload of invalid request can't be skipped, connection is closed
+                log!(error, "Invalid request {} with length {}", op, len);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid request",
+                ));
+            }
+        };
+
+        let (status, payload) = match response {
+            Ok(payload) => (STATUS_OK, payload),
+            Err(e) => {
+                log!(
+                    debug,
+                    "Request {} at {} failed: {:?}",
+                    header[0],
+                    blk_idx,
+                    e
+                );
+                (STATUS_ERROR, Vec::new())
+            }
+        };
+        // one write, so response isn't delayed by Nagle's algorithm
+        let mut response = Vec::with_capacity(5 + payload.len());
+        response.push(status);
+        response.extend_from_slice(&to_u32(payload.len())?.to_le_bytes());
+        response.extend_from_slice(&payload);
+        stream.write_all(&response)?;
+        stream.flush()?;
+    }
+}
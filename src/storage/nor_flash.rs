@@ -0,0 +1,130 @@
+use embassy_futures::block_on;
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::{Capabilities, Geometry, IoCounters, Storage};
+
+/// Blocking adapter over async NOR flash (e.g. `Partition` of embassy-embedded-hal or SPI NOR driver), block size
+/// is erase size of the flash and every block is erased before write. It isn't async storage: futures of the flash
+/// are busy-polled to completion with `block_on` on the calling thread, so the executor and all its tasks are blocked
+/// for the whole flash operation, and a future which waits for another task of the same executor (e.g. for mutex of
+/// shared flash held by it) never completes. Use the filesystem from a task which is allowed to block (e.g. separate
+/// flush task with flash not used by other tasks, see `embassy-logger` example) or from executor of its own.
+#[derive(Debug)]
+pub struct BlockingNorFlashStorage<F: NorFlash> {
+    flash: F,
+    counters: IoCounters,
+}
+
+impl<F: NorFlash> BlockingNorFlashStorage<F> {
+    pub fn new(flash: F) -> Result<Self, Error> {
+        if F::ERASE_SIZE == 0 || F::ERASE_SIZE % F::WRITE_SIZE != 0 {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+        if flash.capacity() / F::ERASE_SIZE < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+        // offsets of the flash are u32
+        if u32::try_from(flash.capacity()).is_err() {
+            return Err(Error::ArithmeticOverflow);
+        }
+
+        Ok(Self {
+            flash,
+            counters: IoCounters::default(),
+        })
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn blk_range(&self, blk_idx: usize) -> Result<(u32, u32), Error> {
+        if blk_idx >= self.max_block_index() {
+            return Err(Error::BlockOutOfRange);
+        }
+        // capacity is checked to fit u32
+        let begin = (blk_idx * F::ERASE_SIZE) as u32;
+        Ok((begin, begin + F::ERASE_SIZE as u32))
+    }
+}
+
+impl<F: NorFlash> Storage for BlockingNorFlashStorage<F> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        let (begin, _) = self.blk_range(blk_idx)?;
+        if data.len() < F::ERASE_SIZE {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        block_on(self.flash.read(begin, &mut data[..F::ERASE_SIZE])).map_err(|e| {
+            log!(error, "Can't read flash at {}: {:?}", begin, e);
+            Error::CanNotPerformRead
+        })?;
+
+        Ok(F::ERASE_SIZE)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        let (begin, end) = self.blk_range(blk_idx)?;
+        if data.len() != F::ERASE_SIZE {
+            return Err(Error::DataLenNotEqualToBlockSize);
+        }
+
+        block_on(async {
+            self.flash.erase(begin, end).await?;
+            self.flash.write(begin, data).await
+        })
+        .map_err(|e| {
+            log!(error, "Can't write flash at {}: {:?}", begin, e);
+            Error::CanNotPerformWrite
+        })?;
+        self.counters.erases += 1;
+        self.counters.programs += 1;
+        self.counters.programmed_bytes += F::ERASE_SIZE as u64;
+
+        Ok(F::ERASE_SIZE)
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        let (begin, end) = self.blk_range(blk_idx)?;
+        block_on(self.flash.erase(begin, end)).map_err(|e| {
+            log!(error, "Can't erase flash at {}: {:?}", begin, e);
+            Error::CanNotPerformWrite
+        })?;
+        self.counters.erases += 1;
+
+        Ok(())
+    }
+
+    fn io_counters(&self) -> Option<IoCounters> {
+        Some(self.counters)
+    }
+
+    fn block_size(&self) -> usize {
+        F::ERASE_SIZE
+    }
+
+    fn geometry(&self) -> Geometry {
+        Geometry {
+            block_size: F::ERASE_SIZE,
+            min_block_index: self.min_block_index(),
+            max_block_index: self.max_block_index(),
+            erase_size: Some(F::ERASE_SIZE),
+            read_size: F::READ_SIZE,
+            write_size: F::WRITE_SIZE,
+            capabilities: Capabilities {
+                erase: true,
+                ..Capabilities::default()
+            },
+        }
+    }
+
+    fn min_block_index(&self) -> usize {
+        0
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.flash.capacity() / F::ERASE_SIZE
+    }
+}
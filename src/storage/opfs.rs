@@ -0,0 +1,120 @@
+use web_sys::{FileSystemReadWriteOptions, FileSystemSyncAccessHandle};
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::Storage;
+use crate::utils::validate_block_index;
+
+/// Storage over a file in browser Origin Private File System.
+/// Sync access handle is available only in dedicated web workers, it must be created by
+/// `FileSystemFileHandle.createSyncAccessHandle()` before creating storage.
+pub struct OpfsStorage {
+    begin_block: u32,
+    end_block: u32,
+    block_size: u32,
+    handle: FileSystemSyncAccessHandle,
+}
+
+impl OpfsStorage {
+    pub fn new(
+        handle: FileSystemSyncAccessHandle,
+        begin_block: u32,
+        end_block: u32,
+        block_size: u32,
+    ) -> Self {
+        OpfsStorage {
+            begin_block,
+            end_block,
+            block_size,
+            handle,
+        }
+    }
+
+    /// Flush all written data to the file
+    pub fn flush(&self) -> Result<(), Error> {
+        self.handle.flush().map_err(|_e| Error::CanNotPerformWrite)
+    }
+
+    pub fn into_handle(self) -> FileSystemSyncAccessHandle {
+        self.handle
+    }
+
+    fn options(&self, blk_idx: usize) -> FileSystemReadWriteOptions {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(self.blk_byte_offset(blk_idx) as f64);
+        options
+    }
+}
+
+impl Storage for OpfsStorage {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() < self.block_size() {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let data = &mut data[..self.block_size()];
+        let read = self
+            .handle
+            .read_with_u8_array_and_options(data, &self.options(blk_idx))
+            .map_err(|e| {
+                log!(
+                    error,
+                    "Can't perform read, block: {}, err: {:?}",
+                    blk_idx,
+                    e
+                );
+                Error::CanNotPerformRead
+            })?;
+
+        // file can be shorter than storage, not written part is treated as zeroes
+        let read = read as usize;
+        if read < data.len() {
+            data[read..].fill(0);
+        }
+
+        Ok(self.block_size())
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+        if data.len() != self.block_size() {
+            return Err(Error::DataLenNotEqualToBlockSize);
+        }
+
+        let written = self
+            .handle
+            .write_with_u8_array_and_options(data, &self.options(blk_idx))
+            .map_err(|e| {
+                log!(
+                    error,
+                    "Can't perform write, block: {}, err: {:?}",
+                    blk_idx,
+                    e
+                );
+                Error::CanNotPerformWrite
+            })?;
+        if written as usize != data.len() {
+            return Err(Error::CanNotPerformWrite);
+        }
+
+        Ok(self.block_size())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    fn min_block_index(&self) -> usize {
+        self.begin_block as usize
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.end_block as usize
+    }
+}
@@ -0,0 +1,181 @@
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::{Capabilities, Geometry, IoCounters, Storage};
+use crate::utils::validate_block_index;
+
+/// Storage which packs `blocks_per_page` blocks of the filesystem into one page of `inner` (e.g. 2 KB or 4 KB page
+/// of NAND), so records don't have to be as big as the page. Blocks are collected in RAM buffer and the page
+/// is written once, when its last block is written, blocks of not written page are lost on power loss and
+/// restore finds blocks of the previous page. Block `i` is slot `i % blocks_per_page` of page
+/// `inner.min_block_index() + i / blocks_per_page`, block size of `inner` must be `P`.
+/// `sync` doesn't write partial page, call `flush` before shutdown (the page is written again when it's filled,
+/// so `inner` must allow rewrite of a page, e.g. it's erased by `erase` of the last block of the page).
+#[derive(Debug)]
+pub struct PagedStorage<S: Storage, const P: usize> {
+    inner: S,
+    blocks_per_page: usize,
+    /// Page collected in `page`
+    pending: Option<usize>,
+    page: [u8; P],
+    scratch: [u8; P],
+}
+
+impl<S: Storage, const P: usize> PagedStorage<S, P> {
+    pub fn new(inner: S, blocks_per_page: usize) -> Result<Self, Error> {
+        if inner.block_size() != P || blocks_per_page == 0 || !P.is_multiple_of(blocks_per_page) {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        let pages = inner.max_block_index() - inner.min_block_index();
+        if pages * blocks_per_page < 2 {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        Ok(Self {
+            inner,
+            blocks_per_page,
+            pending: None,
+            page: [0_u8; P],
+            scratch: [0_u8; P],
+        })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Pending blocks are dropped, call `flush` before it to keep them
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Page of `inner` which has written blocks only in RAM
+    pub fn pending_page(&self) -> Option<usize> {
+        self.pending
+    }
+
+    /// Write partially filled page, blocks after the last written one keep content of the page
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(page_idx) = self.pending.take() {
+            log!(debug, "Flush partial page {}", page_idx);
+            self.inner.write(page_idx, &self.page)?;
+        }
+
+        Ok(())
+    }
+
+    /// Index of the page of `inner` and offset of block `blk_idx` in it
+    fn locate(&self, blk_idx: usize) -> (usize, usize) {
+        let page_idx = self.inner.min_block_index() + blk_idx / self.blocks_per_page;
+        let slot = blk_idx % self.blocks_per_page;
+        (page_idx, slot * self.block_size())
+    }
+}
+
+impl<S: Storage, const P: usize> Storage for PagedStorage<S, P> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        let block_size = self.block_size();
+        if data.len() < block_size {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let (page_idx, offset) = self.locate(blk_idx);
+        let page = if self.pending == Some(page_idx) {
+            &self.page
+        } else {
+            self.inner.read(page_idx, &mut self.scratch)?;
+            &self.scratch
+        };
+        data[..block_size].copy_from_slice(&page[offset..offset + block_size]);
+
+        Ok(block_size)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        let block_size = self.block_size();
+        if data.len() != block_size {
+            return Err(Error::DataLenNotEqualToBlockSize);
+        }
+
+        let (page_idx, offset) = self.locate(blk_idx);
+        if self.pending != Some(page_idx) {
+            // out of order write (e.g. update of config block), previous page is written partially
+            self.flush()?;
+            self.inner.read(page_idx, &mut self.page)?;
+            self.pending = Some(page_idx);
+        }
+        self.page[offset..offset + block_size].copy_from_slice(data);
+
+        if offset + block_size == P {
+            self.pending = None;
+            self.inner.write(page_idx, &self.page)?;
+        }
+
+        Ok(block_size)
+    }
+
+    fn block_size(&self) -> usize {
+        P / self.blocks_per_page
+    }
+
+    fn min_block_index(&self) -> usize {
+        0
+    }
+
+    fn max_block_index(&self) -> usize {
+        (self.inner.max_block_index() - self.inner.min_block_index()) * self.blocks_per_page
+    }
+
+    /// Page is erased by erase of its last block
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        validate_block_index(self, blk_idx)?;
+
+        let (page_idx, offset) = self.locate(blk_idx);
+        if offset + self.block_size() == P && self.pending != Some(page_idx) {
+            self.inner.erase(page_idx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Only pages which are covered by `blocks` completely are discarded
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        let min = self.inner.min_block_index();
+        let begin = min + blocks.start.div_ceil(self.blocks_per_page);
+        let end = min + blocks.end / self.blocks_per_page;
+        if begin < end {
+            self.inner.discard(begin..end)?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.inner.sync()
+    }
+
+    fn io_counters(&self) -> Option<IoCounters> {
+        self.inner.io_counters()
+    }
+
+    fn geometry(&self) -> Geometry {
+        let inner = self.inner.geometry();
+        Geometry {
+            block_size: self.block_size(),
+            min_block_index: self.min_block_index(),
+            max_block_index: self.max_block_index(),
+            write_size: P,
+            capabilities: Capabilities {
+                write_in_place: false,
+                ..inner.capabilities
+            },
+            ..inner
+        }
+    }
+}
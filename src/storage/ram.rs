@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::storage::Storage;
+use crate::storage::{SharedRead, StaticGeometry, Storage};
 use crate::utils::validate_block_index;
 
 #[derive(Debug)]
@@ -13,7 +13,7 @@ impl<const S: usize, const B: usize> RamStorage<S, B> {
             return Err(Error::TooSmallBuffer);
         }
 
-        if S % B != 0 {
+        if !S.is_multiple_of(B) {
             return Err(Error::InvalidBlockSizeForStorage);
         }
 
@@ -21,32 +21,50 @@ impl<const S: usize, const B: usize> RamStorage<S, B> {
     }
 }
 
+impl<const S: usize, const B: usize> StaticGeometry for RamStorage<S, B> {
+    const BLOCK_SIZE: usize = B;
+    const BLOCK_COUNT: usize = {
+        assert!(S >= 2 * B, "Ram storage must fit at least 2 blocks");
+        assert!(
+            S.is_multiple_of(B),
+            "Ram storage size must be multiple of block size"
+        );
+        S / B
+    };
+}
+
 impl<const S: usize, const B: usize> Storage for RamStorage<S, B> {
     fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.read_shared(blk_idx, data)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
         validate_block_index(self, blk_idx)?;
 
-        if data.len() < self.block_size() {
-            return Err(Error::NotEnoughSpaceForRead);
+        if data.len() != self.block_size() {
+            return Err(Error::DataLenNotEqualToBlockSize);
         }
 
         let begin = blk_idx * self.block_size();
         let end = begin + self.block_size();
-
-        data[..self.block_size()].copy_from_slice(&self.data[begin..end]);
+        self.data[begin..end].copy_from_slice(data);
 
         Ok(self.block_size())
     }
 
-    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
-        validate_block_index(self, blk_idx)?;
+    fn can_write_in_place(&self) -> bool {
+        true
+    }
 
-        if data.len() != self.block_size() {
-            return Err(Error::DataLenNotEqualToBlockSize);
-        }
+    fn write_in_place<F>(&mut self, blk_idx: usize, writer: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        validate_block_index(self, blk_idx)?;
 
         let begin = blk_idx * self.block_size();
         let end = begin + self.block_size();
-        self.data[begin..end].copy_from_slice(data);
+        writer(&mut self.data[begin..end]);
 
         Ok(self.block_size())
     }
@@ -63,3 +81,20 @@ impl<const S: usize, const B: usize> Storage for RamStorage<S, B> {
         S / B
     }
 }
+
+impl<const S: usize, const B: usize> SharedRead for RamStorage<S, B> {
+    fn read_shared(&self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() < self.block_size() {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let begin = blk_idx * self.block_size();
+        let end = begin + self.block_size();
+
+        data[..self.block_size()].copy_from_slice(&self.data[begin..end]);
+
+        Ok(self.block_size())
+    }
+}
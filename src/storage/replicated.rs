@@ -0,0 +1,167 @@
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::{Capabilities, Geometry, IoCounters, SharedRead, Storage};
+
+/// Storage which mirrors every successful write of `primary` to `replica` (e.g. removable card to eMMC),
+/// block `min_block_index() + i` of primary is written to block `replica.min_block_index() + i`.
+/// Replication is best effort, errors of replica are counted, but don't fail writes. Reads use only primary.
+/// Replica can be opened as a filesystem with `restore`.
+#[derive(Debug)]
+pub struct ReplicatedStorage<P: Storage, R: Storage> {
+    primary: P,
+    replica: R,
+    stats: ReplicationStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationStats {
+    pub replicated_blocks: usize,
+    pub failed_blocks: usize,
+    pub last_error: Option<Error>,
+}
+
+impl<P: Storage, R: Storage> ReplicatedStorage<P, R> {
+    /// Replica must have the same block size and at least the same number of blocks
+    pub fn new(primary: P, replica: R) -> Result<Self, Error> {
+        if primary.block_size() != replica.block_size() {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        let primary_len = primary.max_block_index() - primary.min_block_index();
+        let replica_len = replica.max_block_index() - replica.min_block_index();
+        if replica_len < primary_len {
+            return Err(Error::TooSmallFilesystem);
+        }
+
+        Ok(Self {
+            primary,
+            replica,
+            stats: ReplicationStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> &ReplicationStats {
+        &self.stats
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn replica(&self) -> &R {
+        &self.replica
+    }
+
+    pub fn into_inner(self) -> (P, R) {
+        (self.primary, self.replica)
+    }
+}
+
+impl<P: Storage, R: Storage> Storage for ReplicatedStorage<P, R> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.primary.read(blk_idx, data)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        let written = self.primary.write(blk_idx, data)?;
+
+        let replica_idx = blk_idx - self.primary.min_block_index() + self.replica.min_block_index();
+        match self.replica.write(replica_idx, data) {
+            Ok(_) => self.stats.replicated_blocks += 1,
+            Err(e) => {
+                log!(warn, "Can't replicate block {}: {:?}", blk_idx, e);
+                self.stats.failed_blocks += 1;
+                self.stats.last_error = Some(e);
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        self.primary.erase(blk_idx)?;
+
+        let replica_idx = blk_idx - self.primary.min_block_index() + self.replica.min_block_index();
+        if let Err(e) = self.replica.erase(replica_idx) {
+            log!(warn, "Can't erase replica block {}: {:?}", blk_idx, e);
+            self.stats.failed_blocks += 1;
+            self.stats.last_error = Some(e);
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        self.primary.discard(blocks.clone())?;
+
+        let shift =
+            |idx: usize| idx - self.primary.min_block_index() + self.replica.min_block_index();
+        let replica_blocks = shift(blocks.start)..shift(blocks.end);
+        if let Err(e) = self.replica.discard(replica_blocks) {
+            log!(warn, "Can't discard replica blocks {:?}: {:?}", blocks, e);
+            self.stats.last_error = Some(e);
+        }
+
+        Ok(())
+    }
+
+    /// Only failure of the primary fails sync, see `erase`
+    fn sync(&mut self) -> Result<(), Error> {
+        self.primary.sync()?;
+
+        if let Err(e) = self.replica.sync() {
+            log!(warn, "Can't sync replica: {:?}", e);
+            self.stats.last_error = Some(e);
+        }
+
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.primary.block_size()
+    }
+
+    fn min_block_index(&self) -> usize {
+        self.primary.min_block_index()
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.primary.max_block_index()
+    }
+
+    /// Operations of both storages, storage which doesn't track them is skipped
+    fn io_counters(&self) -> Option<IoCounters> {
+        match (self.primary.io_counters(), self.replica.io_counters()) {
+            (Some(p), Some(r)) => Some(IoCounters {
+                programs: p.programs + r.programs,
+                erases: p.erases + r.erases,
+                programmed_bytes: p.programmed_bytes + r.programmed_bytes,
+            }),
+            (p, r) => p.or(r),
+        }
+    }
+
+    /// Layout of the primary, operations are forwarded to both storages, so granularity is the coarser one
+    fn geometry(&self) -> Geometry {
+        let primary = self.primary.geometry();
+        let replica = self.replica.geometry();
+        Geometry {
+            read_size: primary.read_size.max(replica.read_size),
+            write_size: primary.write_size.max(replica.write_size),
+            capabilities: Capabilities {
+                erase: primary.capabilities.erase || replica.capabilities.erase,
+                discard: primary.capabilities.discard || replica.capabilities.discard,
+                write_in_place: false,
+            },
+            ..primary
+        }
+    }
+}
+
+impl<P: SharedRead, R: Storage> SharedRead for ReplicatedStorage<P, R> {
+    fn read_shared(&self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.primary.read_shared(blk_idx, data)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error::Error;
+use crate::storage::{SharedRead, Storage};
+use crate::utils::validate_block_index;
+
+/// Storage over a borrowed memory region, block size is set at runtime.
+/// Can be used to parse or create a filesystem image which was loaded to memory (e.g. a file uploaded in browser).
+#[derive(Debug)]
+pub struct SliceStorage<'a> {
+    data: &'a mut [u8],
+    block_size: usize,
+}
+
+impl<'a> SliceStorage<'a> {
+    pub fn new(data: &'a mut [u8], block_size: usize) -> Result<Self, Error> {
+        if block_size == 0 || !data.len().is_multiple_of(block_size) {
+            return Err(Error::InvalidBlockSizeForStorage);
+        }
+
+        if data.len() / block_size < 2 {
+            return Err(Error::TooSmallBuffer);
+        }
+
+        Ok(Self { data, block_size })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> Storage for SliceStorage<'a> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        self.read_shared(blk_idx, data)
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() != self.block_size {
+            return Err(Error::DataLenNotEqualToBlockSize);
+        }
+
+        let begin = blk_idx * self.block_size;
+        let end = begin + self.block_size;
+        self.data[begin..end].copy_from_slice(data);
+
+        Ok(self.block_size)
+    }
+
+    fn can_write_in_place(&self) -> bool {
+        true
+    }
+
+    fn write_in_place<F>(&mut self, blk_idx: usize, writer: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        validate_block_index(self, blk_idx)?;
+
+        let begin = blk_idx * self.block_size;
+        let end = begin + self.block_size;
+        writer(&mut self.data[begin..end]);
+
+        Ok(self.block_size)
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn min_block_index(&self) -> usize {
+        0
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.data.len() / self.block_size
+    }
+}
+
+impl<'a> SharedRead for SliceStorage<'a> {
+    fn read_shared(&self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        validate_block_index(self, blk_idx)?;
+
+        if data.len() < self.block_size {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        let begin = blk_idx * self.block_size;
+        let end = begin + self.block_size;
+        data[..self.block_size].copy_from_slice(&self.data[begin..end]);
+
+        Ok(self.block_size)
+    }
+}
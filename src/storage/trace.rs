@@ -0,0 +1,416 @@
+//! Record io of a storage with [`TraceStorage`] (e.g. on device which fails to restore) and reproduce it
+//! on host with [`ReplayStorage`].
+//!
+//! Trace is saved as text, the first line is `appendfs-trace <block_size> <min_block_index> <max_block_index>`,
+//! every next line is an event: `<op> <blk_idx> <len> <ok|err> <hash|-> <data|->`, hash is crc32 of data
+//! and data is hex encoded. `len` of `discard` is number of blocks, `blk_idx` of `sync` is 0.
+
+use std::format;
+use std::io::{self, BufRead, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::log;
+use crate::storage::{Geometry, IoCounters, Storage};
+
+const TRACE_MAGIC: &str = "appendfs-trace";
+const HASH_ALGORITHM: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    Read,
+    Write,
+    Erase,
+    Discard,
+    Sync,
+}
+
+impl TraceOp {
+    fn name(self) -> &'static str {
+        match self {
+            TraceOp::Read => "read",
+            TraceOp::Write => "write",
+            TraceOp::Erase => "erase",
+            TraceOp::Discard => "discard",
+            TraceOp::Sync => "sync",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Read,
+            Self::Write,
+            Self::Erase,
+            Self::Discard,
+            Self::Sync,
+        ]
+        .into_iter()
+        .find(|op| op.name() == name)
+    }
+}
+
+/// What is recorded for reads and writes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceDetail {
+    /// Only indexes and lengths
+    #[default]
+    Indexes,
+    /// Crc32 of data, replay checks that the same data is written
+    Hashes,
+    /// Hash and the whole data, required to replay reads
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: TraceOp,
+    pub blk_idx: usize,
+    pub len: usize,
+    /// Operation succeeded, replay fails the same operation otherwise
+    pub ok: bool,
+    pub hash: Option<u32>,
+    pub data: Option<Vec<u8>>,
+}
+
+/// Storage which records every operation of `inner` storage, see [`TraceDetail`]
+#[derive(Debug)]
+pub struct TraceStorage<S: Storage> {
+    inner: S,
+    detail: TraceDetail,
+    events: Vec<TraceEvent>,
+}
+
+impl<S: Storage> TraceStorage<S> {
+    pub fn new(inner: S, detail: TraceDetail) -> Self {
+        Self {
+            inner,
+            detail,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    pub fn into_inner(self) -> (S, Vec<TraceEvent>) {
+        (self.inner, self.events)
+    }
+
+    /// Save recorded events, read them with [`ReplayStorage::from_reader`]
+    pub fn write_trace<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{} {} {} {}",
+            TRACE_MAGIC,
+            self.inner.block_size(),
+            self.inner.min_block_index(),
+            self.inner.max_block_index()
+        )?;
+        for event in &self.events {
+            let hash = event
+                .hash
+                .map_or_else(|| String::from("-"), |h| format!("{:08x}", h));
+            let data = event.data.as_ref().map_or_else(
+                || String::from("-"),
+                |d| d.iter().map(|b| format!("{:02x}", b)).collect(),
+            );
+            writeln!(
+                writer,
+                "{} {} {} {} {} {}",
+                event.op.name(),
+                event.blk_idx,
+                event.len,
+                if event.ok { "ok" } else { "err" },
+                hash,
+                data
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, op: TraceOp, blk_idx: usize, len: usize, ok: bool, data: Option<&[u8]>) {
+        let (hash, data) = match (self.detail, data) {
+            (TraceDetail::Hashes, Some(data)) => (Some(HASH_ALGORITHM.checksum(data)), None),
+            (TraceDetail::Data, Some(data)) => {
+                (Some(HASH_ALGORITHM.checksum(data)), Some(data.to_vec()))
+            }
+            _ => (None, None),
+        };
+        self.events.push(TraceEvent {
+            op,
+            blk_idx,
+            len,
+            ok,
+            hash,
+            data,
+        });
+    }
+}
+
+impl<S: Storage> Storage for TraceStorage<S> {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        let res = self.inner.read(blk_idx, data);
+        let len = *res.as_ref().unwrap_or(&0);
+        let read = res.is_ok().then(|| &data[..len]);
+        self.record(TraceOp::Read, blk_idx, len, res.is_ok(), read);
+        res
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        let res = self.inner.write(blk_idx, data);
+        self.record(TraceOp::Write, blk_idx, data.len(), res.is_ok(), Some(data));
+        res
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        let res = self.inner.erase(blk_idx);
+        self.record(TraceOp::Erase, blk_idx, 0, res.is_ok(), None);
+        res
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        let res = self.inner.discard(blocks.clone());
+        self.record(
+            TraceOp::Discard,
+            blocks.start,
+            blocks.len(),
+            res.is_ok(),
+            None,
+        );
+        res
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        let res = self.inner.sync();
+        self.record(TraceOp::Sync, 0, 0, res.is_ok(), None);
+        res
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn min_block_index(&self) -> usize {
+        self.inner.min_block_index()
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.inner.max_block_index()
+    }
+
+    fn geometry(&self) -> Geometry {
+        self.inner.geometry()
+    }
+
+    fn io_counters(&self) -> Option<IoCounters> {
+        self.inner.io_counters()
+    }
+}
+
+/// Storage which answers operations with recorded events, reads need trace with [`TraceDetail::Data`].
+/// Operation which differs from the next event (op, index or hash of written data) fails and is counted
+/// in `divergences`, so replay shows where the code under test behaves differently than on the device.
+#[derive(Debug)]
+pub struct ReplayStorage {
+    events: Vec<TraceEvent>,
+    next: usize,
+    divergences: usize,
+    block_size: usize,
+    min_block_index: usize,
+    max_block_index: usize,
+}
+
+impl ReplayStorage {
+    pub fn new(
+        events: Vec<TraceEvent>,
+        block_size: usize,
+        min_block_index: usize,
+        max_block_index: usize,
+    ) -> Self {
+        Self {
+            events,
+            next: 0,
+            divergences: 0,
+            block_size,
+            min_block_index,
+            max_block_index,
+        }
+    }
+
+    /// Parse trace saved by [`TraceStorage::write_trace`]
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid trace line: {}", line),
+            )
+        };
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| invalid(""))??;
+        let mut fields = header.split(' ');
+        if fields.next() != Some(TRACE_MAGIC) {
+            return Err(invalid(&header));
+        }
+        let mut geometry = fields.map(|f| f.parse::<usize>().map_err(|_| invalid(&header)));
+        let mut next_value = || geometry.next().ok_or_else(|| invalid(&header))?;
+        let (block_size, min_block_index, max_block_index) =
+            (next_value()?, next_value()?, next_value()?);
+
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            events.push(Self::parse_event(&line).ok_or_else(|| invalid(&line))?);
+        }
+
+        Ok(Self::new(
+            events,
+            block_size,
+            min_block_index,
+            max_block_index,
+        ))
+    }
+
+    fn parse_event(line: &str) -> Option<TraceEvent> {
+        let mut fields = line.split(' ');
+        let op = TraceOp::from_name(fields.next()?)?;
+        let blk_idx = fields.next()?.parse().ok()?;
+        let len = fields.next()?.parse().ok()?;
+        let ok = match fields.next()? {
+            "ok" => true,
+            "err" => false,
+            _ => return None,
+        };
+        let hash = match fields.next()? {
+            "-" => None,
+            hash => Some(u32::from_str_radix(hash, 16).ok()?),
+        };
+        let data = match fields.next()? {
+            "-" => None,
+            hex => Some(
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+        };
+
+        Some(TraceEvent {
+            op,
+            blk_idx,
+            len,
+            ok,
+            hash,
+            data,
+        })
+    }
+
+    /// All events were replayed
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    /// Index of the next event
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    /// Number of operations which didn't match the trace
+    pub fn divergences(&self) -> usize {
+        self.divergences
+    }
+
+    /// The next event in case it's `op` of `blk_idx`
+    fn take(&mut self, op: TraceOp, blk_idx: usize) -> Option<&TraceEvent> {
+        match self.events.get(self.next) {
+            Some(event) if event.op == op && event.blk_idx == blk_idx => {
+                self.next += 1;
+                Some(&self.events[self.next - 1])
+            }
+            event => {
+                log!(
+                    warn,
+                    "Replay diverged at event {}: {:?} {} instead of {:?}",
+                    self.next,
+                    op,
+                    blk_idx,
+                    event
+                );
+                self.divergences += 1;
+                None
+            }
+        }
+    }
+}
+
+impl Storage for ReplayStorage {
+    fn read(&mut self, blk_idx: usize, data: &mut [u8]) -> Result<usize, Error> {
+        let event = self
+            .take(TraceOp::Read, blk_idx)
+            .ok_or(Error::CanNotPerformRead)?;
+        if !event.ok {
+            return Err(Error::CanNotPerformRead);
+        }
+        let recorded = event.data.as_ref().ok_or(Error::CanNotPerformRead)?;
+        if data.len() < recorded.len() {
+            return Err(Error::NotEnoughSpaceForRead);
+        }
+
+        data[..recorded.len()].copy_from_slice(recorded);
+        Ok(recorded.len())
+    }
+
+    fn write(&mut self, blk_idx: usize, data: &[u8]) -> Result<usize, Error> {
+        let event = self
+            .take(TraceOp::Write, blk_idx)
+            .ok_or(Error::CanNotPerformWrite)?;
+        let (ok, len, hash) = (event.ok, event.len, event.hash);
+        if len != data.len() || hash.is_some_and(|h| h != HASH_ALGORITHM.checksum(data)) {
+            log!(warn, "Replay: different data is written to {}", blk_idx);
+            self.divergences += 1;
+            return Err(Error::CanNotPerformWrite);
+        }
+        if !ok {
+            return Err(Error::CanNotPerformWrite);
+        }
+
+        Ok(len)
+    }
+
+    fn erase(&mut self, blk_idx: usize) -> Result<(), Error> {
+        match self.take(TraceOp::Erase, blk_idx) {
+            Some(event) if event.ok => Ok(()),
+            _ => Err(Error::CanNotPerformWrite),
+        }
+    }
+
+    fn discard(&mut self, blocks: Range<usize>) -> Result<(), Error> {
+        match self.take(TraceOp::Discard, blocks.start) {
+            Some(event) if event.ok => Ok(()),
+            _ => Err(Error::CanNotPerformWrite),
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        match self.take(TraceOp::Sync, 0) {
+            Some(event) if event.ok => Ok(()),
+            _ => Err(Error::CanNotPerformWrite),
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn min_block_index(&self) -> usize {
+        self.min_block_index
+    }
+
+    fn max_block_index(&self) -> usize {
+        self.max_block_index
+    }
+}
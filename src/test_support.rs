@@ -0,0 +1,110 @@
+//! Helpers for integration tests of crates which use appendfs (`test_support` feature): RAM rings of common
+//! geometries, deterministic block content and assertions over filesystem content.
+
+use crate::block::{BlockLayout, CrcMode, FsId};
+use crate::error::Error;
+use crate::fs::Filesystem;
+use crate::storage::ram::RamStorage;
+use crate::storage::Storage;
+pub use crate::utils::slices_are_equal;
+
+/// Fs id used by helpers which format storage
+pub const TEST_FS_ID: FsId = 1952805748;
+
+/// 8 blocks of 64 bytes, ring wraps after a few appends
+pub type TinyRing = RamStorage<{ 64 * 8 }, 64>;
+/// 64 sectors of SD card (512 bytes)
+pub type SdRing = RamStorage<{ 512 * 64 }, 512>;
+/// 16 erase sectors of NOR flash (4096 bytes)
+pub type FlashRing = RamStorage<{ 4096 * 16 }, 4096>;
+
+/// Empty RAM ring of `SIZE` bytes with `BLOCK` byte blocks, panics in case geometry isn't valid
+pub fn ram_ring<const SIZE: usize, const BLOCK: usize>() -> RamStorage<SIZE, BLOCK> {
+    RamStorage::new().expect("Invalid geometry of RAM ring")
+}
+
+/// Writer of deterministic block content, every byte depends on `seed` and its position,
+/// so swapped, shifted or stale blocks are detected by [`is_pattern`]
+pub fn pattern(seed: u64) -> impl FnOnce(&mut [u8]) {
+    move |data| {
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = pattern_byte(seed, i);
+        }
+    }
+}
+
+/// `data` was written by [`pattern`] with `seed`
+pub fn is_pattern(seed: u64, data: &[u8]) -> bool {
+    data.iter()
+        .enumerate()
+        .all(|(i, b)| *b == pattern_byte(seed, i))
+}
+
+fn pattern_byte(seed: u64, i: usize) -> u8 {
+    let x =
+        seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (i as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    (x >> 56) as u8
+}
+
+/// Format `storage` with [`TEST_FS_ID`] and append blocks of [`pattern`] with `seeds`
+pub fn fill<S: Storage, const BS: usize>(
+    storage: &mut S,
+    seeds: impl IntoIterator<Item = u64>,
+) -> Result<(), Error> {
+    let mut fs = Filesystem::<_, BS>::new_with_force(storage, TEST_FS_ID, CrcMode::Full, true)?;
+    for seed in seeds {
+        fs.append(pattern(seed))?;
+    }
+
+    Ok(())
+}
+
+/// Panics in case used blocks of `fs` (from the oldest one) aren't [`pattern`] blocks of `seeds`
+pub fn assert_blocks<S: Storage, const BS: usize, L: BlockLayout, B: AsMut<[u8]>>(
+    fs: &mut Filesystem<'_, S, BS, L, B>,
+    seeds: &[u64],
+) {
+    assert_eq!(fs.used_blocks(), seeds.len(), "Unexpected number of blocks");
+    for (blk_offset, seed) in seeds.iter().enumerate() {
+        let mut is_valid = false;
+        fs.read(blk_offset, |data| is_valid = is_pattern(*seed, data))
+            .unwrap_or_else(|e| panic!("Can't read block at {}: {:?}", blk_offset, e));
+        assert!(
+            is_valid,
+            "Block at {} isn't pattern of seed {}",
+            blk_offset, seed
+        );
+    }
+}
+
+/// Panics in case `storage` isn't restored with `expected` used blocks
+pub fn assert_restores<S: Storage, const BS: usize>(storage: &mut S, expected: usize) {
+    let fs = Filesystem::<_, BS>::restore(storage)
+        .unwrap_or_else(|e| panic!("Can't restore fs: {:?}", e));
+    assert_eq!(
+        fs.used_blocks(),
+        expected,
+        "Unexpected number of restored blocks"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_blocks, assert_restores, fill, is_pattern, pattern, ram_ring, SdRing};
+    use crate::fs::Filesystem;
+
+    #[test]
+    fn test_support() {
+        let mut data = [0_u8; 16];
+        pattern(3)(&mut data);
+        assert!(is_pattern(3, &data));
+        assert!(!is_pattern(4, &data));
+
+        let mut storage: SdRing = ram_ring();
+        fill::<_, 512>(&mut storage, 0..70).expect("Can't fill storage");
+        assert_restores::<_, 512>(&mut storage, 62);
+        let mut fs = Filesystem::<_, 512>::restore(&mut storage).expect("Can't restore fs");
+        let seeds: [u64; 62] = core::array::from_fn(|i| i as u64 + 8);
+        assert_blocks(&mut fs, &seeds);
+    }
+}
@@ -0,0 +1,73 @@
+//! Time sources for timestamps of v2 block header (see [`crate::block::HeaderV2`]),
+//! set it with [`crate::fs::Filesystem::set_time_source`].
+
+use core::fmt;
+
+/// Milliseconds, block timestamps are milliseconds since unix epoch in case wall clock is known
+pub type Timestamp = u64;
+
+pub trait TimeSource {
+    /// Monotonic milliseconds since boot (e.g. systick counter), must not decrease
+    fn ticks(&mut self) -> Timestamp;
+
+    /// Milliseconds since unix epoch, `None` in case wall clock isn't known (e.g. RTC isn't set yet)
+    fn wall_clock(&mut self) -> Option<Timestamp> {
+        None
+    }
+}
+
+/// Time source of embedded target without wall clock, closure returns ticks (e.g. of embassy `Instant`)
+pub struct FnTimeSource<F: FnMut() -> Timestamp>(pub F);
+
+impl<F: FnMut() -> Timestamp> TimeSource for FnTimeSource<F> {
+    fn ticks(&mut self) -> Timestamp {
+        (self.0)()
+    }
+}
+
+/// Ticks of `Instant` since creation and wall clock of `SystemTime`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdTimeSource {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdTimeSource {
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdTimeSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for StdTimeSource {
+    fn ticks(&mut self) -> Timestamp {
+        self.start.elapsed().as_millis() as Timestamp
+    }
+
+    fn wall_clock(&mut self) -> Option<Timestamp> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some(now.as_millis() as Timestamp)
+    }
+}
+
+pub(crate) struct Clock<'a> {
+    pub(crate) source: &'a mut (dyn TimeSource + Send),
+}
+
+impl<'a> fmt::Debug for Clock<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Clock").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,177 @@
+//! Filesystem for tokio services (e.g. ingest of network streams), blocking io of the storage
+//! (e.g. [`crate::storage::file::FileStorage`]) is performed by a dedicated thread, so the runtime is never blocked.
+
+use std::sync::mpsc;
+use std::thread;
+use std::vec::Vec;
+
+use tokio::sync::oneshot;
+
+use crate::block::FsId;
+use crate::error::Error;
+use crate::fs::{AppendedBlock, DynFilesystem, FormatPolicy, FsStats};
+use crate::log;
+use crate::storage::Storage;
+
+enum Request {
+    Append(Vec<u8>, oneshot::Sender<Result<AppendedBlock, Error>>),
+    Read(usize, oneshot::Sender<Result<Vec<u8>, Error>>),
+    Stats(oneshot::Sender<FsStats>),
+}
+
+/// Handle of the filesystem owned by io thread, it can be cloned to append from several tasks.
+/// Thread stops when all handles are dropped.
+#[derive(Debug, Clone)]
+pub struct TokioFs {
+    requests: mpsc::Sender<Request>,
+    data_size: usize,
+}
+
+impl TokioFs {
+    /// Restore filesystem of `storage` (or format it with `fs_id`, see [`DynFilesystem::restore_or_format`])
+    /// in a new io thread
+    pub async fn open<S>(storage: S, fs_id: FsId) -> Result<Self, Error>
+    where
+        S: Storage + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel();
+        let (opened, on_open) = oneshot::channel();
+        thread::Builder::new()
+            .name("appendfs-io".into())
+            .spawn(move || Self::serve(storage, fs_id, receiver, opened))
+            .map_err(|_| Error::CanNotPerformWrite)?;
+
+        let data_size = on_open.await.map_err(|_| Error::CanNotPerformRead)??;
+        Ok(Self {
+            requests,
+            data_size,
+        })
+    }
+
+    /// Append block with `data`, it must not be longer than `data_size`, rest of the block is filled with zeros
+    pub async fn append(&self, data: Vec<u8>) -> Result<AppendedBlock, Error> {
+        if data.len() > self.data_size {
+            return Err(Error::InvalidBlockSizeForWrite);
+        }
+
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Append(data, reply))?;
+        response.await.map_err(|_| Error::CanNotPerformWrite)?
+    }
+
+    /// Read data of the block at `blk_offset` from the oldest one, see [`DynFilesystem::read`]
+    pub async fn read(&self, blk_offset: usize) -> Result<Vec<u8>, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Read(blk_offset, reply))?;
+        response.await.map_err(|_| Error::CanNotPerformRead)?
+    }
+
+    pub async fn stats(&self) -> Result<FsStats, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Stats(reply))?;
+        response.await.map_err(|_| Error::CanNotPerformRead)
+    }
+
+    /// Size of data of one block
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    fn send(&self, request: Request) -> Result<(), Error> {
+        // io thread lives while there are handles, so it fails only in case the thread panicked
+        self.requests
+            .send(request)
+            .map_err(|_| Error::CanNotPerformWrite)
+    }
+
+    fn serve<S: Storage>(
+        mut storage: S,
+        fs_id: FsId,
+        requests: mpsc::Receiver<Request>,
+        opened: oneshot::Sender<Result<usize, Error>>,
+    ) {
+        let mut fs = match DynFilesystem::restore_or_format(
+            &mut storage,
+            || fs_id,
+            FormatPolicy::Preserve,
+        ) {
+            Ok(fs) => fs,
+            Err(e) => {
+                let _ = opened.send(Err(e));
+                return;
+            }
+        };
+        if opened.send(Ok(fs.data_size())).is_err() {
+            return;
+        }
+
+        // replies are dropped in case requesting task was cancelled
+        for request in requests {
+            match request {
+                Request::Append(data, reply) => {
+                    let res = fs.append(|blk_data| {
+                        blk_data[..data.len()].copy_from_slice(&data);
+                        blk_data[data.len()..].fill(0);
+                    });
+                    let _ = reply.send(res);
+                }
+                Request::Read(blk_offset, reply) => {
+                    let mut data = Vec::new();
+                    let res = fs
+                        .read(blk_offset, |blk_data| data.extend_from_slice(blk_data))
+                        .map(|_| data);
+                    let _ = reply.send(res);
+                }
+                Request::Stats(reply) => {
+                    let _ = reply.send(fs.stats());
+                }
+            }
+        }
+        log!(
+            debug,
+            "All handles of fs {} are dropped, stop io thread",
+            fs_id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokioFs;
+    use crate::block::FsId;
+    use crate::error::Error;
+    use crate::storage::ram::RamStorage;
+
+    const FS_ID: FsId = 2038274651;
+
+    #[tokio::test]
+    async fn test_tokio_fs() {
+        const BLOCK_SIZE: usize = 64;
+
+        let storage =
+            RamStorage::<{ BLOCK_SIZE * 8 }, BLOCK_SIZE>::new().expect("Can't create storage");
+        let fs = TokioFs::open(storage, FS_ID).await.expect("Can't open fs");
+
+        let tasks: std::vec::Vec<_> = (0..3u8)
+            .map(|i| {
+                let fs = fs.clone();
+                tokio::spawn(async move { fs.append(std::vec![i; 4]).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.expect("Task failed").expect("Can't append");
+        }
+
+        let stats = fs.stats().await.expect("Can't get stats");
+        assert_eq!((stats.id, stats.used_blocks), (FS_ID, 3));
+        let data = fs.read(0).await.expect("Can't read");
+        assert_eq!(data.len(), fs.data_size());
+        assert_eq!(&data[4..], &std::vec![0; fs.data_size() - 4][..]);
+
+        assert!(matches!(
+            fs.append(std::vec![0; fs.data_size() + 1]).await,
+            Err(Error::InvalidBlockSizeForWrite)
+        ));
+        assert!(fs.read(3).await.is_err());
+    }
+}
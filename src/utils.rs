@@ -1,4 +1,7 @@
+use core::ops::Range;
+
 use crate::error::Error;
+use crate::fs::config_block::CONFIG_COPIES;
 use crate::storage::Storage;
 
 pub fn slices_are_equal<T: core::cmp::PartialEq>(a: &[T], b: &[T]) -> bool {
@@ -14,12 +17,93 @@ pub fn validate_block_index<S: Storage>(storage: &S, blk_idx: usize) -> Result<(
     Ok(())
 }
 
+/// Wrap `blk_idx` to the range `min_blk..max_blk`, indexes below `min_blk` are trimmed to `min_blk`
 pub fn trim_block_idx_with_wraparound(blk_idx: usize, min_blk: usize, max_blk: usize) -> usize {
     if blk_idx < min_blk {
         min_blk
     } else if blk_idx >= max_blk {
-        blk_idx % max_blk + min_blk
+        (blk_idx - min_blk) % (max_blk - min_blk) + min_blk
     } else {
         blk_idx
     }
 }
+
+/// Storage indexes of data blocks of the filesystem over storage range `min_blk..max_blk`,
+/// the first blocks of the range are copies of config block (see [`CONFIG_COPIES`]) followed by
+/// `reserved` blocks (see [`crate::fs::Filesystem::new_with_reserved`])
+pub fn data_blk_range(min_blk: usize, max_blk: usize, reserved: usize) -> Range<usize> {
+    min_blk
+        .saturating_add(CONFIG_COPIES)
+        .saturating_add(reserved)..max_blk
+}
+
+/// Storage index of the data block `blk_offset` blocks after `base_blk_idx` along the ring of storage range
+/// `min_blk..max_blk` (config and `reserved` blocks are skipped). It's layout of the ring without pinned and bad
+/// blocks, e.g. `base_blk_idx` is index of the oldest block (see [`crate::fs::Filesystem::blk_idx_of`]).
+pub fn ring_blk_idx(
+    base_blk_idx: usize,
+    blk_offset: usize,
+    min_blk: usize,
+    max_blk: usize,
+    reserved: usize,
+) -> usize {
+    let ring = data_blk_range(min_blk, max_blk, reserved);
+    if ring.is_empty() {
+        return ring.start;
+    }
+
+    let base = trim_block_idx_with_wraparound(base_blk_idx, ring.start, ring.end) - ring.start;
+    ring.start + (base + blk_offset % ring.len()) % ring.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{data_blk_range, ring_blk_idx, trim_block_idx_with_wraparound};
+    use crate::block::CrcMode;
+    use crate::fs::Filesystem;
+    use crate::storage::ram::RamStorage;
+
+    #[test]
+    fn test_ring_blk_idx() {
+        assert_eq!(data_blk_range(2048, 4096, 0), 2050..4096);
+        assert_eq!(data_blk_range(2048, 4096, 3), 2053..4096);
+        assert_eq!(ring_blk_idx(2050, 0, 2048, 4096, 0), 2050);
+        assert_eq!(ring_blk_idx(4095, 1, 2048, 4096, 0), 2050);
+        assert_eq!(ring_blk_idx(4095, 1, 2048, 4096, 3), 2053);
+        assert_eq!(ring_blk_idx(3000, 2046 * 2 + 5, 2048, 4096, 0), 3005);
+        // index outside of the ring is wrapped like storage indexes
+        assert_eq!(ring_blk_idx(0, 3, 2048, 4096, 0), 2053);
+        assert_eq!(
+            ring_blk_idx(
+                usize::MAX - 2,
+                usize::MAX - 1,
+                usize::MAX - 5,
+                usize::MAX,
+                0
+            ),
+            usize::MAX - 3
+        );
+        assert_eq!(trim_block_idx_with_wraparound(4097, 2049, 4096), 2050);
+
+        // the same layout as filesystem uses, reserved blocks don't fit to config of 128 byte block
+        // with all header features
+        const BLOCK_SIZE: usize = 256;
+        type Fs<'a> = Filesystem<'a, RamStorage<{ BLOCK_SIZE * 8 }, BLOCK_SIZE>, BLOCK_SIZE>;
+        for reserved in [0, 2] {
+            let mut storage = RamStorage::new().expect("Can't create storage");
+            let mut fs = Fs::new_with_reserved(&mut storage, 7, CrcMode::Full, reserved)
+                .expect("Can't create fs");
+            for i in 0..13 {
+                fs.append(|blk_data| blk_data.fill(i))
+                    .expect("Can't append");
+                let oldest = fs.blk_idx_of(0).expect("Can't get index");
+                for blk_offset in 0..fs.used_blocks() {
+                    assert_eq!(
+                        fs.blk_idx_of(blk_offset).expect("Can't get index"),
+                        ring_blk_idx(oldest, blk_offset, 0, 8, reserved)
+                    );
+                }
+            }
+        }
+    }
+}